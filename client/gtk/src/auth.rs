@@ -86,14 +86,18 @@ impl Client {
         &self,
         credentials: Credentials,
         display_name: Option<String>,
+        registration_token: Option<String>,
     ) -> Result<UserId> {
+        let register = RegisterUser { credentials, display_name, registration_token };
         let response = self.post_auth(
-            AuthRequest::RegisterUser(RegisterUser { credentials, display_name }),
+            AuthRequest::RegisterUser(register),
             self.server.url().join("register")?,
         ).await?;
 
         match response? {
             AuthOk::User(user) => Ok(user),
+            // The account exists, but there is nothing more to do with it until it is approved
+            AuthOk::PendingApproval(_) => Err(Error::AuthErrorResponse(AuthError::PendingApproval)),
             _ => Err(Error::UnexpectedMessage),
         }
     }
@@ -1,12 +1,15 @@
 use std::rc::Rc;
 use std::sync::Mutex;
 
+use chrono::{DateTime, Utc};
 use futures::{FutureExt, Stream, StreamExt};
 use futures::future::{Abortable, AbortHandle};
 use futures::channel::mpsc::{self, UnboundedSender};
 
 pub use chat::*;
+pub use command::*;
 pub use community::*;
+pub use crypto::*;
 pub use message::*;
 pub use notification::*;
 pub use profile::*;
@@ -27,9 +30,14 @@ mod message;
 mod profile;
 mod chat;
 mod notification;
+mod command;
+mod crypto;
 
 pub const HEARTBEAT_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(2);
 
+/// How long to wait before reconnecting when the server tells us that it is shutting down
+pub const SHUTDOWN_RECONNECT_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+
 lazy_static::lazy_static! {
     /// Channel through which messages to the invite-listener are sent, to allow for following invite
     /// links from other apps through the `vertex://` protocol
@@ -64,6 +72,8 @@ pub struct ClientState {
     pub selected_room: Option<RoomEntry>,
     pub message_entry_is_empty: bool,
     pub admin_perms: AdminPermissionFlags,
+    /// Reports made since the reports list was last viewed
+    pub unread_reports: u32,
 }
 
 #[derive(Clone)]
@@ -74,6 +84,7 @@ pub struct Client {
     pub user: User,
     pub profiles: ProfileCache,
     pub embeds: EmbedCache,
+    pub crypto: Crypto,
 
     notifier: Notifier,
 
@@ -94,6 +105,7 @@ impl Client {
         let mut event_receiver = req_manager.receive_from(receiver);
 
         let ready = client_ready(&mut event_receiver).await?;
+        req_manager.set_ratelimits(ready.ratelimits);
 
         let user = User::new(
             request.clone(),
@@ -106,12 +118,18 @@ impl Client {
         let profiles = ProfileCache::new(request.clone(), user.clone());
         let embeds = EmbedCache::new();
 
+        let crypto = Crypto::new(request.clone(), ready.user, ws.device);
+        if let Err(err) = crypto.publish_keys().await {
+            log::warn!("failed to publish device keys: {:?}", err);
+        }
+
         let state = SharedMut::new(ClientState {
             communities: Vec::new(),
             chat: None,
             selected_room: None,
             message_entry_is_empty: true,
             admin_perms: ready.admin_permissions,
+            unread_reports: 0,
         });
 
         let (abort_signal, abort_handle) = futures::future::abortable(futures::future::pending());
@@ -122,6 +140,7 @@ impl Client {
             user,
             profiles,
             embeds,
+            crypto,
             notifier: Notifier::new(),
             abort_handle,
             state: state.downgrade(),
@@ -152,15 +171,34 @@ impl Client {
             }
             ServerEvent::AddRoom { community, structure } => self.handle_add_room(community, structure).await,
             ServerEvent::AddMessage { community, room, message } => self.handle_add_message(community, room, message).await,
+            ServerEvent::Delete(delete) => self.handle_delete(delete).await,
             ServerEvent::SessionLoggedOut => {
                 let screen = screen::login::build().await;
                 window::set_screen(&screen.main);
                 self.abort_handle.abort();
             }
+            ServerEvent::ServerShuttingDown => {
+                window::set_screen(&screen::loading::build());
+                self.abort_handle.abort();
+
+                scheduler::spawn(async {
+                    tokio::time::delay_for(SHUTDOWN_RECONNECT_DELAY).await;
+                    crate::start().await;
+                });
+            }
             ServerEvent::AdminPermissionsChanged(new_perms) => {
                 let state = self.state.upgrade().unwrap();
                 state.write().await.admin_perms = new_perms;
             }
+            ServerEvent::NewReport(report) => self.handle_new_report(report).await,
+            ServerEvent::RoomSettingsChanged { community, room, settings } => {
+                self.handle_room_settings_changed(community, room, settings).await
+            }
+            ServerEvent::PrekeysLow(_) => {
+                if let Err(err) = self.crypto.publish_keys().await {
+                    log::warn!("failed to top up prekeys: {:?}", err);
+                }
+            }
             unexpected => log::warn!("unhandled server event: {:?}", unexpected),
         }
     }
@@ -175,6 +213,22 @@ impl Client {
         self.abort_handle.abort();
     }
 
+    async fn handle_new_report(&self, report: Report) {
+        log::info!("new {} report #{}", report.kind, report.id);
+
+        let state = self.state.upgrade().unwrap();
+        let mut state = state.write().await;
+        state.unread_reports += 1;
+        self.ui.set_unread_reports(state.unread_reports);
+    }
+
+    /// Clears the unread report badge, once the admin has seen the reports list
+    pub async fn mark_reports_read(&self) {
+        let state = self.state.upgrade().unwrap();
+        state.write().await.unread_reports = 0;
+        self.ui.set_unread_reports(0);
+    }
+
     async fn handle_add_room(&self, community: CommunityId, room: RoomStructure) {
         if let Some(community) = self.community_by_id(community).await {
             community.add_room(room).await;
@@ -183,6 +237,22 @@ impl Client {
         }
     }
 
+    async fn handle_room_settings_changed(
+        &self,
+        community: CommunityId,
+        room: RoomId,
+        settings: RoomSettings,
+    ) {
+        let community = match self.community_by_id(community).await {
+            Some(community) => community,
+            None => return,
+        };
+
+        if let Some(room) = community.room_by_id(room).await {
+            room.set_settings(settings).await;
+        }
+    }
+
     async fn handle_add_message(&self, community: CommunityId, room: RoomId, message: Message) {
         if let Some(community) = self.community_by_id(community).await {
             if let Some(room) = community.room_by_id(room).await {
@@ -193,12 +263,20 @@ impl Client {
                 let a11y_narration = focused && selected && config::get().narrate_new_messages;
 
                 if (!focused || !selected) || a11y_narration {
-                    let profile = self.profiles.get_or_default(message.author, message.author_profile_version).await;
+                    let profile = self.profiles.for_message(&message).await;
+                    let text = match &message.ciphertext {
+                        Some(ciphertext) => {
+                            let decrypted = self.crypto.decrypt(message.author, ciphertext).await;
+                            decrypted.map(|decrypted| decrypted.text)
+                        }
+                        None => message.content.clone(),
+                    };
+
                     self.notifier.notify_message(
                         &profile,
                         &community.state.read().await.name,
                         &room.name,
-                        message.content.as_ref().map(|s| s as &str),
+                        text.as_ref().map(|s| s as &str),
                         a11y_narration,
                     ).await;
                 }
@@ -216,6 +294,18 @@ impl Client {
         log::warn!("received message for invalid room: {:?}#{:?}", community, room);
     }
 
+    async fn handle_delete(&self, delete: Delete) {
+        if let Some(community) = self.community_by_id(delete.community).await {
+            if let Some(room) = community.room_by_id(delete.room).await {
+                if let Some(chat) = self.chat_for(room.id).await {
+                    chat.remove(delete.message).await;
+                }
+
+                room.remove_message(delete.message).await;
+            }
+        }
+    }
+
     pub async fn create_community(&self, name: &str) -> Result<CommunityEntry> {
         let request = ClientRequest::CreateCommunity { name: name.to_owned() };
         let request = self.request.send(request).await;
@@ -236,6 +326,29 @@ impl Client {
         }
     }
 
+    pub async fn list_public_communities(&self, query: &str) -> Result<Vec<PublicCommunity>> {
+        let request = ClientRequest::ListPublicCommunities {
+            query: query.to_owned(),
+            offset: 0,
+        };
+        let request = self.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::PublicCommunities(communities) => Ok(communities),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn join_public_community(&self, id: CommunityId) -> Result<CommunityEntry> {
+        let request = ClientRequest::JoinPublicCommunity(id);
+        let request = self.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::AddCommunity(community) => Ok(self.add_community(community).await),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
     async fn add_community(&self, community: CommunityStructure) -> CommunityEntry {
         let widget = self.ui.add_community(community.name.clone(), community.description.clone());
 
@@ -244,6 +357,7 @@ impl Client {
             widget,
             community.id,
             community.name,
+            community.public,
         );
 
         entry.widget.bind_events(&entry);
@@ -274,6 +388,7 @@ impl Client {
 
     pub async fn select_room(&self, room: RoomEntry) {
         let chat = self.ui.select_room(&room);
+        self.ui.show_room_settings(&room.state.read().await.settings);
         let chat = Chat::new(
             self.clone(),
             chat,
@@ -297,6 +412,19 @@ impl Client {
             community: room.community,
             room: room.id,
         }).await;
+
+        if let Some(community) = self.community_by_id(room.community).await {
+            if let Err(err) = community.refresh_commands().await {
+                log::warn!("failed to get commands for community: {:?}", err);
+            }
+        }
+    }
+
+    /// Shows the room's settings under the message entry, if it is the room being looked at
+    pub async fn update_room_notice(&self, room: &RoomEntry) {
+        if self.is_selected(room.community, room.id).await {
+            self.ui.show_room_settings(&room.state.read().await.settings);
+        }
     }
 
     pub async fn deselect_room(&self) {
@@ -396,6 +524,16 @@ impl Client {
         }
     }
 
+    pub async fn search_audit_log(&self, criteria: AuditLogCriteria) -> Result<Vec<AuditLogEntry>> {
+        let req = ClientRequest::AdminAction(AdminRequest::SearchAuditLog(criteria));
+        let req = self.request.send(req).await;
+
+        match req.response().await? {
+            OkResponse::Admin(AdminResponse::AuditLog(entries)) => Ok(entries),
+            _ => Err(Error::UnexpectedMessage)
+        }
+    }
+
     async fn do_to_many(
         &self,
         users: Vec<UserId>,
@@ -419,7 +557,7 @@ impl Client {
     pub async fn ban_users(&self, users: Vec<UserId>) -> Result<Vec<(UserId, Error)>> {
         self.do_to_many(
             users,
-            |user| ClientRequest::AdminAction(AdminRequest::Ban(user))
+            |user| ClientRequest::AdminAction(AdminRequest::Ban { user, restriction: Restriction::default() })
         ).await
     }
 
@@ -455,14 +593,75 @@ impl Client {
         ).await
     }
 
-    pub async fn report_message(
+    pub async fn list_pending_users(&self) -> Result<Vec<PendingUser>> {
+        let req = ClientRequest::AdminAction(AdminRequest::ListPendingUsers);
+        let req = self.request.send(req).await;
+
+        match req.response().await? {
+            OkResponse::Admin(AdminResponse::PendingUsers(users)) => Ok(users),
+            _ => Err(Error::UnexpectedMessage)
+        }
+    }
+
+    pub async fn approve_users(&self, users: Vec<UserId>) -> Result<Vec<(UserId, Error)>> {
+        self.do_to_many(
+            users,
+            |user| ClientRequest::AdminAction(AdminRequest::ApproveUser(user))
+        ).await
+    }
+
+    pub async fn reject_users(&self, users: Vec<UserId>) -> Result<Vec<(UserId, Error)>> {
+        self.do_to_many(
+            users,
+            |user| ClientRequest::AdminAction(AdminRequest::RejectUser(user))
+        ).await
+    }
+
+    pub async fn list_registration_tokens(&self) -> Result<Vec<RegistrationToken>> {
+        let req = ClientRequest::AdminAction(AdminRequest::ListRegistrationTokens);
+        let req = self.request.send(req).await;
+
+        match req.response().await? {
+            OkResponse::Admin(AdminResponse::RegistrationTokens(tokens)) => Ok(tokens),
+            _ => Err(Error::UnexpectedMessage)
+        }
+    }
+
+    pub async fn create_registration_token(
         &self,
-        message: MessageId,
+        expiration_datetime: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
+    ) -> Result<RegistrationToken> {
+        let req = ClientRequest::AdminAction(AdminRequest::CreateRegistrationToken {
+            expiration_datetime,
+            max_uses,
+        });
+        let req = self.request.send(req).await;
+
+        match req.response().await? {
+            OkResponse::Admin(AdminResponse::RegistrationToken(token)) => Ok(token),
+            _ => Err(Error::UnexpectedMessage)
+        }
+    }
+
+    pub async fn revoke_registration_token(&self, token: String) -> Result<()> {
+        let req = ClientRequest::AdminAction(AdminRequest::RevokeRegistrationToken(token));
+        let req = self.request.send(req).await;
+
+        match req.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage)
+        }
+    }
+
+    pub async fn report(
+        &self,
+        target: ReportTarget,
         short_desc: &str,
         extended_desc: &str,
     ) -> Result<()> {
-        let request = ClientRequest::ReportUser {
-            message,
+        let request = ClientRequest::Report {
+            target,
             short_desc: short_desc.to_string(),
             extended_desc: extended_desc.to_string(),
         };
@@ -486,6 +685,60 @@ impl Client {
         }
     }
 
+    pub async fn claim_report(&self, id: i32) -> Result<()> {
+        let request = ClientRequest::AdminAction(AdminRequest::ClaimReport(id));
+        let request = self.request.send(request).await;
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn unclaim_report(&self, id: i32) -> Result<()> {
+        let request = ClientRequest::AdminAction(AdminRequest::UnclaimReport(id));
+        let request = self.request.send(request).await;
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn add_report_note(&self, id: i32, text: String) -> Result<()> {
+        let request = ClientRequest::AdminAction(AdminRequest::AddReportNote { id, text });
+        let request = self.request.send(request).await;
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn get_report_context(&self, id: i32) -> Result<Vec<ReportContextMessage>> {
+        let request = ClientRequest::AdminAction(AdminRequest::GetReportContext(id));
+        let request = self.request.send(request).await;
+        match request.response().await? {
+            OkResponse::Admin(AdminResponse::ReportContext(context)) => Ok(context),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn resolve_report(
+        &self,
+        id: i32,
+        ban_user: bool,
+        delete_message: bool,
+    ) -> Result<()> {
+        let request = ClientRequest::AdminAction(AdminRequest::ResolveReport {
+            id,
+            ban_user,
+            delete_message,
+        });
+        let request = self.request.send(request).await;
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
     pub async fn set_compromised(&self, typ: SetCompromisedType) -> Result<()> {
         let request = ClientRequest::AdminAction(AdminRequest::SetAccountsCompromised(typ));
         let request = self.request.send(request).await;
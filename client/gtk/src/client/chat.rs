@@ -70,9 +70,11 @@ impl ChatState {
         id: MessageId,
     ) -> MessageEntryWidget {
         let rich = RichMessage::parse(content.text.clone());
+        let encrypted = content.encrypted;
         let widget = self.widget.add_message(content, side, self.client.clone(), id);
 
-        if rich.has_embeds() {
+        // Loading embeds would reveal the links in an encrypted message to the sites they point to
+        if rich.has_embeds() && !encrypted {
             let client = self.client.clone();
             let widget = widget.clone();
 
@@ -123,6 +125,12 @@ impl ChatState {
         }
     }
 
+    fn remove(&mut self, id: MessageId) {
+        let entries = std::mem::take(&mut self.entries);
+        self.entries = entries.into_iter().filter(|entry| entry.id != id).collect();
+        self.widget.remove_message(id);
+    }
+
     fn clear(&mut self) {
         self.widget.clear();
         self.entries.clear();
@@ -167,11 +175,21 @@ impl Chat {
     }
 
     async fn build_content(&self, message: &Message) -> MessageContent {
+        let (text, trust) = match &message.ciphertext {
+            Some(ciphertext) => match self.client.crypto.decrypt(message.author, ciphertext).await {
+                Some(decrypted) => (Some(decrypted.text), Some(decrypted.trust)),
+                None => (Some("<Unable to decrypt message>".to_string()), None),
+            },
+            None => (message.content.clone(), None),
+        };
+
         MessageContent {
             author: message.author,
-            profile: self.client.profiles.get_or_default(message.author, message.author_profile_version).await,
-            text: message.content.clone(),
+            profile: self.client.profiles.for_message(message).await,
+            text,
             time: message.time_sent,
+            encrypted: message.ciphertext.is_some(),
+            trust,
         }
     }
 
@@ -202,6 +220,12 @@ impl Chat {
         }
     }
 
+    pub async fn remove(&self, id: MessageId) {
+        let mut state = self.state.write().await;
+        state.remove(id);
+        state.flush();
+    }
+
     #[inline]
     pub fn accepts(&self, room: RoomId) -> bool {
         self.room.id == room
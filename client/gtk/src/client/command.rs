@@ -0,0 +1,131 @@
+use vertex::prelude::*;
+
+use crate::{Client, Error, Result};
+use crate::screen::active::dialog;
+
+use super::{CommunityEntry, RoomEntry};
+
+/// A command that may be completed from what has been typed so far, shown as an autocompletion
+#[derive(Debug, Clone)]
+pub struct CommandSuggestion {
+    pub name: String,
+    pub usage: String,
+    pub description: String,
+}
+
+/// Splits `/name arguments...` into the command name and the rest of the text
+fn split_command(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start().strip_prefix('/')?;
+    let end = text.find(char::is_whitespace).unwrap_or_else(|| text.len());
+    Some((&text[..end], text[end..].trim()))
+}
+
+/// Whether the message entry content should be treated as a command rather than a message. A
+/// leading double slash escapes this, e.g `//path` is sent as `/path`.
+pub fn is_command(text: &str) -> bool {
+    let text = text.trim_start();
+    text.starts_with('/') && !text.starts_with("//")
+}
+
+impl Client {
+    /// Returns the commands which match the partially typed command, if any is being typed
+    pub async fn command_suggestions(&self, text: &str) -> Vec<CommandSuggestion> {
+        let prefix = match split_command(text) {
+            // Only suggest until the user starts typing the arguments
+            Some((name, "")) if !text.ends_with(char::is_whitespace) => name,
+            _ => return Vec::new(),
+        };
+
+        let mut suggestions: Vec<CommandSuggestion> = vertex::BUILTIN_COMMANDS.iter()
+            .filter(|command| command.name.starts_with(prefix))
+            .map(|command| CommandSuggestion {
+                name: command.name.to_string(),
+                usage: command.usage.to_string(),
+                description: command.description.to_string(),
+            })
+            .collect();
+
+        if let Some(community) = self.selected_community().await {
+            let commands = community.commands().await;
+            let bot_suggestions = commands.into_iter()
+                .filter(|registered| registered.command.name.starts_with(prefix))
+                .map(|registered| CommandSuggestion {
+                    usage: registered.command.usage(),
+                    name: registered.command.name,
+                    description: registered.command.description,
+                });
+
+            suggestions.extend(bot_suggestions);
+        }
+
+        suggestions
+    }
+
+    /// Runs the command typed into the message entry in the given room
+    pub async fn run_command(&self, room: RoomEntry, text: &str) {
+        if let Err(err) = self.try_run_command(room, text).await {
+            dialog::show_generic_error(&err);
+        }
+    }
+
+    async fn try_run_command(&self, room: RoomEntry, text: &str) -> Result<()> {
+        let (name, rest) = match split_command(text) {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+
+        let community = self.community_by_id(room.community).await
+            .ok_or(Error::ErrorResponse(vertex::responses::Error::InvalidCommunity))?;
+
+        match name {
+            "me" if !rest.is_empty() => room.send_message(format!("*{}*", rest)).await,
+            "shrug" => {
+                let message = format!("{} ¯\\_(ツ)_/¯", rest);
+                room.send_message(message.trim_start().to_string()).await;
+            }
+            "invite" => {
+                let invite = community.create_invite(None, None).await?;
+                dialog::show_invite_dialog(invite);
+            }
+            "topic" if !rest.is_empty() => community.change_description(rest.to_string()).await?,
+            "nick" if !rest.is_empty() => self.user.change_display_name(rest.to_string()).await?,
+            "report-room" => {
+                let target = ReportTarget::Room { community: room.community, room: room.id };
+                dialog::show_report(self.clone(), target);
+            }
+            _ => self.invoke_bot_command(&community, &room, name, rest).await?,
+        }
+
+        Ok(())
+    }
+
+    async fn invoke_bot_command(
+        &self,
+        community: &CommunityEntry,
+        room: &RoomEntry,
+        name: &str,
+        rest: &str,
+    ) -> Result<()> {
+        let commands = community.commands().await;
+        let registered = commands.into_iter()
+            .find(|registered| registered.command.name == name)
+            .ok_or(Error::ErrorResponse(vertex::responses::Error::InvalidCommand))?;
+
+        let arguments = registered.command.parse_arguments(rest)
+            .ok_or(Error::ErrorResponse(vertex::responses::Error::InvalidCommand))?;
+
+        let request = ClientRequest::InvokeCommand {
+            community: community.id,
+            room: room.id,
+            bot: registered.bot,
+            command: registered.command.name,
+            arguments,
+        };
+        let request = self.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+}
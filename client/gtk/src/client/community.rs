@@ -11,7 +11,11 @@ use crate::screen::active::CommunityEntryWidget;
 
 pub struct CommunityState {
     pub name: String,
+    /// Whether the community is listed in the server's public directory
+    pub public: bool,
     rooms: Vec<RoomEntry>,
+    /// The commands that bots have registered in this community
+    commands: Vec<RegisteredCommand>,
 }
 
 #[derive(Clone)]
@@ -30,19 +34,27 @@ impl CommunityEntry {
         widget: CommunityEntryWidget,
         id: CommunityId,
         name: String,
+        public: bool,
     ) -> Self {
         let state = SharedMut::new(CommunityState {
             name,
+            public,
             rooms: Vec::new(),
+            commands: Vec::new(),
         });
         CommunityEntry { client, widget, id, state }
     }
 
     pub async fn create_invite(
         &self,
-        expiration_datetime: Option<DateTime<Utc>>
+        expiration_datetime: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
     ) -> Result<InviteCode> {
-        let request = ClientRequest::CreateInvite { community: self.id, expiration_datetime };
+        let request = ClientRequest::CreateInvite {
+            community: self.id,
+            expiration_datetime,
+            max_uses,
+        };
         let request = self.client.request.send(request).await;
 
         match request.response().await? {
@@ -51,8 +63,42 @@ impl CommunityEntry {
         }
     }
 
-    pub async fn create_room(&self, name: &str) -> Result<RoomEntry> {
-        let request = ClientRequest::CreateRoom { name: name.to_owned(), community: self.id };
+    pub async fn list_invites(&self) -> Result<Vec<Invite>> {
+        let request = ClientRequest::ListInvites { community: self.id };
+        let request = self.client.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::Invites(invites) => Ok(invites),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn revoke_invite(&self, code: InviteCode) -> Result<()> {
+        let request = ClientRequest::RevokeInvite { community: self.id, code };
+        let request = self.client.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn invite_uses(&self, code: InviteCode) -> Result<Vec<InviteUse>> {
+        let request = ClientRequest::GetInviteUses { community: self.id, code };
+        let request = self.client.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::InviteUses(uses) => Ok(uses),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn create_room(&self, name: &str, encrypted: bool) -> Result<RoomEntry> {
+        let request = ClientRequest::CreateRoom {
+            name: name.to_owned(),
+            community: self.id,
+            encrypted,
+        };
         let request = self.client.request.send(request).await;
 
         let response = request.response().await?;
@@ -63,6 +109,50 @@ impl CommunityEntry {
         }
     }
 
+    pub async fn change_description(&self, new: String) -> Result<()> {
+        let request = ClientRequest::ChangeCommunityDescription { community: self.id, new };
+        let request = self.client.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn set_public(&self, public: bool) -> Result<()> {
+        let request = ClientRequest::SetCommunityPublic { community: self.id, public };
+        let request = self.client.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::NoData => {
+                self.state.write().await.public = public;
+                Ok(())
+            }
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn is_public(&self) -> bool {
+        self.state.read().await.public
+    }
+
+    pub async fn refresh_commands(&self) -> Result<()> {
+        let request = ClientRequest::ListCommands { community: self.id };
+        let request = self.client.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::Commands(commands) => {
+                self.state.write().await.commands = commands;
+                Ok(())
+            }
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn commands(&self) -> Vec<RegisteredCommand> {
+        self.state.read().await.commands.clone()
+    }
+
     pub async fn room_by_id(&self, id: RoomId) -> Option<RoomEntry> {
         self.state.read().await.rooms.iter()
             .find(|&room| room.id == id)
@@ -82,6 +172,8 @@ impl CommunityEntry {
             self.id,
             room.id,
             room.name,
+            room.encrypted,
+            room.settings,
         );
 
         let mut state = self.state.write().await;
@@ -0,0 +1,520 @@
+//! End-to-end encryption for encrypted rooms. Each message is encrypted once with a random message
+//! key, and the message key is then wrapped for every device in the community with the session key
+//! that the sender has with that device. A session is set up the first time a device sends to
+//! another, by combining Diffie-Hellman exchanges between the sender's identity key, a fresh
+//! ephemeral key, and the recipient device's identity key and one-time prekey (if it had one left).
+//! The recipient deletes the prekey secret once it has derived the session key. The server only
+//! ever sees the resulting envelope.
+//!
+//! The identity key of every device is remembered the first time it is seen. Messages are checked
+//! to come from a device of their author, and are marked if that device has not been verified. A
+//! verified device whose identity key changes is no longer encrypted to until it is verified again.
+
+use std::rc::Rc;
+
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use vertex::prelude::*;
+
+use crate::key_store::{InboundSession, KnownDevice, OutboundSession};
+use crate::{key_store, net, Error, Result, SharedMut};
+
+/// Top up the prekeys on the server when fewer than this many are left
+const MIN_PREKEYS: u32 = 20;
+const PREKEY_BATCH_SIZE: usize = 50;
+
+const KEY_INFO: &[u8] = b"vertex message key";
+
+/// The ciphertext of a message, as sent to the server
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    sender_device: DeviceId,
+    sender_identity: Vec<u8>,
+    nonce: Vec<u8>,
+    body: Vec<u8>,
+    recipients: Vec<WrappedKey>,
+}
+
+/// The message key, encrypted for one device with the sender's session key for it
+#[derive(Serialize, Deserialize)]
+struct WrappedKey {
+    device: DeviceId,
+    /// The ephemeral key that the session was set up with
+    ephemeral: Vec<u8>,
+    /// The public half of the one-time prekey that the session was set up with, if any
+    prekey: Option<Vec<u8>>,
+    nonce: Vec<u8>,
+    key: Vec<u8>,
+}
+
+/// How far the device that sent a message can be trusted to belong to its author
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SenderTrust {
+    /// The device has been verified by the user
+    Verified,
+    /// The device belongs to the author, but has not been verified
+    Unverified,
+    /// The device was verified, but its identity key has changed since
+    KeyChanged,
+    /// The device could not be confirmed to belong to the author
+    Unknown,
+}
+
+pub struct Encrypted {
+    pub ciphertext: Vec<u8>,
+    /// How many devices the message was not encrypted to, because their identity keys changed
+    /// since they were verified
+    pub withheld: usize,
+}
+
+pub struct Decrypted {
+    pub text: String,
+    pub trust: SenderTrust,
+}
+
+#[derive(Clone)]
+pub struct Crypto {
+    request: Rc<net::RequestSender>,
+    user: UserId,
+    device: DeviceId,
+    keys: SharedMut<key_store::StoredKeys>,
+}
+
+impl Crypto {
+    pub(super) fn new(request: Rc<net::RequestSender>, user: UserId, device: DeviceId) -> Self {
+        let mut keys = key_store::get_stored_keys(device).unwrap_or_default();
+        if keys.identity_secret.is_none() {
+            keys.identity_secret = Some(StaticSecret::new(&mut OsRng).to_bytes());
+            key_store::store_keys(device, &keys);
+        }
+
+        Crypto { request, user, device, keys: SharedMut::new(keys) }
+    }
+
+    /// Publishes this device's identity key, topping up its one-time prekeys if they are running
+    /// low
+    pub async fn publish_keys(&self) -> Result<()> {
+        let remaining = self.publish(Vec::new()).await?;
+        if remaining >= MIN_PREKEYS {
+            return Ok(());
+        }
+
+        let secrets: Vec<StaticSecret> = (0..PREKEY_BATCH_SIZE)
+            .map(|_| StaticSecret::new(&mut OsRng))
+            .collect();
+
+        // Store the secrets first, so that a prekey is never handed out that we cannot use
+        {
+            let mut keys = self.keys.write().await;
+            keys.prekey_secrets.extend(secrets.iter().map(StaticSecret::to_bytes));
+            key_store::store_keys(self.device, &keys);
+        }
+
+        let prekeys = secrets.iter()
+            .map(|secret| PublicKey::from(secret).as_bytes().to_vec())
+            .collect();
+        self.publish(prekeys).await?;
+
+        Ok(())
+    }
+
+    async fn publish(&self, one_time_prekeys: Vec<Vec<u8>>) -> Result<u32> {
+        let request = ClientRequest::PublishDeviceKeys {
+            identity_key: self.identity_key().await,
+            one_time_prekeys,
+        };
+        let request = self.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::RemainingPrekeys(remaining) => Ok(remaining),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn identity_key(&self) -> Vec<u8> {
+        PublicKey::from(&self.identity_secret().await).as_bytes().to_vec()
+    }
+
+    async fn identity_secret(&self) -> StaticSecret {
+        let keys = self.keys.read().await;
+        StaticSecret::from(keys.identity_secret.expect("identity key generated on creation"))
+    }
+
+    /// Encrypts a message to every device in the community, except for verified devices whose
+    /// identity keys have changed
+    pub async fn encrypt(&self, community: CommunityId, text: &str) -> Result<Encrypted> {
+        // Prekeys are only needed for devices that there is no session with yet
+        let except_devices = self.keys.read().await.outbound_sessions.keys().copied().collect();
+        let request = ClientRequest::ClaimDeviceKeys { community, except_devices };
+        let request = self.request.send(request).await;
+        let bundles = match request.response().await? {
+            OkResponse::DeviceKeys(bundles) => bundles,
+            _ => return Err(Error::UnexpectedMessage),
+        };
+
+        let mut message_key = [0; 32];
+        let mut nonce = [0; 24];
+        OsRng.fill_bytes(&mut message_key);
+        OsRng.fill_bytes(&mut nonce);
+
+        let body = cipher(&message_key).encrypt(XNonce::from_slice(&nonce), text.as_bytes())
+            .map_err(|_| Error::ProtocolError(None))?;
+
+        let identity = self.identity_secret().await;
+        let mut recipients = Vec::with_capacity(bundles.len());
+        let mut withheld = 0;
+
+        for bundle in bundles {
+            match self.check_device(bundle.user, bundle.device, &bundle.identity_key).await {
+                Some(SenderTrust::Verified) | Some(SenderTrust::Unverified) => {}
+                _ => {
+                    withheld += 1;
+                    continue;
+                }
+            }
+
+            let wrapped = self.outbound_session(&identity, &bundle).await
+                .and_then(|session| wrap_key(bundle.device, &session, &message_key));
+            recipients.extend(wrapped);
+        }
+
+        let envelope = Envelope {
+            sender_device: self.device,
+            sender_identity: PublicKey::from(&identity).as_bytes().to_vec(),
+            nonce: nonce.to_vec(),
+            body,
+            recipients,
+        };
+
+        let ciphertext = serde_json::to_vec(&envelope).expect("unable to serialize envelope");
+        Ok(Encrypted { ciphertext, withheld })
+    }
+
+    /// Gets the session with the device, setting up a new one if there is none yet or if the
+    /// device's identity key has changed since
+    async fn outbound_session(
+        &self,
+        identity: &StaticSecret,
+        bundle: &DeviceKeyBundle,
+    ) -> Option<OutboundSession> {
+        let mut keys = self.keys.write().await;
+        if let Some(session) = keys.outbound_sessions.get(&bundle.device) {
+            if session.identity_key == bundle.identity_key {
+                return Some(session.clone());
+            }
+        }
+
+        let session = new_session(identity, bundle)?;
+        keys.outbound_sessions.insert(bundle.device, session.clone());
+        key_store::store_keys(self.device, &keys);
+
+        Some(session)
+    }
+
+    /// Decrypts a message sent to this device by the given author. Returns `None` if it was not
+    /// encrypted for this device, e.g because it was sent before this device logged in, or if it
+    /// was sent from a device of another user.
+    pub async fn decrypt(&self, author: UserId, ciphertext: &[u8]) -> Option<Decrypted> {
+        let envelope: Envelope = serde_json::from_slice(ciphertext).ok()?;
+        let wrapped = envelope.recipients.iter().find(|key| key.device == self.device)?;
+
+        if wrapped.nonce.len() != 24 || envelope.nonce.len() != 24 {
+            return None;
+        }
+
+        let sender = envelope.sender_device;
+        let (session_key, is_new) = self.inbound_session(&envelope, wrapped).await?;
+        let message_key = cipher(&session_key)
+            .decrypt(XNonce::from_slice(&wrapped.nonce), &wrapped.key[..])
+            .ok()?;
+
+        if message_key.len() != 32 {
+            return None;
+        }
+
+        // Only keep the session and give up the prekey once the sender has shown that it has the
+        // session key, so that a prekey cannot be used up by a forged message
+        if is_new {
+            let identity_key = &envelope.sender_identity;
+            self.store_inbound_session(sender, identity_key, wrapped, session_key).await;
+        }
+
+        let text = cipher(&message_key)
+            .decrypt(XNonce::from_slice(&envelope.nonce), &envelope.body[..])
+            .ok()?;
+        let text = String::from_utf8(text).ok()?;
+
+        let trust = self.sender_trust(author, sender, &envelope.sender_identity).await?;
+        Some(Decrypted { text, trust })
+    }
+
+    /// Gets the key of the session that the sender used, deriving it if it is new. Returns whether
+    /// it is new along with the key.
+    async fn inbound_session(
+        &self,
+        envelope: &Envelope,
+        wrapped: &WrappedKey,
+    ) -> Option<([u8; 32], bool)> {
+        let keys = self.keys.read().await;
+        let existing = keys.inbound_sessions.get(&envelope.sender_device)
+            .and_then(|sessions| sessions.iter().find(|session| {
+                session.ephemeral == wrapped.ephemeral
+                    && session.identity_key == envelope.sender_identity
+            }));
+
+        if let Some(session) = existing {
+            return Some((session.key, false));
+        }
+
+        let sender = public_key(&envelope.sender_identity)?;
+        let ephemeral = public_key(&wrapped.ephemeral)?;
+
+        let identity = StaticSecret::from(keys.identity_secret?);
+        let prekey = match &wrapped.prekey {
+            Some(public) => Some(find_prekey(&keys.prekey_secrets, public)?),
+            None => None,
+        };
+        drop(keys);
+
+        let mut material = identity.diffie_hellman(&sender).as_bytes().to_vec();
+        material.extend_from_slice(identity.diffie_hellman(&ephemeral).as_bytes());
+        if let Some(prekey) = prekey {
+            material.extend_from_slice(prekey.diffie_hellman(&ephemeral).as_bytes());
+        }
+
+        Some((derive_key(&material, &ephemeral), true))
+    }
+
+    /// Keeps a new session so that later messages in it can be decrypted, and deletes the secret
+    /// of the prekey that it was set up with, since it is no longer needed
+    async fn store_inbound_session(
+        &self,
+        sender: DeviceId,
+        identity_key: &[u8],
+        wrapped: &WrappedKey,
+        key: [u8; 32],
+    ) {
+        let mut keys = self.keys.write().await;
+
+        let sessions = keys.inbound_sessions.entry(sender).or_default();
+        if !sessions.iter().any(|session| session.ephemeral == wrapped.ephemeral) {
+            sessions.push(InboundSession {
+                identity_key: identity_key.to_vec(),
+                ephemeral: wrapped.ephemeral.clone(),
+                key,
+            });
+        }
+
+        if let Some(prekey) = &wrapped.prekey {
+            keys.prekey_secrets.retain(|secret| {
+                PublicKey::from(&StaticSecret::from(*secret)).as_bytes() != &prekey[..]
+            });
+        }
+
+        key_store::store_keys(self.device, &keys);
+    }
+
+    /// Checks that the device that sent a message belongs to its author. If the device has not
+    /// been seen before, or its identity key has changed, this is checked with the server.
+    async fn sender_trust(
+        &self,
+        author: UserId,
+        device: DeviceId,
+        identity_key: &[u8],
+    ) -> Option<SenderTrust> {
+        let known = {
+            let keys = self.keys.read().await;
+            keys.devices.get(&device)
+                .map(|known| known.user == author && known.identity_key == identity_key)
+                .unwrap_or(false)
+        };
+
+        if !known && device != self.device {
+            let bundles = match self.device_keys(author).await {
+                Ok(bundles) => bundles,
+                Err(_) => return Some(SenderTrust::Unknown),
+            };
+
+            let listed = bundles.iter()
+                .any(|bundle| bundle.device == device && bundle.identity_key == identity_key);
+            if !listed {
+                return Some(SenderTrust::Unknown);
+            }
+        }
+
+        self.check_device(author, device, identity_key).await
+    }
+
+    /// Checks a device's identity key against the one last seen for it, remembering the device if
+    /// it has not been seen before. Returns `None` if the device belongs to another user.
+    ///
+    /// An unverified device's identity key may change, e.g if the device lost its keys, since it
+    /// was never checked in the first place. A verified device whose identity key has changed must
+    /// be verified again.
+    async fn check_device(
+        &self,
+        user: UserId,
+        device: DeviceId,
+        identity_key: &[u8],
+    ) -> Option<SenderTrust> {
+        if device == self.device {
+            let own = user == self.user && self.identity_key().await == identity_key;
+            return if own { Some(SenderTrust::Verified) } else { None };
+        }
+
+        let mut keys = self.keys.write().await;
+        match keys.devices.get(&device) {
+            Some(known) if known.user != user => return None,
+            Some(known) if known.identity_key == identity_key => {
+                let trust = if known.verified {
+                    SenderTrust::Verified
+                } else {
+                    SenderTrust::Unverified
+                };
+                return Some(trust);
+            }
+            Some(known) if known.verified => return Some(SenderTrust::KeyChanged),
+            _ => {}
+        }
+
+        let known = KnownDevice { user, identity_key: identity_key.to_vec(), verified: false };
+        keys.devices.insert(device, known);
+        key_store::store_keys(self.device, &keys);
+
+        Some(SenderTrust::Unverified)
+    }
+
+    /// Gets the identity keys of all of the user's devices, to verify them
+    pub async fn device_keys(&self, user: UserId) -> Result<Vec<DeviceKeyBundle>> {
+        let request = ClientRequest::GetDeviceKeys { user };
+        let request = self.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::DeviceKeys(bundles) => Ok(bundles),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    /// How far the device can be trusted with this identity key. A device whose identity key has
+    /// changed since it was verified is no longer verified.
+    pub async fn device_trust(&self, device: DeviceId, identity_key: &[u8]) -> SenderTrust {
+        let keys = self.keys.read().await;
+        match keys.devices.get(&device) {
+            Some(known) if known.identity_key == identity_key && known.verified => {
+                SenderTrust::Verified
+            }
+            Some(known) if known.identity_key != identity_key && known.verified => {
+                SenderTrust::KeyChanged
+            }
+            _ => SenderTrust::Unverified,
+        }
+    }
+
+    /// Marks the device as verified or not with this identity key, which replaces the one that was
+    /// last seen for it
+    pub async fn set_verified(
+        &self,
+        user: UserId,
+        device: DeviceId,
+        identity_key: Vec<u8>,
+        verified: bool,
+    ) {
+        let mut keys = self.keys.write().await;
+        keys.devices.insert(device, KnownDevice { user, identity_key, verified });
+        key_store::store_keys(self.device, &keys);
+    }
+
+    /// Forgets this device's keys, e.g when logging out
+    pub fn forget(&self) {
+        key_store::forget_keys(self.device);
+    }
+}
+
+/// A human readable fingerprint of an identity key, to compare out of band
+pub fn fingerprint(identity_key: &[u8]) -> String {
+    let hash = Sha256::digest(identity_key);
+    hash[..16].chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn new_session(identity: &StaticSecret, bundle: &DeviceKeyBundle) -> Option<OutboundSession> {
+    let recipient = public_key(&bundle.identity_key)?;
+    let prekey = match &bundle.one_time_prekey {
+        Some(key) => Some(public_key(key)?),
+        None => None,
+    };
+
+    let ephemeral = StaticSecret::new(&mut OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    let mut material = identity.diffie_hellman(&recipient).as_bytes().to_vec();
+    material.extend_from_slice(ephemeral.diffie_hellman(&recipient).as_bytes());
+    if let Some(prekey) = &prekey {
+        material.extend_from_slice(ephemeral.diffie_hellman(prekey).as_bytes());
+    }
+
+    Some(OutboundSession {
+        identity_key: bundle.identity_key.clone(),
+        ephemeral: ephemeral_public.as_bytes().to_vec(),
+        prekey: bundle.one_time_prekey.clone(),
+        key: derive_key(&material, &ephemeral_public),
+    })
+}
+
+fn wrap_key(
+    device: DeviceId,
+    session: &OutboundSession,
+    message_key: &[u8; 32],
+) -> Option<WrappedKey> {
+    let mut nonce = [0; 24];
+    OsRng.fill_bytes(&mut nonce);
+
+    let key = cipher(&session.key)
+        .encrypt(XNonce::from_slice(&nonce), &message_key[..])
+        .ok()?;
+
+    Some(WrappedKey {
+        device,
+        ephemeral: session.ephemeral.clone(),
+        prekey: session.prekey.clone(),
+        nonce: nonce.to_vec(),
+        key,
+    })
+}
+
+fn find_prekey(secrets: &[[u8; 32]], public: &[u8]) -> Option<StaticSecret> {
+    secrets.iter()
+        .map(|secret| StaticSecret::from(*secret))
+        .find(|secret| PublicKey::from(secret).as_bytes() == public)
+}
+
+fn derive_key(material: &[u8], ephemeral: &PublicKey) -> [u8; 32] {
+    let mut key = [0; 32];
+    Hkdf::<Sha256>::new(Some(ephemeral.as_bytes()), material)
+        .expand(KEY_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF output length");
+    key
+}
+
+fn cipher(key: &[u8]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+fn public_key(bytes: &[u8]) -> Option<PublicKey> {
+    if bytes.len() != 32 {
+        return None;
+    }
+
+    let mut key = [0; 32];
+    key.copy_from_slice(bytes);
+    Some(PublicKey::from(key))
+}
@@ -4,7 +4,7 @@ pub use embed::*;
 pub use rich::*;
 use vertex::prelude::*;
 
-
+use super::SenderTrust;
 
 mod rich;
 mod embed;
@@ -25,6 +25,10 @@ pub struct MessageContent {
     pub profile: Profile,
     pub text: Option<String>, // TODO properly handle deletion
     pub time: DateTime<Utc>,
+    /// Whether the message was end-to-end encrypted
+    pub encrypted: bool,
+    /// How far the device that sent an encrypted message can be trusted, if it was decrypted
+    pub trust: Option<SenderTrust>,
 }
 
 pub struct MessageRingBuffer {
@@ -60,6 +64,18 @@ impl MessageRingBuffer {
         self.write_index = (self.write_index + 1) % self.buffer.capacity();
     }
 
+    pub fn remove(&mut self, id: MessageId) {
+        if !self.contains(id) {
+            return;
+        }
+
+        let capacity = self.buffer.capacity();
+        let messages = std::mem::replace(self, MessageRingBuffer::new(capacity)).collect();
+        for message in messages.into_iter().filter(|m| m.id != id) {
+            self.push(message);
+        }
+    }
+
     #[inline]
     pub fn contains(&self, id: MessageId) -> bool {
         self.buffer.iter().any(|m| m.id == id)
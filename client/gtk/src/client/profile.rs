@@ -11,6 +11,7 @@ fn create_default_profile(user: UserId) -> Profile {
         version: ProfileVersion(0),
         username: name.clone(),
         display_name: name,
+        bot: false,
     }
 }
 
@@ -42,6 +43,17 @@ impl ProfileCache {
         }
     }
 
+    /// Gets the profile to show for the author of the message, with the display name that it was
+    /// posted under if that differs from the author's
+    pub async fn for_message(&self, message: &Message) -> Profile {
+        let mut profile = self.get_or_default(message.author, message.author_profile_version).await;
+        if let Some(display_name) = &message.author_display_name {
+            profile.display_name = display_name.clone();
+        }
+
+        profile
+    }
+
     pub async fn get(&self, id: UserId, version: ProfileVersion) -> Result<Profile> {
         if id == self.user.id {
             return Ok(self.user.profile().await);
@@ -1,7 +1,9 @@
+use std::time::Duration;
+
 use chrono::Utc;
 
 use vertex::prelude::*;
-use crate::{Client, Error, Result, SharedMut};
+use crate::{Client, Error, Result, SharedMut, scheduler};
 
 use super::message::*;
 use crate::screen::active::{RoomEntryWidget};
@@ -9,6 +11,7 @@ use crate::screen::active::{RoomEntryWidget};
 pub struct RoomState {
     pub message_buffer: MessageRingBuffer,
     pub last_read: Option<MessageId>,
+    pub settings: RoomSettings,
 }
 
 #[derive(Clone)]
@@ -21,6 +24,7 @@ pub struct RoomEntry {
     pub id: RoomId,
 
     pub name: String,
+    pub encrypted: bool,
 
     pub state: SharedMut<RoomState>,
 }
@@ -32,13 +36,16 @@ impl RoomEntry {
         community: CommunityId,
         id: RoomId,
         name: String,
+        encrypted: bool,
+        settings: RoomSettings,
     ) -> Self {
         let state = SharedMut::new(RoomState {
             message_buffer: MessageRingBuffer::new(MESSAGE_PAGE_SIZE),
             last_read: None,
+            settings,
         });
 
-        RoomEntry { client, widget, community, id, name, state }
+        RoomEntry { client, widget, community, id, name, encrypted, state }
     }
 
     pub(crate) async fn get_updates(&self) -> Result<RoomUpdate> {
@@ -69,38 +76,98 @@ impl RoomEntry {
                     profile,
                     text: Some(content.clone()),
                     time: Utc::now(),
+                    encrypted: self.encrypted,
+                    trust: None,
                 }
             ).await;
 
             let result = self.send_message_request(content.clone()).await;
             match result {
-                Ok(confirmation) => {
+                Ok((confirmation, ciphertext)) => {
+                    let content = if ciphertext.is_some() { None } else { Some(content) };
                     let message = Message {
                         id: confirmation.id,
                         author: user,
                         author_profile_version: profile_version,
                         time_sent: confirmation.time_sent,
-                        content: Some(content),
+                        content,
+                        ciphertext,
+                        author_display_name: None,
                     };
 
                     pending.upgrade(message.clone()).await;
                     self.push_message(message).await;
                 }
-                Err(_) => pending.set_error(),
+                Err(err) => {
+                    pending.set_error();
+                    self.show_send_error(err).await;
+                }
+            }
+        }
+    }
+
+    /// Tells the user why the room would not take their message, if it was because of the room's
+    /// settings. Slow mode notices are cleared once the user can send again.
+    async fn show_send_error(&self, err: Error) {
+        let err = match err {
+            Error::ErrorResponse(err) => err,
+            _ => return,
+        };
+
+        match err {
+            vertex::responses::Error::SlowMode { retry_after_secs } => {
+                self.client.ui.set_room_notice(Some(&err.to_string()));
+
+                let room = self.clone();
+                scheduler::spawn(async move {
+                    tokio::time::delay_for(Duration::from_secs(retry_after_secs as u64)).await;
+                    room.client.update_room_notice(&room).await;
+                });
+            }
+            vertex::responses::Error::ReadOnlyRoom | vertex::responses::Error::MessageTooLong => {
+                self.client.ui.set_room_notice(Some(&err.to_string()));
             }
+            _ => {}
         }
     }
 
-    async fn send_message_request(&self, content: String) -> Result<MessageConfirmation> {
+    pub async fn set_settings(&self, settings: RoomSettings) {
+        self.state.write().await.settings = settings;
+        self.client.update_room_notice(self).await;
+    }
+
+    /// Sends the message, encrypting it first if the room is encrypted. The ciphertext is returned
+    /// along with the confirmation, so that the message can be stored as the server has it.
+    async fn send_message_request(
+        &self,
+        content: String,
+    ) -> Result<(MessageConfirmation, Option<Vec<u8>>)> {
+        let (content, ciphertext) = if self.encrypted {
+            let encrypted = self.client.crypto.encrypt(self.community, &content).await?;
+            if encrypted.withheld > 0 {
+                let notice = format!(
+                    "{} verified device(s) have changed their keys and were left out of this \
+                    message. Verify them again to include them.",
+                    encrypted.withheld,
+                );
+                self.client.ui.set_room_notice(Some(&notice));
+            }
+
+            (String::new(), Some(encrypted.ciphertext))
+        } else {
+            (content, None)
+        };
+
         let request = ClientRequest::SendMessage(ClientSentMessage {
             to_community: self.community,
             to_room: self.id,
             content,
+            ciphertext: ciphertext.clone(),
         });
 
         let request = self.client.request.send(request).await;
         match request.response().await? {
-            OkResponse::ConfirmMessage(confirmation) => Ok(confirmation),
+            OkResponse::ConfirmMessage(confirmation) => Ok((confirmation, ciphertext)),
             _ => Err(Error::UnexpectedMessage),
         }
     }
@@ -161,6 +228,11 @@ impl RoomEntry {
         state.message_buffer.push(message);
     }
 
+    pub async fn remove_message(&self, id: MessageId) {
+        let mut state = self.state.write().await;
+        state.message_buffer.remove(id);
+    }
+
     pub async fn collect_recent_history(&self) -> Vec<Message> {
         let state = self.state.read().await;
         state.message_buffer.iter().cloned().collect()
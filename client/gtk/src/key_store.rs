@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use keyring::{Keyring, KeyringError};
+use serde::{Deserialize, Serialize};
+
+use vertex::prelude::*;
+
+/// The secret keys of this device for end-to-end encryption, along with the sessions it has with
+/// other devices and the devices that it knows of. These are kept per device, since the server
+/// hands out prekeys per device.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoredKeys {
+    pub identity_secret: Option<[u8; 32]>,
+    /// Secrets of the one-time prekeys that have not been used yet. Each is deleted once a session
+    /// has been set up with it.
+    pub prekey_secrets: Vec<[u8; 32]>,
+    /// The devices whose identity keys have been seen, by device
+    pub devices: HashMap<DeviceId, KnownDevice>,
+    /// The sessions used to wrap message keys for other devices, by recipient device
+    pub outbound_sessions: HashMap<DeviceId, OutboundSession>,
+    /// The sessions that other devices have set up with this one, by sender device. These are kept
+    /// so that message history can still be decrypted after the prekey secrets are gone.
+    pub inbound_sessions: HashMap<DeviceId, Vec<InboundSession>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KnownDevice {
+    pub user: UserId,
+    pub identity_key: Vec<u8>,
+    /// Whether the user has verified this identity key out of band
+    pub verified: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OutboundSession {
+    /// The identity key of the recipient device that the session was set up with
+    pub identity_key: Vec<u8>,
+    pub ephemeral: Vec<u8>,
+    /// The public half of the one-time prekey that the session was set up with, if any
+    pub prekey: Option<Vec<u8>>,
+    pub key: [u8; 32],
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InboundSession {
+    /// The identity key of the sender device that the session was set up with
+    pub identity_key: Vec<u8>,
+    pub ephemeral: Vec<u8>,
+    pub key: [u8; 32],
+}
+
+fn with_keyring<T>(device: DeviceId, f: impl FnOnce(&Keyring) -> T) -> T {
+    let username = device.0.to_string();
+    f(&Keyring::new("vertex_client_gtk_keys", &username))
+}
+
+pub fn store_keys(device: DeviceId, keys: &StoredKeys) {
+    let serialized = serde_json::to_string(keys).expect("unable to serialize keys");
+    with_keyring(device, |keyring| keyring.set_password(&serialized))
+        .expect("unable to store keys");
+}
+
+pub fn get_stored_keys(device: DeviceId) -> Option<StoredKeys> {
+    with_keyring(device, |keyring| keyring.get_password()).ok()
+        .and_then(|keys_str| serde_json::from_str::<StoredKeys>(&keys_str).ok())
+}
+
+pub fn forget_keys(device: DeviceId) {
+    match with_keyring(device, |keyring| keyring.delete_password()) {
+        Ok(_) => {},
+        Err(KeyringError::NoPasswordFound) => {},
+        Err(e) => Err(e).expect("unable to forget keys"),
+    };
+}
@@ -34,6 +34,7 @@ static RUNNING: AtomicBool = AtomicBool::new(false);
 pub mod auth;
 pub mod client;
 pub mod connect;
+pub mod key_store;
 pub mod net;
 pub mod screen;
 pub mod token_store;
@@ -35,29 +35,76 @@ impl RequestIdGenerator {
     }
 }
 
+type DirectRatelimiter = RateLimiter<NotKeyed, InMemoryState, DefaultClock>;
+
+fn new_ratelimiter(per_min: u32) -> DirectRatelimiter {
+    let per_min = NonZeroU32::new(per_min).unwrap_or_else(|| NonZeroU32::new(1).unwrap());
+    RateLimiter::direct(Quota::per_minute(per_min))
+}
+
+/// Mirrors the server's ratelimits, so that we wait rather than being ratelimited
+struct Ratelimiters {
+    general: DirectRatelimiter,
+    classes: HashMap<RatelimitClass, DirectRatelimiter>,
+}
+
+impl Ratelimiters {
+    fn new(quotas: Option<RatelimitQuotas>) -> Ratelimiters {
+        let quotas = match quotas {
+            Some(quotas) => quotas,
+            // Until the server tells us otherwise
+            None => return Ratelimiters {
+                general: new_ratelimiter(RATELIMIT_BURST_PER_MIN),
+                classes: HashMap::new(),
+            },
+        };
+
+        let classes = [RatelimitClass::Message, RatelimitClass::Invite, RatelimitClass::RoomCreation];
+        let classes = classes.iter()
+            .map(|&class| (class, new_ratelimiter(quotas.class_per_min(class))))
+            .collect();
+
+        Ratelimiters {
+            general: new_ratelimiter(quotas.general_per_min),
+            classes,
+        }
+    }
+
+    async fn until_ready(&self, class: Option<RatelimitClass>) {
+        self.general.until_ready().await;
+
+        if let Some(ratelimiter) = class.and_then(|class| self.classes.get(&class)) {
+            ratelimiter.until_ready().await;
+        }
+    }
+}
+
 struct RequestTracker {
     pending_requests: RefCell<HashMap<RequestId, EnqueuedRequest>>,
-    ratelimiter: RateLimiter<NotKeyed, InMemoryState, DefaultClock>,
+    ratelimiters: RefCell<Rc<Ratelimiters>>,
 }
 
 impl RequestTracker {
     fn new() -> RequestTracker {
         RequestTracker {
             pending_requests: RefCell::new(HashMap::new()),
-            ratelimiter: RateLimiter::direct(
-                Quota::per_minute(NonZeroU32::new(RATELIMIT_BURST_PER_MIN).unwrap())
-            ),
+            ratelimiters: RefCell::new(Rc::new(Ratelimiters::new(None))),
         }
     }
 
-    async fn enqueue(&self, id: RequestId) -> Option<oneshot::Receiver<Result<OkResponse>>> {
-        let mut pending_requests = self.pending_requests.borrow_mut();
-        if pending_requests.contains_key(&id) {
+    async fn enqueue(
+        &self,
+        id: RequestId,
+        class: Option<RatelimitClass>,
+    ) -> Option<oneshot::Receiver<Result<OkResponse>>> {
+        if self.pending_requests.borrow().contains_key(&id) {
             return None;
         }
 
-        self.ratelimiter.until_ready().await;
+        let ratelimiters = self.ratelimiters.borrow().clone();
+        ratelimiters.until_ready(class).await;
 
+        let mut pending_requests = self.pending_requests.borrow_mut();
         let (send, recv) = oneshot::channel();
         pending_requests.insert(id, EnqueuedRequest(send));
 
@@ -99,6 +146,11 @@ impl RequestManager {
         }
     }
 
+    /// Matches our ratelimits to the quotas that the server has advertised
+    pub fn set_ratelimits(&self, quotas: RatelimitQuotas) {
+        *self.tracker.ratelimiters.borrow_mut() = Rc::new(Ratelimiters::new(Some(quotas)));
+    }
+
     pub fn sender(&self, net: net::Sender) -> RequestSender {
         RequestSender {
             tracker: self.tracker.clone(),
@@ -154,7 +206,8 @@ impl RequestSender {
     pub async fn send(&self, request: ClientRequest) -> Request {
         let id = self.id_gen.next();
 
-        let receiver = self.tracker.enqueue(id).await.expect("unable to enqueue message");
+        let class = request.ratelimit_class();
+        let receiver = self.tracker.enqueue(id, class).await.expect("unable to enqueue message");
 
         let message = ClientMessage { id, request };
         self.net.send(message).await;
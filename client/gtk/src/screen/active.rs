@@ -4,7 +4,7 @@ use gtk::prelude::*;
 
 use crate::{AuthParameters, Client, Error, Result, token_store, scheduler, config};
 use crate::auth;
-use crate::client::RoomEntry;
+use crate::client::{self, RoomEntry};
 use crate::connect::AsConnector;
 use crate::Glade;
 use crate::screen;
@@ -14,6 +14,7 @@ use std::sync::RwLock;
 use std::rc::Rc;
 use gdk::enums::key;
 use vertex::requests::AuthError;
+use vertex::structures::RoomSettings;
 
 pub mod community;
 pub mod dialog;
@@ -51,6 +52,7 @@ pub struct Ui {
     content: gtk::Box,
     communities: gtk::ListBox,
     settings_button: gtk::Button,
+    unread_reports_badge: gtk::Label,
     add_community_button: gtk::Button,
 
     pub chat: gtk::Box,
@@ -58,6 +60,8 @@ pub struct Ui {
     pub message_scroll: gtk::ScrolledWindow,
     pub message_list: gtk::ListBox,
     pub message_entry: gtk::TextView,
+    command_hint: gtk::Label,
+    room_notice: gtk::Label,
 
     message_scroll_state: Rc<RwLock<MessageScrollState>>,
 }
@@ -76,6 +80,7 @@ impl Ui {
             content: builder.get_object("content").unwrap(),
             communities: builder.get_object("communities").unwrap(),
             settings_button: builder.get_object("settings_button").unwrap(),
+            unread_reports_badge: builder.get_object("unread_reports_badge").unwrap(),
             add_community_button: builder.get_object("add_community_button").unwrap(),
 
             chat: builder.get_object("chat").unwrap(),
@@ -83,6 +88,8 @@ impl Ui {
             message_scroll: builder.get_object("message_scroll").unwrap(),
             message_list: builder.get_object("message_list").unwrap(),
             message_entry,
+            command_hint: builder.get_object("command_hint").unwrap(),
+            room_notice: builder.get_object("room_notice").unwrap(),
             message_scroll_state: Rc::new(RwLock::new(MessageScrollState::default())),
         }
     }
@@ -92,6 +99,57 @@ impl Ui {
             self.message_list.remove(&child);
         }
     }
+
+    pub fn set_room_notice(&self, notice: Option<&str>) {
+        match notice {
+            Some(notice) => {
+                self.room_notice.set_text(notice);
+                self.room_notice.show();
+            }
+            None => self.room_notice.hide(),
+        }
+    }
+
+    /// Shows whether the selected room is read-only or in slow mode, so that members know before
+    /// their messages are refused
+    pub fn show_room_settings(&self, settings: &RoomSettings) {
+        let mut notices = Vec::new();
+        if settings.read_only {
+            notices.push("This room is read-only, so only some members can post in it.".to_string());
+        }
+
+        if settings.slow_mode_secs > 0 {
+            notices.push(format!(
+                "Slow mode is on: members can send one message every {} seconds.",
+                settings.slow_mode_secs,
+            ));
+        }
+
+        if notices.is_empty() {
+            self.set_room_notice(None);
+        } else {
+            self.set_room_notice(Some(&notices.join(" ")));
+        }
+    }
+
+    /// Shows how many reports have come in since the admin last looked at the reports list
+    pub fn set_unread_reports(&self, count: u32) {
+        if count == 0 {
+            self.unread_reports_badge.hide();
+            self.settings_button.set_tooltip_text(None);
+            return;
+        }
+
+        let tooltip = if count == 1 {
+            "1 new report".to_string()
+        } else {
+            format!("{} new reports", count)
+        };
+
+        self.unread_reports_badge.set_text(&count.to_string());
+        self.unread_reports_badge.show();
+        self.settings_button.set_tooltip_text(Some(&tooltip));
+    }
 }
 
 impl Ui {
@@ -170,6 +228,21 @@ impl Ui {
                 let client = client_cloned.clone();
                 match key_event.get_keyval() {
                     key::Return => {},
+                    key::Tab => {
+                        let entry = entry.clone();
+                        scheduler::spawn(async move {
+                            let buf = entry.get_buffer().unwrap();
+                            let (begin, end) = &buf.get_bounds();
+                            let content = buf.get_text(begin, end, false);
+                            let content = content.as_ref().map(|c| c.as_str()).unwrap_or_default();
+
+                            let suggestions = client.command_suggestions(content).await;
+                            if let Some(suggestion) = suggestions.first() {
+                                buf.set_text(&format!("/{} ", suggestion.name));
+                            }
+                        });
+                        return Inhibit(true);
+                    },
                     key::Escape => {
                         return if config::get().message_editor_tweaks {
                             entry.grab_remove();
@@ -193,9 +266,18 @@ impl Ui {
                         let content = buf.get_text(begin, end, false);
                         let content = content.as_ref().map(|c| c.as_str()).unwrap_or_default();
 
-                        if !content.trim().is_empty() {
+                        if client::is_command(content) {
+                            buf.set_text("");
+                            client.run_command(selected_room, content).await;
+                        } else if !content.trim().is_empty() {
+                            // A leading double slash escapes a command
+                            let content = match content.trim_start().strip_prefix("//") {
+                                Some(rest) => format!("/{}", rest),
+                                None => content.to_string(),
+                            };
+
                             buf.set_text("");
-                            selected_room.send_message(content.to_string()).await;
+                            selected_room.send_message(content).await;
                         }
                     }
                 });
@@ -204,6 +286,34 @@ impl Ui {
             }
         );
 
+        let client_cloned = client.clone();
+        let command_hint = self.command_hint.clone();
+        self.message_entry.get_buffer().unwrap().connect_changed(
+            move |buf| {
+                let (begin, end) = &buf.get_bounds();
+                let content = buf.get_text(begin, end, false);
+                let content = content.map(|c| c.to_string()).unwrap_or_default();
+
+                let client = client_cloned.clone();
+                let command_hint = command_hint.clone();
+                scheduler::spawn(async move {
+                    let suggestions = client.command_suggestions(&content).await;
+                    if suggestions.is_empty() {
+                        command_hint.hide();
+                        return;
+                    }
+
+                    let hint = suggestions.iter()
+                        .map(|s| format!("{} — {}", s.usage, s.description))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    command_hint.set_text(&hint);
+                    command_hint.show();
+                });
+            }
+        );
+
         let adjustment = self.message_scroll.get_vadjustment().unwrap();
         adjustment.connect_value_changed(
             (client.clone(), self.message_scroll_state.clone()).connector()
@@ -326,6 +436,7 @@ impl Ui {
 
     pub fn deselect_room(&self) {
         self.clear_messages();
+        self.set_room_notice(None);
 
         if config::get().message_editor_tweaks {
             self.message_entry.set_editable(false);
@@ -400,6 +511,7 @@ fn describe_error(error: Error) -> String {
         Error::AuthErrorResponse(err) => match err {
             AuthError::Internal => "Internal server error".to_string(),
             AuthError::InvalidToken => "Invalid token".to_string(),
+            err @ AuthError::UserBanned(_) | err @ AuthError::UserLocked(_) => format!("{}", err),
             _ => "Unknown auth error".to_string(),
         },
 
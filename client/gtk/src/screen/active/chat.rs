@@ -38,7 +38,7 @@ impl ChatWidget {
 
     fn next_group(&mut self, author: UserId, profile: Profile, time: DateTime<Utc>, side: ChatSide) -> &mut MessageGroupWidget {
         match self.group_for(side) {
-            Some(group) if group.can_combine(author, time) => {}
+            Some(group) if group.can_combine(author, &profile.display_name, time) => {}
             _ => self.add_group(author, profile, time, side),
         }
 
@@ -72,12 +72,18 @@ impl ChatWidget {
     ) -> MessageEntryWidget {
         let msg_list = self.message_list.clone();
         let group = self.next_group(content.author, content.profile, content.time, side);
-        group.add_message(
+        let entry = group.add_message(
             content.text,
             id,
             side,
             &msg_list, client
-        )
+        );
+
+        if let Some(trust) = content.trust {
+            entry.set_trust(trust);
+        }
+
+        entry
     }
 
     pub fn remove_message(&mut self, id: MessageId) {
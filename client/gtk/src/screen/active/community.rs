@@ -8,6 +8,7 @@ use crate::Glade;
 
 use super::*;
 use atk::{AtkObjectExt, RelationType, RelationSetExt};
+use vertex::requests::ReportTarget;
 
 #[derive(Clone)]
 pub struct CommunityEntryWidget {
@@ -111,14 +112,15 @@ fn build_menu(community_entry: client::CommunityEntry) -> gtk::Popover {
     let menu: gtk::Popover = builder.get_object("community_menu").unwrap();
     let invite_button: gtk::Button = builder.get_object("invite_button").unwrap();
     let create_channel_button: gtk::Button = builder.get_object("create_channel_button").unwrap();
-    let _settings_button: gtk::Button = builder.get_object("settings_button").unwrap();
+    let settings_button: gtk::Button = builder.get_object("settings_button").unwrap();
+    let report_button: gtk::Button = builder.get_object("report_button").unwrap();
 
     invite_button.connect_clicked(
         (menu.clone(), community_entry.clone()).connector()
             .do_async(move |(menu, community_entry), _| async move {
                 menu.hide();
 
-                match community_entry.create_invite(None).await {
+                match community_entry.create_invite(None, None).await {
                     Ok(invite) => dialog::show_invite_dialog(invite),
                     Err(err) => dialog::show_generic_error(&err),
                 }
@@ -126,8 +128,17 @@ fn build_menu(community_entry: client::CommunityEntry) -> gtk::Popover {
             .build_cloned_consumer()
     );
 
+    settings_button.connect_clicked(
+        (menu.clone(), community_entry.clone()).connector()
+            .do_async(move |(menu, community_entry), _| async move {
+                menu.hide();
+                dialog::show_community_settings(community_entry).await;
+            })
+            .build_cloned_consumer()
+    );
+
     create_channel_button.connect_clicked(
-        (menu.clone(), community_entry).connector()
+        (menu.clone(), community_entry.clone()).connector()
             .do_sync(move |(menu, community_entry), _| {
                 menu.hide();
                 dialog::show_create_room(community_entry);
@@ -135,6 +146,16 @@ fn build_menu(community_entry: client::CommunityEntry) -> gtk::Popover {
             .build_cloned_consumer()
     );
 
+    report_button.connect_clicked(
+        (menu.clone(), community_entry).connector()
+            .do_sync(move |(menu, community_entry), _| {
+                menu.hide();
+                let target = ReportTarget::Community(community_entry.id);
+                dialog::show_report(community_entry.client, target);
+            })
+            .build_cloned_consumer()
+    );
+
     menu
 }
 
@@ -9,6 +9,7 @@ use crate::window;
 use gtk::{DialogFlags, ResponseType, Label, EntryBuilder, WidgetExt, TextBufferBuilder, ScrolledWindowBuilder};
 use atk::{RelationType, AtkObjectExt, RelationSetExt};
 use futures::Future;
+use chrono::{DateTime, Duration, Utc};
 
 pub fn show_add_community(client: Client) {
     window::show_dialog(|window| {
@@ -18,7 +19,8 @@ pub fn show_add_community(client: Client) {
             DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
             &[
                 ("Create", ResponseType::Other(0)),
-                ("Join", ResponseType::Other(1))
+                ("Join", ResponseType::Other(1)),
+                ("Browse", ResponseType::Other(2)),
             ],
         );
 
@@ -38,6 +40,7 @@ pub fn show_add_community(client: Client) {
                     match x {
                         0 => show_create_community(client.clone()),
                         1 => show_join_community(client.clone()),
+                        2 => show_browse_communities(client.clone()),
                         _ => {}
                     }
                     dialog.emit_close();
@@ -52,8 +55,8 @@ pub fn show_add_community(client: Client) {
 
 async fn create_community(client: Client, name: &str) -> Result<()> {
     let community = client.create_community(name).await?;
-    community.create_room("General").await?;
-    community.create_room("Off Topic").await?;
+    community.create_room("General", false).await?;
+    community.create_room("Off Topic", false).await?;
     Ok(())
 }
 
@@ -172,7 +175,157 @@ pub fn show_join_community(client: Client) {
     });
 }
 
+/// Shows the server's public directory of communities, which can be searched by name and joined
+/// without an invite
+pub fn show_browse_communities(client: Client) {
+    window::show_dialog(|window| {
+        let dialog = gtk::Dialog::new_with_buttons(
+            None,
+            Some(&window.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            &[("Done", ResponseType::Ok)],
+        );
+
+        let label = Label::new(Some("Browse Communities"));
+        label.get_style_context().add_class("title");
+        let title_box = gtk::BoxBuilder::new()
+            .orientation(gtk::Orientation::Horizontal)
+            .hexpand(true)
+            .child(&label)
+            .build();
+
+        let search = EntryBuilder::new()
+            .placeholder_text("Search communities...")
+            .build();
+
+        let placeholder = Label::new(Some("No public communities were found."));
+        placeholder.show();
+
+        let community_list = gtk::ListBoxBuilder::new()
+            .name("public_community_list")
+            .selection_mode(gtk::SelectionMode::None)
+            .build();
+        community_list.set_placeholder(Some(&placeholder));
+
+        let community_scroll = ScrolledWindowBuilder::new()
+            .child(&community_list)
+            .min_content_width(420)
+            .min_content_height(300)
+            .max_content_height(300)
+            .build();
+
+        let content = dialog.get_content_area();
+        content.add(&title_box);
+        content.add(&search);
+        content.add(&community_scroll);
+
+        let refresh = (client.clone(), community_list).connector()
+            .do_async(|(client, community_list), query: String| async move {
+                refresh_public_communities(&client, &community_list, &query).await;
+            })
+            .build();
+
+        refresh(String::new());
+        search.connect_activate(move |search| {
+            if let Ok(query) = search.try_get_text() {
+                refresh(query);
+            }
+        });
+
+        dialog.connect_response(|dialog, _| dialog.emit_close());
+
+        (dialog, title_box)
+    });
+}
+
+async fn refresh_public_communities(client: &Client, community_list: &gtk::ListBox, query: &str) {
+    let communities = match client.list_public_communities(query).await {
+        Ok(communities) => communities,
+        Err(err) => {
+            show_generic_error(&err);
+            return;
+        }
+    };
+
+    for row in community_list.get_children() {
+        community_list.remove(&row);
+    }
+
+    for community in communities {
+        let joined = client.community_by_id(community.id).await.is_some();
+        community_list.add(&build_public_community_row(client, community, joined));
+    }
+
+    community_list.show_all();
+}
+
+fn build_public_community_row(
+    client: &Client,
+    community: PublicCommunity,
+    joined: bool,
+) -> gtk::ListBoxRow {
+    let name = Label::new(Some(&community.name));
+    name.set_xalign(0.0);
+
+    let description = Label::new(Some(&community.description));
+    description.set_xalign(0.0);
+    description.set_line_wrap(true);
+
+    let members = match community.members {
+        1 => "1 member".to_string(),
+        n => format!("{} members", n),
+    };
+    let members = Label::new(Some(&members));
+    members.set_xalign(0.0);
+    members.get_style_context().add_class("community_members");
+
+    let join = gtk::Button::new_with_label(if joined { "Joined" } else { "Join" });
+    join.set_sensitive(!joined);
+
+    let text = gtk::BoxBuilder::new()
+        .orientation(gtk::Orientation::Vertical)
+        .child(&name)
+        .child(&description)
+        .child(&members)
+        .build();
+    let container = gtk::BoxBuilder::new()
+        .orientation(gtk::Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    container.pack_start(&text, true, true, 0);
+    container.pack_end(&join, false, false, 0);
+
+    let id = community.id;
+    join.connect_clicked(
+        client.connector()
+            .do_async(move |client, join: gtk::Button| async move {
+                join.set_sensitive(false);
+                match client.join_public_community(id).await {
+                    Ok(_) => join.set_label("Joined"),
+                    Err(err) => {
+                        join.set_sensitive(true);
+                        show_generic_error(&err);
+                    }
+                }
+            })
+            .build_cloned_consumer()
+    );
+
+    let row = gtk::ListBoxRow::new();
+    row.add(&container);
+    row
+}
+
 pub fn show_invite_dialog(invite: InviteCode) {
+    show_code_dialog("Invite Code", &invite.0);
+}
+
+pub fn show_registration_token_dialog(token: &str) {
+    show_code_dialog("Registration Token", token);
+}
+
+/// Shows a code for the user to copy and give to someone else
+fn show_code_dialog(title: &str, code: &str) {
     window::show_dialog(|window| {
         let dialog = gtk::Dialog::new_with_buttons(
             None,
@@ -181,7 +334,7 @@ pub fn show_invite_dialog(invite: InviteCode) {
             &[("Ok", ResponseType::Ok)],
         );
 
-        let label = Label::new(Some("Invite Code"));
+        let label = Label::new(Some(title));
         label.get_style_context().add_class("title");
         let title_box = gtk::BoxBuilder::new()
             .orientation(gtk::Orientation::Horizontal)
@@ -191,8 +344,8 @@ pub fn show_invite_dialog(invite: InviteCode) {
 
         let code_view: gtk::TextView = gtk::TextViewBuilder::new()
             .editable(false)
-            .name("Invite code")
-            .buffer(&gtk::TextBufferBuilder::new().text(&invite.0).build())
+            .name(title)
+            .buffer(&gtk::TextBufferBuilder::new().text(code).build())
             .build();
 
         let objs = (code_view.get_accessible(), label.get_accessible());
@@ -220,6 +373,274 @@ pub fn show_invite_dialog(invite: InviteCode) {
     });
 }
 
+/// Expiry options offered when creating an invite, as (id, label) pairs
+pub const INVITE_EXPIRY_OPTIONS: &[(&str, &str)] = &[
+    ("never", "Never expires"),
+    ("hour", "Expires in 1 hour"),
+    ("day", "Expires in 1 day"),
+    ("week", "Expires in 7 days"),
+];
+
+pub fn invite_expiry(id: &str) -> Option<DateTime<Utc>> {
+    let duration = match id {
+        "hour" => Duration::hours(1),
+        "day" => Duration::days(1),
+        "week" => Duration::weeks(1),
+        _ => return None,
+    };
+
+    Some(Utc::now() + duration)
+}
+
+/// Shows the community's settings: whether it is listed in the public directory, and its invites
+pub async fn show_community_settings(community: client::CommunityEntry) {
+    let invites = match community.list_invites().await {
+        Ok(invites) => invites,
+        Err(err) => {
+            show_generic_error(&err);
+            return;
+        }
+    };
+    let public = community.is_public().await;
+
+    window::show_dialog(|window| {
+        let dialog = gtk::Dialog::new_with_buttons(
+            None,
+            Some(&window.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            &[("Done", ResponseType::Ok)],
+        );
+
+        let heading = Label::new(Some("Community Settings"));
+        heading.get_style_context().add_class("title");
+        let title_box = gtk::BoxBuilder::new()
+            .orientation(gtk::Orientation::Horizontal)
+            .hexpand(true)
+            .child(&heading)
+            .build();
+
+        let public_check = gtk::CheckButton::new_with_label("List in the public directory");
+        public_check.set_tooltip_text(Some("Anyone on the server can find and join it."));
+        public_check.set_active(public);
+
+        // If the change fails, the check is reset to the community's actual state. This toggles it
+        // again, but no request is sent since it then matches.
+        public_check.connect_toggled(
+            community.connector()
+                .do_async(|community, check: gtk::CheckButton| async move {
+                    let public = check.get_active();
+                    if public == community.is_public().await {
+                        return;
+                    }
+
+                    if let Err(err) = community.set_public(public).await {
+                        check.set_active(community.is_public().await);
+                        show_generic_error(&err);
+                    }
+                })
+                .build_cloned_consumer()
+        );
+
+        let invites_heading = Label::new(Some("Invites"));
+        invites_heading.set_xalign(0.0);
+
+        let max_uses = gtk::SpinButton::new_with_range(0.0, 1000.0, 1.0);
+        max_uses.set_tooltip_text(Some("How many times the invite can be used. 0 is unlimited."));
+
+        let expiry = gtk::ComboBoxText::new();
+        for &(id, label) in INVITE_EXPIRY_OPTIONS {
+            expiry.append(Some(id), label);
+        }
+        expiry.set_active_id(Some("never"));
+
+        let create = gtk::Button::new_with_label("Create invite");
+        let create_box = gtk::BoxBuilder::new()
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(6)
+            .child(&Label::new(Some("Max uses")))
+            .child(&max_uses)
+            .child(&expiry)
+            .child(&create)
+            .build();
+
+        let placeholder = Label::new(Some("There are no invites to this community."));
+        placeholder.show();
+
+        let invite_list = gtk::ListBoxBuilder::new()
+            .name("invite_list")
+            .selection_mode(gtk::SelectionMode::None)
+            .build();
+        invite_list.set_placeholder(Some(&placeholder));
+
+        for invite in invites {
+            invite_list.add(&build_invite_row(&community, &invite_list, invite));
+        }
+
+        let invite_scroll = ScrolledWindowBuilder::new()
+            .child(&invite_list)
+            .min_content_width(480)
+            .min_content_height(300)
+            .max_content_height(300)
+            .build();
+
+        create.connect_clicked(
+            (community.clone(), invite_list, max_uses, expiry).connector()
+                .do_async(|(community, invite_list, max_uses, expiry), _| async move {
+                    let max_uses = match max_uses.get_value_as_int() {
+                        0 => None,
+                        max => Some(max as u32),
+                    };
+                    let expiration = expiry.get_active_id().and_then(|id| invite_expiry(&id));
+
+                    match community.create_invite(expiration, max_uses).await {
+                        Ok(_) => refresh_invites(&community, &invite_list).await,
+                        Err(err) => show_generic_error(&err),
+                    }
+                })
+                .build_cloned_consumer()
+        );
+
+        let content = dialog.get_content_area();
+        content.add(&title_box);
+        content.add(&public_check);
+        content.add(&invites_heading);
+        content.add(&create_box);
+        content.add(&invite_scroll);
+
+        dialog.connect_response(|dialog, _| dialog.emit_close());
+
+        (dialog, title_box)
+    });
+}
+
+async fn refresh_invites(community: &client::CommunityEntry, invite_list: &gtk::ListBox) {
+    let invites = match community.list_invites().await {
+        Ok(invites) => invites,
+        Err(err) => {
+            show_generic_error(&err);
+            return;
+        }
+    };
+
+    for row in invite_list.get_children() {
+        invite_list.remove(&row);
+    }
+
+    for invite in invites {
+        invite_list.add(&build_invite_row(community, invite_list, invite));
+    }
+
+    invite_list.show_all();
+}
+
+fn build_invite_row(
+    community: &client::CommunityEntry,
+    invite_list: &gtk::ListBox,
+    invite: Invite,
+) -> gtk::ListBoxRow {
+    let code = Label::new(Some(&invite.code.0));
+    code.set_selectable(true);
+    code.set_xalign(0.0);
+    code.get_style_context().add_class("invite_code_text");
+
+    let creator = match invite.creator {
+        Some(creator) => creator.username,
+        None => "an unknown user".to_string(),
+    };
+    let uses = match invite.max_uses {
+        Some(max) => format!("{}/{} uses", invite.uses, max),
+        None => format!("{} uses", invite.uses),
+    };
+    let expires = match invite.expiration_date {
+        Some(date) => format!("expires {}", date.format("%F %R")),
+        None => "never expires".to_string(),
+    };
+
+    let details = Label::new(Some(&format!(
+        "Created by {} on {}, {}, {}",
+        creator,
+        invite.created_at.format("%F"),
+        uses,
+        expires,
+    )));
+    details.set_xalign(0.0);
+    details.get_style_context().add_class("invite_details");
+
+    let revoke = gtk::Button::new_with_label("Revoke");
+
+    let text = gtk::BoxBuilder::new()
+        .orientation(gtk::Orientation::Vertical)
+        .child(&code)
+        .child(&details)
+        .build();
+    let header = gtk::BoxBuilder::new()
+        .orientation(gtk::Orientation::Horizontal)
+        .spacing(6)
+        .build();
+    header.pack_start(&text, true, true, 0);
+    header.pack_end(&revoke, false, false, 0);
+
+    let joined = gtk::BoxBuilder::new()
+        .orientation(gtk::Orientation::Vertical)
+        .build();
+    let expander = gtk::Expander::new(Some("Joined through this invite"));
+    expander.add(&joined);
+
+    let container = gtk::BoxBuilder::new()
+        .orientation(gtk::Orientation::Vertical)
+        .child(&header)
+        .child(&expander)
+        .build();
+
+    let row = gtk::ListBoxRow::new();
+    row.add(&container);
+    row.show_all();
+
+    revoke.connect_clicked(
+        (community.clone(), invite_list.clone(), row.clone(), invite.code.clone()).connector()
+            .do_async(|(community, invite_list, row, code), _| async move {
+                match community.revoke_invite(code).await {
+                    Ok(()) => invite_list.remove(&row),
+                    Err(err) => show_generic_error(&err),
+                }
+            })
+            .build_cloned_consumer()
+    );
+
+    // Who joined is only loaded the first time that the expander is opened
+    expander.connect_property_expanded_notify(
+        (community.clone(), joined, invite.code).connector()
+            .do_async(|(community, joined, code), expander: gtk::Expander| async move {
+                if !expander.get_expanded() || !joined.get_children().is_empty() {
+                    return;
+                }
+
+                match community.invite_uses(code).await {
+                    Ok(uses) if uses.is_empty() => {
+                        joined.add(&Label::new(Some("Nobody has joined through this invite yet.")));
+                    }
+                    Ok(uses) => {
+                        for invite_use in uses {
+                            let label = Label::new(Some(&format!(
+                                "{} on {}",
+                                invite_use.user.username,
+                                invite_use.joined_at.format("%F %R"),
+                            )));
+                            label.set_xalign(0.0);
+                            joined.add(&label);
+                        }
+                    }
+                    Err(err) => show_generic_error(&err),
+                }
+
+                joined.show_all();
+            })
+            .build_cloned_consumer()
+    );
+
+    row
+}
+
 pub fn show_create_room(community: client::CommunityEntry) {
     window::show_dialog(|window| {
         let dialog = gtk::Dialog::new_with_buttons(
@@ -246,14 +667,21 @@ pub fn show_create_room(community: client::CommunityEntry) {
                 .build_cloned_consumer()
         );
 
+        let encrypted = gtk::CheckButton::new_with_label("End-to-end encrypted");
+        encrypted.set_tooltip_text(Some(
+            "Only members' devices can read messages. This cannot be changed later."
+        ));
+
         let content = dialog.get_content_area();
         content.add(&title_box);
         content.add(&entry);
+        content.add(&encrypted);
 
         dialog.connect_response(
             community.connector()
                 .do_async(move |community, (dialog, response_type): (gtk::Dialog, ResponseType)| {
                     let entry = entry.clone();
+                    let encrypted = encrypted.get_active();
                     async move {
                         if response_type != ResponseType::Apply {
                             dialog.emit_close();
@@ -261,7 +689,7 @@ pub fn show_create_room(community: client::CommunityEntry) {
                         }
 
                         if let Ok(name) = entry.try_get_text() {
-                            if let Err(err) = community.create_room(&name).await {
+                            if let Err(err) = community.create_room(&name, encrypted).await {
                                 show_generic_error(&err);
                             }
                         }
@@ -276,7 +704,7 @@ pub fn show_create_room(community: client::CommunityEntry) {
     });
 }
 
-pub fn show_report_message(client: Client, msg: MessageId) {
+pub fn show_report(client: Client, target: ReportTarget) {
     window::show_dialog(|window| {
         let dialog = gtk::Dialog::new_with_buttons(
             None,
@@ -285,7 +713,14 @@ pub fn show_report_message(client: Client, msg: MessageId) {
             &[("Report", ResponseType::Apply)],
         );
 
-        let label = Label::new(Some("Report A Message"));
+        let heading = match target {
+            ReportTarget::Message(_) => "Report A Message",
+            ReportTarget::User(_) => "Report A User",
+            ReportTarget::Room { .. } => "Report A Room",
+            ReportTarget::Community(_) => "Report A Community",
+        };
+
+        let label = Label::new(Some(heading));
         label.get_style_context().add_class("title");
         let title_box = gtk::BoxBuilder::new()
             .orientation(gtk::Orientation::Horizontal)
@@ -340,7 +775,7 @@ pub fn show_report_message(client: Client, msg: MessageId) {
                         let long_desc = long_desc.as_ref().map(|c| c.as_str()).unwrap_or_default();
 
                         if let Ok(short_desc) = short.try_get_text() {
-                            let res = client.report_message(msg, &short_desc, long_desc).await;
+                            let res = client.report(target, &short_desc, long_desc).await;
                             if let Err(e) = res {
                                 show_generic_error(&e);
                             }
@@ -355,13 +790,101 @@ pub fn show_report_message(client: Client, msg: MessageId) {
     });
 }
 
-pub fn show_choose_report_action(client: Client, user: UserId) {
+/// Shows the fingerprints of the user's devices, so that they can be compared out of band with the
+/// ones that the user sees, and lets each device be marked as verified
+pub async fn show_verify_devices(client: Client, user: UserId) {
+    let bundles = match client.crypto.device_keys(user).await {
+        Ok(bundles) => bundles,
+        Err(err) => {
+            show_generic_error(&err);
+            return;
+        }
+    };
+
+    let mut devices = Vec::with_capacity(bundles.len());
+    for bundle in bundles {
+        let trust = client.crypto.device_trust(bundle.device, &bundle.identity_key).await;
+        devices.push((bundle, trust));
+    }
+
+    let own_fingerprint = client::fingerprint(&client.crypto.identity_key().await);
+
     window::show_dialog(|window| {
         let dialog = gtk::Dialog::new_with_buttons(
             None,
             Some(&window.window),
             DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
-            &[("None", ResponseType::Other(0)), ("Ban", ResponseType::Other(1))],
+            &[("Done", ResponseType::Ok)],
+        );
+
+        let heading = Label::new(Some("Verify Devices"));
+        heading.get_style_context().add_class("title");
+        let title_box = gtk::BoxBuilder::new()
+            .orientation(gtk::Orientation::Horizontal)
+            .hexpand(true)
+            .child(&heading)
+            .build();
+
+        let description = Label::new(Some(&format!(
+            "Compare these fingerprints with the ones shown on the user's devices.\n\
+            This device's fingerprint is {}",
+            own_fingerprint,
+        )));
+        description.set_line_wrap(true);
+
+        let content = dialog.get_content_area();
+        content.add(&title_box);
+        content.add(&description);
+
+        if devices.is_empty() {
+            content.add(&Label::new(Some("This user has no devices with encryption keys.")));
+        }
+
+        for (bundle, trust) in devices {
+            let fingerprint = client::fingerprint(&bundle.identity_key);
+            let label = match trust {
+                client::SenderTrust::KeyChanged => {
+                    format!("{} (key changed since verified)", fingerprint)
+                }
+                _ => fingerprint,
+            };
+
+            let check = gtk::CheckButton::new_with_label(&label);
+            check.set_active(trust == client::SenderTrust::Verified);
+
+            let DeviceKeyBundle { user, device, identity_key, .. } = bundle;
+            check.connect_toggled(
+                client.connector()
+                    .do_async(move |client, check: gtk::CheckButton| {
+                        let identity_key = identity_key.clone();
+                        async move {
+                            let verified = check.get_active();
+                            client.crypto.set_verified(user, device, identity_key, verified).await;
+                        }
+                    })
+                    .build_cloned_consumer()
+            );
+
+            content.add(&check);
+        }
+
+        dialog.connect_response(|dialog, _| dialog.emit_close());
+
+        (dialog, title_box)
+    });
+}
+
+/// Asks which actions should be taken against the reported user before accepting the report.
+/// `on_resolved` is called once the report has been accepted.
+pub fn show_choose_report_action<C>(client: Client, id: i32, has_message: bool, on_resolved: C)
+    where C: Fn(Client) + Clone + 'static
+{
+    window::show_dialog(|window| {
+        let dialog = gtk::Dialog::new_with_buttons(
+            None,
+            Some(&window.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            &[("Accept", ResponseType::Ok), ("Cancel", ResponseType::Cancel)],
         );
 
         let heading = Label::new(Some("Choose an action"));
@@ -372,17 +895,25 @@ pub fn show_choose_report_action(client: Client, user: UserId) {
             .child(&heading)
             .build();
 
+        let ban = gtk::CheckButton::new_with_label("Ban the reported user");
+        let delete = gtk::CheckButton::new_with_label("Delete the reported message");
+        delete.set_sensitive(has_message);
+
         let content = dialog.get_content_area();
         content.add(&title_box);
+        content.add(&ban);
+        content.add(&delete);
 
         dialog.connect_response(
-            client.connector()
-                .do_async(move |client, (dialog, response_type): (gtk::Dialog, ResponseType)| {
+            (client, on_resolved, ban, delete).connector()
+                .do_async(move |shared, (dialog, response): (gtk::Dialog, ResponseType)| {
+                    let (client, on_resolved, ban, delete) = shared;
                     async move {
-                        if let ResponseType::Other(1) = response_type {
-                            match client.ban_users(vec![user]).await.map(|mut v| v.pop()) {
-                                Err(ref e) | Ok(Some((_, ref e))) => show_generic_error(&e),
-                                _ => {}
+                        if response == ResponseType::Ok {
+                            let (ban, delete) = (ban.get_active(), delete.get_active());
+                            match client.resolve_report(id, ban, delete).await {
+                                Ok(()) => on_resolved(client),
+                                Err(e) => show_generic_error(&e),
                             }
                         }
 
@@ -3,7 +3,7 @@ use gtk::prelude::*;
 
 use vertex::prelude::*;
 
-use crate::client::{ChatSide, InviteEmbed, MessageEmbed, MessageStatus, OpenGraphEmbed};
+use crate::client::{ChatSide, InviteEmbed, MessageEmbed, MessageStatus, OpenGraphEmbed, SenderTrust};
 use crate::{Glade, resource};
 
 use super::*;
@@ -14,6 +14,9 @@ use atk::AtkObjectExt;
 #[derive(Clone, PartialEq, Eq)]
 pub struct MessageGroupWidget {
     author: UserId,
+    /// The name shown for the group, which may differ between messages of the same author if
+    /// they were posted under other names, e.g by a webhook
+    display_name: String,
     origin_time: DateTime<Utc>,
     interactable: bool,
     flavour: MessageGroupFlavour,
@@ -61,10 +64,13 @@ impl MessageGroupWidget {
             static ref GLADE: Glade = Glade::open("active/message_entry.glade").unwrap();
         }
 
+        let display_name = profile.display_name.clone();
+
         if is_inline {
             let title = format!(
-                "{} at {} said",
+                "{}{} at {} said",
                 profile.display_name,
+                if profile.bot { " (bot)" } else { "" },
                 pretty_date(origin_time),
             );
             let title = gtk::LabelBuilder::new()
@@ -78,6 +84,7 @@ impl MessageGroupWidget {
 
             MessageGroupWidget {
                 author,
+                display_name,
                 origin_time,
                 flavour,
                 messages: Vec::new(),
@@ -93,6 +100,10 @@ impl MessageGroupWidget {
             author_name.set_text(&profile.display_name);
             author_name.set_can_focus(false);
 
+            let bot_badge: gtk::Label = builder.get_object("bot_badge").unwrap();
+            bot_badge.set_visible(profile.bot);
+            bot_badge.set_no_show_all(true);
+
             let timestamp: gtk::Label = builder.get_object("timestamp").unwrap();
 
             let time_text = pretty_date(origin_time);
@@ -106,6 +117,7 @@ impl MessageGroupWidget {
 
             MessageGroupWidget {
                 author,
+                display_name,
                 origin_time,
                 flavour,
                 messages: Vec::new(),
@@ -114,8 +126,10 @@ impl MessageGroupWidget {
         }
     }
 
-    pub fn can_combine(&self, user: UserId, time: DateTime<Utc>) -> bool {
-        self.author == user && (time - self.origin_time).num_minutes().abs() < 10
+    pub fn can_combine(&self, user: UserId, display_name: &str, time: DateTime<Utc>) -> bool {
+        self.author == user
+            && self.display_name == display_name
+            && (time - self.origin_time).num_minutes().abs() < 10
     }
 
     pub fn add_message(
@@ -126,7 +140,7 @@ impl MessageGroupWidget {
         list: &gtk::ListBox,
         client: Client,
     ) -> MessageEntryWidget {
-        let entry = MessageEntryWidget::build(client, content, id, self.interactable);
+        let entry = MessageEntryWidget::build(client, content, id, self.author, self.interactable);
 
         match &mut self.flavour {
             MessageGroupFlavour::Inline { title, messages } => {
@@ -216,7 +230,7 @@ impl MessageGroupWidget {
         id: MessageId,
         client: Client,
     ) {
-        let entry = MessageEntryWidget::build(client, content, id, self.interactable);
+        let entry = MessageEntryWidget::build(client, content, id, self.author, self.interactable);
 
         match &self.flavour {
             MessageGroupFlavour::Inline { title, .. } => {
@@ -273,6 +287,7 @@ impl MessageEntryWidget {
         client: Client,
         text: Option<String>,
         id: MessageId,
+        author: UserId,
         interactable: bool,
     ) -> Self {
         thread_local! {
@@ -324,7 +339,7 @@ impl MessageEntryWidget {
                 client.connector()
                     .do_sync(move |client, button: gtk::Button| {
                         button.get_style_context().add_class("active");
-                        let menu = Self::build_menu(client, id);
+                        let menu = Self::build_menu(client, id, author);
                         menu.set_relative_to(Some(&button));
                         menu.show();
 
@@ -348,7 +363,7 @@ impl MessageEntryWidget {
         MessageEntryWidget { widget: vbox, text }
     }
 
-    fn build_menu(client: Client, msg: MessageId) -> gtk::Popover {
+    fn build_menu(client: Client, msg: MessageId, author: UserId) -> gtk::Popover {
         lazy_static! {
             static ref GLADE: Glade = Glade::open("active/message_menu.glade").unwrap();
         }
@@ -358,6 +373,12 @@ impl MessageEntryWidget {
                 18,
                 18,
             ).expect("Error loading flag.svg!");
+
+            static VERIFY_ICON: gdk_pixbuf::Pixbuf = gdk_pixbuf::Pixbuf::new_from_file_at_size(
+                &resource("feather/shield.svg"),
+                18,
+                18,
+            ).expect("Error loading shield.svg!");
         }
 
         let builder: gtk::Builder = GLADE.builder();
@@ -367,11 +388,37 @@ impl MessageEntryWidget {
 
         ICON.with(|icon| img.set_from_pixbuf(Some(&icon)));
 
+        let report_author_button: gtk::Button = builder.get_object("report_author_button").unwrap();
+        let report_author_img: gtk::Image = builder.get_object("report_author_icon").unwrap();
+        ICON.with(|icon| report_author_img.set_from_pixbuf(Some(&icon)));
+
+        let verify_button: gtk::Button = builder.get_object("verify_button").unwrap();
+        let verify_img: gtk::Image = builder.get_object("verify_icon").unwrap();
+        VERIFY_ICON.with(|icon| verify_img.set_from_pixbuf(Some(&icon)));
+
         report_button.connect_clicked(
-            (menu.clone(), client).connector()
+            (menu.clone(), client.clone()).connector()
+                .do_sync(move |(menu, client), _| {
+                    dialog::show_report(client, ReportTarget::Message(msg));
+                    menu.hide();
+                })
+                .build_cloned_consumer()
+        );
+
+        report_author_button.connect_clicked(
+            (menu.clone(), client.clone()).connector()
                 .do_sync(move |(menu, client), _| {
-                    dialog::show_report_message(client, msg);
+                    dialog::show_report(client, ReportTarget::User(author));
+                    menu.hide();
+                })
+                .build_cloned_consumer()
+        );
+
+        verify_button.connect_clicked(
+            (menu.clone(), client).connector()
+                .do_async(move |(menu, client), _| async move {
                     menu.hide();
+                    dialog::show_verify_devices(client, author).await;
                 })
                 .build_cloned_consumer()
         );
@@ -386,6 +433,33 @@ impl MessageEntryWidget {
         }
     }
 
+    /// Notes under the message how far the device that sent it can be trusted, unless it has been
+    /// verified
+    pub fn set_trust(&self, trust: SenderTrust) {
+        let (notice, class) = match trust {
+            SenderTrust::Verified => return,
+            SenderTrust::Unverified => ("Sent from an unverified device", "unverified"),
+            SenderTrust::KeyChanged => (
+                "Sent from a device whose key has changed since it was verified",
+                "key_changed",
+            ),
+            SenderTrust::Unknown => (
+                "Could not confirm that this was sent by its author",
+                "key_changed",
+            ),
+        };
+
+        let label = gtk::LabelBuilder::new()
+            .name("message_trust")
+            .label(notice)
+            .halign(gtk::Align::Start)
+            .build();
+        label.get_style_context().add_class(class);
+
+        self.widget.add(&label);
+        label.show();
+    }
+
     pub fn set_status(&self, status: MessageStatus) {
         let style = self.text.get_style_context();
         style.remove_class("pending");
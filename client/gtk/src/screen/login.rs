@@ -116,9 +116,14 @@ pub async fn login(
 }
 
 pub fn describe_error(error: Error) -> String {
+    use vertex::prelude::AuthError;
+
     match error {
         Error::InvalidUrl => "Invalid instance ip".to_owned(),
         Error::ProtocolError(_) => "Protocol error: check your server instance?".to_owned(),
+        Error::AuthErrorResponse(AuthError::PendingApproval) => {
+            "Your account is waiting to be approved by an administrator".to_owned()
+        }
         error => format!("{}", error),
     }
 }
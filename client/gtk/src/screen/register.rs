@@ -14,6 +14,7 @@ pub struct Screen {
     username_entry: gtk::Entry,
     password_entry_1: gtk::Entry,
     password_entry_2: gtk::Entry,
+    registration_token_entry: gtk::Entry,
     register_button: gtk::Button,
     login_button: gtk::Button,
     status_stack: gtk::Stack,
@@ -33,6 +34,7 @@ pub async fn build() -> Screen {
         username_entry: builder.get_object("username_entry").unwrap(),
         password_entry_1: builder.get_object("password_entry_1").unwrap(),
         password_entry_2: builder.get_object("password_entry_2").unwrap(),
+        registration_token_entry: builder.get_object("registration_token_entry").unwrap(),
         register_button: builder.get_object("register_button").unwrap(),
         login_button: builder.get_object("login_button").unwrap(),
         status_stack: builder.get_object("status_stack").unwrap(),
@@ -63,6 +65,10 @@ async fn bind_events(screen: &Screen) {
                 let username = screen.username_entry.try_get_text().unwrap_or_default();
                 let password_1 = screen.password_entry_1.try_get_text().unwrap_or_default();
                 let password_2 = screen.password_entry_2.try_get_text().unwrap_or_default();
+                let registration_token = screen.registration_token_entry.try_get_text()
+                    .ok()
+                    .map(|token| token.trim().to_owned())
+                    .filter(|token| !token.is_empty());
 
                 screen.status_stack.set_visible_child(&screen.spinner);
                 screen.error_label.set_text("");
@@ -74,7 +80,7 @@ async fn bind_events(screen: &Screen) {
                     return;
                 };
 
-                match register(instance_ip, username, password).await {
+                match register(instance_ip, username, password, registration_token).await {
                     Ok(parameters) => {
                         screen::active::start(parameters).await;
                     }
@@ -94,6 +100,7 @@ async fn register(
     instance: String,
     username: String,
     password: String,
+    registration_token: Option<String>,
 ) -> Result<AuthParameters> {
     use vertex::prelude::*;
 
@@ -102,7 +109,7 @@ async fn register(
 
     let auth = crate::auth::Client::new(instance.clone());
 
-    auth.register(credentials.clone(), None).await?;
+    auth.register(credentials.clone(), None, registration_token).await?;
 
     let token = auth.create_token(
         credentials,
@@ -122,9 +129,26 @@ async fn register(
 }
 
 fn describe_error(error: Error) -> String {
+    use vertex::prelude::AuthError;
+
     match error {
         Error::InvalidUrl => "Invalid instance ip".to_owned(),
         Error::ProtocolError(_) => "Protocol error: check your server instance?".to_owned(),
+        Error::AuthErrorResponse(err) => match err {
+            AuthError::RegistrationClosed => {
+                "This server is not accepting new accounts".to_owned()
+            }
+            AuthError::RegistrationTokenRequired => {
+                "This server is invite-only: enter the registration token you were given".to_owned()
+            }
+            AuthError::InvalidRegistrationToken => {
+                "This registration token is invalid, has expired, or has been used up".to_owned()
+            }
+            AuthError::PendingApproval => {
+                "Account registered! You can log in once an administrator approves it".to_owned()
+            }
+            err => format!("{}", err),
+        },
         error => format!("{}", error),
     }
 }
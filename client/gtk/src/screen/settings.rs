@@ -76,6 +76,7 @@ fn bind_events(screen: &Screen, perms: vertex::requests::AdminPermissionFlags) {
         screen.connector()
             .do_async(|screen, _| async move {
                 token_store::forget_token();
+                screen.client.crypto.forget();
                 screen.client.log_out().await;
             })
             .build_cloned_consumer()
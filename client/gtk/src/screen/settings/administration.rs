@@ -11,12 +11,18 @@ use crate::screen::active::dialog;
 use users_search::UsersSearch;
 use admins_list::AdminsList;
 use reports_list::ReportsList;
+use audit_log::AuditLog;
+use pending_users::PendingUsers;
+use registration_tokens::RegistrationTokens;
 use crate::connect::AsConnector;
 
 mod users_search;
 mod admins_list;
 mod parse_search;
 mod reports_list;
+mod audit_log;
+mod pending_users;
+mod registration_tokens;
 
 lazy_static! {
     static ref GLADE: Glade = Glade::open("settings/administration.glade").unwrap();
@@ -33,6 +39,17 @@ pub fn build_administration(
     UsersSearch::build(builder.clone(), client.clone());
     AdminsList::build(builder.clone(), client.clone());
     ReportsList::build(builder.clone() , client.clone());
+    AuditLog::build(builder.clone(), client.clone());
+
+    // Only shown to those who can manage it, since it would otherwise only show an error
+    let registration: gtk::Box = builder.get_object("registration").unwrap();
+    if perms.contains(Perms::MANAGE_REGISTRATION) || perms.contains(Perms::ALL) {
+        PendingUsers::build(builder.clone(), client.clone());
+        RegistrationTokens::build(builder.clone(), client.clone());
+    } else {
+        registration.set_no_show_all(true);
+        registration.hide();
+    }
 
     if perms.contains(Perms::SET_ACCOUNTS_COMPROMISED) || perms.contains(Perms::ALL) {
         let buttons: gtk::Box = builder.get_object("set_compromised_buttons").unwrap();
@@ -100,7 +117,9 @@ enum Action {
     Unban,
     Unlock,
     Demote,
-    Promote { permissions: AdminPermissionFlags }
+    Promote { permissions: AdminPermissionFlags },
+    Approve,
+    Reject,
 }
 
 impl fmt::Display for Action {
@@ -111,6 +130,8 @@ impl fmt::Display for Action {
             Action::Unlock => "unlocking",
             Action::Demote => "demoting",
             Action::Promote { .. } => "promoting",
+            Action::Approve => "approving",
+            Action::Reject => "rejecting",
         };
 
         f.write_str(gerund)
@@ -143,6 +164,8 @@ async fn perform_action(
         Action::Unlock => client.unlock_users(selected).await,
         Action::Demote => client.demote_users(selected).await,
         Action::Promote { permissions } => client.promote_users(selected, permissions).await,
+        Action::Approve => client.approve_users(selected).await,
+        Action::Reject => client.reject_users(selected).await,
     };
 
     match res {
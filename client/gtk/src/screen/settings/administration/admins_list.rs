@@ -47,7 +47,7 @@ impl AdminsList {
         let types: Vec<glib::Type> = Some(bool::static_type())
             .into_iter()
             .chain(Some(String::static_type()).into_iter())
-            .chain(iter::repeat(bool::static_type()).take(5))
+            .chain(iter::repeat(bool::static_type()).take(6))
             .chain(Some(String::static_type()).into_iter()) // Dummy
             .collect();
         gtk::ListStore::new(&types)
@@ -63,6 +63,7 @@ impl AdminsList {
             "Ban/unban",
             "Promote/demote",
             "Set accounts compromised",
+            "Manage registration",
         ];
 
         for (i, header) in headers.iter().enumerate() {
@@ -89,6 +90,7 @@ impl AdminsList {
                                 2 => AdminPermissionFlags::BAN,
                                 3 => AdminPermissionFlags::PROMOTE,
                                 4 => AdminPermissionFlags::SET_ACCOUNTS_COMPROMISED,
+                                5 => AdminPermissionFlags::MANAGE_REGISTRATION,
                                 e => {
                                     panic_error!("Invalid column # {} in admin permissions table!", e);
                                 },
@@ -136,7 +138,7 @@ impl AdminsList {
         }
 
         // Dummy for alignment of checkbutton
-        super::append_text_column("", &self.view, 7);
+        super::append_text_column("", &self.view, 8);
 
         self.view.set_model(Some(&self.list));
     }
@@ -180,9 +182,10 @@ impl AdminsList {
             &user.permissions.contains(AdminPermissionFlags::BAN),
             &user.permissions.contains(AdminPermissionFlags::PROMOTE),
             &user.permissions.contains(AdminPermissionFlags::SET_ACCOUNTS_COMPROMISED),
+            &user.permissions.contains(AdminPermissionFlags::MANAGE_REGISTRATION),
         ];
 
-        let cols: Vec<_> = (0..7).collect();
+        let cols: Vec<_> = (0..8).collect();
         self.list.insert_with_values(None, &cols, arr);
     }
 
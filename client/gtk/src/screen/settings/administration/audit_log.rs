@@ -0,0 +1,98 @@
+use gtk::prelude::*;
+use vertex::prelude::*;
+use crate::{Client, scheduler, TryGetText};
+use std::rc::Rc;
+use crate::connect::AsConnector;
+use crate::screen::active::dialog;
+use super::parse_search;
+
+pub struct AuditLog {
+    list: gtk::ListStore,
+    view: gtk::TreeView,
+    client: Client,
+}
+
+impl AuditLog {
+    pub fn build(builder: gtk::Builder, client: Client) {
+        let list_recent: gtk::Button = builder.get_object("list_audit_log").unwrap();
+        let search: gtk::SearchEntry = builder.get_object("audit_log_search_entry").unwrap();
+
+        let this = Rc::new(AuditLog {
+            list: gtk::ListStore::new(&[String::static_type(); 6]),
+            view: builder.get_object("audit_log_list").unwrap(),
+            client,
+        });
+        this.create_and_setup_view();
+
+        list_recent.connect_clicked(
+            this.connector()
+                .do_async(move |this, _| this.search(AuditLogCriteria::default()))
+                .build_cloned_consumer()
+        );
+
+        search.connect_activate(
+            this.connector()
+                .do_async(move |this, search: gtk::SearchEntry| async move {
+                    let txt = search.try_get_text().unwrap_or_else(|_| "".to_string());
+                    let parsed = match parse_search::do_parse_audit(&txt) {
+                        Ok((_, parsed)) => parsed,
+                        Err(e) => return dialog::show_generic_error(&e),
+                    };
+
+                    this.search(parsed).await;
+                })
+                .build_cloned_consumer()
+        );
+
+        scheduler::spawn(this.search(AuditLogCriteria::default()));
+    }
+
+    fn create_and_setup_view(&self) {
+        let headers = ["Date", "By", "Action", "User", "Community", "Details"];
+        for (i, header) in headers.iter().enumerate() {
+            super::append_text_column(header, &self.view, i as i32);
+        }
+
+        self.view.set_model(Some(&self.list));
+    }
+
+    fn insert_entries(&self, entries: Vec<AuditLogEntry>) {
+        self.list.clear();
+
+        for entry in entries {
+            let actor = entry.actor.map(|user| user.username)
+                .unwrap_or_else(|| "<Command Line>".to_string());
+            let target_user = entry.target_user.map(|user| user.username).unwrap_or_default();
+            let community = entry.community.map(|c| c.name).unwrap_or_default();
+
+            let mut details = entry.target.unwrap_or_default();
+            if entry.parameters != "{}" {
+                if !details.is_empty() {
+                    details.push(' ');
+                }
+                details.push_str(&entry.parameters);
+            }
+
+            let arr: &[&dyn glib::ToValue] = &[
+                &entry.datetime.format("%F %R").to_string(),
+                &actor,
+                &entry.action.to_string().replace('_', " "),
+                &target_user,
+                &community,
+                &details,
+            ];
+
+            let cols: Vec<_> = (0..6).collect();
+            self.list.insert_with_values(None, &cols, arr);
+        }
+
+        self.view.show_all();
+    }
+
+    async fn search(self: Rc<Self>, criteria: AuditLogCriteria) {
+        match self.client.search_audit_log(criteria).await {
+            Ok(entries) => self.insert_entries(entries),
+            Err(err) => dialog::show_generic_error(&err),
+        }
+    }
+}
@@ -7,7 +7,7 @@ use nom::{
     multi::many0,
     combinator::opt,
 };
-use vertex::requests::{ReportStatus, SearchCriteria};
+use vertex::requests::{AuditAction, AuditLogCriteria, ReportKind, ReportStatus, SearchCriteria};
 use chrono::{DateTime, Utc, TimeZone, NaiveDate};
 use nom::error::ErrorKind;
 use itertools::Itertools;
@@ -26,6 +26,7 @@ pub enum Criterion {
     InCommunity(String),
     InRoom(String),
     Status(ReportStatus),
+    Kind(ReportKind),
 }
 
 enum Term<'a> {
@@ -72,6 +73,17 @@ fn criterion_from_term<'a>((txt, term): (&'a str, LabelledTerm<'a>)) -> IResult<
 
             Criterion::Status(status)
         }
+        "kind" => {
+            let kind = match term.text.to_lowercase().as_str() {
+                "message" | "messages" => ReportKind::Message,
+                "user" | "users" | "profile" => ReportKind::User,
+                "room" | "rooms" | "channel" | "channels" => ReportKind::Room,
+                "community" | "communities" => ReportKind::Community,
+                _ => return Err(nom::Err::Error((txt, ErrorKind::ParseTo)))
+            };
+
+            Criterion::Kind(kind)
+        }
         _ => return Err(nom::Err::Error((txt, ErrorKind::ParseTo)))
     };
 
@@ -123,8 +135,43 @@ pub fn do_parse(input: &str) -> IResult<&str, SearchCriteria> {
             Criterion::InCommunity(name) => search_criteria.in_community = Some(name),
             Criterion::InRoom(name) => search_criteria.in_room = Some(name),
             Criterion::Status(status) => search_criteria.status = Some(status),
+            Criterion::Kind(kind) => search_criteria.kind = Some(kind),
         }
     }
     
     Ok((input, search_criteria))
 }
+
+fn audit_criterion_from_term<'a>(
+    criteria: &mut AuditLogCriteria,
+    (txt, term): (&'a str, LabelledTerm<'a>),
+) -> IResult<&'a str, ()> {
+    match term.name {
+        "by" => criteria.actor = Some(term.text.to_string()),
+        "of" => criteria.target_user = Some(term.text.to_string()),
+        "before" => criteria.before_date = Some(parse_date(term.text)?.1),
+        "after" => criteria.after_date = Some(parse_date(term.text)?.1),
+        "community" => criteria.community = Some(term.text.to_string()),
+        "action" => {
+            let action = term.text.to_lowercase().replace(' ', "_").parse::<AuditAction>()
+                .map_err(|_| nom::Err::Error((txt, ErrorKind::ParseTo)))?;
+            criteria.action = Some(action);
+        }
+        _ => return Err(nom::Err::Error((txt, ErrorKind::ParseTo)))
+    };
+
+    Ok((txt, ()))
+}
+
+/// Parses a search of the audit log. Unlike searches of reports, every term must be labelled.
+pub fn do_parse_audit(input: &str) -> IResult<&str, AuditLogCriteria> {
+    let mut criteria = AuditLogCriteria::default();
+    let mut rest = input.trim();
+
+    while !rest.is_empty() {
+        let term = labelled_term(rest)?;
+        rest = audit_criterion_from_term(&mut criteria, term)?.0.trim_start();
+    }
+
+    Ok((input, criteria))
+}
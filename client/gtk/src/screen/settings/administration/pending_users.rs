@@ -0,0 +1,114 @@
+use std::sync::Mutex;
+use gtk::prelude::*;
+use vertex::prelude::*;
+use crate::{Client, scheduler};
+use std::rc::Rc;
+use crate::connect::AsConnector;
+use bimap::BiMap;
+use crate::screen::settings::administration::Action;
+use crate::screen::active::dialog;
+
+/// Users who have registered while registration needs approval, and are waiting to be approved
+pub struct PendingUsers {
+    list: gtk::ListStore,
+    view: gtk::TreeView,
+    username_to_id: Rc<Mutex<BiMap<String, UserId>>>,
+    client: Client,
+}
+
+impl PendingUsers {
+    pub fn build(builder: gtk::Builder, client: Client) {
+        let approve_button: gtk::Button = builder.get_object("approve_button").unwrap();
+        let reject_button: gtk::Button = builder.get_object("reject_button").unwrap();
+
+        let this = Rc::new(PendingUsers {
+            list: gtk::ListStore::new(&[
+                bool::static_type(),
+                String::static_type(),
+                String::static_type(),
+                String::static_type(),
+            ]),
+            view: builder.get_object("pending_users_list").unwrap(),
+            username_to_id: Rc::new(Mutex::new(BiMap::new())),
+            client,
+        });
+        this.create_and_setup_view();
+
+        approve_button.connect_clicked(
+            this.connector()
+                .do_async(move |this, _| async move {
+                    this.clone().perform_action(Action::Approve).await;
+                    this.refresh().await;
+                })
+                .build_cloned_consumer()
+        );
+
+        reject_button.connect_clicked(
+            this.connector()
+                .do_sync(|this, _| {
+                    dialog::show_confirm(
+                        "Confirm Action",
+                        "Are you sure you want to reject\nthe selected users? \
+                        This will\ndelete their accounts.",
+                        this,
+                        |this| async move {
+                            this.clone().perform_action(Action::Reject).await;
+                            this.refresh().await;
+                        },
+                    );
+                })
+                .build_cloned_consumer()
+        );
+
+        scheduler::spawn(this.refresh());
+    }
+
+    fn create_and_setup_view(&self) {
+        super::append_checkbutton_column("Selected", &self.list, &self.view, 0);
+
+        let headers = ["Username", "Display Name", "Registered"];
+        for (i, header) in headers.iter().enumerate() {
+            super::append_text_column(header, &self.view, i as i32 + 1);
+        }
+
+        self.view.set_model(Some(&self.list));
+    }
+
+    async fn perform_action(self: Rc<Self>, action: Action) {
+        super::perform_action(
+            action,
+            self.list.clone(),
+            self.username_to_id.clone(),
+            &self.client,
+        ).await
+    }
+
+    fn insert_users(&self, users: Vec<PendingUser>) {
+        let mut map = self.username_to_id.lock().unwrap();
+        self.list.clear();
+        map.clear();
+
+        for user in users {
+            map.insert(user.username.clone(), user.id);
+
+            let arr: &[&dyn glib::ToValue] = &[
+                &false,
+                &user.username,
+                &user.display_name,
+                &user.registered_at.format("%F %R").to_string(),
+            ];
+
+            let cols: Vec<_> = (0..4).collect();
+            self.list.insert_with_values(None, &cols, arr);
+        }
+
+        self.view.show_all();
+    }
+
+    async fn refresh(self: Rc<Self>) {
+        match self.client.list_pending_users().await {
+            Ok(users) => self.insert_users(users),
+            Err(err) => dialog::show_generic_error(&err),
+        }
+    }
+}
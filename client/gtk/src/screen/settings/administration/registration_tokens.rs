@@ -0,0 +1,150 @@
+use gtk::prelude::*;
+use vertex::prelude::*;
+use crate::{Client, scheduler};
+use std::rc::Rc;
+use crate::connect::AsConnector;
+use crate::screen::active::dialog;
+
+/// Tokens which let someone register while registration is restricted
+pub struct RegistrationTokens {
+    list: gtk::ListStore,
+    view: gtk::TreeView,
+    client: Client,
+}
+
+impl RegistrationTokens {
+    pub fn build(builder: gtk::Builder, client: Client) {
+        let buttons: gtk::Box = builder.get_object("registration_token_buttons").unwrap();
+
+        let this = Rc::new(RegistrationTokens {
+            list: gtk::ListStore::new(&[
+                bool::static_type(),
+                String::static_type(),
+                String::static_type(),
+                String::static_type(),
+                String::static_type(),
+            ]),
+            view: builder.get_object("registration_tokens_list").unwrap(),
+            client,
+        });
+        this.create_and_setup_view();
+
+        let max_uses = gtk::SpinButton::new_with_range(0.0, 1000.0, 1.0);
+        max_uses.set_tooltip_text(Some("How many accounts can be registered. 0 is unlimited."));
+
+        let expiry = gtk::ComboBoxText::new();
+        for &(id, label) in dialog::INVITE_EXPIRY_OPTIONS {
+            expiry.append(Some(id), label);
+        }
+        expiry.set_active_id(Some("never"));
+
+        let create = gtk::Button::new_with_label("Create token");
+        create.connect_clicked(
+            (this.clone(), max_uses.clone(), expiry.clone()).connector()
+                .do_async(|(this, max_uses, expiry), _| async move {
+                    let max_uses = match max_uses.get_value_as_int() {
+                        0 => None,
+                        max => Some(max as u32),
+                    };
+                    let expiration = expiry.get_active_id()
+                        .and_then(|id| dialog::invite_expiry(&id));
+
+                    match this.client.create_registration_token(expiration, max_uses).await {
+                        Ok(token) => {
+                            dialog::show_registration_token_dialog(&token.token);
+                            this.refresh().await;
+                        }
+                        Err(err) => dialog::show_generic_error(&err),
+                    }
+                })
+                .build_cloned_consumer()
+        );
+
+        let revoke = gtk::Button::new_with_label("Revoke");
+        revoke.connect_clicked(
+            this.connector()
+                .do_async(move |this, _| async move {
+                    this.clone().revoke_selected().await;
+                    this.refresh().await;
+                })
+                .build_cloned_consumer()
+        );
+
+        buttons.set_spacing(6);
+        buttons.add(&gtk::Label::new(Some("Max uses")));
+        buttons.add(&max_uses);
+        buttons.add(&expiry);
+        buttons.add(&create);
+        buttons.add(&revoke);
+        buttons.show_all();
+
+        scheduler::spawn(this.refresh());
+    }
+
+    fn create_and_setup_view(&self) {
+        super::append_checkbutton_column("Selected", &self.list, &self.view, 0);
+
+        let headers = ["Token", "Created By", "Expires", "Uses"];
+        for (i, header) in headers.iter().enumerate() {
+            super::append_text_column(header, &self.view, i as i32 + 1);
+        }
+
+        self.view.set_model(Some(&self.list));
+    }
+
+    async fn revoke_selected(self: Rc<Self>) {
+        let mut selected = Vec::new();
+        self.list.foreach(|_, _, iter| {
+            let toggled = self.list.get_value(iter, 0).get::<bool>().unwrap().unwrap();
+            if toggled {
+                let token = self.list.get_value(iter, 1).get::<String>().unwrap().unwrap();
+                selected.push(token);
+            }
+
+            false
+        });
+
+        for token in selected {
+            if let Err(err) = self.client.revoke_registration_token(token).await {
+                dialog::show_generic_error(&err);
+                return;
+            }
+        }
+    }
+
+    fn insert_tokens(&self, tokens: Vec<RegistrationToken>) {
+        self.list.clear();
+
+        for token in tokens {
+            // Unset for tokens created from the command line, or whose creator has been deleted
+            let creator = token.creator.map(|user| user.username).unwrap_or_default();
+            let expires = token.expiration_date
+                .map(|date| date.format("%F %R").to_string())
+                .unwrap_or_else(|| "Never".to_string());
+            let uses = match token.max_uses {
+                Some(max) => format!("{}/{}", token.uses, max),
+                None => token.uses.to_string(),
+            };
+
+            let arr: &[&dyn glib::ToValue] = &[
+                &false,
+                &token.token,
+                &creator,
+                &expires,
+                &uses,
+            ];
+
+            let cols: Vec<_> = (0..5).collect();
+            self.list.insert_with_values(None, &cols, arr);
+        }
+
+        self.view.show_all();
+    }
+
+    async fn refresh(self: Rc<Self>) {
+        match self.client.list_registration_tokens().await {
+            Ok(tokens) => self.insert_tokens(tokens),
+            Err(err) => dialog::show_generic_error(&err),
+        }
+    }
+}
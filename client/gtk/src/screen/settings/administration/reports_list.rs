@@ -47,6 +47,9 @@ impl ReportsList {
             ..Default::default()
         };
         scheduler::spawn(this.search(open));
+
+        let client = this.client.clone();
+        scheduler::spawn(async move { client.mark_reports_read().await });
     }
 
     fn insert_reports(&self, reports: Vec<Report>) {
@@ -74,33 +77,101 @@ impl ReportsList {
             let desc: gtk::Label = builder.get_object("description").unwrap();
             desc.set_text(&format!("Description: \"{}\"", &report.extended_desc));
 
+            let room = report.room.map(|r| r.name).unwrap_or_else(|| "<Deleted>".to_string());
+            let community = report.community.map(|c| c.name)
+                .unwrap_or_else(|| "<Deleted>".to_string());
+            let reported = report.reported.as_ref().map(|x| x.username.clone())
+                .unwrap_or_else(|| "<Deleted User>".to_string());
+
             let loc: gtk::Label = builder.get_object("in").unwrap();
-            loc.set_text(&format!(
-                "In: channel \"{}\" in community \"{}\"",
-                report.room.map(|r| r.name).unwrap_or_else(|| "<Deleted>".to_string()),
-                report.community.map(|c| c.name).unwrap_or_else(|| "<Deleted>".to_string()),
-            ));
+            loc.set_text(&match report.kind {
+                ReportKind::Message => {
+                    format!("In: channel \"{}\" in community \"{}\"", room, community)
+                }
+                ReportKind::User => format!("Reported user: \"{}\"", reported),
+                ReportKind::Room => {
+                    format!("Reported channel: \"{}\" in community \"{}\"", room, community)
+                }
+                ReportKind::Community => format!("Reported community: \"{}\"", community),
+            });
 
             let status: gtk::Label = builder.get_object("status").unwrap();
             status.set_text(&format!("Status: {}", &report.status));
 
-            let profile = Profile {
-                version: ProfileVersion(0), // doesn't matter
-                username: report.reported.username.clone(),
-                display_name: report.reported.username, // its fine
-            };
-            let msg = MessageGroupWidget::build(
-                report.reported.id,
-                profile,
-                report.message.sent_at,
-                false,
-                config::get().screen_reader_message_list,
-            );
-            msg.add_report_message(
-                &main,
-                Some(report.message.text),
-                MessageId::default(), // doesn't matter
-                self.client.clone(),
+            let claim = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+            main.add(&claim);
+            build_claim(self.client.clone(), claim, report.id, report.assignee);
+
+            let has_message = report.message.as_ref().and_then(|m| m.id).is_some();
+            if let (Some(message), Some(author)) = (report.message, report.reported) {
+                let profile = Profile {
+                    version: ProfileVersion(0), // doesn't matter
+                    username: author.username.clone(),
+                    display_name: author.username, // its fine
+                    bot: false,
+                };
+                let msg = MessageGroupWidget::build(
+                    author.id,
+                    profile,
+                    message.sent_at,
+                    false,
+                    config::get().screen_reader_message_list,
+                );
+                msg.add_report_message(
+                    &main,
+                    Some(message.text),
+                    MessageId::default(), // doesn't matter
+                    self.client.clone(),
+                );
+
+                let context = gtk::Box::new(gtk::Orientation::Vertical, 0);
+                main.add(&context);
+                build_context_button(self.client.clone(), context, report.id);
+            }
+
+            // What the reported user, room or community looked like when it was reported
+            if !report.snapshot.is_empty() {
+                let snapshot = gtk::LabelBuilder::new()
+                    .label(&report.snapshot)
+                    .halign(gtk::Align::Start)
+                    .wrap(true)
+                    .build();
+                main.add(&snapshot);
+            }
+
+            let notes = gtk::Box::new(gtk::Orientation::Vertical, 0);
+            main.add(&notes);
+            for note in report.notes {
+                let author = note.author.map(|x| x.username)
+                    .unwrap_or_else(|| "<Deleted User>".to_string());
+                add_note(&notes, &author, &note.text);
+            }
+
+            let note_entry = gtk::EntryBuilder::new()
+                .placeholder_text("Add a note for other administrators...")
+                .build();
+            main.add(&note_entry);
+
+            let id = report.id;
+            note_entry.connect_activate(
+                (self.client.clone(), notes).connector()
+                    .do_async(move |(client, notes), entry: gtk::Entry| async move {
+                        let text = entry.try_get_text().unwrap_or_default();
+                        if text.trim().is_empty() {
+                            return;
+                        }
+
+                        match client.add_report_note(id, text.clone()).await {
+                            Ok(()) => {
+                                let author = client.user.profile().await.username;
+                                add_note(&notes, &author, &text);
+                                notes.show_all();
+                                entry.set_text("");
+                            }
+                            Err(e) => dialog::show_generic_error(&e),
+                        }
+                    })
+                    .build_cloned_consumer()
             );
 
             let buttons = gtk::Box::new(gtk::Orientation::Horizontal, 0);
@@ -111,7 +182,7 @@ impl ReportsList {
                 status,
                 report.status,
                 report.id,
-                report.reported.id,
+                has_message,
             );
 
             main.show_all();
@@ -129,13 +200,120 @@ impl ReportsList {
     }
 }
 
+fn add_note(notes: &gtk::Box, author: &str, text: &str) {
+    let label = gtk::LabelBuilder::new()
+        .label(&format!("Note from \"{}\": {}", author, text))
+        .halign(gtk::Align::Start)
+        .wrap(true)
+        .build();
+    notes.add(&label);
+}
+
+/// Shows the messages around the reported message, as they were when it was reported
+fn build_context_button(client: Client, context: gtk::Box, id: i32) {
+    let button = gtk::Button::new_with_label("Show context");
+    button.connect_clicked(
+        (client, context.clone()).connector()
+            .do_async(move |(client, context), button: gtk::Button| async move {
+                let messages = match client.get_report_context(id).await {
+                    Ok(messages) => messages,
+                    Err(e) => return dialog::show_generic_error(&e),
+                };
+
+                context.remove(&button);
+                if messages.is_empty() {
+                    context.add(&gtk::Label::new(Some("No context was kept for this report")));
+                }
+
+                for message in messages {
+                    let author = message.author.map(|x| x.username)
+                        .unwrap_or_else(|| "<Deleted User>".to_string());
+                    let marker = if message.reported { "> " } else { "" };
+                    let label = gtk::LabelBuilder::new()
+                        .label(&format!(
+                            "{}[{}] {}: {}",
+                            marker,
+                            message.sent_at.format("%F %R"),
+                            author,
+                            message.text,
+                        ))
+                        .halign(gtk::Align::Start)
+                        .wrap(true)
+                        .build();
+
+                    if message.reported {
+                        label.get_style_context().add_class("reported_context_message");
+                    }
+
+                    context.add(&label);
+                }
+
+                context.show_all();
+            })
+            .build_cloned_consumer()
+    );
+
+    context.add(&button);
+}
+
+fn build_claim(client: Client, claim: gtk::Box, id: i32, assignee: Option<ReportUser>) {
+    claim.foreach(|w| claim.remove(w));
+
+    let text = match &assignee {
+        Some(user) => format!("Claimed by \"{}\"", user.username),
+        None => "Not claimed".to_string(),
+    };
+    claim.add(&gtk::Label::new(Some(&text)));
+
+    let own = client.user.id;
+    let button = match &assignee {
+        None => gtk::Button::new_with_label("Claim"),
+        Some(user) if user.id == own => gtk::Button::new_with_label("Unclaim"),
+        Some(_) => {
+            claim.show_all();
+            return;
+        }
+    };
+
+    button.connect_clicked(
+        (client, claim.clone()).connector()
+            .do_async(move |(client, claim), _| {
+                let claiming = assignee.is_none();
+                async move {
+                    let res = if claiming {
+                        client.claim_report(id).await
+                    } else {
+                        client.unclaim_report(id).await
+                    };
+
+                    match res {
+                        Err(e) => dialog::show_generic_error(&e),
+                        Ok(()) => {
+                            let assignee = if claiming {
+                                let username = client.user.profile().await.username;
+                                Some(ReportUser { id: client.user.id, username })
+                            } else {
+                                None
+                            };
+                            build_claim(client, claim, id, assignee);
+                        }
+                    }
+                }
+            })
+            .build_cloned_consumer()
+    );
+
+    claim.add(&button);
+    claim.show_all();
+}
+
 fn build_buttons(
     client: Client,
     buttons: gtk::Box,
     status_label: gtk::Label,
     status: ReportStatus,
     id: i32,
-    user: UserId,
+    has_message: bool,
 ) {
     buttons.foreach(|w| buttons.remove(w));
     status_label.set_text(&format!("Status: {}", &status));
@@ -144,15 +322,17 @@ fn build_buttons(
         let accept = gtk::Button::new_with_label("Accept (choose an action...)");
         accept.connect_clicked(
             (client.clone(), buttons.clone(), status_label.clone()).connector()
-                .do_async(move |(client, buttons, status_label), _| async move {
-                    let status = ReportStatus::Accepted;
-                    match client.set_report_status(id, status).await {
-                        Err(e) => dialog::show_generic_error(&e),
-                        Ok(_) => {
-                            dialog::show_choose_report_action(client.clone(), user);
-                            build_buttons(client, buttons, status_label, status, id, user);
-                        }
-                    }
+                .do_sync(move |(client, buttons, status_label), _| {
+                    dialog::show_choose_report_action(
+                        client,
+                        id,
+                        has_message,
+                        move |client| {
+                            let status = ReportStatus::Accepted;
+                            let (buttons, status_label) = (buttons.clone(), status_label.clone());
+                            build_buttons(client, buttons, status_label, status, id, has_message);
+                        },
+                    );
                 })
                 .build_cloned_consumer()
         );
@@ -164,7 +344,7 @@ fn build_buttons(
                     let status = ReportStatus::Denied;
                     match client.set_report_status(id, status).await {
                         Err(e) => dialog::show_generic_error(&e),
-                        Ok(_) => build_buttons(client, buttons, status_label, status, id, user),
+                        Ok(_) => build_buttons(client, buttons, status_label, status, id, has_message),
                     }
 
                 })
@@ -181,7 +361,7 @@ fn build_buttons(
                     let status = ReportStatus::Opened;
                     match client.set_report_status(id, status).await {
                         Err(e) => dialog::show_generic_error(&e),
-                        Ok(_) => build_buttons(client, buttons, status_label, status, id, user),
+                        Ok(_) => build_buttons(client, buttons, status_label, status, id, has_message),
                     }
                 })
                 .build_cloned_consumer()
@@ -154,6 +154,8 @@ impl UsersSearch {
         let ban: gtk::CheckButton = gtk::CheckButton::new_with_label("Ban/unban users");
         let basic: gtk::CheckButton = gtk::CheckButton::new_with_label("Basic");
         let promote: gtk::CheckButton = gtk::CheckButton::new_with_label("Promote/demote admins");
+        let registration: gtk::CheckButton =
+            gtk::CheckButton::new_with_label("Manage registration");
 
         all.connect_toggled(
             flags.connector()
@@ -179,7 +181,15 @@ impl UsersSearch {
                 .build_cloned_consumer()
         );
 
-        let permissions = [basic, ban, promote, all];
+        registration.connect_toggled(
+            flags.connector()
+                .do_sync(|flags, _| {
+                    flags.clone().borrow_mut().toggle(AdminPermissionFlags::MANAGE_REGISTRATION)
+                })
+                .build_cloned_consumer()
+        );
+
+        let permissions = [basic, ban, promote, registration, all];
 
         window::show_dialog(|window| {
             let dialog = gtk::Dialog::new_with_buttons(
@@ -1,6 +1,6 @@
 use crate::proto;
 use crate::proto::DeserializeError;
-use crate::requests::AdminPermissionFlags;
+use crate::requests::{AdminPermissionFlags, Report};
 use crate::responses::*;
 use crate::structures::*;
 use crate::types::*;
@@ -36,16 +36,32 @@ impl From<ServerMessage> for proto::events::ServerMessage {
 
         let inner = match msg {
             Event(event) => Message::Event(event.into()),
-            Response { id, result } => Message::Response(proto::responses::Response {
-                id: Some(id.into()),
-                response: Some(match result {
-                    Ok(ok) => proto::responses::response::Response::Ok(ok.into()),
-                    Err(err) => {
-                        let err: proto::responses::Error = err.into();
-                        proto::responses::response::Response::Error(err as i32)
-                    }
-                }),
-            }),
+            Response { id, result } => {
+                let restriction = result
+                    .as_ref()
+                    .err()
+                    .and_then(Error::restriction)
+                    .cloned()
+                    .map(Into::into);
+                let retry_after_secs = result
+                    .as_ref()
+                    .err()
+                    .and_then(Error::retry_after_secs)
+                    .unwrap_or(0);
+
+                Message::Response(proto::responses::Response {
+                    id: Some(id.into()),
+                    response: Some(match result {
+                        Ok(ok) => proto::responses::response::Response::Ok(ok.into()),
+                        Err(err) => {
+                            let err: proto::responses::Error = err.into();
+                            proto::responses::response::Response::Error(err as i32)
+                        }
+                    }),
+                    restriction,
+                    retry_after_secs,
+                })
+            }
             MalformedMessage => Message::MalformedMessage(proto::types::None {}),
             RateLimited { ready_in } => Message::RateLimited(proto::events::RateLimited {
                 ready_in_ms: ready_in.as_millis().try_into().unwrap_or(std::u32::MAX),
@@ -73,7 +89,9 @@ impl TryFrom<proto::events::ServerMessage> for ServerMessage {
                     result: Ok(ok.try_into()?),
                 },
                 Response::Error(err) => {
-                    let err = proto::responses::Error::from_i32(err)?.try_into()?;
+                    let err = proto::responses::Error::from_i32(err)?;
+                    let restriction = res.restriction.map(Into::into);
+                    let err = Error::from_proto(err, restriction, res.retry_after_secs)?;
 
                     ServerMessage::Response {
                         id: res.id?.into(),
@@ -128,6 +146,22 @@ pub enum ServerEvent {
         reason: RemoveCommunityReason,
     },
     AdminPermissionsChanged(AdminPermissionFlags),
+    /// A user invoked one of this bot's slash commands
+    CommandInvoked(CommandInvocation),
+    /// The server is shutting down and is about to close the connection. Clients should reconnect
+    /// after a short delay.
+    ServerShuttingDown,
+    /// A user was reported. Sent to administrators.
+    NewReport(Report),
+    /// A room's settings were changed, e.g slow mode was turned on
+    RoomSettingsChanged {
+        community: CommunityId,
+        room: RoomId,
+        settings: RoomSettings,
+    },
+    /// This device's one-time prekeys are running low, as others have claimed them. It should
+    /// publish more. Holds the number of prekeys left.
+    PrekeysLow(u32),
 }
 
 impl From<ServerEvent> for proto::events::ServerEvent {
@@ -171,6 +205,19 @@ impl From<ServerEvent> for proto::events::ServerEvent {
             }
             InternalError => Event::InternalError(proto::types::None {}),
             AdminPermissionsChanged(new) => Event::AdminPermissionsChanged(new.bits()),
+            CommandInvoked(invocation) => Event::CommandInvoked(invocation.into()),
+            ServerShuttingDown => Event::ServerShuttingDown(proto::types::None {}),
+            NewReport(report) => Event::NewReport(report.into()),
+            RoomSettingsChanged {
+                community,
+                room,
+                settings,
+            } => Event::RoomSettingsChanged(proto::events::RoomSettingsChanged {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                settings: Some(settings.into()),
+            }),
+            PrekeysLow(remaining) => Event::PrekeysLow(remaining),
         };
 
         proto::events::ServerEvent { event: Some(inner) }
@@ -216,6 +263,15 @@ impl TryFrom<proto::events::ServerEvent> for ServerEvent {
                 let new = AdminPermissionFlags::from_bits_truncate(new);
                 ServerEvent::AdminPermissionsChanged(new)
             }
+            CommandInvoked(invocation) => ServerEvent::CommandInvoked(invocation.try_into()?),
+            ServerShuttingDown(_) => ServerEvent::ServerShuttingDown,
+            NewReport(report) => ServerEvent::NewReport(report.try_into()?),
+            RoomSettingsChanged(changed) => ServerEvent::RoomSettingsChanged {
+                community: changed.community?.try_into()?,
+                room: changed.room?.try_into()?,
+                settings: changed.settings?.into(),
+            },
+            PrekeysLow(remaining) => ServerEvent::PrekeysLow(remaining),
         })
     }
 }
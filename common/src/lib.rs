@@ -29,6 +29,54 @@ pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
 
 pub const RATELIMIT_BURST_PER_MIN: u32 = 120;
 
+/// Bots are held to a tighter ratelimit than regular users
+pub const BOT_RATELIMIT_BURST_PER_MIN: u32 = 30;
+
+/// A slash command which is handled by the client rather than by a bot
+pub struct BuiltinCommand {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+/// Slash commands which are handled by the client itself, and so cannot be registered by bots
+pub const BUILTIN_COMMANDS: &[BuiltinCommand] = &[
+    BuiltinCommand {
+        name: "me",
+        usage: "/me <action>",
+        description: "Send an action, e.g /me waves",
+    },
+    BuiltinCommand {
+        name: "shrug",
+        usage: "/shrug [message]",
+        description: "Append ¯\\_(ツ)_/¯ to a message",
+    },
+    BuiltinCommand {
+        name: "invite",
+        usage: "/invite",
+        description: "Create an invite code to this community",
+    },
+    BuiltinCommand {
+        name: "topic",
+        usage: "/topic <description>",
+        description: "Change the description of this community",
+    },
+    BuiltinCommand {
+        name: "nick",
+        usage: "/nick <display name>",
+        description: "Change your display name",
+    },
+    BuiltinCommand {
+        name: "report-room",
+        usage: "/report-room",
+        description: "Report this room to the server's administrators",
+    },
+];
+
+pub fn is_builtin_command(name: &str) -> bool {
+    BUILTIN_COMMANDS.iter().any(|builtin| builtin.name == name)
+}
+
 pub fn setup_logging(
     name: &str,
     log_level: log::LevelFilter,
@@ -1,9 +1,10 @@
-use super::administration::AdminRequest;
+use super::administration::{AdminRequest, ReportKind};
 use crate::proto;
 use crate::proto::DeserializeError;
 use crate::structures::*;
 use crate::types::*;
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
 use std::convert::{TryFrom, TryInto};
 
 #[derive(Debug, Clone)]
@@ -40,19 +41,25 @@ impl TryFrom<proto::requests::active::ClientMessage> for ClientMessage {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientSentMessage {
     pub to_community: CommunityId,
     pub to_room: RoomId,
+    /// Must be empty in encrypted rooms, where the content is sent as the ciphertext instead
     pub content: String,
+    #[serde(default)]
+    pub ciphertext: Option<Vec<u8>>,
 }
 
 impl From<ClientSentMessage> for proto::requests::active::ClientSentMessage {
     fn from(msg: ClientSentMessage) -> Self {
+        use proto::requests::active::client_sent_message::Encrypted;
+
         proto::requests::active::ClientSentMessage {
             to_community: Some(msg.to_community.into()),
             to_room: Some(msg.to_room.into()),
             content: msg.content,
+            encrypted: msg.ciphertext.map(Encrypted::Ciphertext),
         }
     }
 }
@@ -61,15 +68,22 @@ impl TryFrom<proto::requests::active::ClientSentMessage> for ClientSentMessage {
     type Error = DeserializeError;
 
     fn try_from(msg: proto::requests::active::ClientSentMessage) -> Result<Self, Self::Error> {
+        use proto::requests::active::client_sent_message::Encrypted;
+
         Ok(ClientSentMessage {
             to_community: msg.to_community?.try_into()?,
             to_room: msg.to_room?.try_into()?,
             content: msg.content,
+            ciphertext: msg.encrypted.map(|e| {
+                let Encrypted::Ciphertext(ciphertext) = e;
+                ciphertext
+            }),
         })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[non_exhaustive]
 pub enum ClientRequest {
     LogOut,
@@ -102,10 +116,17 @@ pub enum ClientRequest {
     CreateRoom {
         name: String,
         community: CommunityId,
+        /// Whether messages in the room are end-to-end encrypted. This cannot be changed later.
+        #[serde(default)]
+        encrypted: bool,
     },
     CreateInvite {
         community: CommunityId,
         expiration_datetime: Option<DateTime<Utc>>,
+        /// How many times the invite can be used to join. `None` if it can be used any number of
+        /// times.
+        #[serde(default)]
+        max_uses: Option<u32>,
     },
     JoinCommunity(InviteCode),
     Delete(Delete),
@@ -125,10 +146,198 @@ pub enum ClientRequest {
         new: String,
     },
     AdminAction(AdminRequest),
-    ReportUser {
-        message: MessageId,
+    /// Report something to the server's administrators
+    Report {
+        target: ReportTarget,
         short_desc: String,
         extended_desc: String,
+    },
+    /// Create a bot account owned by this user. Responds with the bot's user ID.
+    CreateBot {
+        username: String,
+        display_name: String,
+    },
+    /// Create a login token for a bot owned by this user. The token's permissions are restricted
+    /// to those a bot may hold.
+    CreateBotToken {
+        bot: UserId,
+        options: TokenCreationOptions,
+    },
+    /// Install a bot owned by this user into a community that this user is a member of.
+    InstallBot {
+        bot: UserId,
+        community: CommunityId,
+    },
+    /// Create an incoming webhook which posts into the given room. Requires the `MANAGE_WEBHOOKS`
+    /// community permission.
+    CreateWebhook {
+        community: CommunityId,
+        room: RoomId,
+        name: String,
+    },
+    ListWebhooks {
+        community: CommunityId,
+    },
+    RevokeWebhook {
+        community: CommunityId,
+        webhook: WebhookId,
+    },
+    /// Create an outgoing webhook which is notified of the given events in the community. Requires
+    /// the `MANAGE_WEBHOOKS` community permission.
+    CreateOutgoingWebhook {
+        community: CommunityId,
+        url: String,
+        events: WebhookEventFlags,
+    },
+    ListOutgoingWebhooks {
+        community: CommunityId,
+    },
+    DeleteOutgoingWebhook {
+        community: CommunityId,
+        webhook: WebhookId,
+    },
+    /// Get the most recent delivery attempts for an outgoing webhook
+    GetWebhookDeliveries {
+        community: CommunityId,
+        webhook: WebhookId,
+    },
+    /// Set the slash commands that this bot provides in the community, replacing any it declared
+    /// previously. Only bots may register commands.
+    RegisterCommands {
+        community: CommunityId,
+        commands: Vec<BotCommand>,
+    },
+    /// List the slash commands registered by bots in the community
+    ListCommands {
+        community: CommunityId,
+    },
+    /// Invoke a bot's slash command. The bot is sent a `CommandInvoked` event.
+    InvokeCommand {
+        community: CommunityId,
+        room: RoomId,
+        bot: UserId,
+        command: String,
+        arguments: Vec<String>,
+    },
+    /// Publish this device's identity key and a batch of one-time prekeys for end-to-end
+    /// encryption. The identity key replaces any published before, and the prekeys are added to
+    /// those remaining. Responds with the number of prekeys remaining.
+    PublishDeviceKeys {
+        identity_key: Vec<u8>,
+        one_time_prekeys: Vec<Vec<u8>>,
+    },
+    /// Get the identity keys of all of a user's devices, to verify them. No prekeys are claimed.
+    GetDeviceKeys {
+        user: UserId,
+    },
+    /// Claim a key bundle for every device of every member of the community, to encrypt a message
+    /// to them. Each bundle includes a one-time prekey if the device has any left, except for the
+    /// bundles of the given devices, which the client already has sessions with, and of devices
+    /// that this device has already claimed a prekey from in the last day.
+    ClaimDeviceKeys {
+        community: CommunityId,
+        #[serde(default)]
+        except_devices: Vec<DeviceId>,
+    },
+    /// Set how many days messages are kept for in the community, or in one of its rooms if one is
+    /// given. If the number of days is unset, the room falls back to the community's policy, and
+    /// the community to the server's. Requires the `MANAGE_RETENTION` community permission.
+    SetRetention {
+        community: CommunityId,
+        room: Option<RoomId>,
+        days: Option<u32>,
+    },
+    /// Export the community to an archive, e.g to back it up or move it to another server.
+    /// Responds with the archive. Only the community's owners may export it.
+    ExportCommunity {
+        community: CommunityId,
+        include_invite_codes: bool,
+    },
+    /// Recreate a community from an archive, with this user as its owner
+    ImportCommunity {
+        archive: Vec<u8>,
+    },
+    /// Add an automod rule to the community. Responds with the new rule. Requires the
+    /// `MANAGE_AUTOMOD` community permission, as do the other automod requests.
+    AddAutomodRule {
+        community: CommunityId,
+        name: String,
+        trigger: AutomodTrigger,
+        action: AutomodAction,
+    },
+    ListAutomodRules {
+        community: CommunityId,
+    },
+    DeleteAutomodRule {
+        community: CommunityId,
+        rule: i32,
+    },
+    /// Get the most recent messages which triggered the community's automod rules
+    GetAutomodHits {
+        community: CommunityId,
+    },
+    /// Change a room's slow mode, read-only and message length settings. Requires the
+    /// `MANAGE_ROOMS` community permission.
+    SetRoomSettings {
+        community: CommunityId,
+        room: RoomId,
+        settings: RoomSettings,
+    },
+    /// List the community's invite codes. Requires the `MANAGE_INVITES` community permission.
+    ListInvites {
+        community: CommunityId,
+    },
+    /// Revoke an invite code so that it can no longer be used. Requires the `MANAGE_INVITES`
+    /// community permission.
+    RevokeInvite {
+        community: CommunityId,
+        code: InviteCode,
+    },
+    /// Get who joined the community through an invite code. Requires the `MANAGE_INVITES`
+    /// community permission.
+    GetInviteUses {
+        community: CommunityId,
+        code: InviteCode,
+    },
+    /// List or unlist the community in the server's public directory. Requires the `ALL`
+    /// community permission.
+    SetCommunityPublic {
+        community: CommunityId,
+        public: bool,
+    },
+    /// Search the server's public directory. An empty query lists the communities with the most
+    /// members first.
+    ListPublicCommunities {
+        query: String,
+        offset: u32,
+    },
+    /// Join a community listed in the server's public directory
+    JoinPublicCommunity(CommunityId),
+}
+
+/// A class of requests which has its own ratelimit quota, on top of the general quota which
+/// applies to all requests
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum RatelimitClass {
+    Message,
+    Invite,
+    RoomCreation,
+}
+
+impl ClientRequest {
+    pub fn ratelimit_class(&self) -> Option<RatelimitClass> {
+        match self {
+            ClientRequest::SendMessage(_)
+            | ClientRequest::EditMessage(_)
+            | ClientRequest::InvokeCommand { .. } => Some(RatelimitClass::Message),
+            ClientRequest::CreateInvite { .. }
+            | ClientRequest::JoinCommunity(_)
+            | ClientRequest::JoinPublicCommunity(_) => Some(RatelimitClass::Invite),
+            ClientRequest::CreateRoom { .. }
+            | ClientRequest::CreateCommunity { .. }
+            | ClientRequest::ImportCommunity { .. } => Some(RatelimitClass::RoomCreation),
+            _ => None,
+        }
     }
 }
 
@@ -175,18 +384,25 @@ impl From<ClientRequest> for proto::requests::active::ClientRequest {
                 room: Some(room.into()),
             }),
             CreateCommunity { name } => Request::CreateCommunity(request::CreateCommunity { name }),
-            CreateRoom { name, community } => Request::CreateRoom(request::CreateRoom {
+            CreateRoom {
+                name,
+                community,
+                encrypted,
+            } => Request::CreateRoom(request::CreateRoom {
                 name,
                 community: Some(community.into()),
+                encrypted,
             }),
             CreateInvite {
                 community,
                 expiration_datetime: dt,
+                max_uses,
             } => {
-                use request::create_invite::ExpirationDatetime::Present;
+                use request::create_invite::{ExpirationDatetime::Present, MaxUses};
                 Request::CreateInvite(request::CreateInvite {
                     community: Some(community.into()),
                     expiration_datetime: dt.map(|x| Present(x.timestamp())),
+                    max_uses: max_uses.map(MaxUses::MaxUsesPresent),
                 })
             }
             JoinCommunity(code) => Request::JoinCommunity(request::JoinCommunity {
@@ -215,11 +431,187 @@ impl From<ClientRequest> for proto::requests::active::ClientRequest {
                 })
             }
             AdminAction(req) => Request::AdminAction(req.into()),
-            ReportUser { message, short_desc, extended_desc } => {
-                Request::ReportUser(request::ReportUser {
-                    message: Some(message.into()),
-                    short_desc,
-                    extended_desc,
+            Report { target, short_desc, extended_desc } => Request::Report(request::Report {
+                target: Some(target.into()),
+                short_desc,
+                extended_desc,
+            }),
+            CreateBot { username, display_name } => {
+                Request::CreateBot(request::CreateBot { username, display_name })
+            }
+            CreateBotToken { bot, options } => Request::CreateBotToken(request::CreateBotToken {
+                bot: Some(bot.into()),
+                options: Some(options.into()),
+            }),
+            InstallBot { bot, community } => Request::InstallBot(request::InstallBot {
+                bot: Some(bot.into()),
+                community: Some(community.into()),
+            }),
+            CreateWebhook {
+                community,
+                room,
+                name,
+            } => Request::CreateWebhook(request::CreateWebhook {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                name,
+            }),
+            ListWebhooks { community } => Request::ListWebhooks(request::ListWebhooks {
+                community: Some(community.into()),
+            }),
+            RevokeWebhook { community, webhook } => {
+                Request::RevokeWebhook(request::RevokeWebhook {
+                    community: Some(community.into()),
+                    webhook: Some(webhook.into()),
+                })
+            }
+            CreateOutgoingWebhook {
+                community,
+                url,
+                events,
+            } => Request::CreateOutgoingWebhook(request::CreateOutgoingWebhook {
+                community: Some(community.into()),
+                url,
+                events: events.bits(),
+            }),
+            ListOutgoingWebhooks { community } => {
+                Request::ListOutgoingWebhooks(request::ListOutgoingWebhooks {
+                    community: Some(community.into()),
+                })
+            }
+            DeleteOutgoingWebhook { community, webhook } => {
+                Request::DeleteOutgoingWebhook(request::DeleteOutgoingWebhook {
+                    community: Some(community.into()),
+                    webhook: Some(webhook.into()),
+                })
+            }
+            GetWebhookDeliveries { community, webhook } => {
+                Request::GetWebhookDeliveries(request::GetWebhookDeliveries {
+                    community: Some(community.into()),
+                    webhook: Some(webhook.into()),
+                })
+            }
+            RegisterCommands {
+                community,
+                commands,
+            } => Request::RegisterCommands(request::RegisterCommands {
+                community: Some(community.into()),
+                commands: commands.into_iter().map(Into::into).collect(),
+            }),
+            ListCommands { community } => Request::ListCommands(request::ListCommands {
+                community: Some(community.into()),
+            }),
+            InvokeCommand {
+                community,
+                room,
+                bot,
+                command,
+                arguments,
+            } => Request::InvokeCommand(request::InvokeCommand {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                bot: Some(bot.into()),
+                command,
+                arguments,
+            }),
+            PublishDeviceKeys {
+                identity_key,
+                one_time_prekeys,
+            } => Request::PublishDeviceKeys(request::PublishDeviceKeys {
+                identity_key,
+                one_time_prekeys,
+            }),
+            GetDeviceKeys { user } => Request::GetDeviceKeys(request::GetDeviceKeys {
+                user: Some(user.into()),
+            }),
+            ClaimDeviceKeys {
+                community,
+                except_devices,
+            } => Request::ClaimDeviceKeys(request::ClaimDeviceKeys {
+                community: Some(community.into()),
+                except_devices: except_devices.into_iter().map(Into::into).collect(),
+            }),
+            SetRetention {
+                community,
+                room,
+                days,
+            } => {
+                use request::set_retention::Days::Present;
+                Request::SetRetention(request::SetRetention {
+                    community: Some(community.into()),
+                    room: room.map(|x| x.into()),
+                    days: days.map(Present),
+                })
+            }
+            ExportCommunity {
+                community,
+                include_invite_codes,
+            } => Request::ExportCommunity(request::ExportCommunity {
+                community: Some(community.into()),
+                include_invite_codes,
+            }),
+            ImportCommunity { archive } => {
+                Request::ImportCommunity(request::ImportCommunity { archive })
+            }
+            AddAutomodRule {
+                community,
+                name,
+                trigger,
+                action,
+            } => Request::AddAutomodRule(request::AddAutomodRule {
+                community: Some(community.into()),
+                name,
+                trigger: Some(trigger.into()),
+                action: Some(action.into()),
+            }),
+            ListAutomodRules { community } => {
+                Request::ListAutomodRules(request::ListAutomodRules {
+                    community: Some(community.into()),
+                })
+            }
+            DeleteAutomodRule { community, rule } => {
+                Request::DeleteAutomodRule(request::DeleteAutomodRule {
+                    community: Some(community.into()),
+                    rule,
+                })
+            }
+            GetAutomodHits { community } => Request::GetAutomodHits(request::GetAutomodHits {
+                community: Some(community.into()),
+            }),
+            SetRoomSettings {
+                community,
+                room,
+                settings,
+            } => Request::SetRoomSettings(request::SetRoomSettings {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                settings: Some(settings.into()),
+            }),
+            ListInvites { community } => Request::ListInvites(request::ListInvites {
+                community: Some(community.into()),
+            }),
+            RevokeInvite { community, code } => Request::RevokeInvite(request::RevokeInvite {
+                community: Some(community.into()),
+                invite_code: code.0,
+            }),
+            GetInviteUses { community, code } => {
+                Request::GetInviteUses(request::GetInviteUses {
+                    community: Some(community.into()),
+                    invite_code: code.0,
+                })
+            }
+            SetCommunityPublic { community, public } => {
+                Request::SetCommunityPublic(request::SetCommunityPublic {
+                    community: Some(community.into()),
+                    public,
+                })
+            }
+            ListPublicCommunities { query, offset } => {
+                Request::ListPublicCommunities(request::ListPublicCommunities { query, offset })
+            }
+            JoinPublicCommunity(community) => {
+                Request::JoinPublicCommunity(request::JoinPublicCommunity {
+                    community: Some(community.into()),
                 })
             }
         };
@@ -267,9 +659,10 @@ impl TryFrom<proto::requests::active::ClientRequest> for ClientRequest {
             CreateRoom(create) => ClientRequest::CreateRoom {
                 name: create.name,
                 community: create.community?.try_into()?,
+                encrypted: create.encrypted,
             },
             CreateInvite(create) => {
-                use request::create_invite::ExpirationDatetime::Present;
+                use request::create_invite::{ExpirationDatetime::Present, MaxUses};
                 ClientRequest::CreateInvite {
                     community: create.community?.try_into()?,
                     expiration_datetime: create
@@ -277,6 +670,7 @@ impl TryFrom<proto::requests::active::ClientRequest> for ClientRequest {
                         .map(|Present(x)| x)
                         .map(|ts| NaiveDateTime::from_timestamp(ts, 0))
                         .map(|dt| Utc.from_utc_datetime(&dt)),
+                    max_uses: create.max_uses.map(|MaxUses::MaxUsesPresent(x)| x),
                 }
             }
             JoinCommunity(join) => ClientRequest::JoinCommunity(InviteCode(join.invite_code)),
@@ -297,11 +691,150 @@ impl TryFrom<proto::requests::active::ClientRequest> for ClientRequest {
                 community: change.community?.try_into()?,
             },
             AdminAction(action) => ClientRequest::AdminAction(action.try_into()?),
-            ReportUser(report) => ClientRequest::ReportUser {
-                message: report.message?.try_into()?,
+            Report(report) => ClientRequest::Report {
+                target: report.target?.try_into()?,
                 short_desc: report.short_desc,
                 extended_desc: report.extended_desc,
             },
+            CreateBot(create) => ClientRequest::CreateBot {
+                username: create.username,
+                display_name: create.display_name,
+            },
+            CreateBotToken(create) => ClientRequest::CreateBotToken {
+                bot: create.bot?.try_into()?,
+                options: create.options?.try_into()?,
+            },
+            InstallBot(install) => ClientRequest::InstallBot {
+                bot: install.bot?.try_into()?,
+                community: install.community?.try_into()?,
+            },
+            CreateWebhook(create) => ClientRequest::CreateWebhook {
+                community: create.community?.try_into()?,
+                room: create.room?.try_into()?,
+                name: create.name,
+            },
+            ListWebhooks(list) => ClientRequest::ListWebhooks {
+                community: list.community?.try_into()?,
+            },
+            RevokeWebhook(revoke) => ClientRequest::RevokeWebhook {
+                community: revoke.community?.try_into()?,
+                webhook: revoke.webhook?.try_into()?,
+            },
+            CreateOutgoingWebhook(create) => ClientRequest::CreateOutgoingWebhook {
+                community: create.community?.try_into()?,
+                url: create.url,
+                events: WebhookEventFlags::from_bits_truncate(create.events),
+            },
+            ListOutgoingWebhooks(list) => ClientRequest::ListOutgoingWebhooks {
+                community: list.community?.try_into()?,
+            },
+            DeleteOutgoingWebhook(delete) => ClientRequest::DeleteOutgoingWebhook {
+                community: delete.community?.try_into()?,
+                webhook: delete.webhook?.try_into()?,
+            },
+            GetWebhookDeliveries(get) => ClientRequest::GetWebhookDeliveries {
+                community: get.community?.try_into()?,
+                webhook: get.webhook?.try_into()?,
+            },
+            RegisterCommands(register) => {
+                let commands = register
+                    .commands
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<BotCommand>, DeserializeError>>()?;
+
+                ClientRequest::RegisterCommands {
+                    community: register.community?.try_into()?,
+                    commands,
+                }
+            }
+            ListCommands(list) => ClientRequest::ListCommands {
+                community: list.community?.try_into()?,
+            },
+            InvokeCommand(invoke) => ClientRequest::InvokeCommand {
+                community: invoke.community?.try_into()?,
+                room: invoke.room?.try_into()?,
+                bot: invoke.bot?.try_into()?,
+                command: invoke.command,
+                arguments: invoke.arguments,
+            },
+            PublishDeviceKeys(publish) => ClientRequest::PublishDeviceKeys {
+                identity_key: publish.identity_key,
+                one_time_prekeys: publish.one_time_prekeys,
+            },
+            GetDeviceKeys(get) => ClientRequest::GetDeviceKeys {
+                user: get.user?.try_into()?,
+            },
+            ClaimDeviceKeys(claim) => {
+                let except_devices = claim
+                    .except_devices
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<DeviceId>, DeserializeError>>()?;
+
+                ClientRequest::ClaimDeviceKeys {
+                    community: claim.community?.try_into()?,
+                    except_devices,
+                }
+            }
+            SetRetention(set) => {
+                use request::set_retention::Days::Present;
+                ClientRequest::SetRetention {
+                    community: set.community?.try_into()?,
+                    room: set.room.map(|x| x.try_into()).transpose()?,
+                    days: set.days.map(|Present(x)| x),
+                }
+            }
+            ExportCommunity(export) => ClientRequest::ExportCommunity {
+                community: export.community?.try_into()?,
+                include_invite_codes: export.include_invite_codes,
+            },
+            ImportCommunity(import) => ClientRequest::ImportCommunity {
+                archive: import.archive,
+            },
+            AddAutomodRule(add) => ClientRequest::AddAutomodRule {
+                community: add.community?.try_into()?,
+                name: add.name,
+                trigger: add.trigger?.try_into()?,
+                action: add.action?.try_into()?,
+            },
+            ListAutomodRules(list) => ClientRequest::ListAutomodRules {
+                community: list.community?.try_into()?,
+            },
+            DeleteAutomodRule(delete) => ClientRequest::DeleteAutomodRule {
+                community: delete.community?.try_into()?,
+                rule: delete.rule,
+            },
+            GetAutomodHits(get) => ClientRequest::GetAutomodHits {
+                community: get.community?.try_into()?,
+            },
+            SetRoomSettings(set) => ClientRequest::SetRoomSettings {
+                community: set.community?.try_into()?,
+                room: set.room?.try_into()?,
+                settings: set.settings?.into(),
+            },
+            ListInvites(list) => ClientRequest::ListInvites {
+                community: list.community?.try_into()?,
+            },
+            RevokeInvite(revoke) => ClientRequest::RevokeInvite {
+                community: revoke.community?.try_into()?,
+                code: InviteCode(revoke.invite_code),
+            },
+            GetInviteUses(get) => ClientRequest::GetInviteUses {
+                community: get.community?.try_into()?,
+                code: InviteCode(get.invite_code),
+            },
+            SetCommunityPublic(set) => ClientRequest::SetCommunityPublic {
+                community: set.community?.try_into()?,
+                public: set.public,
+            },
+            ListPublicCommunities(list) => ClientRequest::ListPublicCommunities {
+                query: list.query,
+                offset: list.offset,
+            },
+            JoinPublicCommunity(join) => {
+                ClientRequest::JoinPublicCommunity(join.community?.try_into()?)
+            }
         };
 
         Ok(val)
@@ -326,7 +859,70 @@ impl Into<Vec<u8>> for ClientMessage {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Something which can be reported to the server's administrators
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportTarget {
+    Message(MessageId),
+    /// A user's profile, e.g their username or display name
+    User(UserId),
+    Room {
+        community: CommunityId,
+        room: RoomId,
+    },
+    /// A community itself, e.g its name or description
+    Community(CommunityId),
+}
+
+impl ReportTarget {
+    pub fn kind(&self) -> ReportKind {
+        match self {
+            ReportTarget::Message(_) => ReportKind::Message,
+            ReportTarget::User(_) => ReportKind::User,
+            ReportTarget::Room { .. } => ReportKind::Room,
+            ReportTarget::Community(_) => ReportKind::Community,
+        }
+    }
+}
+
+impl From<ReportTarget> for proto::requests::active::report::Target {
+    fn from(target: ReportTarget) -> Self {
+        use proto::requests::active::report::Target;
+
+        match target {
+            ReportTarget::Message(message) => Target::Message(message.into()),
+            ReportTarget::User(user) => Target::User(user.into()),
+            ReportTarget::Room { community, room } => {
+                Target::Room(proto::requests::active::ReportedRoom {
+                    community: Some(community.into()),
+                    room: Some(room.into()),
+                })
+            }
+            ReportTarget::Community(community) => Target::Community(community.into()),
+        }
+    }
+}
+
+impl TryFrom<proto::requests::active::report::Target> for ReportTarget {
+    type Error = DeserializeError;
+
+    fn try_from(target: proto::requests::active::report::Target) -> Result<Self, Self::Error> {
+        use proto::requests::active::report::Target;
+
+        Ok(match target {
+            Target::Message(message) => ReportTarget::Message(message.try_into()?),
+            Target::User(user) => ReportTarget::User(user.try_into()?),
+            Target::Room(room) => ReportTarget::Room {
+                community: room.community?.try_into()?,
+                room: room.room?.try_into()?,
+            },
+            Target::Community(community) => ReportTarget::Community(community.try_into()?),
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Bound<T> {
     Inclusive(T),
     Exclusive(T),
@@ -372,7 +968,8 @@ impl TryFrom<proto::requests::active::Bound> for Bound<MessageId> {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum MessageSelector {
     Before(Bound<MessageId>),
     After(Bound<MessageId>),
@@ -1,12 +1,17 @@
 use crate::proto;
 use crate::proto::DeserializeError;
+use crate::structures::Restriction;
 use crate::types::*;
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 use std::convert::{TryFrom, TryInto};
 use chrono::{DateTime, Utc, NaiveDateTime, TimeZone};
 use std::fmt;
+use std::str::FromStr;
 
 bitflags! {
+    #[derive(Serialize, Deserialize)]
+    #[serde(transparent)]
     pub struct AdminPermissionFlags: i64 {
         /// All permissions. Could be used for the server owner.
         const ALL = 1;
@@ -20,10 +25,13 @@ bitflags! {
         const IS_ADMIN = 1 << 3;
         /// Whether the user can set accounts compromised
         const SET_ACCOUNTS_COMPROMISED = 1 << 4;
+        /// Manage registration tokens and approve or reject users waiting to be approved
+        const MANAGE_REGISTRATION = 1 << 5;
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[non_exhaustive]
 pub enum AdminRequest {
     Promote {
@@ -31,9 +39,18 @@ pub enum AdminRequest {
         permissions: AdminPermissionFlags,
     },
     Demote(UserId),
-    Ban(UserId),
+    Ban {
+        user: UserId,
+        restriction: Restriction,
+    },
     Unban(UserId),
     Unlock(UserId),
+    /// Stop a user from sending or editing messages, while still letting them read
+    Mute {
+        user: UserId,
+        restriction: Restriction,
+    },
+    Unmute(UserId),
     SearchUser {
         name: String,
     },
@@ -45,6 +62,37 @@ pub enum AdminRequest {
         status: ReportStatus,
     },
     SetAccountsCompromised(SetCompromisedType),
+    /// Reload the server's config file
+    ReloadConfig,
+    SearchAuditLog(AuditLogCriteria),
+    /// Assign a report to oneself, so that other administrators know it is being handled
+    ClaimReport(i32),
+    UnclaimReport(i32),
+    /// Add a note to a report, which only administrators can see
+    AddReportNote {
+        id: i32,
+        text: String,
+    },
+    /// Accept a report, taking the given actions against the reported user and message
+    ResolveReport {
+        id: i32,
+        ban_user: bool,
+        delete_message: bool,
+    },
+    /// Get the messages around a reported message, as they were when it was reported
+    GetReportContext(i32),
+    /// Create a token that lets someone register while registration is invite-only
+    CreateRegistrationToken {
+        expiration_datetime: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
+    },
+    ListRegistrationTokens,
+    RevokeRegistrationToken(String),
+    /// List the users who have registered but are waiting to be approved
+    ListPendingUsers,
+    ApproveUser(UserId),
+    /// Reject a user who is waiting to be approved, deleting their account
+    RejectUser(UserId),
 }
 
 impl From<AdminRequest> for proto::requests::administration::AdminRequest {
@@ -61,8 +109,9 @@ impl From<AdminRequest> for proto::requests::administration::AdminRequest {
             Demote(user) => Request::DemoteUser(request::Demote {
                 user: Some(user.into()),
             }),
-            Ban(user) => Request::BanUser(request::Ban {
+            Ban { user, restriction } => Request::BanUser(request::Ban {
                 user: Some(user.into()),
+                restriction: Some(restriction.into()),
             }),
             Unban(user) => Request::UnbanUser(request::Unban {
                 user: Some(user.into()),
@@ -81,6 +130,47 @@ impl From<AdminRequest> for proto::requests::administration::AdminRequest {
             SetAccountsCompromised(typ) => Request::SetAccountsCompromised(
                 request::SetCompromisedType::from(typ) as i32
             ),
+            ReloadConfig => Request::ReloadConfig(proto::types::None {}),
+            SearchAuditLog(criteria) => Request::SearchAuditLog(criteria.into()),
+            ClaimReport(id) => Request::ClaimReport(id),
+            UnclaimReport(id) => Request::UnclaimReport(id),
+            AddReportNote { id, text } => {
+                Request::AddReportNote(request::AddReportNote { id, text })
+            }
+            ResolveReport {
+                id,
+                ban_user,
+                delete_message,
+            } => Request::ResolveReport(request::ResolveReport {
+                id,
+                ban_user,
+                delete_message,
+            }),
+            GetReportContext(id) => Request::GetReportContext(id),
+            Mute { user, restriction } => Request::MuteUser(request::Mute {
+                user: Some(user.into()),
+                restriction: Some(restriction.into()),
+            }),
+            Unmute(user) => Request::UnmuteUser(request::Unmute {
+                user: Some(user.into()),
+            }),
+            CreateRegistrationToken {
+                expiration_datetime,
+                max_uses,
+            } => {
+                use request::create_registration_token::{ExpirationDate, MaxUses};
+
+                Request::CreateRegistrationToken(request::CreateRegistrationToken {
+                    expiration_date: expiration_datetime
+                        .map(|x| ExpirationDate::ExpirationTimestamp(x.timestamp())),
+                    max_uses: max_uses.map(MaxUses::MaxUsesPresent),
+                })
+            }
+            ListRegistrationTokens => Request::ListRegistrationTokens(proto::types::None {}),
+            RevokeRegistrationToken(token) => Request::RevokeRegistrationToken(token),
+            ListPendingUsers => Request::ListPendingUsers(proto::types::None {}),
+            ApproveUser(user) => Request::ApproveUser(user.into()),
+            RejectUser(user) => Request::RejectUser(user.into()),
         };
 
         proto::requests::administration::AdminRequest {
@@ -103,7 +193,10 @@ impl TryFrom<proto::requests::administration::AdminRequest> for AdminRequest {
                 permissions: AdminPermissionFlags::from_bits_truncate(promote.permissions_flags),
             },
             DemoteUser(demote) => AdminRequest::Demote(demote.user?.try_into()?),
-            BanUser(ban) => AdminRequest::Ban(ban.user?.try_into()?),
+            BanUser(ban) => AdminRequest::Ban {
+                user: ban.user?.try_into()?,
+                restriction: ban.restriction.map(Into::into).unwrap_or_default(),
+            },
             UnbanUser(unban) => AdminRequest::Unban(unban.user?.try_into()?),
             UnlockUser(unlock) => AdminRequest::Unlock(unlock.user?.try_into()?),
             SearchUser(search) => AdminRequest::SearchUser { name: search.name },
@@ -119,18 +212,66 @@ impl TryFrom<proto::requests::administration::AdminRequest> for AdminRequest {
                     .ok_or(DeserializeError::InvalidEnumVariant)?;
                 AdminRequest::SetAccountsCompromised(typ.try_into()?)
             },
+            ReloadConfig(_) => AdminRequest::ReloadConfig,
+            SearchAuditLog(criteria) => AdminRequest::SearchAuditLog(criteria.try_into()?),
+            ClaimReport(id) => AdminRequest::ClaimReport(id),
+            UnclaimReport(id) => AdminRequest::UnclaimReport(id),
+            AddReportNote(note) => AdminRequest::AddReportNote {
+                id: note.id,
+                text: note.text,
+            },
+            ResolveReport(resolve) => AdminRequest::ResolveReport {
+                id: resolve.id,
+                ban_user: resolve.ban_user,
+                delete_message: resolve.delete_message,
+            },
+            GetReportContext(id) => AdminRequest::GetReportContext(id),
+            MuteUser(mute) => AdminRequest::Mute {
+                user: mute.user?.try_into()?,
+                restriction: mute.restriction?.into(),
+            },
+            UnmuteUser(unmute) => AdminRequest::Unmute(unmute.user?.try_into()?),
+            CreateRegistrationToken(create) => {
+                use proto::requests::administration::create_registration_token::{
+                    ExpirationDate, MaxUses,
+                };
+
+                AdminRequest::CreateRegistrationToken {
+                    expiration_datetime: create.expiration_date.map(
+                        |ExpirationDate::ExpirationTimestamp(x)| {
+                            let dt = &NaiveDateTime::from_timestamp(x, 0);
+                            Utc.from_utc_datetime(dt)
+                        },
+                    ),
+                    max_uses: create.max_uses.map(|MaxUses::MaxUsesPresent(x)| x),
+                }
+            }
+            ListRegistrationTokens(_) => AdminRequest::ListRegistrationTokens,
+            RevokeRegistrationToken(token) => AdminRequest::RevokeRegistrationToken(token),
+            ListPendingUsers(_) => AdminRequest::ListPendingUsers,
+            ApproveUser(user) => AdminRequest::ApproveUser(user.try_into()?),
+            RejectUser(user) => AdminRequest::RejectUser(user.try_into()?),
         };
 
         Ok(req)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[non_exhaustive]
 pub enum AdminResponse {
     SearchedUsers(Vec<ServerUser>),
     Admins(Vec<Admin>),
     Reports(Vec<Report>),
+    AuditLog(Vec<AuditLogEntry>),
+    /// Oldest first
+    ReportContext(Vec<ReportContextMessage>),
+    RegistrationToken(RegistrationToken),
+    /// Newest first
+    RegistrationTokens(Vec<RegistrationToken>),
+    /// Oldest first, so that those who have waited longest are at the top
+    PendingUsers(Vec<PendingUser>),
 }
 
 impl From<AdminResponse> for proto::requests::administration::AdminResponse {
@@ -152,6 +293,23 @@ impl From<AdminResponse> for proto::requests::administration::AdminResponse {
                 let reports = reports.into_iter().map(Into::into).collect();
                 Response::Reports(request::Reports { reports })
             }
+            AuditLog(entries) => {
+                let entries = entries.into_iter().map(Into::into).collect();
+                Response::AuditLog(request::AuditLog { entries })
+            }
+            ReportContext(messages) => {
+                let messages = messages.into_iter().map(Into::into).collect();
+                Response::ReportContext(request::ReportContext { messages })
+            }
+            RegistrationToken(token) => Response::RegistrationToken(token.into()),
+            RegistrationTokens(tokens) => {
+                let tokens = tokens.into_iter().map(Into::into).collect();
+                Response::RegistrationTokens(request::RegistrationTokens { tokens })
+            }
+            PendingUsers(users) => {
+                let users = users.into_iter().map(Into::into).collect();
+                Response::PendingUsers(request::PendingUsers { users })
+            }
         };
 
         proto::requests::administration::AdminResponse {
@@ -184,13 +342,34 @@ impl TryFrom<proto::requests::administration::AdminResponse> for AdminResponse {
                 let admins: Vec<Report> = res?;
                 AdminResponse::Reports(admins)
             }
+            AuditLog(log) => {
+                let res: Result<_, _> = log.entries.into_iter().map(TryInto::try_into).collect();
+                let entries: Vec<AuditLogEntry> = res?;
+                AdminResponse::AuditLog(entries)
+            }
+            ReportContext(context) => {
+                let res: Result<_, _> = context.messages.into_iter().map(TryInto::try_into).collect();
+                let messages: Vec<ReportContextMessage> = res?;
+                AdminResponse::ReportContext(messages)
+            }
+            RegistrationToken(token) => AdminResponse::RegistrationToken(token.try_into()?),
+            RegistrationTokens(tokens) => {
+                let res: Result<_, _> = tokens.tokens.into_iter().map(TryInto::try_into).collect();
+                let tokens: Vec<self::RegistrationToken> = res?;
+                AdminResponse::RegistrationTokens(tokens)
+            }
+            PendingUsers(users) => {
+                let res: Result<_, _> = users.users.into_iter().map(TryInto::try_into).collect();
+                let users: Vec<PendingUser> = res?;
+                AdminResponse::PendingUsers(users)
+            }
         };
 
         Ok(res)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerUser {
     pub username: String,
     pub display_name: String,
@@ -233,7 +412,7 @@ impl TryFrom<proto::requests::administration::ServerUser> for ServerUser {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Admin {
     pub username: String,
     pub id: UserId,
@@ -264,7 +443,108 @@ impl TryFrom<proto::requests::administration::Admin> for Admin {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// A token which lets someone register an account while registration is invite-only
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationToken {
+    pub token: String,
+    /// Unset if the creator has since been deleted, or if it was created from the command line
+    pub creator: Option<ReportUser>,
+    pub created_at: DateTime<Utc>,
+    pub expiration_date: Option<DateTime<Utc>>,
+    /// How many accounts may be registered with the token. Unlimited if unset.
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+impl From<RegistrationToken> for proto::requests::administration::RegistrationToken {
+    fn from(token: RegistrationToken) -> Self {
+        use proto::requests::administration as admin_proto;
+        use admin_proto::registration_token::{ExpirationDate, MaxUses};
+
+        admin_proto::RegistrationToken {
+            token: token.token,
+            creator: token.creator.map(|creator| {
+                admin_proto::ReportUser {
+                    id: Some(creator.id.into()),
+                    username: creator.username,
+                }
+            }),
+            created_at: token.created_at.timestamp(),
+            expiration_date: token
+                .expiration_date
+                .map(|x| ExpirationDate::ExpirationTimestamp(x.timestamp())),
+            max_uses: token.max_uses.map(MaxUses::MaxUsesPresent),
+            uses: token.uses,
+        }
+    }
+}
+
+impl TryFrom<proto::requests::administration::RegistrationToken> for RegistrationToken {
+    type Error = DeserializeError;
+
+    fn try_from(
+        token: proto::requests::administration::RegistrationToken
+    ) -> Result<Self, DeserializeError> {
+        use proto::requests::administration::registration_token::{ExpirationDate, MaxUses};
+
+        let created_at = &NaiveDateTime::from_timestamp(token.created_at, 0);
+        Ok(RegistrationToken {
+            token: token.token,
+            creator: token.creator.map::<Result<_, DeserializeError>, _>(|creator| {
+                Ok(ReportUser {
+                    id: creator.id?.try_into()?,
+                    username: creator.username,
+                })
+            }).transpose()?,
+            created_at: Utc.from_utc_datetime(&created_at),
+            expiration_date: token.expiration_date.map(|ExpirationDate::ExpirationTimestamp(x)| {
+                let dt = &NaiveDateTime::from_timestamp(x, 0);
+                Utc.from_utc_datetime(dt)
+            }),
+            max_uses: token.max_uses.map(|MaxUses::MaxUsesPresent(x)| x),
+            uses: token.uses,
+        })
+    }
+}
+
+/// A user who has registered, but cannot log in until an administrator approves them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingUser {
+    pub id: UserId,
+    pub username: String,
+    pub display_name: String,
+    pub registered_at: DateTime<Utc>,
+}
+
+impl From<PendingUser> for proto::requests::administration::PendingUser {
+    fn from(user: PendingUser) -> Self {
+        proto::requests::administration::PendingUser {
+            id: Some(user.id.into()),
+            username: user.username,
+            display_name: user.display_name,
+            registered_at: user.registered_at.timestamp(),
+        }
+    }
+}
+
+impl TryFrom<proto::requests::administration::PendingUser> for PendingUser {
+    type Error = DeserializeError;
+
+    fn try_from(
+        user: proto::requests::administration::PendingUser
+    ) -> Result<Self, DeserializeError> {
+        let registered_at = &NaiveDateTime::from_timestamp(user.registered_at, 0);
+        Ok(PendingUser {
+            id: user.id?.try_into()?,
+            username: user.username,
+            display_name: user.display_name,
+            registered_at: Utc.from_utc_datetime(&registered_at),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[repr(i8)]
 pub enum ReportStatus {
     Opened = 0,
@@ -305,43 +585,191 @@ impl fmt::Display for ReportStatus {
     }
 }
 
-#[derive(Debug, Clone)]
+/// What kind of thing a report is about
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i8)]
+pub enum ReportKind {
+    Message = 0,
+    User = 1,
+    Room = 2,
+    Community = 3,
+}
+
+pub struct InvalidReportKind;
+
+impl From<InvalidReportKind> for DeserializeError {
+    fn from(_: InvalidReportKind) -> DeserializeError {
+        DeserializeError::InvalidEnumVariant
+    }
+}
+
+impl TryFrom<i8> for ReportKind {
+    type Error = InvalidReportKind;
+
+    fn try_from(c: i8) -> Result<Self, Self::Error> {
+        match c {
+            0 => Ok(ReportKind::Message),
+            1 => Ok(ReportKind::User),
+            2 => Ok(ReportKind::Room),
+            3 => Ok(ReportKind::Community),
+            _ => Err(InvalidReportKind),
+        }
+    }
+}
+
+impl fmt::Display for ReportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let desc = match self {
+            ReportKind::Message => "message",
+            ReportKind::User => "user",
+            ReportKind::Room => "room",
+            ReportKind::Community => "community",
+        };
+
+        f.write_str(desc)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReportUser {
     pub id: UserId,
     pub username: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReportMessage {
     pub id: Option<MessageId>,
     pub sent_at: DateTime<Utc>,
     pub text: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReportRoom {
     pub id: RoomId,
     pub name: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReportCommunity {
     pub id: CommunityId,
     pub name: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Report {
     pub id: i32,
     pub reporter: Option<ReportUser>,
-    pub reported: ReportUser,
-    pub message: ReportMessage,
+    pub kind: ReportKind,
+    /// The reported user, or the author of the reported message. Unset for reports of rooms and
+    /// communities.
+    pub reported: Option<ReportUser>,
+    /// Only set for reports of messages
+    pub message: Option<ReportMessage>,
     pub room: Option<ReportRoom>,
     pub community: Option<ReportCommunity>,
     pub datetime: DateTime<Utc>,
     pub short_desc: String,
     pub extended_desc: String,
     pub status: ReportStatus,
+    /// The administrator who has claimed the report, if any
+    pub assignee: Option<ReportUser>,
+    /// Oldest first
+    pub notes: Vec<ReportNote>,
+    /// The reported profile, room or community as it was at the time of the report, since it may
+    /// have been changed since. Empty for reports of messages, whose text is kept instead.
+    pub snapshot: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportNote {
+    pub author: Option<ReportUser>,
+    pub datetime: DateTime<Utc>,
+    pub text: String,
+}
+
+impl From<ReportNote> for proto::requests::administration::ReportNote {
+    fn from(note: ReportNote) -> Self {
+        use proto::requests::administration as proto;
+        proto::ReportNote {
+            author: note.author.map(|author| {
+                proto::ReportUser {
+                    id: Some(author.id.into()),
+                    username: author.username,
+                }
+            }),
+            datetime: note.datetime.timestamp(),
+            text: note.text,
+        }
+    }
+}
+
+impl TryFrom<proto::requests::administration::ReportNote> for ReportNote {
+    type Error = DeserializeError;
+
+    fn try_from(
+        note: proto::requests::administration::ReportNote
+    ) -> Result<Self, DeserializeError> {
+        let dt = &NaiveDateTime::from_timestamp(note.datetime, 0);
+        Ok(ReportNote {
+            author: note.author.map::<Result<_, DeserializeError>, _>(|author| {
+                Ok(ReportUser {
+                    id: author.id?.try_into()?,
+                    username: author.username,
+                })
+            }).transpose()?,
+            datetime: Utc.from_utc_datetime(&dt),
+            text: note.text,
+        })
+    }
+}
+
+/// A message from around a reported message, as it was when the report was made
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportContextMessage {
+    pub author: Option<ReportUser>,
+    pub sent_at: DateTime<Utc>,
+    pub text: String,
+    /// Whether this is the message which was reported
+    pub reported: bool,
+}
+
+impl From<ReportContextMessage> for proto::requests::administration::ReportContextMessage {
+    fn from(message: ReportContextMessage) -> Self {
+        use proto::requests::administration as proto;
+        proto::ReportContextMessage {
+            author: message.author.map(|author| {
+                proto::ReportUser {
+                    id: Some(author.id.into()),
+                    username: author.username,
+                }
+            }),
+            sent_at: message.sent_at.timestamp(),
+            text: message.text,
+            reported: message.reported,
+        }
+    }
+}
+
+impl TryFrom<proto::requests::administration::ReportContextMessage> for ReportContextMessage {
+    type Error = DeserializeError;
+
+    fn try_from(
+        message: proto::requests::administration::ReportContextMessage
+    ) -> Result<Self, DeserializeError> {
+        let sent_at = &NaiveDateTime::from_timestamp(message.sent_at, 0);
+        Ok(ReportContextMessage {
+            author: message.author.map::<Result<_, DeserializeError>, _>(|author| {
+                Ok(ReportUser {
+                    id: author.id?.try_into()?,
+                    username: author.username,
+                })
+            }).transpose()?,
+            sent_at: Utc.from_utc_datetime(&sent_at),
+            text: message.text,
+            reported: message.reported,
+        })
+    }
 }
 
 impl PartialEq<Report> for Report {
@@ -361,14 +789,18 @@ impl From<Report> for proto::requests::administration::Report {
                     username: reporter.username,
                 }
             }),
-            reported: Some(proto::ReportUser {
-                id: Some(report.reported.id.into()),
-                username: report.reported.username,
+            reported: report.reported.map(|reported| {
+                proto::ReportUser {
+                    id: Some(reported.id.into()),
+                    username: reported.username,
+                }
             }),
-            message: Some(proto::ReportMessage {
-                id: report.message.id.map(Into::into),
-                text: report.message.text,
-                sent_at: report.message.sent_at.timestamp(),
+            message: report.message.map(|message| {
+                proto::ReportMessage {
+                    id: message.id.map(Into::into),
+                    text: message.text,
+                    sent_at: message.sent_at.timestamp(),
+                }
             }),
             room: report.room.map(|room| {
                 proto::ReportRoom {
@@ -386,6 +818,15 @@ impl From<Report> for proto::requests::administration::Report {
             short_desc: report.short_desc,
             extended_desc: report.extended_desc,
             status: report.status as i8 as u32,
+            assignee: report.assignee.map(|assignee| {
+                proto::ReportUser {
+                    id: Some(assignee.id.into()),
+                    username: assignee.username,
+                }
+            }),
+            notes: report.notes.into_iter().map(Into::into).collect(),
+            kind: report.kind as i8 as u32,
+            snapshot: report.snapshot,
         }
     }
 }
@@ -397,7 +838,6 @@ impl TryFrom<proto::requests::administration::Report> for Report {
         report: proto::requests::administration::Report
     ) -> Result<Self, DeserializeError> {
         let dt = &NaiveDateTime::from_timestamp(report.datetime, 0);
-        let sent_at = &NaiveDateTime::from_timestamp(report.message.clone()?.sent_at, 0);
         Ok(Report {
             id: report.id,
             reporter: report.reporter.map::<Result<_, DeserializeError>, _>(|reporter| {
@@ -406,15 +846,21 @@ impl TryFrom<proto::requests::administration::Report> for Report {
                     username: reporter.username,
                 })
             }).transpose()?,
-            reported: ReportUser {
-                id: report.reported.clone()?.id?.try_into()?,
-                username: report.reported?.username,
-            },
-            message: ReportMessage {
-                id: report.message.clone()?.id.map(TryInto::try_into).transpose()?,
-                text: report.message?.text,
-                sent_at: Utc.from_utc_datetime(&sent_at),
-            },
+            kind: ReportKind::try_from(i8::try_from(report.kind)?)?,
+            reported: report.reported.map::<Result<_, DeserializeError>, _>(|reported| {
+                Ok(ReportUser {
+                    id: reported.id?.try_into()?,
+                    username: reported.username,
+                })
+            }).transpose()?,
+            message: report.message.map::<Result<_, DeserializeError>, _>(|message| {
+                let sent_at = &NaiveDateTime::from_timestamp(message.sent_at, 0);
+                Ok(ReportMessage {
+                    id: message.id.map(TryInto::try_into).transpose()?,
+                    text: message.text,
+                    sent_at: Utc.from_utc_datetime(&sent_at),
+                })
+            }).transpose()?,
             room: report.room.map::<Result<_, DeserializeError>, _>(|room| {
                 Ok(ReportRoom {
                     id: room.id?.try_into()?,
@@ -431,11 +877,19 @@ impl TryFrom<proto::requests::administration::Report> for Report {
             short_desc: report.short_desc,
             extended_desc: report.extended_desc,
             status: ReportStatus::try_from(i8::try_from(report.status)?)?,
+            assignee: report.assignee.map::<Result<_, DeserializeError>, _>(|assignee| {
+                Ok(ReportUser {
+                    id: assignee.id?.try_into()?,
+                    username: assignee.username,
+                })
+            }).transpose()?,
+            notes: report.notes.into_iter().map(TryInto::try_into).collect::<Result<_, _>>()?,
+            snapshot: report.snapshot,
         })
     }
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SearchCriteria {
     pub words: String,
     pub of_user: Option<String>,
@@ -445,6 +899,7 @@ pub struct SearchCriteria {
     pub in_community: Option<String>,
     pub in_room: Option<String>,
     pub status: Option<ReportStatus>,
+    pub kind: Option<ReportKind>,
 }
 
 impl TryFrom<proto::requests::administration::SearchCriteria> for SearchCriteria {
@@ -454,7 +909,7 @@ impl TryFrom<proto::requests::administration::SearchCriteria> for SearchCriteria
         c: proto::requests::administration::SearchCriteria
     ) -> Result<SearchCriteria, DeserializeError> {
         use proto::requests::administration::search_criteria::{
-            OfUser, ByUser, BeforeDate, AfterDate, InCommunity, InRoom, Status
+            OfUser, ByUser, BeforeDate, AfterDate, InCommunity, InRoom, Status, Kind
         };
 
         Ok(SearchCriteria {
@@ -473,7 +928,10 @@ impl TryFrom<proto::requests::administration::SearchCriteria> for SearchCriteria
             in_room: c.in_room.map(|InRoom::InRoomPresent(x)| x),
             status: c.status.map::<Result<_, DeserializeError>, _>(|Status::StatusCode(x)| {
                 Ok(ReportStatus::try_from(i8::try_from(x)?)?)
-            }).transpose()?
+            }).transpose()?,
+            kind: c.kind.map::<Result<_, DeserializeError>, _>(|Kind::KindCode(x)| {
+                Ok(ReportKind::try_from(i8::try_from(x)?)?)
+            }).transpose()?,
         })
     }
 }
@@ -481,7 +939,7 @@ impl TryFrom<proto::requests::administration::SearchCriteria> for SearchCriteria
 impl From<SearchCriteria> for proto::requests::administration::SearchCriteria {
     fn from(c: SearchCriteria) -> Self {
         use proto::requests::administration::search_criteria::{
-            OfUser, ByUser, BeforeDate, AfterDate, InCommunity, InRoom, Status
+            OfUser, ByUser, BeforeDate, AfterDate, InCommunity, InRoom, Status, Kind
         };
 
         proto::requests::administration::SearchCriteria {
@@ -492,12 +950,14 @@ impl From<SearchCriteria> for proto::requests::administration::SearchCriteria {
             after_date: c.after_date.map(|x| AfterDate::AfterTimestamp(x.timestamp())),
             in_community: c.in_community.map(InCommunity::InCommunityPresent),
             in_room: c.in_room.map(InRoom::InRoomPresent),
-            status: c.status.map(|x| Status::StatusCode(x as i8 as u32))
+            status: c.status.map(|x| Status::StatusCode(x as i8 as u32)),
+            kind: c.kind.map(|x| Kind::KindCode(x as i8 as u32)),
         }
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SetCompromisedType {
     All,
     OldHashes,
@@ -526,3 +986,302 @@ impl From<SetCompromisedType> for proto::requests::administration::SetCompromise
         }
     }
 }
+
+/// An action recorded in the audit log, taken by a server administrator or by a moderator of a
+/// community
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i8)]
+pub enum AuditAction {
+    Ban = 0,
+    Unban = 1,
+    Lock = 2,
+    Unlock = 3,
+    Promote = 4,
+    Demote = 5,
+    SetReportStatus = 6,
+    SetAccountsCompromised = 7,
+    ReloadConfig = 8,
+    ResetPassword = 9,
+    RevokeTokens = 10,
+    DeleteCommunity = 11,
+    ImportCommunity = 12,
+    ExportCommunity = 13,
+    ChangeCommunityName = 14,
+    ChangeCommunityDescription = 15,
+    SetRetention = 16,
+    InstallBot = 17,
+    CreateWebhook = 18,
+    RevokeWebhook = 19,
+    CreateOutgoingWebhook = 20,
+    DeleteOutgoingWebhook = 21,
+    DeleteMessage = 22,
+    Mute = 23,
+    Unmute = 24,
+    AddAutomodRule = 25,
+    DeleteAutomodRule = 26,
+    SetRoomSettings = 27,
+    RevokeInvite = 28,
+    SetCommunityPublic = 29,
+    CreateRegistrationToken = 30,
+    RevokeRegistrationToken = 31,
+    ApproveUser = 32,
+    RejectUser = 33,
+}
+
+impl AuditAction {
+    pub const ALL: &'static [AuditAction] = &[
+        AuditAction::Ban,
+        AuditAction::Unban,
+        AuditAction::Lock,
+        AuditAction::Unlock,
+        AuditAction::Promote,
+        AuditAction::Demote,
+        AuditAction::SetReportStatus,
+        AuditAction::SetAccountsCompromised,
+        AuditAction::ReloadConfig,
+        AuditAction::ResetPassword,
+        AuditAction::RevokeTokens,
+        AuditAction::DeleteCommunity,
+        AuditAction::ImportCommunity,
+        AuditAction::ExportCommunity,
+        AuditAction::ChangeCommunityName,
+        AuditAction::ChangeCommunityDescription,
+        AuditAction::SetRetention,
+        AuditAction::InstallBot,
+        AuditAction::CreateWebhook,
+        AuditAction::RevokeWebhook,
+        AuditAction::CreateOutgoingWebhook,
+        AuditAction::DeleteOutgoingWebhook,
+        AuditAction::DeleteMessage,
+        AuditAction::Mute,
+        AuditAction::Unmute,
+        AuditAction::AddAutomodRule,
+        AuditAction::DeleteAutomodRule,
+        AuditAction::SetRoomSettings,
+        AuditAction::RevokeInvite,
+        AuditAction::SetCommunityPublic,
+        AuditAction::CreateRegistrationToken,
+        AuditAction::RevokeRegistrationToken,
+        AuditAction::ApproveUser,
+        AuditAction::RejectUser,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditAction::Ban => "ban",
+            AuditAction::Unban => "unban",
+            AuditAction::Lock => "lock",
+            AuditAction::Unlock => "unlock",
+            AuditAction::Promote => "promote",
+            AuditAction::Demote => "demote",
+            AuditAction::SetReportStatus => "set_report_status",
+            AuditAction::SetAccountsCompromised => "set_accounts_compromised",
+            AuditAction::ReloadConfig => "reload_config",
+            AuditAction::ResetPassword => "reset_password",
+            AuditAction::RevokeTokens => "revoke_tokens",
+            AuditAction::DeleteCommunity => "delete_community",
+            AuditAction::ImportCommunity => "import_community",
+            AuditAction::ExportCommunity => "export_community",
+            AuditAction::ChangeCommunityName => "change_community_name",
+            AuditAction::ChangeCommunityDescription => "change_community_description",
+            AuditAction::SetRetention => "set_retention",
+            AuditAction::InstallBot => "install_bot",
+            AuditAction::CreateWebhook => "create_webhook",
+            AuditAction::RevokeWebhook => "revoke_webhook",
+            AuditAction::CreateOutgoingWebhook => "create_outgoing_webhook",
+            AuditAction::DeleteOutgoingWebhook => "delete_outgoing_webhook",
+            AuditAction::DeleteMessage => "delete_message",
+            AuditAction::Mute => "mute",
+            AuditAction::Unmute => "unmute",
+            AuditAction::AddAutomodRule => "add_automod_rule",
+            AuditAction::DeleteAutomodRule => "delete_automod_rule",
+            AuditAction::SetRoomSettings => "set_room_settings",
+            AuditAction::RevokeInvite => "revoke_invite",
+            AuditAction::SetCommunityPublic => "set_community_public",
+            AuditAction::CreateRegistrationToken => "create_registration_token",
+            AuditAction::RevokeRegistrationToken => "revoke_registration_token",
+            AuditAction::ApproveUser => "approve_user",
+            AuditAction::RejectUser => "reject_user",
+        }
+    }
+}
+
+pub struct InvalidAuditAction;
+
+impl From<InvalidAuditAction> for DeserializeError {
+    fn from(_: InvalidAuditAction) -> DeserializeError {
+        DeserializeError::InvalidEnumVariant
+    }
+}
+
+impl TryFrom<i8> for AuditAction {
+    type Error = InvalidAuditAction;
+
+    fn try_from(c: i8) -> Result<Self, Self::Error> {
+        AuditAction::ALL
+            .iter()
+            .find(|action| **action as i8 == c)
+            .copied()
+            .ok_or(InvalidAuditAction)
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = InvalidAuditAction;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AuditAction::ALL
+            .iter()
+            .find(|action| action.name() == s)
+            .copied()
+            .ok_or(InvalidAuditAction)
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub datetime: DateTime<Utc>,
+    /// Unset for actions taken from the server's command line
+    pub actor: Option<ReportUser>,
+    pub action: AuditAction,
+    pub target_user: Option<ReportUser>,
+    pub community: Option<ReportCommunity>,
+    /// Anything else that the action was taken on, e.g a report or webhook ID
+    pub target: Option<String>,
+    /// The parameters of the action as JSON, e.g the permissions that a user was promoted to
+    pub parameters: String,
+}
+
+impl From<AuditLogEntry> for proto::requests::administration::AuditLogEntry {
+    fn from(entry: AuditLogEntry) -> Self {
+        use proto::requests::administration as proto;
+
+        proto::AuditLogEntry {
+            id: entry.id,
+            datetime: entry.datetime.timestamp(),
+            actor: entry.actor.map(|actor| {
+                proto::ReportUser {
+                    id: Some(actor.id.into()),
+                    username: actor.username,
+                }
+            }),
+            action: entry.action as i8 as u32,
+            target_user: entry.target_user.map(|user| {
+                proto::ReportUser {
+                    id: Some(user.id.into()),
+                    username: user.username,
+                }
+            }),
+            community: entry.community.map(|community| {
+                proto::ReportCommunity {
+                    id: Some(community.id.into()),
+                    name: community.name,
+                }
+            }),
+            target: entry.target.map(proto::audit_log_entry::Target::TargetPresent),
+            parameters: entry.parameters,
+        }
+    }
+}
+
+impl TryFrom<proto::requests::administration::AuditLogEntry> for AuditLogEntry {
+    type Error = DeserializeError;
+
+    fn try_from(
+        entry: proto::requests::administration::AuditLogEntry
+    ) -> Result<Self, DeserializeError> {
+        use proto::requests::administration::audit_log_entry::Target;
+
+        let dt = &NaiveDateTime::from_timestamp(entry.datetime, 0);
+        Ok(AuditLogEntry {
+            id: entry.id,
+            datetime: Utc.from_utc_datetime(&dt),
+            actor: entry.actor.map::<Result<_, DeserializeError>, _>(|actor| {
+                Ok(ReportUser {
+                    id: actor.id?.try_into()?,
+                    username: actor.username,
+                })
+            }).transpose()?,
+            action: AuditAction::try_from(i8::try_from(entry.action)?)?,
+            target_user: entry.target_user.map::<Result<_, DeserializeError>, _>(|user| {
+                Ok(ReportUser {
+                    id: user.id?.try_into()?,
+                    username: user.username,
+                })
+            }).transpose()?,
+            community: entry.community.map::<Result<_, DeserializeError>, _>(|community| {
+                Ok(ReportCommunity {
+                    id: community.id?.try_into()?,
+                    name: community.name,
+                })
+            }).transpose()?,
+            target: entry.target.map(|Target::TargetPresent(x)| x),
+            parameters: entry.parameters,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditLogCriteria {
+    /// The username of the user who took the action
+    pub actor: Option<String>,
+    pub target_user: Option<String>,
+    pub action: Option<AuditAction>,
+    pub community: Option<String>,
+    pub before_date: Option<DateTime<Utc>>,
+    pub after_date: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<proto::requests::administration::AuditLogCriteria> for AuditLogCriteria {
+    type Error = DeserializeError;
+
+    fn try_from(
+        c: proto::requests::administration::AuditLogCriteria
+    ) -> Result<AuditLogCriteria, DeserializeError> {
+        use proto::requests::administration::audit_log_criteria::{
+            Actor, TargetUser, Action, Community, BeforeDate, AfterDate
+        };
+
+        Ok(AuditLogCriteria {
+            actor: c.actor.map(|Actor::ActorPresent(x)| x),
+            target_user: c.target_user.map(|TargetUser::TargetUserPresent(x)| x),
+            action: c.action.map::<Result<_, DeserializeError>, _>(|Action::ActionCode(x)| {
+                Ok(AuditAction::try_from(i8::try_from(x)?)?)
+            }).transpose()?,
+            community: c.community.map(|Community::CommunityPresent(x)| x),
+            before_date: c.before_date.map(|BeforeDate::BeforeTimestamp(x)| {
+                let dt = &NaiveDateTime::from_timestamp(x, 0);
+                Utc.from_utc_datetime(dt)
+            }),
+            after_date: c.after_date.map(|AfterDate::AfterTimestamp(x)| {
+                let dt = &NaiveDateTime::from_timestamp(x, 0);
+                Utc.from_utc_datetime(dt)
+            }),
+        })
+    }
+}
+
+impl From<AuditLogCriteria> for proto::requests::administration::AuditLogCriteria {
+    fn from(c: AuditLogCriteria) -> Self {
+        use proto::requests::administration::audit_log_criteria::{
+            Actor, TargetUser, Action, Community, BeforeDate, AfterDate
+        };
+
+        proto::requests::administration::AuditLogCriteria {
+            actor: c.actor.map(Actor::ActorPresent),
+            target_user: c.target_user.map(TargetUser::TargetUserPresent),
+            action: c.action.map(|x| Action::ActionCode(x as i8 as u32)),
+            community: c.community.map(Community::CommunityPresent),
+            before_date: c.before_date.map(|x| BeforeDate::BeforeTimestamp(x.timestamp())),
+            after_date: c.after_date.map(|x| AfterDate::AfterTimestamp(x.timestamp())),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use crate::proto;
 use crate::proto::DeserializeError;
-use crate::structures::{Credentials, TokenCreationOptions};
+use crate::structures::{Credentials, Restriction, TokenCreationOptions};
 use crate::types::*;
 use serde::{Deserialize, Serialize};
 use std::convert::{TryFrom, TryInto};
@@ -160,15 +160,20 @@ impl TryFrom<proto::requests::auth::RevokeToken> for RevokeToken {
 pub struct RegisterUser {
     pub credentials: Credentials,
     pub display_name: Option<String>,
+    /// Given out by the server's administrators, for servers where registration is invite-only
+    pub registration_token: Option<String>,
 }
 
 impl From<RegisterUser> for proto::requests::auth::RegisterUser {
     fn from(register: RegisterUser) -> Self {
-        use proto::requests::auth::register_user::DisplayName;
+        use proto::requests::auth::register_user::{DisplayName, RegistrationToken};
 
         proto::requests::auth::RegisterUser {
             credentials: Some(register.credentials.into()),
-            display_name: register.display_name.map(DisplayName::Present)
+            display_name: register.display_name.map(DisplayName::Present),
+            registration_token: register
+                .registration_token
+                .map(RegistrationToken::RegistrationTokenPresent),
         }
     }
 }
@@ -177,18 +182,22 @@ impl TryFrom<proto::requests::auth::RegisterUser> for RegisterUser {
     type Error = DeserializeError;
 
     fn try_from(register: proto::requests::auth::RegisterUser) -> Result<Self, Self::Error> {
-        use proto::requests::auth::register_user::DisplayName;
+        use proto::requests::auth::register_user::{DisplayName, RegistrationToken};
 
         let display_name = register.display_name.map(|DisplayName::Present(x)| x);
+        let registration_token = register
+            .registration_token
+            .map(|RegistrationToken::RegistrationTokenPresent(x)| x);
 
         Ok(RegisterUser {
             credentials: register.credentials?.into(),
             display_name,
+            registration_token,
         })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NewToken {
     pub device: DeviceId,
     pub token: AuthToken,
@@ -291,16 +300,18 @@ impl From<AuthResponse> for proto::requests::auth::AuthResponse {
     fn from(result: AuthResponse) -> Self {
         use proto::requests::auth::auth_response::Response;
 
-        let inner = match result {
-            AuthResponse::Ok(ok) => Response::Ok(ok.into()),
+        let (inner, restriction) = match result {
+            AuthResponse::Ok(ok) => (Response::Ok(ok.into()), None),
             AuthResponse::Err(err) => {
+                let restriction = err.restriction().cloned().map(Into::into);
                 let error: proto::requests::auth::AuthError = err.into();
-                Response::Error(error as i32)
+                (Response::Error(error as i32), restriction)
             }
         };
 
         proto::requests::auth::AuthResponse {
             response: Some(inner),
+            restriction,
         }
     }
 }
@@ -316,7 +327,8 @@ impl TryFrom<proto::requests::auth::AuthResponse> for AuthResponse {
             Response::Error(err) => {
                 let error = proto::requests::auth::AuthError::from_i32(err)
                     .ok_or(DeserializeError::InvalidEnumVariant)?;
-                AuthResponse::Err(error.try_into()?)
+                let restriction = response.restriction.map(Into::into);
+                AuthResponse::Err(AuthError::from_proto(error, restriction)?)
             }
         })
     }
@@ -327,6 +339,8 @@ pub enum AuthOk {
     User(UserId),
     Token(NewToken),
     NoData,
+    /// The account was registered, but cannot be logged into until an administrator approves it
+    PendingApproval(UserId),
 }
 
 impl From<AuthOk> for proto::requests::auth::AuthOk {
@@ -338,6 +352,7 @@ impl From<AuthOk> for proto::requests::auth::AuthOk {
             User(user) => Ok::User(user.into()),
             Token(token) => Ok::Token(token.into()),
             NoData => Ok::NoData(proto::types::None {}),
+            PendingApproval(user) => Ok::PendingApproval(user.into()),
         };
 
         proto::requests::auth::AuthOk { ok: Some(inner) }
@@ -354,11 +369,13 @@ impl TryFrom<proto::requests::auth::AuthOk> for AuthOk {
             User(user) => AuthOk::User(user.try_into()?),
             Token(token) => AuthOk::Token(token.try_into()?),
             NoData(_) => AuthOk::NoData,
+            PendingApproval(user) => AuthOk::PendingApproval(user.try_into()?),
         })
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[non_exhaustive]
 pub enum AuthError {
     Internal,
@@ -369,13 +386,23 @@ pub enum AuthError {
     TokenInUse,
     InvalidUser,
     UserCompromised,
-    UserLocked,
-    UserBanned,
+    UserLocked(Restriction),
+    UserBanned(Restriction),
     UsernameAlreadyExists,
     InvalidUsername,
     InvalidPassword,
     InvalidDisplayName,
     InvalidMessage,
+    /// Too many attempts have been made from this address recently
+    RateLimited,
+    /// The server is not accepting new accounts
+    RegistrationClosed,
+    /// The server only accepts new accounts registered with a registration token
+    RegistrationTokenRequired,
+    /// The registration token does not exist, has expired, or has been used up
+    InvalidRegistrationToken,
+    /// The account has been registered, but an administrator has not approved it yet
+    PendingApproval,
 }
 
 impl fmt::Display for AuthError {
@@ -390,29 +417,58 @@ impl fmt::Display for AuthError {
             TokenInUse => write!(f, "Token is in use"),
             InvalidUser => write!(f, "User invalid"),
             UserCompromised => write!(f, "User compromised"),
-            UserLocked => write!(f, "User locked"),
-            UserBanned => write!(f, "User banned"),
+            UserLocked(lock) => write!(f, "User locked {}", lock),
+            UserBanned(ban) => write!(f, "User banned {}", ban),
             UsernameAlreadyExists => write!(f, "Username already exists"),
             InvalidUsername => write!(f, "Invalid username"),
             InvalidPassword => write!(f, "Invalid password"),
             InvalidDisplayName => write!(f, "Invalid display name"),
             InvalidMessage => write!(f, "Invalid message"),
+            RateLimited => write!(f, "Too many attempts, try again later"),
+            RegistrationClosed => write!(f, "Registration is closed"),
+            RegistrationTokenRequired => write!(f, "A registration token is required"),
+            InvalidRegistrationToken => write!(f, "Invalid registration token"),
+            PendingApproval => write!(f, "Account is awaiting approval"),
+        }
+    }
+}
+
+impl AuthError {
+    /// The ban or lock that the user is under, if that is why they could not log in
+    pub fn restriction(&self) -> Option<&Restriction> {
+        match self {
+            AuthError::UserLocked(restriction) | AuthError::UserBanned(restriction) => {
+                Some(restriction)
+            }
+            _ => None,
         }
     }
 }
 
 macro_rules! convert_to_proto {
-    ($err:ident: { $($variant:ident$(,)?)* }) => {
+    (
+        $err:ident: { $($variant:ident$(,)?)* }
+        restricted: { $($restricted:ident$(,)?)* }
+    ) => {
         match $err {
             $(AuthError::$variant => proto::requests::auth::AuthError::$variant,)*
+            $(AuthError::$restricted(_) => proto::requests::auth::AuthError::$restricted,)*
         }
     };
 }
 
 macro_rules! convert_from_proto {
-    ($err:ident: { $($variant:ident$(,)?)* }) => {
+    (
+        $err:ident, $restriction:ident: { $($variant:ident$(,)?)* }
+        restricted: { $($restricted:ident$(,)?)* }
+    ) => {
         match $err {
             $(proto::requests::auth::AuthError::$variant => Ok(AuthError::$variant),)*
+            $(
+                proto::requests::auth::AuthError::$restricted => {
+                    Ok(AuthError::$restricted($restriction.unwrap_or_default()))
+                }
+            )*
         }
     };
 }
@@ -429,24 +485,33 @@ impl From<AuthError> for proto::requests::auth::AuthError {
                 TokenInUse,
                 InvalidUser,
                 UserCompromised,
-                UserLocked,
-                UserBanned,
                 UsernameAlreadyExists,
                 InvalidUsername,
                 InvalidPassword,
                 InvalidDisplayName,
-                InvalidMessage
+                InvalidMessage,
+                RateLimited,
+                RegistrationClosed,
+                RegistrationTokenRequired,
+                InvalidRegistrationToken,
+                PendingApproval
+            }
+            restricted: {
+                UserLocked,
+                UserBanned
             }
         }
     }
 }
 
-impl TryFrom<proto::requests::auth::AuthError> for AuthError {
-    type Error = DeserializeError;
-
-    fn try_from(err: proto::requests::auth::AuthError) -> Result<Self, Self::Error> {
+impl AuthError {
+    /// The restriction is only sent for errors caused by a ban or lock
+    fn from_proto(
+        err: proto::requests::auth::AuthError,
+        restriction: Option<Restriction>,
+    ) -> Result<Self, DeserializeError> {
         convert_from_proto! {
-            err: {
+            err, restriction: {
                 Internal,
                 WrongEndpoint,
                 IncorrectCredentials,
@@ -455,13 +520,20 @@ impl TryFrom<proto::requests::auth::AuthError> for AuthError {
                 TokenInUse,
                 InvalidUser,
                 UserCompromised,
-                UserLocked,
-                UserBanned,
                 UsernameAlreadyExists,
                 InvalidUsername,
                 InvalidPassword,
                 InvalidDisplayName,
-                InvalidMessage
+                InvalidMessage,
+                RateLimited,
+                RegistrationClosed,
+                RegistrationTokenRequired,
+                InvalidRegistrationToken,
+                PendingApproval
+            }
+            restricted: {
+                UserLocked,
+                UserBanned
             }
         }
     }
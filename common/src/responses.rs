@@ -3,13 +3,15 @@ use std::fmt;
 
 use crate::proto;
 use crate::proto::DeserializeError;
-use crate::requests::AdminResponse;
+use crate::requests::{AdminResponse, NewToken};
 use crate::structures::*;
 use crate::types::*;
+use serde::{Deserialize, Serialize};
 
 pub type ResponseResult = Result<OkResponse, Error>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[non_exhaustive]
 pub enum OkResponse {
     NoData,
@@ -25,6 +27,24 @@ pub enum OkResponse {
     RoomUpdate(RoomUpdate),
     MessageHistory(MessageHistory),
     Admin(AdminResponse),
+    NewToken(NewToken),
+    NewWebhook(NewWebhook),
+    Webhooks(Vec<Webhook>),
+    NewOutgoingWebhook(NewOutgoingWebhook),
+    OutgoingWebhooks(Vec<OutgoingWebhook>),
+    WebhookDeliveries(Vec<WebhookDelivery>),
+    Commands(Vec<RegisteredCommand>),
+    DeviceKeys(Vec<DeviceKeyBundle>),
+    /// The number of one-time prekeys that the device has left
+    RemainingPrekeys(u32),
+    /// An exported community, in the server's archive format
+    CommunityArchive(Vec<u8>),
+    AutomodRule(AutomodRule),
+    AutomodRules(Vec<AutomodRule>),
+    AutomodHits(Vec<AutomodHit>),
+    Invites(Vec<Invite>),
+    InviteUses(Vec<InviteUse>),
+    PublicCommunities(Vec<PublicCommunity>),
 }
 
 impl From<OkResponse> for proto::responses::Ok {
@@ -49,6 +69,48 @@ impl From<OkResponse> for proto::responses::Ok {
             RoomUpdate(update) => Response::RoomUpdate(update.into()),
             MessageHistory(history) => Response::MessageHistory(history.into()),
             Admin(admin) => Response::Admin(admin.into()),
+            OkResponse::NewToken(token) => Response::NewToken(token.into()),
+            OkResponse::NewWebhook(new) => Response::NewWebhook(new.into()),
+            OkResponse::Webhooks(webhooks) => Response::Webhooks(responses::Webhooks {
+                webhooks: webhooks.into_iter().map(Into::into).collect(),
+            }),
+            OkResponse::NewOutgoingWebhook(new) => Response::NewOutgoingWebhook(new.into()),
+            OkResponse::OutgoingWebhooks(webhooks) => {
+                Response::OutgoingWebhooks(responses::OutgoingWebhooks {
+                    webhooks: webhooks.into_iter().map(Into::into).collect(),
+                })
+            }
+            OkResponse::WebhookDeliveries(deliveries) => {
+                Response::WebhookDeliveries(responses::WebhookDeliveries {
+                    deliveries: deliveries.into_iter().map(Into::into).collect(),
+                })
+            }
+            OkResponse::Commands(commands) => Response::Commands(responses::Commands {
+                commands: commands.into_iter().map(Into::into).collect(),
+            }),
+            OkResponse::DeviceKeys(bundles) => Response::DeviceKeys(responses::DeviceKeys {
+                bundles: bundles.into_iter().map(Into::into).collect(),
+            }),
+            RemainingPrekeys(remaining) => Response::RemainingPrekeys(remaining),
+            CommunityArchive(archive) => Response::CommunityArchive(archive),
+            OkResponse::AutomodRule(rule) => Response::AutomodRule(rule.into()),
+            OkResponse::AutomodRules(rules) => Response::AutomodRules(responses::AutomodRules {
+                rules: rules.into_iter().map(Into::into).collect(),
+            }),
+            OkResponse::AutomodHits(hits) => Response::AutomodHits(responses::AutomodHits {
+                hits: hits.into_iter().map(Into::into).collect(),
+            }),
+            OkResponse::Invites(invites) => Response::Invites(responses::Invites {
+                invites: invites.into_iter().map(Into::into).collect(),
+            }),
+            OkResponse::InviteUses(uses) => Response::InviteUses(responses::InviteUses {
+                uses: uses.into_iter().map(Into::into).collect(),
+            }),
+            OkResponse::PublicCommunities(communities) => {
+                Response::PublicCommunities(responses::PublicCommunities {
+                    communities: communities.into_iter().map(Into::into).collect(),
+                })
+            }
         };
 
         proto::responses::Ok {
@@ -61,7 +123,7 @@ impl TryFrom<proto::responses::Ok> for OkResponse {
     type Error = DeserializeError;
 
     fn try_from(ok: proto::responses::Ok) -> Result<Self, Self::Error> {
-        use proto::responses::ok::Response::*;
+        use proto::responses::ok::Response::{self, *};
 
         Ok(match ok.response? {
             NoData(_) => OkResponse::NoData,
@@ -77,11 +139,98 @@ impl TryFrom<proto::responses::Ok> for OkResponse {
             RoomUpdate(update) => OkResponse::RoomUpdate(update.try_into()?),
             MessageHistory(history) => OkResponse::MessageHistory(history.try_into()?),
             Admin(admin) => OkResponse::Admin(admin.try_into()?),
+            Response::NewToken(token) => OkResponse::NewToken(token.try_into()?),
+            NewWebhook(new) => OkResponse::NewWebhook(new.try_into()?),
+            Webhooks(list) => {
+                let webhooks = list
+                    .webhooks
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<Webhook>, DeserializeError>>()?;
+                OkResponse::Webhooks(webhooks)
+            }
+            NewOutgoingWebhook(new) => OkResponse::NewOutgoingWebhook(new.try_into()?),
+            OutgoingWebhooks(list) => {
+                let webhooks = list
+                    .webhooks
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<OutgoingWebhook>, DeserializeError>>()?;
+                OkResponse::OutgoingWebhooks(webhooks)
+            }
+            WebhookDeliveries(list) => {
+                let deliveries = list
+                    .deliveries
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<WebhookDelivery>, DeserializeError>>()?;
+                OkResponse::WebhookDeliveries(deliveries)
+            }
+            Commands(list) => {
+                let commands = list
+                    .commands
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<RegisteredCommand>, DeserializeError>>()?;
+                OkResponse::Commands(commands)
+            }
+            DeviceKeys(list) => {
+                let bundles = list
+                    .bundles
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<DeviceKeyBundle>, DeserializeError>>()?;
+                OkResponse::DeviceKeys(bundles)
+            }
+            RemainingPrekeys(remaining) => OkResponse::RemainingPrekeys(remaining),
+            CommunityArchive(archive) => OkResponse::CommunityArchive(archive),
+            AutomodRule(rule) => OkResponse::AutomodRule(rule.try_into()?),
+            AutomodRules(list) => {
+                let rules = list
+                    .rules
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<_>, DeserializeError>>()?;
+                OkResponse::AutomodRules(rules)
+            }
+            AutomodHits(list) => {
+                let hits = list
+                    .hits
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<_>, DeserializeError>>()?;
+                OkResponse::AutomodHits(hits)
+            }
+            Invites(list) => {
+                let invites = list
+                    .invites
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<_>, DeserializeError>>()?;
+                OkResponse::Invites(invites)
+            }
+            InviteUses(list) => {
+                let uses = list
+                    .uses
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<_>, DeserializeError>>()?;
+                OkResponse::InviteUses(uses)
+            }
+            PublicCommunities(list) => {
+                let communities = list
+                    .communities
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<_>, DeserializeError>>()?;
+                OkResponse::PublicCommunities(communities)
+            }
         })
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[non_exhaustive]
 pub enum Error {
     Internal,
@@ -109,6 +258,44 @@ pub enum Error {
     InvalidMessageSelector,
     MessageTooLong,
     Unimplemented,
+    /// The user already owns the maximum number of bots.
+    TooManyBots,
+    InvalidWebhook,
+    InvalidUrl,
+    /// The command does not exist, or was invoked or declared with invalid arguments
+    InvalidCommand,
+    /// The server's config file could not be reloaded, e.g because it is invalid
+    InvalidConfig,
+    /// Too many requests of this kind have been sent recently
+    RateLimited,
+    /// A federated server could not be reached, or rejected the request
+    RemoteServer,
+    /// The action is not possible in an end-to-end encrypted room, or the message was not
+    /// encrypted correctly for the room
+    EncryptedRoom,
+    /// Messages must be kept for at least a day, and at most 36500 days
+    InvalidRetentionPeriod,
+    /// The community archive is malformed, or is of a version that this server does not support
+    InvalidArchive,
+    InvalidReport,
+    /// The report has already been claimed by another administrator
+    ReportAlreadyClaimed,
+    /// The user has been muted by an administrator or by a community's automod, and so cannot send
+    /// or edit messages
+    Muted(Restriction),
+    /// The message was blocked by one of the community's automod rules
+    AutomodBlocked,
+    /// The automod rule is malformed, e.g its pattern is not a valid regular expression
+    InvalidAutomodRule,
+    /// The room is in slow mode, and the user must wait before sending another message to it
+    SlowMode { retry_after_secs: u32 },
+    /// Only some members may send messages to the room
+    ReadOnlyRoom,
+    /// The room settings are out of range, e.g the slow mode interval is too long
+    InvalidRoomSettings,
+    /// An invite's maximum number of uses must be at least 1
+    InvalidMaxUses,
+    InvalidRegistrationToken,
 }
 
 impl fmt::Display for Error {
@@ -135,22 +322,84 @@ impl fmt::Display for Error {
             TooLong => write!(f, "Text field too long"),
             Unimplemented => write!(f, "Unimplemented API"),
             InvalidMessage => write!(f, "Invalid message (deleted?)"),
+            TooManyBots => write!(f, "Too many bots"),
+            InvalidWebhook => write!(f, "Invalid webhook"),
+            InvalidUrl => write!(f, "Invalid URL"),
+            InvalidCommand => write!(f, "Invalid command"),
+            InvalidConfig => write!(f, "Invalid server config"),
+            RateLimited => write!(f, "Too many requests, try again later"),
+            RemoteServer => write!(f, "Error communicating with remote server"),
+            EncryptedRoom => write!(f, "Not possible in an encrypted room"),
+            InvalidRetentionPeriod => write!(f, "Invalid retention period"),
+            InvalidArchive => write!(f, "Invalid community archive"),
+            InvalidReport => write!(f, "Invalid report"),
+            ReportAlreadyClaimed => write!(f, "Report already claimed by another administrator"),
+            Muted(mute) => write!(f, "Muted {}", mute),
+            AutomodBlocked => write!(f, "Message blocked by the community's automod"),
+            InvalidAutomodRule => write!(f, "Invalid automod rule"),
+            SlowMode { retry_after_secs } => write!(
+                f,
+                "Slow mode is on, try again in {} seconds",
+                retry_after_secs
+            ),
+            ReadOnlyRoom => write!(f, "This room is read-only"),
+            InvalidRoomSettings => write!(f, "Invalid room settings"),
+            InvalidMaxUses => write!(f, "Invalid maximum number of invite uses"),
+            InvalidRegistrationToken => write!(f, "Invalid registration token"),
+        }
+    }
+}
+
+impl Error {
+    /// The mute that the user is under, if that is why the request failed
+    pub fn restriction(&self) -> Option<&Restriction> {
+        match self {
+            Error::Muted(restriction) => Some(restriction),
+            _ => None,
+        }
+    }
+
+    /// How long the user must wait before trying again, if the request failed because of it
+    pub fn retry_after_secs(&self) -> Option<u32> {
+        match self {
+            Error::SlowMode { retry_after_secs } => Some(*retry_after_secs),
+            _ => None,
         }
     }
 }
 
 macro_rules! convert_to_proto {
-    ($err:ident: { $($variant:ident$(,)?)* }) => {
+    (
+        $err:ident: { $($variant:ident$(,)?)* }
+        restricted: { $($restricted:ident$(,)?)* }
+        timed: { $($timed:ident$(,)?)* }
+    ) => {
         match $err {
             $(Error::$variant => proto::responses::Error::$variant,)*
+            $(Error::$restricted(_) => proto::responses::Error::$restricted,)*
+            $(Error::$timed { .. } => proto::responses::Error::$timed,)*
         }
     };
 }
 
 macro_rules! convert_from_proto {
-    ($err:ident: { $($variant:ident$(,)?)* }) => {
+    (
+        $err:ident, $restriction:ident, $retry_after_secs:ident: { $($variant:ident$(,)?)* }
+        restricted: { $($restricted:ident$(,)?)* }
+        timed: { $($timed:ident$(,)?)* }
+    ) => {
         match $err {
             $(proto::responses::Error::$variant => Ok(Error::$variant),)*
+            $(
+                proto::responses::Error::$restricted => {
+                    Ok(Error::$restricted($restriction.unwrap_or_default()))
+                }
+            )*
+            $(
+                proto::responses::Error::$timed => {
+                    Ok(Error::$timed { retry_after_secs: $retry_after_secs })
+                }
+            )*
         }
     };
 }
@@ -179,17 +428,45 @@ impl From<Error> for proto::responses::Error {
                 MessageTooLong,
                 Unimplemented,
                 TooLong,
+                TooManyBots,
+                InvalidWebhook,
+                InvalidUrl,
+                InvalidCommand,
+                InvalidConfig,
+                RateLimited,
+                RemoteServer,
+                EncryptedRoom,
+                InvalidRetentionPeriod,
+                InvalidArchive,
+                InvalidReport,
+                ReportAlreadyClaimed,
+                AutomodBlocked,
+                InvalidAutomodRule,
+                ReadOnlyRoom,
+                InvalidRoomSettings,
+                InvalidMaxUses,
+                InvalidRegistrationToken,
+            }
+            restricted: {
+                Muted,
+            }
+            timed: {
+                SlowMode,
             }
         }
     }
 }
 
-impl TryFrom<proto::responses::Error> for Error {
-    type Error = DeserializeError;
-
-    fn try_from(err: proto::responses::Error) -> Result<Self, Self::Error> {
+impl Error {
+    /// The restriction is only sent for errors caused by a mute, and the time to wait only for
+    /// errors caused by slow mode
+    pub(crate) fn from_proto(
+        err: proto::responses::Error,
+        restriction: Option<Restriction>,
+        retry_after_secs: u32,
+    ) -> Result<Self, DeserializeError> {
         convert_from_proto! {
-            err: {
+            err, restriction, retry_after_secs: {
                 Internal,
                 UsernameAlreadyExists,
                 InvalidUsername,
@@ -210,6 +487,30 @@ impl TryFrom<proto::responses::Error> for Error {
                 MessageTooLong,
                 Unimplemented,
                 TooLong,
+                TooManyBots,
+                InvalidWebhook,
+                InvalidUrl,
+                InvalidCommand,
+                InvalidConfig,
+                RateLimited,
+                RemoteServer,
+                EncryptedRoom,
+                InvalidRetentionPeriod,
+                InvalidArchive,
+                InvalidReport,
+                ReportAlreadyClaimed,
+                AutomodBlocked,
+                InvalidAutomodRule,
+                ReadOnlyRoom,
+                InvalidRoomSettings,
+                InvalidMaxUses,
+                InvalidRegistrationToken,
+            }
+            restricted: {
+                Muted,
+            }
+            timed: {
+                SlowMode,
             }
         }
     }
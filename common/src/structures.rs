@@ -1,16 +1,21 @@
 use crate::proto::{self, DeserializeError};
-use crate::requests::AdminPermissionFlags;
+use crate::requests::{AdminPermissionFlags, RatelimitClass};
 use crate::types::*;
 use bitflags::bitflags;
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
 use std::convert::{TryFrom, TryInto};
+use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommunityStructure {
     pub id: CommunityId,
     pub name: String,
     pub description: String,
     pub rooms: Vec<RoomStructure>,
+    /// Whether the community is listed in the server's public directory
+    #[serde(default)]
+    pub public: bool,
 }
 
 impl From<CommunityStructure> for proto::structures::CommunityStructure {
@@ -20,6 +25,7 @@ impl From<CommunityStructure> for proto::structures::CommunityStructure {
             name: community.name,
             description: community.description,
             rooms: community.rooms.into_iter().map(Into::into).collect(),
+            public: community.public,
         }
     }
 }
@@ -39,15 +45,20 @@ impl TryFrom<proto::structures::CommunityStructure> for CommunityStructure {
             name: community.name,
             description: community.description,
             rooms,
+            public: community.public,
         })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RoomStructure {
     pub id: RoomId,
     pub name: String,
     pub unread: bool,
+    /// Whether messages in the room are end-to-end encrypted. Their content is then only sent as
+    /// `Message::ciphertext`, which the server does not read.
+    pub encrypted: bool,
+    pub settings: RoomSettings,
 }
 
 impl From<RoomStructure> for proto::structures::RoomStructure {
@@ -56,6 +67,8 @@ impl From<RoomStructure> for proto::structures::RoomStructure {
             id: Some(room.id.into()),
             name: room.name,
             unread: room.unread,
+            encrypted: room.encrypted,
+            settings: Some(room.settings.into()),
         }
     }
 }
@@ -68,11 +81,50 @@ impl TryFrom<proto::structures::RoomStructure> for RoomStructure {
             id: room.id?.try_into()?,
             name: room.name,
             unread: room.unread,
+            encrypted: room.encrypted,
+            settings: room.settings.map(Into::into).unwrap_or_default(),
         })
     }
 }
 
-#[derive(Debug, Clone)]
+/// Restrictions on who can post in a room, and how often
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RoomSettings {
+    /// The minimum number of seconds between each message that a member sends to the room, or 0
+    /// if slow mode is off. Members who can manage rooms are exempt.
+    pub slow_mode_secs: u32,
+    /// Only members who can post in read-only rooms may send messages to the room
+    pub read_only: bool,
+    /// Overrides the server's maximum message length for the room. It cannot be raised above the
+    /// server's maximum.
+    pub max_message_len: Option<u32>,
+}
+
+impl From<RoomSettings> for proto::structures::RoomSettings {
+    fn from(settings: RoomSettings) -> Self {
+        use proto::structures::room_settings::MaxMessageLen::Present;
+
+        proto::structures::RoomSettings {
+            slow_mode_secs: settings.slow_mode_secs,
+            read_only: settings.read_only,
+            max_message_len: settings.max_message_len.map(Present),
+        }
+    }
+}
+
+impl From<proto::structures::RoomSettings> for RoomSettings {
+    fn from(settings: proto::structures::RoomSettings) -> Self {
+        use proto::structures::room_settings::MaxMessageLen::Present;
+
+        RoomSettings {
+            slow_mode_secs: settings.slow_mode_secs,
+            read_only: settings.read_only,
+            max_message_len: settings.max_message_len.map(|Present(x)| x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageConfirmation {
     pub id: MessageId,
     pub time_sent: DateTime<Utc>,
@@ -99,7 +151,7 @@ impl TryFrom<proto::structures::MessageConfirmation> for MessageConfirmation {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageHistory {
     pub buffer: Vec<Message>,
 }
@@ -135,7 +187,7 @@ impl TryFrom<proto::structures::MessageHistory> for MessageHistory {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RoomUpdate {
     pub last_read: Option<MessageId>,
     pub new_messages: MessageHistory,
@@ -169,18 +221,23 @@ impl TryFrom<proto::structures::RoomUpdate> for RoomUpdate {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: MessageId,
     pub author: UserId,
     pub author_profile_version: ProfileVersion,
     pub time_sent: DateTime<Utc>,
     pub content: Option<String>,
+    /// The encrypted content of a message in an encrypted room. This is opaque to the server.
+    pub ciphertext: Option<Vec<u8>>,
+    /// Shown instead of the author's display name for this message only, e.g when a webhook posts
+    /// under another name
+    pub author_display_name: Option<String>,
 }
 
 impl From<Message> for proto::structures::Message {
     fn from(msg: Message) -> Self {
-        use proto::structures::message::Content;
+        use proto::structures::message::{AuthorDisplayName, Content, Encrypted};
 
         proto::structures::Message {
             id: Some(msg.id.into()),
@@ -188,6 +245,10 @@ impl From<Message> for proto::structures::Message {
             author_profile_version: msg.author_profile_version.0 as u32,
             time_sent: msg.time_sent.timestamp(),
             content: msg.content.map(Content::Present),
+            encrypted: msg.ciphertext.map(Encrypted::Ciphertext),
+            author_display_name: msg
+                .author_display_name
+                .map(AuthorDisplayName::AuthorDisplayNamePresent),
         }
     }
 }
@@ -196,7 +257,7 @@ impl TryFrom<proto::structures::Message> for Message {
     type Error = DeserializeError;
 
     fn try_from(message: proto::structures::Message) -> Result<Self, Self::Error> {
-        use proto::structures::message::Content;
+        use proto::structures::message::{AuthorDisplayName, Content, Encrypted};
         let dt = &NaiveDateTime::from_timestamp(message.time_sent, 0);
 
         Ok(Message {
@@ -208,11 +269,60 @@ impl TryFrom<proto::structures::Message> for Message {
                 let Content::Present(content) = c;
                 content
             }),
+            ciphertext: message.encrypted.map(|e| {
+                let Encrypted::Ciphertext(ciphertext) = e;
+                ciphertext
+            }),
+            author_display_name: message.author_display_name.map(|n| {
+                let AuthorDisplayName::AuthorDisplayNamePresent(name) = n;
+                name
+            }),
         })
     }
 }
 
-#[derive(Debug, Clone)]
+/// A device's published keys, for encrypting messages to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceKeyBundle {
+    pub user: UserId,
+    pub device: DeviceId,
+    pub identity_key: Vec<u8>,
+    /// A one-time prekey, if one was claimed and the device had any left
+    pub one_time_prekey: Option<Vec<u8>>,
+}
+
+impl From<DeviceKeyBundle> for proto::structures::DeviceKeyBundle {
+    fn from(bundle: DeviceKeyBundle) -> Self {
+        use proto::structures::device_key_bundle::OneTimePrekey;
+
+        proto::structures::DeviceKeyBundle {
+            user: Some(bundle.user.into()),
+            device: Some(bundle.device.into()),
+            identity_key: bundle.identity_key,
+            one_time_prekey: bundle.one_time_prekey.map(OneTimePrekey::Present),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::DeviceKeyBundle> for DeviceKeyBundle {
+    type Error = DeserializeError;
+
+    fn try_from(bundle: proto::structures::DeviceKeyBundle) -> Result<Self, Self::Error> {
+        use proto::structures::device_key_bundle::OneTimePrekey;
+
+        Ok(DeviceKeyBundle {
+            user: bundle.user?.try_into()?,
+            device: bundle.device?.try_into()?,
+            identity_key: bundle.identity_key,
+            one_time_prekey: bundle.one_time_prekey.map(|k| {
+                let OneTimePrekey::Present(key) = k;
+                key
+            }),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Edit {
     pub message: MessageId,
     pub community: CommunityId,
@@ -244,7 +354,7 @@ impl TryFrom<proto::structures::Edit> for Edit {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Delete {
     pub message: MessageId,
     pub community: CommunityId,
@@ -280,6 +390,8 @@ pub struct ClientReady {
     pub communities: Vec<CommunityStructure>,
     pub permissions: TokenPermissionFlags,
     pub admin_permissions: AdminPermissionFlags,
+    /// The ratelimits that the server applies to this session
+    pub ratelimits: RatelimitQuotas,
 }
 
 impl From<ClientReady> for proto::structures::ClientReady {
@@ -290,6 +402,7 @@ impl From<ClientReady> for proto::structures::ClientReady {
             communities: ready.communities.into_iter().map(Into::into).collect(),
             permission_flags: ready.permissions.bits(),
             admin_permission_flags: ready.admin_permissions.bits(),
+            ratelimits: Some(ready.ratelimits.into()),
         }
     }
 }
@@ -312,15 +425,59 @@ impl TryFrom<proto::structures::ClientReady> for ClientReady {
             admin_permissions: AdminPermissionFlags::from_bits_truncate(
                 ready.admin_permission_flags,
             ),
+            ratelimits: ready.ratelimits?.into(),
         })
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Requests allowed per minute, in total and for each `RatelimitClass`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RatelimitQuotas {
+    pub general_per_min: u32,
+    pub messages_per_min: u32,
+    pub invites_per_min: u32,
+    pub room_creations_per_min: u32,
+}
+
+impl RatelimitQuotas {
+    pub fn class_per_min(&self, class: RatelimitClass) -> u32 {
+        match class {
+            RatelimitClass::Message => self.messages_per_min,
+            RatelimitClass::Invite => self.invites_per_min,
+            RatelimitClass::RoomCreation => self.room_creations_per_min,
+        }
+    }
+}
+
+impl From<RatelimitQuotas> for proto::structures::RatelimitQuotas {
+    fn from(quotas: RatelimitQuotas) -> Self {
+        proto::structures::RatelimitQuotas {
+            general_per_min: quotas.general_per_min,
+            messages_per_min: quotas.messages_per_min,
+            invites_per_min: quotas.invites_per_min,
+            room_creations_per_min: quotas.room_creations_per_min,
+        }
+    }
+}
+
+impl From<proto::structures::RatelimitQuotas> for RatelimitQuotas {
+    fn from(quotas: proto::structures::RatelimitQuotas) -> Self {
+        RatelimitQuotas {
+            general_per_min: quotas.general_per_min,
+            messages_per_min: quotas.messages_per_min,
+            invites_per_min: quotas.invites_per_min,
+            room_creations_per_min: quotas.room_creations_per_min,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Profile {
     pub version: ProfileVersion,
     pub username: String,
     pub display_name: String,
+    /// Whether this user is a bot account, owned and managed by another user
+    pub bot: bool,
 }
 
 impl From<Profile> for proto::structures::Profile {
@@ -329,6 +486,7 @@ impl From<Profile> for proto::structures::Profile {
             version: profile.version.0,
             username: profile.username,
             display_name: profile.display_name,
+            bot: profile.bot,
         }
     }
 }
@@ -341,6 +499,7 @@ impl TryFrom<proto::structures::Profile> for Profile {
             version: ProfileVersion(profile.version),
             username: profile.username,
             display_name: profile.display_name,
+            bot: profile.bot,
         })
     }
 }
@@ -375,7 +534,622 @@ impl From<proto::structures::Credentials> for Credentials {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+/// An incoming webhook, which posts messages into a room as its own user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: WebhookId,
+    pub room: RoomId,
+    /// The user that messages sent through this webhook are authored by
+    pub user: UserId,
+    pub name: String,
+}
+
+impl From<Webhook> for proto::structures::Webhook {
+    fn from(webhook: Webhook) -> Self {
+        proto::structures::Webhook {
+            id: Some(webhook.id.into()),
+            room: Some(webhook.room.into()),
+            user: Some(webhook.user.into()),
+            name: webhook.name,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::Webhook> for Webhook {
+    type Error = DeserializeError;
+
+    fn try_from(webhook: proto::structures::Webhook) -> Result<Self, Self::Error> {
+        Ok(Webhook {
+            id: webhook.id?.try_into()?,
+            room: webhook.room?.try_into()?,
+            user: webhook.user?.try_into()?,
+            name: webhook.name,
+        })
+    }
+}
+
+/// A newly created webhook, along with its secret token. The token is only ever given out once.
+/// Messages can be posted to it at `/vertex/webhook/<id>/<token>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewWebhook {
+    pub webhook: Webhook,
+    pub token: String,
+}
+
+impl From<NewWebhook> for proto::structures::NewWebhook {
+    fn from(new: NewWebhook) -> Self {
+        proto::structures::NewWebhook {
+            webhook: Some(new.webhook.into()),
+            token: new.token,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::NewWebhook> for NewWebhook {
+    type Error = DeserializeError;
+
+    fn try_from(new: proto::structures::NewWebhook) -> Result<Self, Self::Error> {
+        Ok(NewWebhook {
+            webhook: new.webhook?.try_into()?,
+            token: new.token,
+        })
+    }
+}
+
+/// An outgoing webhook, which is sent a signed JSON POST request when certain events happen in a
+/// community.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutgoingWebhook {
+    pub id: WebhookId,
+    pub url: String,
+    pub events: WebhookEventFlags,
+}
+
+impl From<OutgoingWebhook> for proto::structures::OutgoingWebhook {
+    fn from(webhook: OutgoingWebhook) -> Self {
+        proto::structures::OutgoingWebhook {
+            id: Some(webhook.id.into()),
+            url: webhook.url,
+            events: webhook.events.bits(),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::OutgoingWebhook> for OutgoingWebhook {
+    type Error = DeserializeError;
+
+    fn try_from(webhook: proto::structures::OutgoingWebhook) -> Result<Self, Self::Error> {
+        Ok(OutgoingWebhook {
+            id: webhook.id?.try_into()?,
+            url: webhook.url,
+            events: WebhookEventFlags::from_bits_truncate(webhook.events),
+        })
+    }
+}
+
+/// A newly created outgoing webhook, along with the secret that its requests are signed with
+/// (HMAC-SHA256). The secret is only ever given out once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewOutgoingWebhook {
+    pub webhook: OutgoingWebhook,
+    pub secret: String,
+}
+
+impl From<NewOutgoingWebhook> for proto::structures::NewOutgoingWebhook {
+    fn from(new: NewOutgoingWebhook) -> Self {
+        proto::structures::NewOutgoingWebhook {
+            webhook: Some(new.webhook.into()),
+            secret: new.secret,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::NewOutgoingWebhook> for NewOutgoingWebhook {
+    type Error = DeserializeError;
+
+    fn try_from(new: proto::structures::NewOutgoingWebhook) -> Result<Self, Self::Error> {
+        Ok(NewOutgoingWebhook {
+            webhook: new.webhook?.try_into()?,
+            secret: new.secret,
+        })
+    }
+}
+
+/// A single attempt at delivering an event to an outgoing webhook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    /// The type of event that was delivered, e.g `add_message`
+    pub event: String,
+    pub attempted_at: DateTime<Utc>,
+    /// Which attempt this was, starting at 1
+    pub attempt: u32,
+    /// The HTTP status code returned, if a response was received at all
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub success: bool,
+}
+
+impl From<WebhookDelivery> for proto::structures::WebhookDelivery {
+    fn from(delivery: WebhookDelivery) -> Self {
+        use proto::structures::webhook_delivery::{Error, StatusCode};
+
+        proto::structures::WebhookDelivery {
+            event: delivery.event,
+            attempted_at: delivery.attempted_at.timestamp(),
+            attempt: delivery.attempt,
+            status_code: delivery
+                .status_code
+                .map(|code| StatusCode::StatusCodePresent(code as u32)),
+            error: delivery.error.map(Error::ErrorPresent),
+            success: delivery.success,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::WebhookDelivery> for WebhookDelivery {
+    type Error = DeserializeError;
+
+    fn try_from(delivery: proto::structures::WebhookDelivery) -> Result<Self, Self::Error> {
+        use proto::structures::webhook_delivery::{Error, StatusCode};
+
+        let status_code = match delivery.status_code {
+            Some(StatusCode::StatusCodePresent(code)) => {
+                Some(u16::try_from(code).map_err(|_| DeserializeError::IntOutOfRange)?)
+            }
+            None => None,
+        };
+
+        Ok(WebhookDelivery {
+            event: delivery.event,
+            attempted_at: Utc.from_utc_datetime(&NaiveDateTime::from_timestamp(
+                delivery.attempted_at,
+                0,
+            )),
+            attempt: delivery.attempt,
+            status_code,
+            error: delivery.error.map(|Error::ErrorPresent(e)| e),
+            success: delivery.success,
+        })
+    }
+}
+
+/// A slash command declared by a bot within a community, e.g `/weather <city>`
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BotCommand {
+    /// The name of the command, without the leading slash
+    pub name: String,
+    pub description: String,
+    pub arguments: Vec<CommandArgument>,
+}
+
+impl From<BotCommand> for proto::structures::BotCommand {
+    fn from(command: BotCommand) -> Self {
+        proto::structures::BotCommand {
+            name: command.name,
+            description: command.description,
+            arguments: command.arguments.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::BotCommand> for BotCommand {
+    type Error = DeserializeError;
+
+    fn try_from(command: proto::structures::BotCommand) -> Result<Self, Self::Error> {
+        let arguments = command
+            .arguments
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<CommandArgument>, DeserializeError>>()?;
+
+        Ok(BotCommand {
+            name: command.name,
+            description: command.description,
+            arguments,
+        })
+    }
+}
+
+impl BotCommand {
+    /// Splits the text following the command name into arguments according to this command's
+    /// arguments. Returns `None` if the text does not match them.
+    pub fn parse_arguments(&self, text: &str) -> Option<Vec<String>> {
+        let mut rest = text.trim();
+        let mut args = Vec::with_capacity(self.arguments.len());
+
+        for arg in &self.arguments {
+            if rest.is_empty() {
+                break;
+            }
+
+            let value = match arg.kind {
+                CommandArgumentKind::Rest => rest,
+                CommandArgumentKind::Word | CommandArgumentKind::Integer => {
+                    let end = rest.find(char::is_whitespace).unwrap_or_else(|| rest.len());
+                    &rest[..end]
+                }
+            };
+
+            rest = rest[value.len()..].trim_start();
+            args.push(value.to_string());
+        }
+
+        if rest.is_empty() && self.arguments_valid(&args) {
+            Some(args)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the given, already split, arguments are valid for this command
+    pub fn arguments_valid(&self, args: &[String]) -> bool {
+        if args.len() > self.arguments.len() {
+            return false;
+        }
+
+        self.arguments
+            .iter()
+            .enumerate()
+            .all(|(idx, arg)| match args.get(idx) {
+                Some(value) => match arg.kind {
+                    CommandArgumentKind::Word => {
+                        !value.is_empty() && !value.contains(char::is_whitespace)
+                    }
+                    CommandArgumentKind::Integer => value.parse::<i64>().is_ok(),
+                    CommandArgumentKind::Rest => !value.trim().is_empty(),
+                },
+                None => !arg.required,
+            })
+    }
+
+    /// A short description of how to invoke the command, e.g `/weather <city> [days]`
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for arg in &self.arguments {
+            if arg.required {
+                usage.push_str(&format!(" <{}>", arg.name));
+            } else {
+                usage.push_str(&format!(" [{}]", arg.name));
+            }
+        }
+
+        usage
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CommandArgument {
+    pub name: String,
+    pub description: String,
+    pub kind: CommandArgumentKind,
+    pub required: bool,
+}
+
+impl From<CommandArgument> for proto::structures::CommandArgument {
+    fn from(arg: CommandArgument) -> Self {
+        proto::structures::CommandArgument {
+            name: arg.name,
+            description: arg.description,
+            kind: proto::structures::CommandArgumentKind::from(arg.kind) as i32,
+            required: arg.required,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::CommandArgument> for CommandArgument {
+    type Error = DeserializeError;
+
+    fn try_from(arg: proto::structures::CommandArgument) -> Result<Self, Self::Error> {
+        let kind = proto::structures::CommandArgumentKind::from_i32(arg.kind)
+            .ok_or(DeserializeError::InvalidEnumVariant)?;
+
+        Ok(CommandArgument {
+            name: arg.name,
+            description: arg.description,
+            kind: kind.into(),
+            required: arg.required,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandArgumentKind {
+    /// A single word, i.e up until the next whitespace
+    Word,
+    /// A whole number
+    Integer,
+    /// The rest of the text that the command was invoked with. Only valid as the last argument.
+    Rest,
+}
+
+impl From<CommandArgumentKind> for proto::structures::CommandArgumentKind {
+    fn from(kind: CommandArgumentKind) -> Self {
+        use proto::structures::CommandArgumentKind as Kind;
+
+        match kind {
+            CommandArgumentKind::Word => Kind::Word,
+            CommandArgumentKind::Integer => Kind::Integer,
+            CommandArgumentKind::Rest => Kind::Rest,
+        }
+    }
+}
+
+impl From<proto::structures::CommandArgumentKind> for CommandArgumentKind {
+    fn from(kind: proto::structures::CommandArgumentKind) -> Self {
+        use proto::structures::CommandArgumentKind as Kind;
+
+        match kind {
+            Kind::Word => CommandArgumentKind::Word,
+            Kind::Integer => CommandArgumentKind::Integer,
+            Kind::Rest => CommandArgumentKind::Rest,
+        }
+    }
+}
+
+/// A command along with the bot which registered it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredCommand {
+    pub bot: UserId,
+    pub command: BotCommand,
+}
+
+impl From<RegisteredCommand> for proto::structures::RegisteredCommand {
+    fn from(registered: RegisteredCommand) -> Self {
+        proto::structures::RegisteredCommand {
+            bot: Some(registered.bot.into()),
+            command: Some(registered.command.into()),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::RegisteredCommand> for RegisteredCommand {
+    type Error = DeserializeError;
+
+    fn try_from(registered: proto::structures::RegisteredCommand) -> Result<Self, Self::Error> {
+        Ok(RegisteredCommand {
+            bot: registered.bot?.try_into()?,
+            command: registered.command?.try_into()?,
+        })
+    }
+}
+
+/// Sent to a bot when a user invokes one of its commands
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandInvocation {
+    pub community: CommunityId,
+    pub room: RoomId,
+    pub invoker: UserId,
+    pub command: String,
+    pub arguments: Vec<String>,
+}
+
+impl From<CommandInvocation> for proto::structures::CommandInvocation {
+    fn from(invocation: CommandInvocation) -> Self {
+        proto::structures::CommandInvocation {
+            community: Some(invocation.community.into()),
+            room: Some(invocation.room.into()),
+            invoker: Some(invocation.invoker.into()),
+            command: invocation.command,
+            arguments: invocation.arguments,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::CommandInvocation> for CommandInvocation {
+    type Error = DeserializeError;
+
+    fn try_from(invocation: proto::structures::CommandInvocation) -> Result<Self, Self::Error> {
+        Ok(CommandInvocation {
+            community: invocation.community?.try_into()?,
+            room: invocation.room?.try_into()?,
+            invoker: invocation.invoker?.try_into()?,
+            command: invocation.command,
+            arguments: invocation.arguments,
+        })
+    }
+}
+
+/// A community's automatic moderation rule, which every unencrypted message sent in the community
+/// is checked against
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AutomodRule {
+    pub id: i32,
+    pub name: String,
+    pub trigger: AutomodTrigger,
+    pub action: AutomodAction,
+}
+
+impl From<AutomodRule> for proto::structures::AutomodRule {
+    fn from(rule: AutomodRule) -> Self {
+        proto::structures::AutomodRule {
+            id: rule.id,
+            name: rule.name,
+            trigger: Some(rule.trigger.into()),
+            action: Some(rule.action.into()),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::AutomodRule> for AutomodRule {
+    type Error = DeserializeError;
+
+    fn try_from(rule: proto::structures::AutomodRule) -> Result<Self, Self::Error> {
+        Ok(AutomodRule {
+            id: rule.id,
+            name: rule.name,
+            trigger: rule.trigger?.try_into()?,
+            action: rule.action?.try_into()?,
+        })
+    }
+}
+
+/// What a message must contain, or how it must be sent, for an automod rule to apply to it
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutomodTrigger {
+    /// Any of the given words, ignoring case
+    BannedWords(Vec<String>),
+    /// Text matching a regular expression
+    Pattern(String),
+    /// A link to any of the given domains or their subdomains
+    DeniedDomains(Vec<String>),
+    /// A link to any domain other than the given domains and their subdomains
+    AllowedDomains(Vec<String>),
+    /// An invite link to a community
+    InviteLinks,
+    /// More than the given number of `@mentions` in one message
+    MentionSpam { max_mentions: u32 },
+    /// More than the given number of messages sent by one user within the given number of seconds
+    Flood { max_messages: u32, seconds: u32 },
+}
+
+impl From<AutomodTrigger> for proto::structures::AutomodTrigger {
+    fn from(trigger: AutomodTrigger) -> Self {
+        use proto::structures::automod_trigger::Trigger;
+        use proto::structures::{AutomodFlood, AutomodWords};
+
+        let trigger = match trigger {
+            AutomodTrigger::BannedWords(words) => Trigger::BannedWords(AutomodWords { words }),
+            AutomodTrigger::Pattern(pattern) => Trigger::Pattern(pattern),
+            AutomodTrigger::DeniedDomains(words) => Trigger::DeniedDomains(AutomodWords { words }),
+            AutomodTrigger::AllowedDomains(words) => {
+                Trigger::AllowedDomains(AutomodWords { words })
+            }
+            AutomodTrigger::InviteLinks => Trigger::InviteLinks(proto::types::None {}),
+            AutomodTrigger::MentionSpam { max_mentions } => Trigger::MaxMentions(max_mentions),
+            AutomodTrigger::Flood {
+                max_messages,
+                seconds,
+            } => Trigger::Flood(AutomodFlood {
+                max_messages,
+                seconds,
+            }),
+        };
+
+        proto::structures::AutomodTrigger {
+            trigger: Some(trigger),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::AutomodTrigger> for AutomodTrigger {
+    type Error = DeserializeError;
+
+    fn try_from(trigger: proto::structures::AutomodTrigger) -> Result<Self, Self::Error> {
+        use proto::structures::automod_trigger::Trigger;
+
+        Ok(match trigger.trigger? {
+            Trigger::BannedWords(list) => AutomodTrigger::BannedWords(list.words),
+            Trigger::Pattern(pattern) => AutomodTrigger::Pattern(pattern),
+            Trigger::DeniedDomains(list) => AutomodTrigger::DeniedDomains(list.words),
+            Trigger::AllowedDomains(list) => AutomodTrigger::AllowedDomains(list.words),
+            Trigger::InviteLinks(_) => AutomodTrigger::InviteLinks,
+            Trigger::MaxMentions(max_mentions) => AutomodTrigger::MentionSpam { max_mentions },
+            Trigger::Flood(flood) => AutomodTrigger::Flood {
+                max_messages: flood.max_messages,
+                seconds: flood.seconds,
+            },
+        })
+    }
+}
+
+/// What is done when a message triggers an automod rule
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutomodAction {
+    /// The message is not sent
+    Block,
+    /// The message is sent, but it is reported to the server's administrators for review
+    Flag,
+    /// The message is not sent, and its author is muted in the community for the given number of
+    /// minutes
+    Mute { minutes: u32 },
+}
+
+impl From<AutomodAction> for proto::structures::AutomodAction {
+    fn from(action: AutomodAction) -> Self {
+        use proto::structures::automod_action::Action;
+
+        let action = match action {
+            AutomodAction::Block => Action::Block(proto::types::None {}),
+            AutomodAction::Flag => Action::Flag(proto::types::None {}),
+            AutomodAction::Mute { minutes } => Action::MuteMinutes(minutes),
+        };
+
+        proto::structures::AutomodAction {
+            action: Some(action),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::AutomodAction> for AutomodAction {
+    type Error = DeserializeError;
+
+    fn try_from(action: proto::structures::AutomodAction) -> Result<Self, Self::Error> {
+        use proto::structures::automod_action::Action;
+
+        Ok(match action.action? {
+            Action::Block(_) => AutomodAction::Block,
+            Action::Flag(_) => AutomodAction::Flag,
+            Action::MuteMinutes(minutes) => AutomodAction::Mute { minutes },
+        })
+    }
+}
+
+/// A message which triggered an automod rule, and what was done about it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutomodHit {
+    pub id: i64,
+    pub datetime: DateTime<Utc>,
+    /// `None` if the rule has since been deleted
+    pub rule: Option<i32>,
+    /// The name of the rule at the time that it was triggered
+    pub rule_name: String,
+    pub user: UserId,
+    /// `None` if the room has since been deleted
+    pub room: Option<RoomId>,
+    pub message_text: String,
+    pub action: AutomodAction,
+}
+
+impl From<AutomodHit> for proto::structures::AutomodHit {
+    fn from(hit: AutomodHit) -> Self {
+        use proto::structures::automod_hit::Rule;
+
+        proto::structures::AutomodHit {
+            id: hit.id,
+            datetime: hit.datetime.timestamp(),
+            rule: hit.rule.map(Rule::RulePresent),
+            rule_name: hit.rule_name,
+            user: Some(hit.user.into()),
+            room: hit.room.map(Into::into),
+            message_text: hit.message_text,
+            action: Some(hit.action.into()),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::AutomodHit> for AutomodHit {
+    type Error = DeserializeError;
+
+    fn try_from(hit: proto::structures::AutomodHit) -> Result<Self, Self::Error> {
+        use proto::structures::automod_hit::Rule;
+
+        Ok(AutomodHit {
+            id: hit.id,
+            datetime: Utc.from_utc_datetime(&NaiveDateTime::from_timestamp(hit.datetime, 0)),
+            rule: hit.rule.map(|Rule::RulePresent(x)| x),
+            rule_name: hit.rule_name,
+            user: hit.user?.try_into()?,
+            room: hit.room.map(TryInto::try_into).transpose()?,
+            message_text: hit.message_text,
+            action: hit.action?.try_into()?,
+        })
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct TokenCreationOptions {
     pub device_name: Option<String>,
     pub expiration_datetime: Option<DateTime<Utc>>,
@@ -423,6 +1197,8 @@ impl TryFrom<proto::structures::TokenCreationOptions> for TokenCreationOptions {
 }
 
 bitflags! {
+    #[derive(Serialize, Deserialize)]
+    #[serde(transparent)]
     pub struct TokenPermissionFlags: i64 {
         /// All permissions. Should be used for user devices but not for service logins.
         const ALL = 1;
@@ -452,6 +1228,21 @@ bitflags! {
         const ADMINISTER = 1 << 12;
         /// Report users to server administrators
         const REPORT_USERS = 1 << 13;
+        /// Create bots owned by this user, and create tokens for and install them
+        const MANAGE_BOTS = 1 << 14;
+
+        /// The widest set of permissions that a bot token may be granted. Bots may not join or
+        /// create communities themselves (they must be installed), administer the server, or
+        /// manage other bots.
+        const BOT_MAX = Self::SEND_MESSAGES.bits
+            | Self::EDIT_ANY_MESSAGES.bits
+            | Self::EDIT_OWN_MESSAGES.bits
+            | Self::DELETE_ANY_MESSAGES.bits
+            | Self::DELETE_OWN_MESSAGES.bits
+            | Self::CHANGE_DISPLAY_NAME.bits
+            | Self::CREATE_ROOMS.bits
+            | Self::CREATE_INVITES.bits
+            | Self::REPORT_USERS.bits;
     }
 }
 
@@ -459,6 +1250,16 @@ impl TokenPermissionFlags {
     pub fn has_perms(self, perms: TokenPermissionFlags) -> bool {
         self.contains(TokenPermissionFlags::ALL) || self.contains(perms)
     }
+
+    /// Restricts these permissions to those which a bot token may hold. `ALL` is expanded to
+    /// `BOT_MAX` rather than being passed through.
+    pub fn scoped_for_bot(self) -> TokenPermissionFlags {
+        if self.contains(TokenPermissionFlags::ALL) {
+            TokenPermissionFlags::BOT_MAX
+        } else {
+            self & TokenPermissionFlags::BOT_MAX
+        }
+    }
 }
 
 impl Default for TokenPermissionFlags {
@@ -466,3 +1267,350 @@ impl Default for TokenPermissionFlags {
         TokenPermissionFlags::ALL
     }
 }
+
+bitflags! {
+    /// The events which an outgoing webhook is notified of
+    #[derive(Serialize, Deserialize)]
+    #[serde(transparent)]
+    pub struct WebhookEventFlags: i64 {
+        const ADD_MESSAGE = 1;
+        const EDIT = 1 << 1;
+        const DELETE = 1 << 2;
+        const ADD_ROOM = 1 << 3;
+        const MEMBER_JOIN = 1 << 4;
+    }
+}
+
+bitflags! {
+    /// Permissions that a member holds within a particular community.
+    #[derive(Serialize, Deserialize)]
+    #[serde(transparent)]
+    pub struct CommunityPermissionFlags: i64 {
+        /// All permissions. Held by the creator of the community.
+        const ALL = 1;
+        /// Create, list, and revoke incoming and outgoing webhooks
+        const MANAGE_WEBHOOKS = 1 << 1;
+        /// Set how long messages are kept in the community and its rooms
+        const MANAGE_RETENTION = 1 << 2;
+        /// Create, list, and delete automod rules, and see which messages triggered them
+        const MANAGE_AUTOMOD = 1 << 3;
+        /// Change the settings of rooms, and send messages without waiting for slow mode
+        const MANAGE_ROOMS = 1 << 4;
+        /// Send messages to read-only rooms
+        const POST_IN_READ_ONLY_ROOMS = 1 << 5;
+        /// List and revoke invites to the community, and see who joined through them
+        const MANAGE_INVITES = 1 << 6;
+    }
+}
+
+impl CommunityPermissionFlags {
+    pub fn has_perms(self, perms: CommunityPermissionFlags) -> bool {
+        self.contains(CommunityPermissionFlags::ALL) || self.contains(perms)
+    }
+}
+
+impl Default for CommunityPermissionFlags {
+    fn default() -> Self {
+        CommunityPermissionFlags::empty()
+    }
+}
+
+/// A ban, lock or mute placed on a user by an administrator, or a mute placed on a member of a
+/// community by its automod
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Restriction {
+    pub reason: String,
+    /// `None` if the restriction is permanent
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl Restriction {
+    /// Whether the restriction has not yet expired
+    pub fn is_active(&self) -> bool {
+        self.expires.map(|expires| expires > Utc::now()).unwrap_or(true)
+    }
+}
+
+impl fmt::Display for Restriction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.expires {
+            Some(expires) => write!(f, "until {}", expires.format("%F %R UTC"))?,
+            None => write!(f, "permanently")?,
+        }
+
+        if !self.reason.is_empty() {
+            write!(f, " (reason: {})", self.reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Restriction> for proto::structures::Restriction {
+    fn from(restriction: Restriction) -> Self {
+        use proto::structures::restriction::Expires;
+
+        proto::structures::Restriction {
+            reason: restriction.reason,
+            expires: restriction.expires
+                .map(|dt| dt.timestamp())
+                .map(Expires::ExpiresPresent),
+        }
+    }
+}
+
+impl From<proto::structures::Restriction> for Restriction {
+    fn from(restriction: proto::structures::Restriction) -> Self {
+        use proto::structures::restriction::Expires;
+
+        let expires = restriction
+            .expires
+            .map(|Expires::ExpiresPresent(x)| x)
+            .map(|timestamp| NaiveDateTime::from_timestamp(timestamp, 0))
+            .map(|dt| Utc.from_utc_datetime(&dt));
+
+        Restriction {
+            reason: restriction.reason,
+            expires,
+        }
+    }
+}
+
+/// A user who created an invite, or joined through one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteUser {
+    pub id: UserId,
+    pub username: String,
+}
+
+impl From<InviteUser> for proto::structures::InviteUser {
+    fn from(user: InviteUser) -> Self {
+        proto::structures::InviteUser {
+            id: Some(user.id.into()),
+            username: user.username,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::InviteUser> for InviteUser {
+    type Error = DeserializeError;
+
+    fn try_from(user: proto::structures::InviteUser) -> Result<Self, Self::Error> {
+        Ok(InviteUser {
+            id: user.id?.try_into()?,
+            username: user.username,
+        })
+    }
+}
+
+/// An invite code to a community, as seen by those who manage its invites
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Invite {
+    pub code: InviteCode,
+    /// `None` if the creator has since been deleted, or the code was created before creators were
+    /// recorded
+    pub creator: Option<InviteUser>,
+    pub created_at: DateTime<Utc>,
+    pub expiration_date: Option<DateTime<Utc>>,
+    /// `None` if the code can be used any number of times
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+}
+
+impl From<Invite> for proto::structures::Invite {
+    fn from(invite: Invite) -> Self {
+        use proto::structures::invite::{ExpirationDate, MaxUses};
+
+        proto::structures::Invite {
+            code: invite.code.0,
+            creator: invite.creator.map(Into::into),
+            created_at: invite.created_at.timestamp(),
+            expiration_date: invite
+                .expiration_date
+                .map(|dt| ExpirationDate::ExpirationDatePresent(dt.timestamp())),
+            max_uses: invite.max_uses.map(MaxUses::MaxUsesPresent),
+            uses: invite.uses,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::Invite> for Invite {
+    type Error = DeserializeError;
+
+    fn try_from(invite: proto::structures::Invite) -> Result<Self, Self::Error> {
+        use proto::structures::invite::{ExpirationDate, MaxUses};
+
+        let expiration_date = invite
+            .expiration_date
+            .map(|ExpirationDate::ExpirationDatePresent(x)| x)
+            .map(|timestamp| NaiveDateTime::from_timestamp(timestamp, 0))
+            .map(|dt| Utc.from_utc_datetime(&dt));
+
+        Ok(Invite {
+            code: InviteCode(invite.code),
+            creator: invite.creator.map(TryInto::try_into).transpose()?,
+            created_at: Utc.from_utc_datetime(&NaiveDateTime::from_timestamp(
+                invite.created_at,
+                0,
+            )),
+            expiration_date,
+            max_uses: invite.max_uses.map(|MaxUses::MaxUsesPresent(x)| x),
+            uses: invite.uses,
+        })
+    }
+}
+
+/// A user having joined a community through an invite code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteUse {
+    pub user: InviteUser,
+    pub joined_at: DateTime<Utc>,
+}
+
+impl From<InviteUse> for proto::structures::InviteUse {
+    fn from(invite_use: InviteUse) -> Self {
+        proto::structures::InviteUse {
+            user: Some(invite_use.user.into()),
+            joined_at: invite_use.joined_at.timestamp(),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::InviteUse> for InviteUse {
+    type Error = DeserializeError;
+
+    fn try_from(invite_use: proto::structures::InviteUse) -> Result<Self, Self::Error> {
+        Ok(InviteUse {
+            user: invite_use.user?.try_into()?,
+            joined_at: Utc.from_utc_datetime(&NaiveDateTime::from_timestamp(
+                invite_use.joined_at,
+                0,
+            )),
+        })
+    }
+}
+
+/// A community listed in the server's public directory, which anyone can join without an invite
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicCommunity {
+    pub id: CommunityId,
+    pub name: String,
+    pub description: String,
+    pub members: u32,
+}
+
+impl From<PublicCommunity> for proto::structures::PublicCommunity {
+    fn from(community: PublicCommunity) -> Self {
+        proto::structures::PublicCommunity {
+            id: Some(community.id.into()),
+            name: community.name,
+            description: community.description,
+            members: community.members,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::PublicCommunity> for PublicCommunity {
+    type Error = DeserializeError;
+
+    fn try_from(community: proto::structures::PublicCommunity) -> Result<Self, Self::Error> {
+        Ok(PublicCommunity {
+            id: community.id?.try_into()?,
+            name: community.name,
+            description: community.description,
+            members: community.members,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argument(name: &str, kind: CommandArgumentKind, required: bool) -> CommandArgument {
+        CommandArgument {
+            name: name.to_string(),
+            description: String::new(),
+            kind,
+            required,
+        }
+    }
+
+    fn weather() -> BotCommand {
+        BotCommand {
+            name: "weather".to_string(),
+            description: String::new(),
+            arguments: vec![
+                argument("city", CommandArgumentKind::Word, true),
+                argument("days", CommandArgumentKind::Integer, false),
+                argument("note", CommandArgumentKind::Rest, false),
+            ],
+        }
+    }
+
+    #[test]
+    fn command_arguments_are_split() {
+        let command = weather();
+
+        assert_eq!(
+            command.parse_arguments("  london  "),
+            Some(vec!["london".to_string()])
+        );
+        assert_eq!(
+            command.parse_arguments("london 3 bring  an umbrella "),
+            Some(vec![
+                "london".to_string(),
+                "3".to_string(),
+                "bring  an umbrella".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_command_arguments() {
+        let command = weather();
+
+        assert_eq!(command.parse_arguments(""), None);
+        assert_eq!(command.parse_arguments("london soon"), None);
+        assert!(!command.arguments_valid(&["two words".to_string()]));
+
+        let too_many = vec!["a".to_string(); 4];
+        assert!(!command.arguments_valid(&too_many));
+    }
+
+    #[test]
+    fn command_usage() {
+        assert_eq!(weather().usage(), "/weather <city> [days] [note]");
+    }
+
+    #[test]
+    fn bot_permissions_are_scoped() {
+        assert_eq!(
+            TokenPermissionFlags::ALL.scoped_for_bot(),
+            TokenPermissionFlags::BOT_MAX
+        );
+
+        let requested = TokenPermissionFlags::SEND_MESSAGES
+            | TokenPermissionFlags::ADMINISTER
+            | TokenPermissionFlags::MANAGE_BOTS
+            | TokenPermissionFlags::JOIN_COMMUNITIES;
+        assert_eq!(
+            requested.scoped_for_bot(),
+            TokenPermissionFlags::SEND_MESSAGES
+        );
+    }
+
+    #[test]
+    fn bot_permissions_never_include_all() {
+        let scoped = TokenPermissionFlags::all().scoped_for_bot();
+        assert!(!scoped.contains(TokenPermissionFlags::ALL));
+        assert!(!scoped.has_perms(TokenPermissionFlags::ADMINISTER));
+        assert!(scoped.has_perms(TokenPermissionFlags::SEND_MESSAGES));
+    }
+
+    #[test]
+    fn unknown_permission_bits_are_dropped() {
+        let flags = TokenPermissionFlags::from_bits_truncate(1 << 1 | 1 << 40);
+        assert_eq!(flags, TokenPermissionFlags::SEND_MESSAGES);
+    }
+}
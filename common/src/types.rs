@@ -26,23 +26,39 @@ macro_rules! impl_protobuf_conversions {
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default)]
+#[serde(transparent)]
+#[derive(
+    Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default, Serialize, Deserialize,
+)]
 pub struct UserId(pub Uuid);
 
-#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default)]
+#[serde(transparent)]
+#[derive(
+    Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default, Serialize, Deserialize,
+)]
 pub struct CommunityId(pub Uuid);
 
-#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default)]
+#[serde(transparent)]
+#[derive(
+    Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default, Serialize, Deserialize,
+)]
 pub struct RoomId(pub Uuid);
 
-#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default)]
+#[serde(transparent)]
+#[derive(
+    Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default, Serialize, Deserialize,
+)]
 pub struct MessageId(pub Uuid);
 
 #[serde(transparent)]
 #[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct DeviceId(pub Uuid);
 
-impl_protobuf_conversions! { DeviceId, MessageId, RoomId, CommunityId, UserId }
+#[serde(transparent)]
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct WebhookId(pub Uuid);
+
+impl_protobuf_conversions! { DeviceId, MessageId, RoomId, CommunityId, UserId, WebhookId }
 
 /// Does not need to be sequential; just unique within a desired time-span (or not, if you're a fan
 /// of trying to handle two responses with the same id attached). This exists for the client-side
@@ -68,7 +84,8 @@ impl From<proto::types::RequestId> for RequestId {
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone)]
+#[serde(transparent)]
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct ProfileVersion(pub u32);
 
 impl fmt::Display for DeviceId {
@@ -87,5 +104,47 @@ impl fmt::Display for AuthToken {
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+#[serde(transparent)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct InviteCode(pub String);
+
+impl InviteCode {
+    /// Splits the invite code into the code itself and the server it is for, if it is for another
+    /// server (e.g `abc123@vertex.example.com`)
+    pub fn qualified(&self) -> Qualified {
+        Qualified::from(self.0.as_str())
+    }
+}
+
+/// A name qualified by the server that it belongs to, written `name@server`. Federated users and
+/// invite codes from other servers are referred to like this. If there is no server, the name is
+/// local to the server it was given to.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct Qualified {
+    pub name: String,
+    pub server: Option<String>,
+}
+
+impl From<&str> for Qualified {
+    fn from(s: &str) -> Self {
+        match s.rfind('@') {
+            Some(idx) => Qualified {
+                name: s[..idx].to_string(),
+                server: Some(s[idx + 1..].to_string()),
+            },
+            None => Qualified {
+                name: s.to_string(),
+                server: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Qualified {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.server {
+            Some(server) => write!(f, "{}@{}", self.name, server),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
@@ -0,0 +1,690 @@
+//! Subcommands of the server binary for administering the server from the command line, e.g
+//! `vertex_server ban USERNAME`. These work directly on the database, so they can be used whether
+//! or not the server is running.
+//!
+//! A running server does not see every change straight away: users who are banned, locked, muted,
+//! or have their tokens revoked are only affected once they next log in, and deleted communities
+//! stay loaded until the server is restarted. Likewise, imported communities are only loaded once
+//! the server is restarted.
+//!
+//! Actions taken here are recorded in the audit log without an actor.
+
+use std::str::FromStr;
+
+use chrono::{Duration, Utc};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use futures::TryStreamExt;
+use log::info;
+use rand::RngCore;
+use uuid::Uuid;
+
+use vertex::prelude::*;
+
+use crate::archive::{self, CommunityArchive};
+use crate::auth;
+use crate::config::Config;
+use crate::database::{AuditEvent, Database, UserRecord};
+
+pub fn subcommands() -> Vec<App<'static, 'static>> {
+    let username = || {
+        Arg::with_name("USERNAME")
+            .help("The username of the user")
+            .required(true)
+    };
+
+    // Bans, locks and mutes are permanent unless a duration is given
+    let restriction_args = || {
+        vec![
+            Arg::with_name("reason")
+                .long("reason")
+                .takes_value(true)
+                .help("Why the user is being restricted, which they are told"),
+            Arg::with_name("for")
+                .long("for")
+                .takes_value(true)
+                .help("How long until the restriction is lifted, e.g 30m, 12h, 7d or 2w"),
+        ]
+    };
+
+    vec![
+        SubCommand::with_name("list-users").about("Lists all users"),
+        SubCommand::with_name("search-users")
+            .about("Searches for users with a similar username")
+            .arg(Arg::with_name("NAME").required(true)),
+        SubCommand::with_name("ban")
+            .about("Bans a user and revokes their tokens")
+            .arg(username())
+            .args(&restriction_args()),
+        SubCommand::with_name("unban")
+            .about("Unbans a user")
+            .arg(username()),
+        SubCommand::with_name("lock")
+            .about("Locks a user's account and revokes their tokens")
+            .arg(username())
+            .args(&restriction_args()),
+        SubCommand::with_name("unlock")
+            .about("Unlocks a user's account")
+            .arg(username()),
+        SubCommand::with_name("mute")
+            .about("Stops a user from sending or editing messages")
+            .arg(username())
+            .args(&restriction_args()),
+        SubCommand::with_name("unmute")
+            .about("Unmutes a user")
+            .arg(username()),
+        SubCommand::with_name("reset-password")
+            .about("Resets a user's password to a random one and revokes their tokens")
+            .arg(username()),
+        SubCommand::with_name("revoke-tokens")
+            .about("Revokes all of a user's tokens, logging out all of their devices")
+            .arg(username()),
+        SubCommand::with_name("set-compromised")
+            .about("Marks accounts as compromised, so that their passwords must be changed")
+            .arg(
+                Arg::with_name("old-hashes")
+                    .long("old-hashes")
+                    .help("Only marks accounts whose passwords are hashed with an old scheme"),
+            ),
+        SubCommand::with_name("approve-user")
+            .about("Approves a user who registered while registration needed approval")
+            .arg(username()),
+        SubCommand::with_name("reject-user")
+            .about("Rejects a user waiting to be approved, deleting their account")
+            .arg(username()),
+        SubCommand::with_name("create-registration-token")
+            .about("Creates a token that lets someone register while registration is restricted")
+            .arg(
+                Arg::with_name("for")
+                    .long("for")
+                    .takes_value(true)
+                    .help("How long until the token expires, e.g 30m, 12h, 7d or 2w"),
+            )
+            .arg(
+                Arg::with_name("max-uses")
+                    .long("max-uses")
+                    .takes_value(true)
+                    .help("How many accounts may be registered with the token"),
+            ),
+        SubCommand::with_name("list-communities").about("Lists all communities"),
+        SubCommand::with_name("delete-community")
+            .about("Deletes a community along with its rooms and messages")
+            .arg(Arg::with_name("ID").required(true)),
+        SubCommand::with_name("export-community")
+            .about("Exports a community to an archive file, to back it up or move it elsewhere")
+            .arg(Arg::with_name("ID").required(true))
+            .arg(Arg::with_name("FILE").required(true))
+            .arg(
+                Arg::with_name("invite-codes")
+                    .long("invite-codes")
+                    .help("Includes the community's invite codes in the archive"),
+            ),
+        SubCommand::with_name("import-community")
+            .about("Recreates a community from an archive file")
+            .arg(Arg::with_name("FILE").required(true))
+            .arg(
+                Arg::with_name("owner")
+                    .long("owner")
+                    .takes_value(true)
+                    .help("The username of the user to make the only member, with all permissions"),
+            ),
+        SubCommand::with_name("list-reports")
+            .about("Lists reports, newest first")
+            .arg(
+                Arg::with_name("status")
+                    .long("status")
+                    .takes_value(true)
+                    .possible_values(&["open", "accepted", "denied"])
+                    .default_value("open"),
+            )
+            .arg(
+                Arg::with_name("kind")
+                    .long("kind")
+                    .takes_value(true)
+                    .possible_values(&["message", "user", "room", "community"]),
+            ),
+        SubCommand::with_name("set-report-status")
+            .about("Changes the status of a report")
+            .arg(Arg::with_name("ID").required(true))
+            .arg(
+                Arg::with_name("STATUS")
+                    .required(true)
+                    .possible_values(&["open", "accepted", "denied"]),
+            ),
+    ]
+}
+
+/// Runs the given subcommand to completion
+pub async fn run(name: &str, args: &ArgMatches<'_>, db: &Database, config: &Config) {
+    match name {
+        "list-users" => {
+            let users: Vec<UserRecord> = db
+                .list_all_server_users()
+                .await
+                .unwrap_or_else(|e| panic_error!("Error listing users: {:?}", e))
+                .try_collect()
+                .await
+                .unwrap_or_else(|e| panic_error!("Error listing users: {:?}", e));
+
+            users.into_iter().for_each(print_user);
+        }
+        "search-users" => {
+            let name = args.value_of("NAME").unwrap().to_string();
+            let users: Vec<UserRecord> = db
+                .search_user(name)
+                .await
+                .unwrap_or_else(|e| panic_error!("Error searching users: {:?}", e))
+                .try_collect()
+                .await
+                .unwrap_or_else(|e| panic_error!("Error searching users: {:?}", e));
+
+            users.into_iter().for_each(print_user);
+        }
+        "ban" => {
+            let user = user_id(db, args).await;
+            let ban = restriction(args);
+            let event = restriction_event(AuditAction::Ban, user, &ban);
+            db.set_banned(user, Some(ban))
+                .await
+                .unwrap_or_else(|e| panic_error!("Error banning user: {:?}", e))
+                .unwrap_or_else(|_| panic_error!("User was deleted while banning them"));
+            revoke_tokens(db, user).await;
+            audit(db, event).await;
+            info!("User banned");
+        }
+        "unban" => {
+            let user = user_id(db, args).await;
+            db.set_banned(user, None)
+                .await
+                .unwrap_or_else(|e| panic_error!("Error unbanning user: {:?}", e))
+                .unwrap_or_else(|_| panic_error!("User was deleted while unbanning them"));
+            audit(db, user_event(AuditAction::Unban, user)).await;
+            info!("User unbanned");
+        }
+        "lock" => {
+            let user = user_id(db, args).await;
+            let lock = restriction(args);
+            let event = restriction_event(AuditAction::Lock, user, &lock);
+            db.set_locked(user, Some(lock))
+                .await
+                .unwrap_or_else(|e| panic_error!("Error locking user: {:?}", e))
+                .unwrap_or_else(|_| panic_error!("User was deleted while locking them"));
+            revoke_tokens(db, user).await;
+            audit(db, event).await;
+            info!("User locked");
+        }
+        "unlock" => {
+            let user = user_id(db, args).await;
+            db.set_locked(user, None)
+                .await
+                .unwrap_or_else(|e| panic_error!("Error unlocking user: {:?}", e))
+                .unwrap_or_else(|_| panic_error!("User was deleted while unlocking them"));
+            audit(db, user_event(AuditAction::Unlock, user)).await;
+            info!("User unlocked");
+        }
+        "mute" => {
+            let user = user_id(db, args).await;
+            let mute = restriction(args);
+            let event = restriction_event(AuditAction::Mute, user, &mute);
+            db.set_muted(user, Some(mute))
+                .await
+                .unwrap_or_else(|e| panic_error!("Error muting user: {:?}", e))
+                .unwrap_or_else(|_| panic_error!("User was deleted while muting them"));
+            audit(db, event).await;
+            info!("User muted");
+        }
+        "unmute" => {
+            let user = user_id(db, args).await;
+            db.set_muted(user, None)
+                .await
+                .unwrap_or_else(|e| panic_error!("Error unmuting user: {:?}", e))
+                .unwrap_or_else(|_| panic_error!("User was deleted while unmuting them"));
+            audit(db, user_event(AuditAction::Unmute, user)).await;
+            info!("User unmuted");
+        }
+        "reset-password" => reset_password(db, args).await,
+        "revoke-tokens" => {
+            let user = user_id(db, args).await;
+            revoke_tokens(db, user).await;
+            audit(db, user_event(AuditAction::RevokeTokens, user)).await;
+        }
+        "set-compromised" => {
+            let res = if args.is_present("old-hashes") {
+                db.set_accounts_with_old_hashes_compromised().await
+            } else {
+                db.set_all_accounts_compromised().await
+            };
+
+            res.unwrap_or_else(|e| panic_error!("Error setting accounts compromised: {:?}", e));
+
+            let typ = if args.is_present("old-hashes") {
+                SetCompromisedType::OldHashes
+            } else {
+                SetCompromisedType::All
+            };
+            let event = AuditEvent {
+                parameters: serde_json::json!({ "type": typ }),
+                ..AuditEvent::new(None, AuditAction::SetAccountsCompromised)
+            };
+            audit(db, event).await;
+            info!("Accounts set as compromised and their tokens revoked");
+        }
+        "approve-user" => {
+            let user = user_id(db, args).await;
+            db.approve_user(user)
+                .await
+                .unwrap_or_else(|e| panic_error!("Error approving user: {:?}", e))
+                .unwrap_or_else(|_| panic_error!("User is not waiting to be approved"));
+            audit(db, user_event(AuditAction::ApproveUser, user)).await;
+            info!("User approved");
+        }
+        "reject-user" => {
+            let user = user_id(db, args).await;
+
+            // Recorded beforehand, so that their username can still be looked up
+            audit(db, user_event(AuditAction::RejectUser, user)).await;
+            db.reject_user(user)
+                .await
+                .unwrap_or_else(|e| panic_error!("Error rejecting user: {:?}", e))
+                .unwrap_or_else(|_| panic_error!("User is not waiting to be approved"));
+            info!("User rejected and deleted");
+        }
+        "create-registration-token" => create_registration_token(db, args).await,
+        "list-communities" => {
+            let stream = db
+                .get_all_communities()
+                .await
+                .unwrap_or_else(|e| panic_error!("Error listing communities: {:?}", e));
+            let communities: Vec<_> = stream
+                .try_collect()
+                .await
+                .unwrap_or_else(|e| panic_error!("Error listing communities: {:?}", e));
+
+            for community in communities {
+                println!("{}  {}", community.id.0, community.name);
+            }
+        }
+        "delete-community" => {
+            let id = parse_uuid(args.value_of("ID").unwrap());
+
+            // Recorded beforehand, so that the community's name can still be looked up
+            let event = AuditEvent {
+                community: Some(CommunityId(id)),
+                ..AuditEvent::new(None, AuditAction::DeleteCommunity)
+            };
+            audit(db, event).await;
+
+            db.delete_community(CommunityId(id))
+                .await
+                .unwrap_or_else(|e| panic_error!("Error deleting community: {:?}", e))
+                .unwrap_or_else(|_| panic_error!("Invalid community {}", id));
+            info!("Community deleted. Restart the server for it to be unloaded.");
+        }
+        "export-community" => export_community(db, config, args).await,
+        "import-community" => import_community(db, config, args).await,
+        "list-reports" => list_reports(db, args).await,
+        "set-report-status" => {
+            let id = args.value_of("ID").unwrap();
+            let id = i32::from_str(id).unwrap_or_else(|_| panic_error!("Invalid report ID {}", id));
+            let status = parse_status(args.value_of("STATUS").unwrap());
+
+            db.set_report_status(id, status)
+                .await
+                .unwrap_or_else(|e| panic_error!("Error setting report status: {:?}", e));
+
+            let event = AuditEvent {
+                target: Some(format!("report #{}", id)),
+                parameters: serde_json::json!({ "status": status }),
+                ..AuditEvent::new(None, AuditAction::SetReportStatus)
+            };
+            audit(db, event).await;
+            info!("Report {} set as {}", id, status);
+        }
+        _ => unreachable!("Unknown subcommand {}", name),
+    }
+}
+
+async fn user_id(db: &Database, args: &ArgMatches<'_>) -> UserId {
+    let name = args.value_of("USERNAME").unwrap();
+    db.get_user_by_name(name.to_string())
+        .await
+        .unwrap_or_else(|e| panic_error!("Error getting user {}: {:?}", name, e))
+        .unwrap_or_else(|| panic_error!("Invalid username {}", name))
+        .id
+}
+
+async fn audit(db: &Database, event: AuditEvent) {
+    db.log_audit_event(event)
+        .await
+        .unwrap_or_else(|e| panic_error!("Error recording action in audit log: {:?}", e));
+}
+
+fn user_event(action: AuditAction, user: UserId) -> AuditEvent {
+    AuditEvent {
+        target_user: Some(user),
+        ..AuditEvent::new(None, action)
+    }
+}
+
+fn restriction(args: &ArgMatches<'_>) -> Restriction {
+    let expires = args
+        .value_of("for")
+        .map(|duration| Utc::now() + parse_duration(duration));
+
+    Restriction {
+        reason: args.value_of("reason").unwrap_or_default().to_string(),
+        expires,
+    }
+}
+
+fn restriction_event(action: AuditAction, user: UserId, restriction: &Restriction) -> AuditEvent {
+    AuditEvent {
+        parameters: serde_json::json!({
+            "reason": restriction.reason,
+            "expires": restriction.expires,
+        }),
+        ..user_event(action, user)
+    }
+}
+
+/// Parses durations such as `30m`, `12h`, `7d` and `2w`
+fn parse_duration(duration: &str) -> Duration {
+    if duration.len() < 2 {
+        invalid_duration(duration);
+    }
+
+    let (amount, unit) = duration.split_at(duration.len() - 1);
+    let amount = i64::from_str(amount).unwrap_or_else(|_| invalid_duration(duration));
+    if amount <= 0 {
+        invalid_duration(duration);
+    }
+
+    match unit {
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        "w" => Duration::weeks(amount),
+        _ => invalid_duration(duration),
+    }
+}
+
+fn invalid_duration(duration: &str) -> ! {
+    panic_error!("Invalid duration {}, expected e.g 30m, 12h, 7d or 2w", duration)
+}
+
+async fn revoke_tokens(db: &Database, user: UserId) {
+    let revoked = db
+        .revoke_all_tokens(user)
+        .await
+        .unwrap_or_else(|e| panic_error!("Error revoking tokens: {:?}", e));
+    info!("Revoked {} token(s)", revoked);
+}
+
+async fn reset_password(db: &Database, args: &ArgMatches<'_>) {
+    let user = user_id(db, args).await;
+
+    let mut password_bytes: [u8; 18] = [0; 18];
+    rand::thread_rng().fill_bytes(&mut password_bytes);
+    let password = base64::encode_config(&password_bytes, base64::URL_SAFE_NO_PAD);
+    let (hash, hash_scheme_version) = auth::hash(password.clone()).await;
+
+    db.change_password(user, hash, hash_scheme_version)
+        .await
+        .unwrap_or_else(|e| panic_error!("Error resetting password: {:?}", e))
+        .unwrap_or_else(|_| panic_error!("User was deleted while resetting their password"));
+    revoke_tokens(db, user).await;
+    audit(db, user_event(AuditAction::ResetPassword, user)).await;
+
+    println!("New password: {}", password);
+}
+
+async fn create_registration_token(db: &Database, args: &ArgMatches<'_>) {
+    let expiration_date = args
+        .value_of("for")
+        .map(|duration| Utc::now() + parse_duration(duration));
+    let max_uses = args.value_of("max-uses").map(|max| match u32::from_str(max) {
+        Ok(max) if max > 0 && max <= i32::max_value() as u32 => max,
+        _ => panic_error!("Invalid maximum number of uses {}", max),
+    });
+
+    let token = db
+        .create_registration_token(None, expiration_date, max_uses)
+        .await
+        .unwrap_or_else(|e| panic_error!("Error creating registration token: {:?}", e));
+
+    let event = AuditEvent {
+        parameters: serde_json::json!({
+            "expires": expiration_date,
+            "max_uses": max_uses,
+        }),
+        ..AuditEvent::new(None, AuditAction::CreateRegistrationToken)
+    };
+    audit(db, event).await;
+
+    println!("{}", token.token);
+}
+
+async fn export_community(db: &Database, config: &Config, args: &ArgMatches<'_>) {
+    let id = CommunityId(parse_uuid(args.value_of("ID").unwrap()));
+    let path = args.value_of("FILE").unwrap();
+    let include_invite_codes = args.is_present("invite-codes");
+
+    let archive = archive::export(db, config, id, include_invite_codes)
+        .await
+        .unwrap_or_else(|e| panic_error!("Error exporting community: {}", e));
+    std::fs::write(path, archive.to_bytes())
+        .unwrap_or_else(|e| panic_error!("Error writing archive to {}: {}", path, e));
+
+    let event = AuditEvent {
+        community: Some(id),
+        parameters: serde_json::json!({ "include_invite_codes": include_invite_codes }),
+        ..AuditEvent::new(None, AuditAction::ExportCommunity)
+    };
+    audit(db, event).await;
+
+    let messages: usize = archive.rooms.iter().map(|room| room.messages.len()).sum();
+    info!(
+        "Exported {} room(s) and {} message(s) to {}",
+        archive.rooms.len(),
+        messages,
+        path
+    );
+}
+
+async fn import_community(db: &Database, config: &Config, args: &ArgMatches<'_>) {
+    let path = args.value_of("FILE").unwrap();
+    let bytes = std::fs::read(path)
+        .unwrap_or_else(|e| panic_error!("Error reading archive from {}: {}", path, e));
+
+    let owner = match args.value_of("owner") {
+        Some(name) => Some(
+            db.get_user_by_name(name.to_string())
+                .await
+                .unwrap_or_else(|e| panic_error!("Error getting user {}: {:?}", name, e))
+                .unwrap_or_else(|| panic_error!("Invalid username {}", name))
+                .id,
+        ),
+        None => None,
+    };
+
+    let archive = CommunityArchive::from_bytes(&bytes)
+        .unwrap_or_else(|_| panic_error!("{} is not a valid archive", path));
+    let record = archive::import(db, config, archive)
+        .await
+        .unwrap_or_else(|e| panic_error!("Error importing community: {}", e));
+
+    if let Some(owner) = owner {
+        db.add_to_community(record.id, owner, CommunityPermissionFlags::ALL)
+            .await
+            .unwrap_or_else(|e| panic_error!("Error adding owner to community: {:?}", e))
+            .unwrap_or_else(|_| panic_error!("Owner was deleted while importing the community"));
+    }
+
+    let event = AuditEvent {
+        community: Some(record.id),
+        ..AuditEvent::new(None, AuditAction::ImportCommunity)
+    };
+    audit(db, event).await;
+
+    info!(
+        "Imported community {} as {}. Restart the server for it to be loaded.",
+        record.name, record.id.0
+    );
+}
+
+async fn list_reports(db: &Database, args: &ArgMatches<'_>) {
+    let criteria = SearchCriteria {
+        status: Some(parse_status(args.value_of("status").unwrap())),
+        kind: args.value_of("kind").map(parse_kind),
+        ..Default::default()
+    };
+
+    let reports = db
+        .search_reports(criteria)
+        .await
+        .unwrap_or_else(|e| panic_error!("Error listing reports: {:?}", e));
+
+    for report in reports {
+        let reporter = report
+            .reporter
+            .map(|user| user.username)
+            .unwrap_or_else(|| "<deleted user>".to_string());
+        let community = report
+            .community
+            .map(|community| community.name)
+            .unwrap_or_else(|| "<deleted community>".to_string());
+        let room = report
+            .room
+            .map(|room| room.name)
+            .unwrap_or_else(|| "<deleted room>".to_string());
+        let reported = report
+            .reported
+            .map(|user| user.username)
+            .unwrap_or_else(|| "<deleted user>".to_string());
+
+        let target = match report.kind {
+            ReportKind::Message => format!("a message by {} in {}/{}", reported, community, room),
+            ReportKind::User => format!("user {}", reported),
+            ReportKind::Room => format!("room {}/{}", community, room),
+            ReportKind::Community => format!("community {}", community),
+        };
+
+        println!(
+            "#{} ({}) at {}: {} reported {}",
+            report.id,
+            report.status,
+            report.datetime.format("%Y-%m-%d %H:%M"),
+            reporter,
+            target,
+        );
+        println!("    {}", report.short_desc);
+        if !report.extended_desc.is_empty() {
+            println!("    {}", report.extended_desc);
+        }
+        if let Some(message) = report.message {
+            println!("    Message: {}", message.text);
+        }
+        for line in report.snapshot.lines() {
+            println!("    {}", line);
+        }
+        if let Some(assignee) = report.assignee {
+            println!("    Claimed by {}", assignee.username);
+        }
+        for note in report.notes {
+            let author = note
+                .author
+                .map(|user| user.username)
+                .unwrap_or_else(|| "<deleted user>".to_string());
+            println!("    Note from {}: {}", author, note.text);
+        }
+    }
+}
+
+fn print_user(user: UserRecord) {
+    let mut flags = Vec::new();
+    if let Some(ban) = &user.banned {
+        flags.push(format!("banned {}", ban));
+    }
+    if let Some(lock) = &user.locked {
+        flags.push(format!("locked {}", lock));
+    }
+    if let Some(mute) = &user.muted {
+        flags.push(format!("muted {}", mute));
+    }
+    if user.compromised {
+        flags.push("compromised".to_string());
+    }
+    if user.bot {
+        flags.push("bot".to_string());
+    }
+    if user.pending_approval {
+        flags.push("pending approval".to_string());
+    }
+
+    println!(
+        "{}  {} ({})  {}",
+        user.id.0,
+        user.username,
+        user.display_name,
+        flags.join(", "),
+    );
+}
+
+fn parse_uuid(id: &str) -> Uuid {
+    Uuid::parse_str(id).unwrap_or_else(|_| panic_error!("Invalid ID {}", id))
+}
+
+fn parse_status(status: &str) -> ReportStatus {
+    match status {
+        "open" => ReportStatus::Opened,
+        "accepted" => ReportStatus::Accepted,
+        "denied" => ReportStatus::Denied,
+        _ => unreachable!("clap only allows valid statuses"),
+    }
+}
+
+fn parse_kind(kind: &str) -> ReportKind {
+    match kind {
+        "message" => ReportKind::Message,
+        "user" => ReportKind::User,
+        "room" => ReportKind::Room,
+        "community" => ReportKind::Community,
+        _ => unreachable!("clap only allows valid kinds"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30m"), Duration::minutes(30));
+        assert_eq!(parse_duration("12h"), Duration::hours(12));
+        assert_eq!(parse_duration("7d"), Duration::days(7));
+        assert_eq!(parse_duration("2w"), Duration::weeks(2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn duration_without_unit() {
+        parse_duration("30");
+    }
+
+    #[test]
+    #[should_panic]
+    fn duration_with_unknown_unit() {
+        parse_duration("3y");
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_duration() {
+        parse_duration("0d");
+    }
+
+    #[test]
+    #[should_panic]
+    fn negative_duration() {
+        parse_duration("-1d");
+    }
+}
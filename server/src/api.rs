@@ -0,0 +1,116 @@
+//! A JSON REST API exposing the same requests as the websocket protocol, for scripting and ops
+//! tooling. Requests are authenticated with the device ID in the `X-Vertex-Device` header and the
+//! token in the `Authorization: Bearer <token>` header, and are served by a short-lived session
+//! which is logged out again once the request has been handled.
+
+use std::net::SocketAddr;
+
+use http::StatusCode;
+use serde::Serialize;
+use uuid::Uuid;
+use warp::Reply;
+
+use vertex::prelude::*;
+
+use crate::client::session::{self, ApiRequest};
+use crate::client::{ActiveSession, Authenticator};
+use crate::{handle_disconnected, metrics, Global};
+
+#[derive(Serialize)]
+struct ErrorBody<E: Serialize> {
+    error: E,
+    /// A human readable description of the error
+    message: String,
+}
+
+pub async fn request(
+    global: Global,
+    addr: Option<SocketAddr>,
+    device: Uuid,
+    authorization: String,
+    request: ClientRequest,
+) -> Box<dyn Reply> {
+    // Every request logs in afresh, so it is held to the same per-address quota as other logins
+    if let Err(e) = global.ratelimiters.load().check_auth(addr) {
+        return reply_auth_err(e);
+    }
+
+    let token = match authorization.strip_prefix("Bearer ") {
+        Some(token) => AuthToken(token.to_string()),
+        None => return reply_auth_err(AuthError::InvalidToken),
+    };
+
+    let authenticator = Authenticator {
+        global: global.clone(),
+    };
+
+    let details = match authenticator.login(DeviceId(device), token).await {
+        Ok(details) => details,
+        Err(e) => return reply_auth_err(e),
+    };
+    let (user, device, perms, hsv, bot) = details;
+
+    if global.ratelimiters.load().check_general(device, bot).is_err() {
+        metrics::record_ratelimited(bot);
+        return Box::new(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    match session::insert(global.database.clone(), user, device, hsv).await {
+        Ok(Ok(())) => {}
+        Ok(Err(())) => return reply_auth_err(AuthError::TokenInUse),
+        Err(_) => return reply_auth_err(AuthError::Internal),
+    }
+
+    let actor = ActiveSession::new(None, global, user, device, perms, bot);
+    if session::upgrade(user, device, actor.clone()).is_err() {
+        // The session was removed in the meantime, e.g by the token being revoked
+        return reply_err(Error::LoggedOut);
+    }
+
+    let res = actor
+        .address()
+        .send(ApiRequest(request))
+        .await
+        .map_err(handle_disconnected("ClientSession"));
+
+    match res {
+        Ok(Ok(ok)) => Box::new(warp::reply::json(&ok)),
+        Ok(Err(e)) | Err(e) => reply_err(e),
+    }
+}
+
+fn reply_err(error: Error) -> Box<dyn Reply> {
+    let status = match error {
+        Error::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        Error::LoggedOut => StatusCode::UNAUTHORIZED,
+        Error::AccessDenied => StatusCode::FORBIDDEN,
+        Error::InvalidRoom
+        | Error::InvalidCommunity
+        | Error::InvalidUser
+        | Error::InvalidMessage
+        | Error::InvalidWebhook
+        | Error::DeviceDoesNotExist => StatusCode::NOT_FOUND,
+        Error::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Error::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        Error::RemoteServer => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::BAD_REQUEST,
+    };
+
+    reply_json_err(error.to_string(), error, status)
+}
+
+fn reply_auth_err(error: AuthError) -> Box<dyn Reply> {
+    let status = match error {
+        AuthError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        AuthError::TokenInUse => StatusCode::CONFLICT,
+        AuthError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::UNAUTHORIZED,
+    };
+
+    reply_json_err(error.to_string(), error, status)
+}
+
+fn reply_json_err<E: Serialize>(message: String, error: E, status: StatusCode) -> Box<dyn Reply> {
+    let body = ErrorBody { error, message };
+    Box::new(warp::reply::with_status(warp::reply::json(&body), status))
+}
@@ -0,0 +1,362 @@
+//! The archive format that communities are exported to and imported from, to back them up or to
+//! move them to another server. Archives are JSON, and carry a version so that the format can
+//! change without breaking older archives.
+//!
+//! Users are identified by username, qualified as `user@server` if they are not users of the server
+//! that the community was exported from. Since whoever uploads an archive controls everything in
+//! it, nothing in it is trusted to say who anyone is: when an archive is imported, each of its
+//! users is represented by a new stand-in, so that their messages keep their author's name without
+//! being attributed to any real user. Members and their permissions are not imported - the
+//! importer is the only member, and everyone else must be invited again.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use vertex::prelude::*;
+
+use crate::{auth, community};
+use crate::config::Config;
+use crate::database::{
+    CommunityRecord, Database, DbResult, MessageRecord, RoomRecord, UserRecord,
+};
+
+/// The newest version of the archive format, which archives are exported as
+pub const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommunityArchive {
+    pub version: u32,
+    /// The name of the server that the community was exported from
+    pub server: String,
+    pub exported_at: DateTime<Utc>,
+    pub name: String,
+    pub description: Option<String>,
+    pub retention_days: Option<u32>,
+    /// Everyone who is a member of the community or who authored a message in it
+    pub users: Vec<ArchivedUser>,
+    pub members: Vec<ArchivedMember>,
+    pub rooms: Vec<ArchivedRoom>,
+    /// Only included if asked for, since anyone with the archive could use them to join
+    #[serde(default)]
+    pub invite_codes: Vec<ArchivedInviteCode>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedUser {
+    pub username: String,
+    /// The user's ID on their home server
+    pub id: UserId,
+    pub display_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedMember {
+    pub username: String,
+    pub permissions: CommunityPermissionFlags,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedRoom {
+    pub name: String,
+    pub encrypted: bool,
+    pub retention_days: Option<u32>,
+    #[serde(default)]
+    pub settings: RoomSettings,
+    /// Oldest first
+    pub messages: Vec<ArchivedMessage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedMessage {
+    pub author: String,
+    pub time_sent: DateTime<Utc>,
+    pub content: Option<String>,
+    #[serde(default)]
+    pub ciphertext: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedInviteCode {
+    pub code: InviteCode,
+    pub expiration_date: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+    #[serde(default)]
+    pub uses: u32,
+}
+
+impl CommunityArchive {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Error serializing community archive")
+    }
+
+    /// Parses an archive, checking that its version is supported
+    pub fn from_bytes(bytes: &[u8]) -> Result<CommunityArchive, Error> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        let version: Version = serde_json::from_slice(bytes).map_err(|_| Error::InvalidArchive)?;
+        if version.version != ARCHIVE_VERSION {
+            return Err(Error::InvalidArchive);
+        }
+
+        serde_json::from_slice(bytes).map_err(|_| Error::InvalidArchive)
+    }
+}
+
+/// Names the users in an archive, collecting them as they are first seen
+struct UserArchiver<'a> {
+    db: &'a Database,
+    names: HashMap<UserId, String>,
+    users: Vec<ArchivedUser>,
+}
+
+impl<'a> UserArchiver<'a> {
+    async fn name(&mut self, id: UserId) -> DbResult<Option<String>> {
+        if let Some(username) = self.names.get(&id) {
+            return Ok(Some(username.clone()));
+        }
+
+        let record = match self.db.get_user_by_id(id).await? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+
+        // Stand-ins for users of other servers are already named `user@server`
+        let home_id = match self.db.get_remote_user(id).await? {
+            Some(remote) => remote.remote_id,
+            None => id,
+        };
+
+        self.users.push(ArchivedUser {
+            username: record.username.clone(),
+            id: home_id,
+            display_name: record.display_name,
+        });
+        self.names.insert(id, record.username.clone());
+
+        Ok(Some(record.username))
+    }
+}
+
+/// Exports the community to an archive
+pub async fn export(
+    db: &Database,
+    config: &Config,
+    community: CommunityId,
+    include_invite_codes: bool,
+) -> Result<CommunityArchive, Error> {
+    let record = db
+        .get_community_metadata(community)
+        .await?
+        .ok_or(Error::InvalidCommunity)?;
+
+    let mut users = UserArchiver {
+        db,
+        names: HashMap::new(),
+        users: Vec::new(),
+    };
+
+    let mut members = Vec::new();
+    for (user, permissions) in db.get_community_members(community).await? {
+        if let Some(username) = users.name(user).await? {
+            members.push(ArchivedMember {
+                username,
+                permissions,
+            });
+        }
+    }
+
+    let room_records: Vec<RoomRecord> = db
+        .get_rooms_in_community(community)
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut rooms = Vec::with_capacity(room_records.len());
+    for room in room_records {
+        let message_records: Vec<MessageRecord> = db
+            .get_all_messages_in_room(room.id)
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut messages = Vec::with_capacity(message_records.len());
+        for message in message_records {
+            if let Some(author) = users.name(message.author).await? {
+                messages.push(ArchivedMessage {
+                    author,
+                    time_sent: message.date,
+                    content: message.content,
+                    ciphertext: message.ciphertext,
+                });
+            }
+        }
+
+        rooms.push(ArchivedRoom {
+            name: room.name,
+            encrypted: room.encrypted,
+            retention_days: room.retention_days,
+            settings: room.settings,
+            messages,
+        });
+    }
+
+    let invite_codes = if include_invite_codes {
+        db.get_invite_codes_in_community(community)
+            .await?
+            .into_iter()
+            .map(|record| ArchivedInviteCode {
+                expiration_date: record.expiration_date,
+                max_uses: record.max_uses,
+                uses: record.uses,
+                code: InviteCode(record.into()),
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    Ok(CommunityArchive {
+        version: ARCHIVE_VERSION,
+        server: config.server_name.clone(),
+        exported_at: Utc::now(),
+        name: record.name,
+        description: record.description,
+        retention_days: record.retention_days,
+        users: users.users,
+        members,
+        rooms,
+        invite_codes,
+    })
+}
+
+/// Recreates a community from an archive, returning its record so that it can be loaded. The
+/// importer, if any, is left to be added to the community by the caller.
+pub async fn import(
+    db: &Database,
+    config: &Config,
+    archive: CommunityArchive,
+) -> Result<CommunityRecord, Error> {
+    if !valid_archive(&archive, config) {
+        return Err(Error::InvalidArchive);
+    }
+
+    // Maps usernames in the archive to the stand-ins for them
+    let mut users = HashMap::with_capacity(archive.users.len());
+    for user in &archive.users {
+        let record = UserRecord::new_imported(user.display_name.clone());
+        let id = record.id;
+        if db.create_user(record).await?.is_err() {
+            // The username is derived from a new UUID, so this should never happen
+            panic_error!("Newly generated imported username conflicts with another!");
+        }
+
+        users.insert(user.username.clone(), id);
+    }
+
+    let id = db.create_community(archive.name.clone()).await?;
+    if let Err(e) = populate(db, id, &archive, &users).await {
+        // Don't leave a half-imported community behind
+        db.delete_community(id).await?.ok();
+        return Err(e);
+    }
+
+    db.get_community_metadata(id).await?.ok_or(Error::Internal)
+}
+
+async fn populate(
+    db: &Database,
+    id: CommunityId,
+    archive: &CommunityArchive,
+    users: &HashMap<String, UserId>,
+) -> Result<(), Error> {
+    if let Some(description) = &archive.description {
+        db.change_community_description(id, description.clone()).await?;
+    }
+
+    if archive.retention_days.is_some() {
+        db.set_community_retention(id, archive.retention_days).await?;
+    }
+
+    for room in &archive.rooms {
+        let room_id = db.create_room(id, room.name.clone(), room.encrypted).await?;
+        if room.retention_days.is_some() {
+            db.set_room_retention(room_id, room.retention_days).await?;
+        }
+
+        if room.settings != RoomSettings::default() {
+            db.set_room_settings(room_id, &room.settings).await?;
+        }
+
+        // Messages are inserted oldest first, so that they keep their order
+        for message in &room.messages {
+            db.create_message(
+                MessageId(Uuid::new_v4()),
+                users[&message.author],
+                id,
+                room_id,
+                message.time_sent,
+                message.content.clone(),
+                message.ciphertext.clone(),
+                None,
+            )
+            .await?;
+        }
+    }
+
+    for code in &archive.invite_codes {
+        let (expiration_date, max_uses) = (code.expiration_date, code.max_uses);
+        db.add_invite_code(id, code.code.clone(), expiration_date, max_uses, code.uses)
+            .await?
+            .map_err(|_| Error::InvalidArchive)?;
+    }
+
+    Ok(())
+}
+
+fn valid_archive(archive: &CommunityArchive, config: &Config) -> bool {
+    let usernames: HashSet<&str> = archive.users.iter().map(|u| u.username.as_str()).collect();
+    let valid_name = |name: &str, max: u16| !name.is_empty() && name.len() <= max as usize;
+    let max_description = config.max_community_description_len as usize;
+
+    let valid_message = |encrypted: bool, message: &ArchivedMessage| {
+        let valid_content = match (encrypted, &message.content, &message.ciphertext) {
+            (false, Some(content), None) => content.len() <= config.max_message_len as usize,
+            (true, None, Some(ciphertext)) => {
+                ciphertext.len() <= config.max_ciphertext_len as usize
+            }
+            _ => false,
+        };
+
+        valid_content && usernames.contains(message.author.as_str())
+    };
+
+    let valid_room = |room: &ArchivedRoom| {
+        valid_name(&room.name, config.max_channel_name_len)
+            && community::valid_retention(room.retention_days)
+            && community::valid_room_settings(&room.settings, config)
+            && room.messages.iter().all(|m| valid_message(room.encrypted, m))
+    };
+
+    // Use counts are stored as INTEGERs
+    let max_uses = i32::max_value() as u32;
+    let valid_invite_code = |code: &ArchivedInviteCode| {
+        code.max_uses.map(|max| max > 0 && max <= max_uses).unwrap_or(true)
+            && code.uses <= max_uses
+    };
+
+    valid_name(&archive.name, config.max_community_name_len)
+        && archive.users.iter().all(|u| auth::valid_display_name(&u.display_name, config))
+        && archive.description.as_ref().map(|d| d.len() <= max_description).unwrap_or(true)
+        && community::valid_retention(archive.retention_days)
+        && archive.members.iter().all(|m| usernames.contains(m.username.as_str()))
+        && archive.rooms.iter().all(valid_room)
+        && archive.invite_codes.iter().all(valid_invite_code)
+}
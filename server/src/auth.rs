@@ -48,11 +48,17 @@ pub fn normalize_username(username: &str, _config: &Config) -> String {
 }
 
 pub fn prepare_username(username: &str, config: &Config) -> Result<String, TooShort> {
-    if valid_username(username, config) {
-        Ok(normalize_username(username, config))
-    } else {
-        Err(TooShort)
+    if !valid_username(username, config) {
+        return Err(TooShort);
     }
+
+    // '@' is reserved for the qualified usernames of federated users (`user@server`)
+    let username = normalize_username(username, config);
+    if username.contains('@') {
+        return Err(TooShort);
+    }
+
+    Ok(username)
 }
 
 // The `<E: Send + 'static>`s here are to allow the caller to specify an error type for easier use,
@@ -81,13 +87,23 @@ pub fn verify(
         use HashSchemeVersion::*;
 
         match scheme_version {
-            Argon2V1 => argon2::verify_encoded(&hash, pass.as_bytes())
-                .expect("Error verifying password hash"),
+            // A hash that can't be decoded can't be matched by any password
+            Argon2V1 => argon2::verify_encoded(&hash, pass.as_bytes()).unwrap_or(false),
         }
     })
     .map(|r| r.expect("Error in tokio password verifying task"))
 }
 
 pub async fn verify_user(user: UserRecord, password: String) -> bool {
+    // Bots have no password and can only log in with tokens issued by their owner
+    if user.bot {
+        return false;
+    }
+
+    // Neither do stand-ins for users of other servers or authors of imported messages
+    if user.password_hash.is_empty() {
+        return false;
+    }
+
     verify(password, user.password_hash, user.hash_scheme_version).await
 }
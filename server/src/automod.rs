@@ -0,0 +1,424 @@
+//! Automatic moderation of the messages sent in a community, according to rules set by its
+//! moderators. Only unencrypted messages can be checked, since the server cannot read the rest.
+
+use chrono::{DateTime, Duration, Utc};
+use linkify::{LinkFinder, LinkKind};
+use log::warn;
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, VecDeque};
+use url::Url;
+use vertex::prelude::*;
+
+pub const MAX_RULES_PER_COMMUNITY: usize = 50;
+const MAX_RULE_NAME_LEN: usize = 64;
+/// The most words or domains that a single rule may list
+const MAX_RULE_ENTRIES: usize = 500;
+const MAX_ENTRY_LEN: usize = 64;
+const MAX_PATTERN_LEN: usize = 1024;
+/// Compiled regular expressions may not be any larger than this, so that rules can't be used to
+/// slow every message sent in the community down
+const MAX_REGEX_SIZE: usize = 1 << 20;
+const MAX_FLOOD_SECONDS: u32 = 60 * 60;
+const MAX_MUTE_MINUTES: u32 = 60 * 24 * 28;
+
+pub struct InvalidRule;
+
+impl From<InvalidRule> for Error {
+    fn from(_: InvalidRule) -> Error {
+        Error::InvalidAutomodRule
+    }
+}
+
+/// What a rule's trigger is checked with, compiled ahead of time
+enum Matcher {
+    Regex(Regex),
+    DeniedDomains(Vec<String>),
+    AllowedDomains(Vec<String>),
+    InviteLinks,
+    MentionSpam(usize),
+    Flood {
+        max_messages: usize,
+        window: Duration,
+    },
+}
+
+impl Matcher {
+    fn new(trigger: &AutomodTrigger) -> Result<Matcher, InvalidRule> {
+        let matcher = match trigger {
+            AutomodTrigger::BannedWords(words) => {
+                let words = entries(words)?;
+                let alternatives: Vec<String> = words.iter().map(|w| regex::escape(w)).collect();
+                let pattern = format!(r"(?i)\b(?:{})\b", alternatives.join("|"));
+                Matcher::Regex(compile(&pattern)?)
+            }
+            AutomodTrigger::Pattern(pattern) => {
+                if pattern.is_empty() || pattern.len() > MAX_PATTERN_LEN {
+                    return Err(InvalidRule);
+                }
+
+                Matcher::Regex(compile(pattern)?)
+            }
+            AutomodTrigger::DeniedDomains(domains) => Matcher::DeniedDomains(entries(domains)?),
+            AutomodTrigger::AllowedDomains(domains) => Matcher::AllowedDomains(entries(domains)?),
+            AutomodTrigger::InviteLinks => Matcher::InviteLinks,
+            AutomodTrigger::MentionSpam { max_mentions } => {
+                Matcher::MentionSpam(*max_mentions as usize)
+            }
+            AutomodTrigger::Flood {
+                max_messages,
+                seconds,
+            } => {
+                if *max_messages == 0 || *seconds == 0 || *seconds > MAX_FLOOD_SECONDS {
+                    return Err(InvalidRule);
+                }
+
+                Matcher::Flood {
+                    max_messages: *max_messages as usize,
+                    window: Duration::seconds(*seconds as i64),
+                }
+            }
+        };
+
+        Ok(matcher)
+    }
+
+    /// `sent` is when the user sent their recent messages, including this one, oldest first
+    fn matches(&self, text: &str, sent: &VecDeque<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+        match self {
+            Matcher::Regex(regex) => regex.is_match(text),
+            Matcher::DeniedDomains(denied) => links(text).iter().any(|url| in_domains(url, denied)),
+            Matcher::AllowedDomains(allowed) => {
+                links(text).iter().any(|url| !in_domains(url, allowed))
+            }
+            Matcher::InviteLinks => links(text).iter().any(is_invite),
+            Matcher::MentionSpam(max) => {
+                let mentions = text
+                    .split_whitespace()
+                    .filter(|word| word.len() > 1 && word.starts_with('@'))
+                    .count();
+                mentions > *max
+            }
+            Matcher::Flood {
+                max_messages,
+                window,
+            } => {
+                let since = now - *window;
+                sent.iter().rev().take_while(|at| **at > since).count() > *max_messages
+            }
+        }
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, InvalidRule> {
+    RegexBuilder::new(pattern)
+        .size_limit(MAX_REGEX_SIZE)
+        .build()
+        .map_err(|_| InvalidRule)
+}
+
+/// Checks a list of words or domains, lowercasing them
+fn entries(list: &[String]) -> Result<Vec<String>, InvalidRule> {
+    if list.is_empty() || list.len() > MAX_RULE_ENTRIES {
+        return Err(InvalidRule);
+    }
+
+    list.iter()
+        .map(|entry| {
+            let entry = entry.trim();
+            if entry.is_empty() || entry.len() > MAX_ENTRY_LEN {
+                Err(InvalidRule)
+            } else {
+                Ok(entry.to_lowercase())
+            }
+        })
+        .collect()
+}
+
+fn links(text: &str) -> Vec<Url> {
+    let mut finder = LinkFinder::new();
+    finder.kinds(&[LinkKind::Url]);
+    finder
+        .links(text)
+        .filter_map(|link| Url::parse(link.as_str()).ok())
+        .collect()
+}
+
+/// Whether the link is to any of the domains, or any of their subdomains
+fn in_domains(url: &Url, domains: &[String]) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host.to_lowercase(),
+        None => return false,
+    };
+
+    domains
+        .iter()
+        .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+}
+
+/// Invite links are either to the `/vertex/invite/<code>` page of any server, or use the
+/// `vertex://` protocol which it redirects to
+fn is_invite(url: &Url) -> bool {
+    url.scheme() == "vertex" || url.path().starts_with("/vertex/invite/")
+}
+
+/// Checks that a rule is valid before it is created
+pub fn validate(
+    name: &str,
+    trigger: &AutomodTrigger,
+    action: &AutomodAction,
+) -> Result<(), InvalidRule> {
+    if name.trim().is_empty() || name.len() > MAX_RULE_NAME_LEN {
+        return Err(InvalidRule);
+    }
+
+    if let AutomodAction::Mute { minutes } = action {
+        if *minutes == 0 || *minutes > MAX_MUTE_MINUTES {
+            return Err(InvalidRule);
+        }
+    }
+
+    Matcher::new(trigger).map(|_| ())
+}
+
+struct CompiledRule {
+    rule: AutomodRule,
+    matcher: Matcher,
+}
+
+/// The automod rules of a single community, compiled so that each message can be checked quickly
+#[derive(Default)]
+pub struct Automod {
+    rules: Vec<CompiledRule>,
+    /// When each member recently sent messages, for flood rules. Empty if there are none.
+    recently_sent: HashMap<UserId, VecDeque<DateTime<Utc>>>,
+    /// The longest window of any flood rule
+    flood_window: Option<Duration>,
+}
+
+impl Automod {
+    pub fn new(rules: Vec<AutomodRule>) -> Automod {
+        let mut automod = Automod::default();
+        for rule in rules {
+            automod.add(rule);
+        }
+
+        automod
+    }
+
+    /// Rules which do not compile are ignored, since they must have been valid when they were
+    /// created
+    pub fn add(&mut self, rule: AutomodRule) {
+        match Matcher::new(&rule.trigger) {
+            Ok(matcher) => self.rules.push(CompiledRule { rule, matcher }),
+            Err(InvalidRule) => warn!("Automod rule {} no longer compiles, ignoring", rule.id),
+        }
+
+        self.update_flood_window();
+    }
+
+    pub fn remove(&mut self, id: i32) {
+        self.rules.retain(|compiled| compiled.rule.id != id);
+        self.update_flood_window();
+    }
+
+    fn update_flood_window(&mut self) {
+        self.flood_window = self
+            .rules
+            .iter()
+            .filter_map(|compiled| match compiled.matcher {
+                Matcher::Flood { window, .. } => Some(window),
+                _ => None,
+            })
+            .max();
+
+        if self.flood_window.is_none() {
+            self.recently_sent.clear();
+        }
+    }
+
+    /// Records that the user sent a message, and returns the first rule that it triggers, if any
+    pub fn check(&mut self, user: UserId, text: &str) -> Option<AutomodRule> {
+        let now = Utc::now();
+        let empty = VecDeque::new();
+
+        let sent = match self.flood_window {
+            Some(window) => {
+                let since = now - window;
+                self.recently_sent.retain(|_, sent| {
+                    while sent.front().map(|at| *at <= since).unwrap_or(false) {
+                        sent.pop_front();
+                    }
+
+                    !sent.is_empty()
+                });
+
+                let sent = self.recently_sent.entry(user).or_default();
+                sent.push_back(now);
+                &*sent
+            }
+            None => &empty,
+        };
+
+        first_match(&self.rules, text, sent, now)
+    }
+
+    /// Returns the first rule that the new content of an edited message triggers, if any. Edits
+    /// are not sent messages, so they never trigger flood rules.
+    pub fn check_edit(&self, text: &str) -> Option<AutomodRule> {
+        first_match(&self.rules, text, &VecDeque::new(), Utc::now())
+    }
+}
+
+fn first_match(
+    rules: &[CompiledRule],
+    text: &str,
+    sent: &VecDeque<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Option<AutomodRule> {
+    rules
+        .iter()
+        .find(|compiled| compiled.matcher.matches(text, sent, now))
+        .map(|compiled| compiled.rule.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(trigger: AutomodTrigger, text: &str) -> bool {
+        let matcher = Matcher::new(&trigger).ok().expect("Rule should be valid");
+        matcher.matches(text, &VecDeque::new(), Utc::now())
+    }
+
+    fn words(list: &[&str]) -> Vec<String> {
+        list.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn banned_words_match_whole_words_ignoring_case() {
+        let trigger = || AutomodTrigger::BannedWords(words(&["heck", "a.b"]));
+
+        assert!(matches(trigger(), "what the HECK"));
+        assert!(matches(trigger(), "heck!"));
+        assert!(matches(trigger(), "see a.b"));
+        assert!(!matches(trigger(), "checking"));
+        assert!(!matches(trigger(), "see axb"));
+    }
+
+    #[test]
+    fn patterns() {
+        let trigger = || AutomodTrigger::Pattern(r"\d{4}-\d{4}".to_string());
+
+        assert!(matches(trigger(), "call 1234-5678"));
+        assert!(!matches(trigger(), "call 123-5678"));
+    }
+
+    #[test]
+    fn denied_domains_include_subdomains() {
+        let trigger = || AutomodTrigger::DeniedDomains(words(&["Example.com"]));
+
+        assert!(matches(trigger(), "see https://example.com/page"));
+        assert!(matches(trigger(), "see https://www.EXAMPLE.com"));
+        assert!(!matches(trigger(), "see https://notexample.com"));
+        assert!(!matches(trigger(), "example.com without a scheme"));
+    }
+
+    #[test]
+    fn allowed_domains() {
+        let trigger = || AutomodTrigger::AllowedDomains(words(&["example.com"]));
+
+        assert!(!matches(trigger(), "see https://docs.example.com"));
+        assert!(!matches(trigger(), "no links here"));
+        assert!(matches(
+            trigger(),
+            "https://example.com and https://example.org"
+        ));
+    }
+
+    #[test]
+    fn invite_links() {
+        assert!(matches(
+            AutomodTrigger::InviteLinks,
+            "join https://chat.example.com/vertex/invite/abc"
+        ));
+        assert!(!matches(
+            AutomodTrigger::InviteLinks,
+            "see https://chat.example.com/about"
+        ));
+    }
+
+    #[test]
+    fn mention_spam() {
+        let trigger = || AutomodTrigger::MentionSpam { max_mentions: 2 };
+
+        assert!(!matches(trigger(), "@a @b hello"));
+        assert!(matches(trigger(), "@a @b @c hello"));
+        assert!(!matches(trigger(), "@ @ @ @"));
+    }
+
+    #[test]
+    fn flood() {
+        let trigger = AutomodTrigger::Flood {
+            max_messages: 2,
+            seconds: 10,
+        };
+        let matcher = Matcher::new(&trigger).ok().unwrap();
+
+        let now = Utc::now();
+        let old = now - Duration::seconds(30);
+        let recent = now - Duration::seconds(5);
+
+        let sent: VecDeque<_> = vec![old, old, recent, now].into_iter().collect();
+        assert!(!matcher.matches("", &sent, now));
+
+        let sent: VecDeque<_> = vec![recent, recent, now].into_iter().collect();
+        assert!(matcher.matches("", &sent, now));
+    }
+
+    fn rule(id: i32, trigger: AutomodTrigger) -> AutomodRule {
+        AutomodRule {
+            id,
+            name: format!("rule {}", id),
+            trigger,
+            action: AutomodAction::Block,
+        }
+    }
+
+    #[test]
+    fn edits_are_checked_but_do_not_flood() {
+        let flood = AutomodTrigger::Flood {
+            max_messages: 1,
+            seconds: 60,
+        };
+        let words = AutomodTrigger::BannedWords(words(&["heck"]));
+        let automod = Automod::new(vec![rule(1, flood), rule(2, words)]);
+
+        for _ in 0..3 {
+            assert!(automod.check_edit("hello").is_none());
+        }
+        assert_eq!(automod.check_edit("heck").map(|rule| rule.id), Some(2));
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let invalid = |trigger: AutomodTrigger| Matcher::new(&trigger).is_err();
+
+        assert!(invalid(AutomodTrigger::BannedWords(Vec::new())));
+        assert!(invalid(AutomodTrigger::BannedWords(words(&["  "]))));
+        assert!(invalid(AutomodTrigger::Pattern(String::new())));
+        assert!(invalid(AutomodTrigger::Pattern("(".to_string())));
+        assert!(invalid(AutomodTrigger::Flood {
+            max_messages: 0,
+            seconds: 10
+        }));
+        assert!(invalid(AutomodTrigger::Flood {
+            max_messages: 5,
+            seconds: MAX_FLOOD_SECONDS + 1
+        }));
+
+        let action = AutomodAction::Mute { minutes: 0 };
+        assert!(validate("rule", &AutomodTrigger::InviteLinks, &action).is_err());
+        assert!(validate(" ", &AutomodTrigger::InviteLinks, &AutomodAction::Block).is_err());
+        assert!(validate("rule", &AutomodTrigger::InviteLinks, &AutomodAction::Flag).is_ok());
+    }
+}
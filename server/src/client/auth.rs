@@ -6,7 +6,8 @@ use vertex::prelude::*;
 
 use crate::auth;
 use crate::auth::HashSchemeVersion;
-use crate::database;
+use crate::config::RegistrationMode;
+use crate::database::{self, DbResult};
 
 pub struct Authenticator {
     pub global: crate::Global,
@@ -17,7 +18,7 @@ impl Authenticator {
         &self,
         device: DeviceId,
         pass: AuthToken,
-    ) -> Result<(UserId, DeviceId, TokenPermissionFlags, HashSchemeVersion), AuthError> {
+    ) -> Result<(UserId, DeviceId, TokenPermissionFlags, HashSchemeVersion, bool), AuthError> {
         let token = match self.global.database.get_token(device).await? {
             Some(token) => token,
             None => return Err(AuthError::InvalidToken),
@@ -29,14 +30,11 @@ impl Authenticator {
         };
 
         // Check if can log in with this token
-        if user.locked {
-            return Err(AuthError::UserLocked);
-        } else if user.banned {
-            return Err(AuthError::UserBanned);
-        } else if user.compromised {
+        check_restrictions(&user)?;
+        if user.compromised {
             return Err(AuthError::UserCompromised);
         } else if (Utc::now() - token.last_used).num_days()
-            > self.global.config.token_stale_days as i64
+            > self.global.config().token_stale_days as i64
         {
             return Err(AuthError::StaleToken);
         }
@@ -61,35 +59,77 @@ impl Authenticator {
             return Err(AuthError::InvalidToken);
         }
 
-        Ok((user_id, device, permission_flags, user.hash_scheme_version))
+        Ok((
+            user_id,
+            device,
+            permission_flags,
+            user.hash_scheme_version,
+            user.bot,
+        ))
     }
 
     pub async fn create_user(
         &self,
         credentials: Credentials,
         display_name: String,
+        registration_token: Option<String>,
     ) -> AuthResponse {
-        if !auth::valid_password(&credentials.password, &self.global.config) {
+        // Registration tokens are only needed when registration is restricted
+        let mode = self.global.config().registration_mode;
+        let registration_token = match mode {
+            RegistrationMode::Open => None,
+            RegistrationMode::Closed => return AuthResponse::Err(AuthError::RegistrationClosed),
+            RegistrationMode::ServerInviteToken if registration_token.is_none() => {
+                return AuthResponse::Err(AuthError::RegistrationTokenRequired);
+            }
+            RegistrationMode::ServerInviteToken | RegistrationMode::AdminApproval => {
+                registration_token
+            }
+        };
+
+        if !auth::valid_password(&credentials.password, &self.global.config()) {
             return AuthResponse::Err(AuthError::InvalidPassword);
         }
 
-        let username = match auth::prepare_username(&credentials.username, &self.global.config) {
+        let username = match auth::prepare_username(&credentials.username, &self.global.config()) {
             Ok(name) => name,
             Err(auth::TooShort) => return AuthResponse::Err(AuthError::InvalidUsername),
         };
 
-        if !auth::valid_display_name(&display_name, &self.global.config) {
+        if !auth::valid_display_name(&display_name, &self.global.config()) {
             return AuthResponse::Err(AuthError::InvalidDisplayName);
         }
 
+        // A use of the token is claimed before the account is created, so that it can't be used
+        // more times than it allows by registering at the same time
+        let db = &self.global.database;
+        let claimed = match registration_token {
+            Some(token) => match db.claim_registration_token(token).await? {
+                Some(claimed) => Some(claimed),
+                None => return AuthResponse::Err(AuthError::InvalidRegistrationToken),
+            },
+            None => None,
+        };
+
         let (hash, hash_version) = auth::hash(credentials.password).await;
 
-        let user = database::UserRecord::new(username, display_name, hash, hash_version);
+        let pending_approval = mode == RegistrationMode::AdminApproval && claimed.is_none();
+        let user = database::UserRecord {
+            pending_approval,
+            ..database::UserRecord::new(username, display_name, hash, hash_version)
+        };
         let user_id = user.id;
 
-        match self.global.database.create_user(user).await? {
+        match db.create_user(user).await? {
+            Ok(()) if pending_approval => AuthResponse::Ok(AuthOk::PendingApproval(user_id)),
             Ok(()) => AuthResponse::Ok(AuthOk::User(user_id)),
-            Err(_) => AuthResponse::Err(AuthError::UsernameAlreadyExists),
+            Err(_) => {
+                if let Some(claimed) = claimed {
+                    db.release_registration_token(claimed).await?;
+                }
+
+                AuthResponse::Err(AuthError::UsernameAlreadyExists)
+            }
         }
     }
 
@@ -103,6 +143,21 @@ impl Authenticator {
             _ => return AuthResponse::Err(AuthError::InvalidMessage),
         };
 
+        // Tell banned and locked users why, now that they have proven who they are
+        match self.global.database.get_user_by_id(user).await? {
+            Some(record) => check_restrictions(&record)?,
+            None => return AuthResponse::Err(AuthError::InvalidUser),
+        }
+
+        AuthResponse::Ok(AuthOk::Token(self.issue_token(user, options).await?))
+    }
+
+    /// Generates and stores a new token for the given user, without checking any credentials.
+    pub async fn issue_token(
+        &self,
+        user: UserId,
+        options: TokenCreationOptions,
+    ) -> DbResult<NewToken> {
         let mut token_bytes: [u8; 32] = [0; 32]; // 256 bits
         rand::thread_rng().fill_bytes(&mut token_bytes);
 
@@ -130,10 +185,10 @@ impl Authenticator {
             panic_error!("Newly generated UUID conflicts with another!");
         }
 
-        AuthResponse::Ok(AuthOk::Token(NewToken {
+        Ok(NewToken {
             device,
             token: auth_token,
-        }))
+        })
     }
 
     pub async fn refresh_token(
@@ -165,7 +220,7 @@ impl Authenticator {
         old_credentials: Credentials,
         new_password: String,
     ) -> AuthResponse {
-        if !auth::valid_password(&new_password, &self.global.config) {
+        if !auth::valid_password(&new_password, &self.global.config()) {
             return AuthResponse::Err(AuthError::InvalidPassword);
         }
 
@@ -193,7 +248,7 @@ impl Authenticator {
     }
 
     async fn verify_credentials(&self, credentials: Credentials) -> AuthResponse {
-        let username = auth::normalize_username(&credentials.username, &self.global.config);
+        let username = auth::normalize_username(&credentials.username, &self.global.config());
         let password = credentials.password;
 
         let user = match self.global.database.get_user_by_name(username).await? {
@@ -209,3 +264,16 @@ impl Authenticator {
         }
     }
 }
+
+/// Bans and locks which have expired but have not yet been swept are ignored
+fn check_restrictions(user: &database::UserRecord) -> Result<(), AuthError> {
+    if let Some(lock) = user.locked.as_ref().filter(|lock| lock.is_active()) {
+        Err(AuthError::UserLocked(lock.clone()))
+    } else if let Some(ban) = user.banned.as_ref().filter(|ban| ban.is_active()) {
+        Err(AuthError::UserBanned(ban.clone()))
+    } else if user.pending_approval {
+        Err(AuthError::PendingApproval)
+    } else {
+        Ok(())
+    }
+}
@@ -2,7 +2,10 @@ use super::manager;
 use crate::auth::HashSchemeVersion;
 use crate::client::session::LogoutThisSession;
 use crate::client::Session;
+use crate::community;
+use crate::database::{AuditEvent, ClaimReportError};
 use crate::handle_disconnected;
+use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
 use vertex::prelude::*;
 use xtra::prelude::*;
@@ -24,9 +27,11 @@ impl super::ActiveSession {
         request: AdminRequest,
     ) -> Result<OkResponse, Error> {
         match request {
-            AdminRequest::Ban(user) => self.ban(user).await,
+            AdminRequest::Ban { user, restriction } => self.ban(user, restriction).await,
             AdminRequest::Unban(user) => self.unban(user).await,
             AdminRequest::Unlock(user) => self.unlock(user).await,
+            AdminRequest::Mute { user, restriction } => self.mute(user, restriction).await,
+            AdminRequest::Unmute(user) => self.unmute(user).await,
             AdminRequest::Promote { user, permissions } => self.promote(user, permissions).await,
             AdminRequest::Demote(user) => self.demote(user).await,
             AdminRequest::SearchUser { name } => self.search_user(name).await,
@@ -37,6 +42,31 @@ impl super::ActiveSession {
                 self.set_report_status(id, status).await
             }
             AdminRequest::SetAccountsCompromised(typ) => self.set_accounts_compromised(typ).await,
+            AdminRequest::ReloadConfig => self.reload_config().await,
+            AdminRequest::SearchAuditLog(criteria) => self.search_audit_log(criteria).await,
+            AdminRequest::ClaimReport(id) => self.claim_report(id, true).await,
+            AdminRequest::UnclaimReport(id) => self.claim_report(id, false).await,
+            AdminRequest::AddReportNote { id, text } => self.add_report_note(id, text).await,
+            AdminRequest::ResolveReport {
+                id,
+                ban_user,
+                delete_message,
+            } => self.resolve_report(id, ban_user, delete_message).await,
+            AdminRequest::GetReportContext(id) => self.get_report_context(id).await,
+            AdminRequest::CreateRegistrationToken {
+                expiration_datetime,
+                max_uses,
+            } => {
+                self.create_registration_token(expiration_datetime, max_uses)
+                    .await
+            }
+            AdminRequest::ListRegistrationTokens => self.list_registration_tokens().await,
+            AdminRequest::RevokeRegistrationToken(token) => {
+                self.revoke_registration_token(token).await
+            }
+            AdminRequest::ListPendingUsers => self.list_pending_users().await,
+            AdminRequest::ApproveUser(user) => self.approve_user(user).await,
+            AdminRequest::RejectUser(user) => self.reject_user(user).await,
             _ => Err(Error::Unimplemented),
         }
     }
@@ -50,26 +80,125 @@ impl super::ActiveSession {
         Ok(perms.contains(AdminPermissionFlags::ALL) || perms.contains(check))
     }
 
-    async fn ban(&mut self, user: UserId) -> Result<OkResponse, Error> {
+    /// Whether the user is an admin at all, whichever permissions they have
+    fn is_admin(&self) -> Result<bool, Error> {
+        Ok(!self.admin_perms()?.is_empty())
+    }
+
+    /// An audit event for an action taken by this user on a report
+    fn audit_report_event(&self, action: AuditAction, id: i32) -> AuditEvent {
+        AuditEvent {
+            target: Some(format!("report #{}", id)),
+            ..AuditEvent::new(Some(self.user), action)
+        }
+    }
+
+    /// Records an admin action taken by this user in the audit log
+    async fn audit(&self, event: AuditEvent) -> Result<(), Error> {
+        Ok(self.global.database.log_audit_event(event).await?)
+    }
+
+    /// An audit event for an action taken by this user on another user
+    fn audit_user_event(&self, action: AuditAction, user: UserId) -> AuditEvent {
+        AuditEvent {
+            target_user: Some(user),
+            ..AuditEvent::new(Some(self.user), action)
+        }
+    }
+
+    async fn reload_config(&mut self) -> Result<OkResponse, Error> {
+        if !self.has_admin_perms(AdminPermissionFlags::ALL)? {
+            return Err(Error::AccessDenied);
+        }
+
+        crate::reload_config(&self.global).map_err(|_| Error::InvalidConfig)?;
+        self.audit(AuditEvent::new(Some(self.user), AuditAction::ReloadConfig)).await?;
+        Ok(OkResponse::NoData)
+    }
+
+    async fn ban(&mut self, user: UserId, ban: Restriction) -> Result<OkResponse, Error> {
+        self.check_can_restrict(user, &ban).await?;
+
+        let parameters = restriction_parameters(&ban);
+        self.global
+            .database
+            .set_banned(user, Some(ban))
+            .await?
+            .map_err(|_| Error::InvalidUser)?;
+
+        // They will be told why they were banned when they try to log back in
+        manager::remove_and_notify_user(user);
+
+        let event = AuditEvent {
+            parameters,
+            ..self.audit_user_event(AuditAction::Ban, user)
+        };
+        self.audit(event).await?;
+        Ok(OkResponse::NoData)
+    }
+
+    async fn mute(&mut self, user: UserId, mute: Restriction) -> Result<OkResponse, Error> {
+        self.check_can_restrict(user, &mute).await?;
+
+        let parameters = restriction_parameters(&mute);
+        self.global
+            .database
+            .set_muted(user, Some(mute.clone()))
+            .await?
+            .map_err(|_| Error::InvalidUser)?;
+        manager::set_muted(user, Some(mute));
+
+        let event = AuditEvent {
+            parameters,
+            ..self.audit_user_event(AuditAction::Mute, user)
+        };
+        self.audit(event).await?;
+        Ok(OkResponse::NoData)
+    }
+
+    async fn unmute(&mut self, user: UserId) -> Result<OkResponse, Error> {
         if !self.has_admin_perms(AdminPermissionFlags::BAN)? {
             return Err(Error::AccessDenied);
         }
 
-        let db = &self.global.database;
-        let their_perms = db
+        self.global
+            .database
+            .set_muted(user, None)
+            .await?
+            .map_err(|_| Error::InvalidUser)?;
+        manager::set_muted(user, None);
+
+        self.audit(self.audit_user_event(AuditAction::Unmute, user)).await?;
+        Ok(OkResponse::NoData)
+    }
+
+    /// Checks that this admin may ban or mute the user, and that the restriction is valid
+    async fn check_can_restrict(
+        &self,
+        user: UserId,
+        restriction: &Restriction,
+    ) -> Result<(), Error> {
+        if !self.has_admin_perms(AdminPermissionFlags::BAN)? {
+            return Err(Error::AccessDenied);
+        }
+
+        if restriction.reason.len() > self.global.config().max_message_len as usize {
+            return Err(Error::TooLong);
+        }
+
+        let their_perms = self
+            .global
+            .database
             .get_admin_permissions(user)
             .await
             .map_err(|_| Error::InvalidUser)?; // Error assumes that we are getting own user
 
-        // Don't allow banning more privileged users
+        // Don't allow restricting more privileged users
         if their_perms.contains(self.admin_perms()?) {
             return Err(Error::AccessDenied);
         }
 
-        db.set_banned(user, true)
-            .await?
-            .map_err(|_| Error::InvalidUser)
-            .map(|_| OkResponse::NoData)
+        Ok(())
     }
 
     async fn unban(&mut self, user: UserId) -> Result<OkResponse, Error> {
@@ -79,10 +208,12 @@ impl super::ActiveSession {
 
         let db = &self.global.database;
 
-        db.set_banned(user, false)
+        db.set_banned(user, None)
             .await?
-            .map_err(|_| Error::InvalidUser)
-            .map(|_| OkResponse::NoData)
+            .map_err(|_| Error::InvalidUser)?;
+
+        self.audit(self.audit_user_event(AuditAction::Unban, user)).await?;
+        Ok(OkResponse::NoData)
     }
 
     async fn unlock(&mut self, user: UserId) -> Result<OkResponse, Error> {
@@ -92,10 +223,12 @@ impl super::ActiveSession {
 
         let db = &self.global.database;
 
-        db.set_locked(user, false)
+        db.set_locked(user, None)
             .await?
-            .map_err(|_| Error::InvalidUser)
-            .map(|_| OkResponse::NoData)
+            .map_err(|_| Error::InvalidUser)?;
+
+        self.audit(self.audit_user_event(AuditAction::Unlock, user)).await?;
+        Ok(OkResponse::NoData)
     }
 
     async fn promote(
@@ -129,6 +262,12 @@ impl super::ActiveSession {
 
         notify_of_admin_perm_change(user, perms);
 
+        let event = AuditEvent {
+            parameters: serde_json::json!({ "permissions": perms.bits() }),
+            ..self.audit_user_event(AuditAction::Promote, user)
+        };
+        self.audit(event).await?;
+
         Ok(OkResponse::NoData)
     }
 
@@ -157,6 +296,7 @@ impl super::ActiveSession {
             .map_err(|_| Error::InvalidUser)?;
 
         notify_of_admin_perm_change(user, no_perms);
+        self.audit(self.audit_user_event(AuditAction::Demote, user)).await?;
 
         Ok(OkResponse::NoData)
     }
@@ -180,8 +320,7 @@ impl super::ActiveSession {
     }
 
     async fn search_reports(&mut self, criteria: SearchCriteria) -> Result<OkResponse, Error> {
-        let stream = self.global.database.search_reports(criteria).await?;
-        let reports: Vec<Report> = stream.try_collect().await?;
+        let reports = self.global.database.search_reports(criteria).await?;
         Ok(OkResponse::Admin(AdminResponse::Reports(reports)))
     }
 
@@ -191,6 +330,219 @@ impl super::ActiveSession {
         status: ReportStatus,
     ) -> Result<OkResponse, Error> {
         self.global.database.set_report_status(id, status).await?;
+
+        let event = AuditEvent {
+            parameters: serde_json::json!({ "status": status }),
+            ..self.audit_report_event(AuditAction::SetReportStatus, id)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn claim_report(&mut self, id: i32, claim: bool) -> Result<OkResponse, Error> {
+        if !self.is_admin()? {
+            return Err(Error::AccessDenied);
+        }
+
+        let assignee = if claim { Some(self.user) } else { None };
+        let res = self
+            .global
+            .database
+            .set_report_assignee(id, self.user, assignee)
+            .await?;
+
+        match res {
+            Ok(()) => Ok(OkResponse::NoData),
+            Err(ClaimReportError::NonexistentReport) => Err(Error::InvalidReport),
+            Err(ClaimReportError::AlreadyClaimed) => Err(Error::ReportAlreadyClaimed),
+        }
+    }
+
+    async fn add_report_note(&mut self, id: i32, text: String) -> Result<OkResponse, Error> {
+        if !self.is_admin()? {
+            return Err(Error::AccessDenied);
+        }
+
+        if text.len() > self.global.config().max_message_len as usize {
+            return Err(Error::TooLong);
+        }
+
+        self.global
+            .database
+            .add_report_note(id, self.user, text)
+            .await?
+            .map_err(|_| Error::InvalidReport)?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn get_report_context(&mut self, id: i32) -> Result<OkResponse, Error> {
+        if !self.is_admin()? {
+            return Err(Error::AccessDenied);
+        }
+
+        match self.global.database.get_report_context(id).await? {
+            Some(context) => Ok(OkResponse::Admin(AdminResponse::ReportContext(context))),
+            None => Err(Error::InvalidReport),
+        }
+    }
+
+    /// Acts on a report by banning the reported user and/or deleting the reported message, and
+    /// then marks it as accepted
+    async fn resolve_report(
+        &mut self,
+        id: i32,
+        ban_user: bool,
+        delete_message: bool,
+    ) -> Result<OkResponse, Error> {
+        if !self.is_admin()? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.global.database;
+        let report = db.get_report(id).await?.ok_or(Error::InvalidReport)?;
+
+        let reported = report.reported.map(|user| user.id);
+        if ban_user {
+            // Reports of rooms and communities have no user to ban
+            let ban = Restriction {
+                reason: format!("Report #{}: {}", id, report.short_desc),
+                expires: None,
+            };
+            self.ban(reported.ok_or(Error::InvalidUser)?, ban).await?;
+        }
+
+        // The message may have already been deleted since it was reported
+        let message = report.message.and_then(|msg| msg.id).filter(|_| delete_message);
+        if let Some(message) = message {
+            if let Some(delete) = self.global.database.delete_message(message).await? {
+                let event = AuditEvent {
+                    target_user: reported,
+                    community: Some(delete.community),
+                    target: Some(message.0.to_string()),
+                    parameters: serde_json::json!({ "report": id }),
+                    ..AuditEvent::new(Some(self.user), AuditAction::DeleteMessage)
+                };
+                self.audit(event).await?;
+
+                community::notify_deleted(vec![delete]);
+            }
+        }
+
+        self.set_report_status(id, ReportStatus::Accepted).await
+    }
+
+    async fn search_audit_log(&mut self, criteria: AuditLogCriteria) -> Result<OkResponse, Error> {
+        // Any admin may view the audit log, whichever permissions they have
+        if !self.is_admin()? {
+            return Err(Error::AccessDenied);
+        }
+
+        let stream = self.global.database.search_audit_log(criteria).await?;
+        let entries: Vec<AuditLogEntry> = stream.try_collect().await?;
+        Ok(OkResponse::Admin(AdminResponse::AuditLog(entries)))
+    }
+
+    async fn create_registration_token(
+        &mut self,
+        expiration_date: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
+    ) -> Result<OkResponse, Error> {
+        if !self.has_admin_perms(AdminPermissionFlags::MANAGE_REGISTRATION)? {
+            return Err(Error::AccessDenied);
+        }
+
+        // Stored as an INTEGER, so it must also fit in an i32
+        if let Some(max_uses) = max_uses {
+            if max_uses == 0 || max_uses > i32::max_value() as u32 {
+                return Err(Error::InvalidMaxUses);
+            }
+        }
+
+        let token = self
+            .global
+            .database
+            .create_registration_token(Some(self.user), expiration_date, max_uses)
+            .await?;
+
+        // The token itself is left out, since anyone who can read the audit log could use it
+        let event = AuditEvent {
+            parameters: serde_json::json!({
+                "expires": expiration_date,
+                "max_uses": max_uses,
+            }),
+            ..AuditEvent::new(Some(self.user), AuditAction::CreateRegistrationToken)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::Admin(AdminResponse::RegistrationToken(token)))
+    }
+
+    async fn list_registration_tokens(&mut self) -> Result<OkResponse, Error> {
+        if !self.has_admin_perms(AdminPermissionFlags::MANAGE_REGISTRATION)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let tokens = self.global.database.get_registration_tokens().await?;
+        Ok(OkResponse::Admin(AdminResponse::RegistrationTokens(tokens)))
+    }
+
+    async fn revoke_registration_token(&mut self, token: String) -> Result<OkResponse, Error> {
+        if !self.has_admin_perms(AdminPermissionFlags::MANAGE_REGISTRATION)? {
+            return Err(Error::AccessDenied);
+        }
+
+        self.global
+            .database
+            .delete_registration_token(token)
+            .await?
+            .map_err(|_| Error::InvalidRegistrationToken)?;
+
+        self.audit(AuditEvent::new(Some(self.user), AuditAction::RevokeRegistrationToken))
+            .await?;
+        Ok(OkResponse::NoData)
+    }
+
+    async fn list_pending_users(&mut self) -> Result<OkResponse, Error> {
+        if !self.has_admin_perms(AdminPermissionFlags::MANAGE_REGISTRATION)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let users = self.global.database.get_pending_users().await?;
+        Ok(OkResponse::Admin(AdminResponse::PendingUsers(users)))
+    }
+
+    async fn approve_user(&mut self, user: UserId) -> Result<OkResponse, Error> {
+        if !self.has_admin_perms(AdminPermissionFlags::MANAGE_REGISTRATION)? {
+            return Err(Error::AccessDenied);
+        }
+
+        self.global
+            .database
+            .approve_user(user)
+            .await?
+            .map_err(|_| Error::InvalidUser)?;
+
+        self.audit(self.audit_user_event(AuditAction::ApproveUser, user)).await?;
+        Ok(OkResponse::NoData)
+    }
+
+    async fn reject_user(&mut self, user: UserId) -> Result<OkResponse, Error> {
+        if !self.has_admin_perms(AdminPermissionFlags::MANAGE_REGISTRATION)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.global.database;
+        let pending = db.get_user_by_id(user).await?.filter(|u| u.pending_approval);
+        if pending.is_none() {
+            return Err(Error::InvalidUser);
+        }
+
+        // Recorded beforehand, so that their username can still be looked up
+        self.audit(self.audit_user_event(AuditAction::RejectUser, user)).await?;
+
+        db.reject_user(user).await?.map_err(|_| Error::InvalidUser)?;
         Ok(OkResponse::NoData)
     }
 
@@ -209,6 +561,12 @@ impl super::ActiveSession {
             SetCompromisedType::OldHashes => db.set_accounts_with_old_hashes_compromised().await?,
         }
 
+        let event = AuditEvent {
+            parameters: serde_json::json!({ "type": typ }),
+            ..AuditEvent::new(Some(self.user), AuditAction::SetAccountsCompromised)
+        };
+        self.audit(event).await?;
+
         // Log out logged-in users
         super::manager::USERS.retain(|_, user| {
             if user.hash_scheme_version < HashSchemeVersion::LATEST || all {
@@ -232,6 +590,13 @@ impl super::ActiveSession {
     }
 }
 
+fn restriction_parameters(restriction: &Restriction) -> serde_json::Value {
+    serde_json::json!({
+        "reason": restriction.reason,
+        "expires": restriction.expires,
+    })
+}
+
 fn notify_of_admin_perm_change(user: UserId, new: AdminPermissionFlags) {
     let mut active = match manager::get_active_user_mut(user) {
         Ok(user) => user,
@@ -248,3 +613,22 @@ fn notify_of_admin_perm_change(user: UserId, new: AdminPermissionFlags) {
             let _ = a.admin_permissions_changed(new);
         });
 }
+
+/// Tells every online admin about a new report, so that it can be dealt with promptly
+pub fn notify_admins_of_report(report: Report) {
+    let sessions: Vec<_> = manager::USERS
+        .iter()
+        .filter(|user| !user.admin_perms.is_empty())
+        .flat_map(|user| {
+            user.sessions
+                .values()
+                .filter_map(Session::as_active_actor)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    let send = ServerMessage::Event(ServerEvent::NewReport(report));
+    for session in sessions {
+        let _ = session.send(send.clone());
+    }
+}
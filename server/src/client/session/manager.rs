@@ -19,6 +19,8 @@ pub struct ActiveUser {
     pub admin_perms: AdminPermissionFlags,
     /// Stored here so, in case of set to compromised, we can check if to log this user out
     pub hash_scheme_version: HashSchemeVersion,
+    /// Stored here so that it needn't be fetched every time the user sends a message
+    pub muted: Option<Restriction>,
 }
 
 impl ActiveUser {
@@ -31,6 +33,7 @@ impl ActiveUser {
     ) -> DbResult<Self> {
         let communities = db.get_communities_for_user(user).await?;
         let admin_perms = db.get_admin_permissions(user).await?;
+        let muted = db.get_user_by_id(user).await?.and_then(|user| user.muted);
         let db = &db; // To prevent move
 
         let communities = communities
@@ -50,6 +53,7 @@ impl ActiveUser {
             sessions,
             admin_perms,
             hash_scheme_version,
+            muted,
         })
     }
 }
@@ -95,10 +99,17 @@ impl Session {
 #[derive(Debug)]
 pub struct UserCommunity {
     pub rooms: HashMap<RoomId, UserRoom>,
+    pub permissions: CommunityPermissionFlags,
 }
 
 impl UserCommunity {
     pub async fn load(db: &Database, user: UserId, community: CommunityId) -> DbResult<Self> {
+        let permissions = db
+            .get_community_membership(community, user)
+            .await?
+            .map(|member| member.permissions)
+            .unwrap_or_default();
+
         let stream = db
             .get_user_room_states(user, community)
             .await?
@@ -114,7 +125,7 @@ impl UserCommunity {
 
         let rooms = stream.try_collect().await?;
 
-        Ok(UserCommunity { rooms })
+        Ok(UserCommunity { rooms, permissions })
     }
 }
 
@@ -193,6 +204,13 @@ pub fn remove_and_notify_user(user: UserId) {
     }
 }
 
+/// Updates the mute of the user, if they are logged in
+pub fn set_muted(user: UserId, muted: Option<Restriction>) {
+    if let Ok(mut active_user) = get_active_user_mut(user) {
+        active_user.muted = muted;
+    }
+}
+
 pub fn remove_and_notify_device(user: UserId, device: DeviceId) -> Result<(), Error> {
     match remove_device(user, device) {
         Some(Session::Active { actor, .. }) => actor
@@ -9,12 +9,14 @@ use warp::filters::ws::WebSocket;
 use xtra::prelude::*;
 use async_trait::async_trait;
 
+pub use administrator::notify_admins_of_report;
 pub use manager::*;
 use vertex::prelude::*;
 
 use crate::community::{self, Connect, CreateRoom, GetRoomInfo, Join, COMMUNITIES};
 use crate::database::*;
-use crate::{handle_disconnected, Global};
+use crate::ratelimit::Ratelimiters;
+use crate::{federation, handle_disconnected, metrics, Global};
 use regular_user::*;
 use std::fmt;
 use xtra::KeepRunning;
@@ -30,6 +32,14 @@ impl xtra::Message for LogoutThisSession {
     type Result = ();
 }
 
+/// Sent to every session when the server shuts down
+#[derive(Debug)]
+pub struct ShutDownSession;
+
+impl xtra::Message for ShutDownSession {
+    type Result = ();
+}
+
 struct CheckHeartbeat;
 
 impl xtra::Message for CheckHeartbeat {
@@ -62,6 +72,14 @@ pub struct AddRoom {
     pub structure: RoomStructure,
 }
 
+/// A request made over the REST API. The session is stopped once the request has been handled.
+#[derive(Debug)]
+pub struct ApiRequest(pub ClientRequest);
+
+impl xtra::Message for ApiRequest {
+    type Result = Result<OkResponse, Error>;
+}
+
 #[derive(Debug)]
 pub struct WsMessage(pub Result<ws::Message, warp::Error>);
 
@@ -87,14 +105,39 @@ impl Handler<WsMessage> for ActiveSession {
     }
 }
 
+#[spaad::entangled]
+#[async_trait]
+impl Handler<ApiRequest> for ActiveSession {
+    async fn handle(
+        &mut self,
+        m: ApiRequest,
+        ctx: &mut Context<Self>,
+    ) -> Result<OkResponse, Error> {
+        let (user, device, perms, bot) = (self.user, self.device, self.perms, self.bot);
+        let handler = RequestHandler {
+            session: self,
+            ctx,
+            user,
+            device,
+            perms,
+            bot,
+        };
+        let result = handler.handle_request(m.0).await;
+        ctx.stop();
+        result
+    }
+}
+
 #[spaad::entangled]
 pub struct ActiveSession {
-    pub ws: SplitSink<WebSocket, ws::Message>,
+    /// The websocket to the client. This is `None` for sessions serving a single REST API request.
+    pub ws: Option<SplitSink<WebSocket, ws::Message>>,
     pub global: crate::Global,
     pub heartbeat: Instant,
     pub user: UserId,
     pub device: DeviceId,
     pub perms: TokenPermissionFlags,
+    pub bot: bool,
 }
 
 #[spaad::entangled]
@@ -105,6 +148,7 @@ impl fmt::Debug for ActiveSession {
             .field("user", &self.user)
             .field("device", &self.device)
             .field("perms", &self.perms)
+            .field("bot", &self.bot)
             .finish()
     }
 }
@@ -112,6 +156,10 @@ impl fmt::Debug for ActiveSession {
 #[spaad::entangled]
 impl Actor for ActiveSession {
     fn started(&mut self, ctx: &mut Context<Self>) {
+        if self.ws.is_none() {
+            return;
+        }
+
         ctx.notify_immediately(NotifyClientReady);
         ctx.notify_interval(HEARTBEAT_TIMEOUT, || CheckHeartbeat);
     }
@@ -155,15 +203,31 @@ impl Handler<LogoutThisSession> for ActiveSession {
     }
 }
 
+#[spaad::entangled]
+#[async_trait]
+impl Handler<ShutDownSession> for ActiveSession {
+    async fn handle(&mut self, _: ShutDownSession, ctx: &mut Context<Self>) {
+        self.send(ServerMessage::Event(ServerEvent::ServerShuttingDown), ctx)
+            .await;
+
+        if let Some(ws) = &mut self.ws {
+            let _ = ws.close().await;
+        }
+
+        ctx.stop();
+    }
+}
+
 #[spaad::entangled]
 impl ActiveSession {
     #[spaad::spawn]
     pub fn new(
-        ws: SplitSink<WebSocket, ws::Message>,
+        ws: Option<SplitSink<WebSocket, ws::Message>>,
         global: Global,
         user: UserId,
         device: DeviceId,
         perms: TokenPermissionFlags,
+        bot: bool,
     ) -> Self {
         ActiveSession {
             ws,
@@ -172,11 +236,15 @@ impl ActiveSession {
             user,
             device,
             perms,
+            bot,
         }
     }
 
     async fn try_send<M: Into<Vec<u8>>>(&mut self, msg: M) -> Result<(), warp::Error> {
-        self.ws.send(ws::Message::binary(msg)).await
+        match &mut self.ws {
+            Some(ws) => ws.send(ws::Message::binary(msg)).await,
+            None => Ok(()),
+        }
     }
 
     #[spaad::handler]
@@ -203,6 +271,19 @@ impl ActiveSession {
             .contains_key(&id))
     }
 
+    /// Returns whether the user is in the community and holds the given permissions within it.
+    fn has_community_perms(
+        &self,
+        id: &CommunityId,
+        perms: CommunityPermissionFlags,
+    ) -> Result<bool, Error> {
+        let user = manager::get_active_user(self.user)?;
+        Ok(match user.communities.get(id) {
+            Some(community) => community.permissions.has_perms(perms),
+            None => false,
+        })
+    }
+
     // in future, this will change with permissioning
     fn in_room(&self, community: &CommunityId, room: &RoomId) -> Result<bool, Error> {
         let user = manager::get_active_user(self.user)?;
@@ -247,6 +328,9 @@ impl ActiveSession {
             .await?
             .ok_or(Error::InvalidUser)?;
 
+        // This is fetched first so that the active user is not held while waiting on other servers
+        let remote_communities = federation::remote_communities(&self.global, self.user).await?;
+
         let active = manager::get_active_user(self.user)?;
         let mut communities = Vec::with_capacity(active.communities.len());
 
@@ -264,6 +348,8 @@ impl ActiveSession {
                         id: info.id,
                         name: info.name,
                         unread: room.unread,
+                        encrypted: info.encrypted,
+                        settings: info.settings,
                     })
                 })
                 .collect::<Result<Vec<RoomStructure>, Error>>()?;
@@ -280,21 +366,26 @@ impl ActiveSession {
                 name: info.name.clone(),
                 description: info.description(),
                 rooms,
+                public: info.public,
             };
 
             communities.push(structure);
         }
 
+        communities.extend(remote_communities);
+
         let ready = ClientReady {
             user: self.user,
             profile: Profile {
                 version: user.profile_version,
                 username: user.username,
                 display_name: user.display_name,
+                bot: user.bot,
             },
             communities,
             permissions: self.perms,
             admin_permissions: active.admin_perms,
+            ratelimits: Ratelimiters::quotas(&self.global.config(), self.bot),
         };
 
         let msg = ServerMessage::Event(ServerEvent::ClientReady(ready));
@@ -309,24 +400,23 @@ impl ActiveSession {
         ctx: &mut Context<Self>,
     ) -> Result<(), warp::Error> {
         let message = message?;
-        {
-            let ratelimiter = self.global.ratelimiter.load();
-
-            if let Err(not_until) = ratelimiter.check_key(&self.device) {
-                self.try_send(ServerMessage::RateLimited {
-                    ready_in: not_until.wait_time_from(Instant::now()),
-                })
-                .await?;
-
-                return Ok(());
-            }
+        let ratelimited = self
+            .global
+            .ratelimiters
+            .load()
+            .check_general(self.device, self.bot);
+
+        if let Err(ready_in) = ratelimited {
+            metrics::record_ratelimited(self.bot);
+            self.try_send(ServerMessage::RateLimited { ready_in }).await?;
+            return Ok(());
         }
 
         if message.is_ping() || message.is_pong() {
             self.heartbeat = Instant::now();
 
-            if message.is_ping() {
-                self.ws.send(ws::Message::ping(vec![])).await?; // Doesn't let us send pong :(
+            if let (true, Some(ws)) = (message.is_ping(), &mut self.ws) {
+                ws.send(ws::Message::ping(vec![])).await?; // Doesn't let us send pong :(
             }
         } else if message.is_binary() {
             let msg = match ClientMessage::from_protobuf_bytes(message.as_bytes()) {
@@ -338,13 +428,14 @@ impl ActiveSession {
                 }
             };
 
-            let (user, device, perms) = (self.user, self.device, self.perms);
+            let (user, device, perms, bot) = (self.user, self.device, self.perms, self.bot);
             let handler = RequestHandler {
                 session: self,
                 ctx,
                 user,
                 device,
                 perms,
+                bot,
             };
             let result = handler.handle_request(msg.request).await;
 
@@ -1,29 +1,68 @@
 //! Methods that can be executed by regular users
 
+use std::collections::HashSet;
+
 use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
+use rand::RngCore;
+use uuid::Uuid;
 use xtra::Context;
 
-use crate::client::session::{manager, UserCommunity, UserRoom};
-use crate::community::CommunityActor;
+use crate::client::session::{administrator, manager, UserCommunity, UserRoom};
+use crate::client::Authenticator;
+use crate::database::AuditEvent;
+use crate::community::{
+    AddAutomodRule, AddOutgoingWebhook, Community, CommunityActor, InstallBot, RemoveAutomodRule,
+    RemoveOutgoingWebhook, SetRoomSettings,
+};
 use crate::community::COMMUNITIES;
-use crate::{auth, community, handle_disconnected, IdentifiedMessage};
+use crate::{
+    archive, auth, automod, community, federation, handle_disconnected, metrics,
+    outgoing_webhook, IdentifiedMessage,
+};
 
 use super::*;
 
+const MAX_WEBHOOK_URL_LEN: usize = 2048;
+const MAX_COMMANDS_PER_BOT: usize = 50;
+const MAX_COMMAND_ARGUMENTS: usize = 10;
+const MAX_COMMAND_NAME_LEN: usize = 32;
+const MAX_COMMAND_DESCRIPTION_LEN: usize = 256;
+const MAX_DEVICE_KEY_LEN: usize = 64;
+const MAX_PREKEYS_PER_DEVICE: u32 = 100;
+/// Devices are told to publish more prekeys once they have fewer than this many left
+const LOW_PREKEYS: u32 = 20;
+const PUBLIC_COMMUNITIES_PER_PAGE: u32 = 50;
+
 pub struct RequestHandler<'a> {
     pub session: &'a mut __ActiveSessionActor::ActiveSession,
     pub ctx: &'a mut Context<__ActiveSessionActor::ActiveSession>,
     pub user: UserId,
     pub device: DeviceId,
     pub perms: TokenPermissionFlags,
+    pub bot: bool,
 }
 
 impl<'a> RequestHandler<'a> {
     pub async fn handle_request(self, request: ClientRequest) -> Result<OkResponse, Error> {
+        let _timer = metrics::time_request(&request);
+
+        let ratelimited = self
+            .session
+            .global
+            .ratelimiters
+            .load()
+            .check_request(self.device, &request);
+
+        if let Err(e) = ratelimited {
+            metrics::record_ratelimited(self.bot);
+            return Err(e);
+        }
+
         match request {
             ClientRequest::SendMessage(message) => self.send_message(message).await,
             ClientRequest::EditMessage(edit) => self.edit_message(edit).await,
+            ClientRequest::Delete(delete) => self.delete_message(delete).await,
             ClientRequest::JoinCommunity(code) => self.join_community(code).await,
             ClientRequest::CreateCommunity { name } => self.create_community(name).await,
             ClientRequest::LogOut => self.log_out().await,
@@ -34,13 +73,19 @@ impl<'a> RequestHandler<'a> {
             ClientRequest::ChangeDisplayName { new_display_name } => {
                 self.change_display_name(new_display_name).await
             }
-            ClientRequest::CreateRoom { name, community } => {
-                self.create_room(name, community).await
-            }
+            ClientRequest::CreateRoom {
+                name,
+                community,
+                encrypted,
+            } => self.create_room(name, community, encrypted).await,
             ClientRequest::CreateInvite {
                 community,
                 expiration_datetime,
-            } => self.create_invite(community, expiration_datetime).await,
+                max_uses,
+            } => {
+                self.create_invite(community, expiration_datetime, max_uses)
+                    .await
+            }
             ClientRequest::GetRoomUpdate {
                 community,
                 room,
@@ -74,11 +119,110 @@ impl<'a> RequestHandler<'a> {
 
                 self.session.handle_admin_request(req).await
             }
-            ClientRequest::ReportUser {
-                message,
+            ClientRequest::Report {
+                target,
                 short_desc,
                 extended_desc,
-            } => self.report_user(message, short_desc, extended_desc).await,
+            } => self.report(target, short_desc, extended_desc).await,
+            ClientRequest::CreateBot {
+                username,
+                display_name,
+            } => self.create_bot(username, display_name).await,
+            ClientRequest::CreateBotToken { bot, options } => {
+                self.create_bot_token(bot, options).await
+            }
+            ClientRequest::InstallBot { bot, community } => {
+                self.install_bot(bot, community).await
+            }
+            ClientRequest::CreateWebhook {
+                community,
+                room,
+                name,
+            } => self.create_webhook(community, room, name).await,
+            ClientRequest::ListWebhooks { community } => self.list_webhooks(community).await,
+            ClientRequest::RevokeWebhook { community, webhook } => {
+                self.revoke_webhook(community, webhook).await
+            }
+            ClientRequest::CreateOutgoingWebhook {
+                community,
+                url,
+                events,
+            } => self.create_outgoing_webhook(community, url, events).await,
+            ClientRequest::ListOutgoingWebhooks { community } => {
+                self.list_outgoing_webhooks(community).await
+            }
+            ClientRequest::DeleteOutgoingWebhook { community, webhook } => {
+                self.delete_outgoing_webhook(community, webhook).await
+            }
+            ClientRequest::GetWebhookDeliveries { community, webhook } => {
+                self.get_webhook_deliveries(community, webhook).await
+            }
+            ClientRequest::RegisterCommands {
+                community,
+                commands,
+            } => self.register_commands(community, commands).await,
+            ClientRequest::ListCommands { community } => self.list_commands(community).await,
+            ClientRequest::InvokeCommand {
+                community,
+                room,
+                bot,
+                command,
+                arguments,
+            } => {
+                self.invoke_command(community, room, bot, command, arguments)
+                    .await
+            }
+            ClientRequest::PublishDeviceKeys {
+                identity_key,
+                one_time_prekeys,
+            } => self.publish_device_keys(identity_key, one_time_prekeys).await,
+            ClientRequest::GetDeviceKeys { user } => self.get_device_keys(user).await,
+            ClientRequest::ClaimDeviceKeys {
+                community,
+                except_devices,
+            } => self.claim_device_keys(community, except_devices).await,
+            ClientRequest::SetRetention {
+                community,
+                room,
+                days,
+            } => self.set_retention(community, room, days).await,
+            ClientRequest::ExportCommunity {
+                community,
+                include_invite_codes,
+            } => self.export_community(community, include_invite_codes).await,
+            ClientRequest::ImportCommunity { archive } => self.import_community(archive).await,
+            ClientRequest::AddAutomodRule {
+                community,
+                name,
+                trigger,
+                action,
+            } => self.add_automod_rule(community, name, trigger, action).await,
+            ClientRequest::ListAutomodRules { community } => {
+                self.list_automod_rules(community).await
+            }
+            ClientRequest::DeleteAutomodRule { community, rule } => {
+                self.delete_automod_rule(community, rule).await
+            }
+            ClientRequest::GetAutomodHits { community } => self.get_automod_hits(community).await,
+            ClientRequest::SetRoomSettings {
+                community,
+                room,
+                settings,
+            } => self.set_room_settings(community, room, settings).await,
+            ClientRequest::ListInvites { community } => self.list_invites(community).await,
+            ClientRequest::RevokeInvite { community, code } => {
+                self.revoke_invite(community, code).await
+            }
+            ClientRequest::GetInviteUses { community, code } => {
+                self.get_invite_uses(community, code).await
+            }
+            ClientRequest::SetCommunityPublic { community, public } => {
+                self.set_community_public(community, public).await
+            }
+            ClientRequest::ListPublicCommunities { query, offset } => {
+                self.list_public_communities(query, offset).await
+            }
+            ClientRequest::JoinPublicCommunity(id) => self.join_public_community(id).await,
             _ => Err(Error::Unimplemented),
         }
     }
@@ -88,14 +232,28 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::AccessDenied);
         }
 
-        if !self.session.in_community(&message.to_community)? {
-            return Err(Error::InvalidCommunity);
-        }
+        self.check_not_muted()?;
 
-        if message.content.len() > self.session.global.config.max_message_len as usize {
+        let config = self.session.global.config();
+        let ciphertext_len = message.ciphertext.as_ref().map(Vec::len).unwrap_or(0);
+        if message.content.len() > config.max_message_len as usize
+            || ciphertext_len > config.max_ciphertext_len as usize
+        {
             return Err(Error::MessageTooLong);
         }
 
+        if !self.session.in_community(&message.to_community)? {
+            let db = &self.session.global.database;
+            let server = db
+                .get_remote_community_membership(self.user, message.to_community)
+                .await?
+                .ok_or(Error::InvalidCommunity)?;
+
+            let confirmation =
+                federation::send_message(self.user, self.device, &server, message).await?;
+            return Ok(OkResponse::ConfirmMessage(confirmation));
+        }
+
         let community = community::address_of(message.to_community)?;
         let message = IdentifiedMessage {
             user: self.user,
@@ -115,11 +273,13 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::AccessDenied);
         }
 
+        self.check_not_muted()?;
+
         if !self.session.in_community(&edit.community)? {
             return Err(Error::InvalidCommunity);
         }
 
-        if edit.new_content.len() > self.session.global.config.max_message_len as usize {
+        if edit.new_content.len() > self.session.global.config().max_message_len as usize {
             return Err(Error::MessageTooLong);
         }
 
@@ -136,6 +296,26 @@ impl<'a> RequestHandler<'a> {
         Ok(OkResponse::NoData)
     }
 
+    /// Deletes one of the user's own messages
+    async fn delete_message(self, delete: Delete) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::DELETE_OWN_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_room(&delete.community, &delete.room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        let db = &self.session.global.database;
+        match db.delete_own_message(self.user, delete).await? {
+            Some(delete) => {
+                community::notify_deleted(vec![delete]);
+                Ok(OkResponse::NoData)
+            }
+            None => Err(Error::InvalidMessage),
+        }
+    }
+
     async fn log_out(self) -> Result<OkResponse, Error> {
         if let Err(NonexistentDevice) = self
             .session
@@ -164,8 +344,8 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::AccessDenied);
         }
 
-        let new_username = match auth::prepare_username(&new_username, &self.session.global.config)
-        {
+        let config = self.session.global.config();
+        let new_username = match auth::prepare_username(&new_username, &config) {
             Ok(name) => name,
             Err(auth::TooShort) => return Err(Error::InvalidUsername),
         };
@@ -189,7 +369,7 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::AccessDenied);
         }
 
-        if !auth::valid_display_name(&new_display_name, &self.session.global.config) {
+        if !auth::valid_display_name(&new_display_name, &self.session.global.config()) {
             return Err(Error::InvalidDisplayName);
         }
 
@@ -214,7 +394,7 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::AccessDenied);
         }
 
-        let max = self.session.global.config.max_community_name_len as usize;
+        let max = self.session.global.config().max_community_name_len as usize;
         if name.is_empty() || name.len() > max {
             return Err(Error::TooLong);
         }
@@ -227,8 +407,8 @@ impl<'a> RequestHandler<'a> {
 
         match res {
             Ok(_) => {
-                CommunityActor::create_and_spawn(name, id, db.clone(), self.user);
-                self.join_community_by_id(id).await
+                CommunityActor::create_and_spawn(name, id, self.session.global.clone(), self.user);
+                self.join_community_by_id(id, CommunityPermissionFlags::ALL).await
             }
             Err(_) => {
                 self.ctx.stop(); // The user did not exist at the time of request
@@ -242,26 +422,88 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::AccessDenied);
         }
 
+        let qualified = code.qualified();
+        let code = match qualified.server {
+            Some(server) if server != self.session.global.config().server_name => {
+                return self.join_remote_community(server, InviteCode(qualified.name)).await;
+            }
+            _ => InviteCode(qualified.name),
+        };
+
         if code.0.len() > 11 {
             return Err(Error::InvalidInviteCode);
         }
 
-        let database = &self.session.global.database;
-        let id = match database.get_community_from_invite_code(code).await? {
-            Ok(Some(id)) => id,
+        let database = self.session.global.database.clone();
+        let claimed = match database.claim_invite_code(code).await? {
+            Ok(Some(claimed)) => claimed,
             Ok(None) | Err(_) => return Err(Error::InvalidInviteCode),
         };
 
-        self.join_community_by_id(id).await
+        let user = self.user;
+        let id = claimed.community;
+
+        match self.join_community_by_id(id, CommunityPermissionFlags::empty()).await {
+            Ok(res) => {
+                database.record_invite_use(claimed, user).await?;
+                Ok(res)
+            }
+            Err(e) => {
+                database.release_invite_code(claimed).await?;
+                Err(e)
+            }
+        }
+    }
+
+    async fn join_public_community(self, id: CommunityId) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::JOIN_COMMUNITIES) {
+            return Err(Error::AccessDenied);
+        }
+
+        // Communities which are not public are treated as though they do not exist, so that they
+        // can't be discovered by guessing ids
+        let public = community::get(id).map(|info| info.public).unwrap_or(false);
+        if !public {
+            return Err(Error::InvalidCommunity);
+        }
+
+        self.join_community_by_id(id, CommunityPermissionFlags::empty()).await
+    }
+
+    async fn join_remote_community(
+        self,
+        server: String,
+        code: InviteCode,
+    ) -> Result<OkResponse, Error> {
+        let global = &self.session.global;
+        let community = federation::join(global, self.user, &server, code).await?;
+
+        if let Ok(user) = manager::get_active_user(self.user) {
+            let send = ServerMessage::Event(ServerEvent::AddCommunity(community.clone()));
+            user.sessions
+                .iter()
+                .filter(|(id, _)| **id != self.device)
+                .filter_map(|(_, session)| session.as_active_actor())
+                .for_each(|session| {
+                    let _ = session.send(send.clone());
+                });
+        }
+
+        Ok(OkResponse::AddCommunity(community))
     }
 
-    async fn join_community_by_id(self, id: CommunityId) -> Result<OkResponse, Error> {
+    async fn join_community_by_id(
+        self,
+        id: CommunityId,
+        permissions: CommunityPermissionFlags,
+    ) -> Result<OkResponse, Error> {
         let community = community::address_of(id)?;
 
         let join = Join {
             user: self.user,
             device_id: self.device,
             session: self.ctx.address().unwrap().into(),
+            permissions,
         };
 
         let res = community
@@ -297,7 +539,12 @@ impl<'a> RequestHandler<'a> {
         }
     }
 
-    async fn create_room(self, name: String, community: CommunityId) -> Result<OkResponse, Error> {
+    async fn create_room(
+        self,
+        name: String,
+        community: CommunityId,
+        encrypted: bool,
+    ) -> Result<OkResponse, Error> {
         if !self.perms.has_perms(TokenPermissionFlags::CREATE_ROOMS) {
             return Err(Error::AccessDenied);
         }
@@ -306,7 +553,7 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::InvalidCommunity);
         }
 
-        let max = self.session.global.config.max_channel_name_len as usize;
+        let max = self.session.global.config().max_channel_name_len as usize;
         if name.is_empty() || name.len() > max {
             return Err(Error::TooLong);
         }
@@ -317,6 +564,7 @@ impl<'a> RequestHandler<'a> {
         let create = CreateRoom {
             creator: self.device,
             name: name.clone(),
+            encrypted,
         };
         let id = community
             .send(create)
@@ -333,6 +581,8 @@ impl<'a> RequestHandler<'a> {
             id,
             name,
             unread: true,
+            encrypted,
+            settings: RoomSettings::default(),
         };
         community.rooms.insert(
             room.id,
@@ -352,6 +602,7 @@ impl<'a> RequestHandler<'a> {
         self,
         id: CommunityId,
         expiration_date: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
     ) -> Result<OkResponse, Error> {
         if !self.perms.has_perms(TokenPermissionFlags::CREATE_INVITES) {
             return Err(Error::AccessDenied);
@@ -361,10 +612,19 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::InvalidCommunity);
         }
 
+        // Stored as an INTEGER, so it must also fit in an i32
+        if let Some(max_uses) = max_uses {
+            if max_uses == 0 || max_uses > i32::max_value() as u32 {
+                return Err(Error::InvalidMaxUses);
+            }
+        }
+
         if COMMUNITIES.contains_key(&id) {
             let db = &self.session.global.database;
-            let max = self.session.global.config.max_invite_codes_per_community as i64;
-            let res = db.create_invite_code(id, expiration_date, max).await?;
+            let max = self.session.global.config().max_invite_codes_per_community as i64;
+            let res = db
+                .create_invite_code(id, self.user, expiration_date, max_uses, max)
+                .await?;
 
             match res {
                 Ok(code) => Ok(OkResponse::NewInvite(code)),
@@ -503,7 +763,14 @@ impl<'a> RequestHandler<'a> {
             community.name = new.clone();
             drop(community); // Drop lock
             let db = &self.session.global.database;
-            db.change_community_name(id, new).await?;
+            db.change_community_name(id, new.clone()).await?;
+
+            let event = AuditEvent {
+                parameters: serde_json::json!({ "name": new }),
+                ..self.community_event(AuditAction::ChangeCommunityName, id)
+            };
+            self.audit(event).await?;
+
             Ok(OkResponse::NoData)
         } else {
             Err(Error::InvalidCommunity)
@@ -523,16 +790,80 @@ impl<'a> RequestHandler<'a> {
             community.description = Some(new.clone());
             drop(community); // Drop lock
             let db = &self.session.global.database;
-            db.change_community_description(id, new).await?;
+            db.change_community_description(id, new.clone()).await?;
+
+            let event = AuditEvent {
+                parameters: serde_json::json!({ "description": new }),
+                ..self.community_event(AuditAction::ChangeCommunityDescription, id)
+            };
+            self.audit(event).await?;
+
+            Ok(OkResponse::NoData)
+        } else {
+            Err(Error::InvalidCommunity)
+        }
+    }
+
+    async fn set_community_public(
+        self,
+        id: CommunityId,
+        public: bool,
+    ) -> Result<OkResponse, Error> {
+        if !self
+            .session
+            .has_community_perms(&id, CommunityPermissionFlags::ALL)?
+        {
+            return Err(Error::AccessDenied);
+        }
+
+        if let Some(mut community) = COMMUNITIES.get_mut(&id) {
+            community.public = public;
+            drop(community); // Drop lock
+            let db = &self.session.global.database;
+            db.set_community_public(id, public).await?;
+
+            let event = AuditEvent {
+                parameters: serde_json::json!({ "public": public }),
+                ..self.community_event(AuditAction::SetCommunityPublic, id)
+            };
+            self.audit(event).await?;
+
             Ok(OkResponse::NoData)
         } else {
             Err(Error::InvalidCommunity)
         }
     }
 
-    async fn report_user(
+    async fn list_public_communities(
+        self,
+        query: String,
+        offset: u32,
+    ) -> Result<OkResponse, Error> {
+        if query.len() > self.session.global.config().max_community_name_len as usize {
+            return Err(Error::TooLong);
+        }
+
+        let db = &self.session.global.database;
+        let records = db
+            .search_public_communities(&query, offset, PUBLIC_COMMUNITIES_PER_PAGE)
+            .await?;
+
+        let communities = records
+            .into_iter()
+            .map(|record| PublicCommunity {
+                id: record.id,
+                name: record.name,
+                description: Community::desc_or_default(&record.description),
+                members: record.members,
+            })
+            .collect();
+
+        Ok(OkResponse::PublicCommunities(communities))
+    }
+
+    async fn report(
         self,
-        message: MessageId,
+        target: ReportTarget,
         short_desc: String,
         extended_desc: String,
     ) -> Result<OkResponse, Error> {
@@ -540,29 +871,965 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::AccessDenied);
         }
 
-        let msg_len = self.session.global.config.max_message_len as usize;
+        let config = self.session.global.config();
+        let msg_len = config.max_message_len as usize;
+        let context_messages = config.report_context_messages;
         if short_desc.len() > 100 || extended_desc.len() > msg_len {
             return Err(Error::TooLong)
         }
 
+        let invalid_target = match target {
+            ReportTarget::Message(_) => Error::InvalidMessage,
+            ReportTarget::User(_) => Error::InvalidUser,
+            ReportTarget::Room { .. } => Error::InvalidRoom,
+            ReportTarget::Community(_) => Error::InvalidCommunity,
+        };
+
+        let report = self.new_report(target).await?;
         let db = &self.session.global.database;
-        let msg = match db.get_message_by_id(message).await? {
-            Some(m) => m,
-            None => return Err(Error::InvalidMessage),
+        let res = db
+            .report(Some(self.user), report, &short_desc, &extended_desc, context_messages)
+            .await?;
+
+        let id = match res {
+            Ok(id) => id,
+            Err(ReportUserError::InvalidReporter) => return Err(Error::LoggedOut),
+            Err(ReportUserError::InvalidTarget) => return Err(invalid_target),
         };
 
-        if !self.session.in_room(&msg.community, &msg.room)? {
-            return Err(Error::InvalidMessage);
+        if let Some(report) = db.get_report(id).await? {
+            administrator::notify_admins_of_report(report);
         }
 
-        let res = db
-            .report_message(self.user, msg, &short_desc, &extended_desc)
-            .await?;
+        Ok(OkResponse::NoData)
+    }
 
-        match res {
-            Ok(_) => Ok(OkResponse::NoData),
-            Err(ReportUserError::InvalidReporter) => Err(Error::LoggedOut),
-            Err(ReportUserError::InvalidMessage) => Err(Error::InvalidMessage),
+    /// Records what the reported message, user, room or community looks like now, since it may be
+    /// changed before an administrator looks at the report. Users may only report rooms and
+    /// communities that they are in, and messages in rooms that they are in.
+    async fn new_report(&self, target: ReportTarget) -> Result<NewReport, Error> {
+        let db = &self.session.global.database;
+        let report = match target {
+            ReportTarget::Message(message) => {
+                let msg = db.get_message_by_id(message).await?.ok_or(Error::InvalidMessage)?;
+                if !self.session.in_room(&msg.community, &msg.room)? {
+                    return Err(Error::InvalidMessage);
+                }
+
+                NewReport::message(msg).ok_or(Error::InvalidMessage)?
+            }
+            ReportTarget::User(user) => {
+                let record = db.get_user_by_id(user).await?.ok_or(Error::InvalidUser)?;
+                NewReport {
+                    kind: ReportKind::User,
+                    reported_user: Some(user),
+                    community: None,
+                    room: None,
+                    message: None,
+                    snapshot: format!(
+                        "Username: {}\nDisplay name: {}",
+                        record.username, record.display_name
+                    ),
+                }
+            }
+            ReportTarget::Room { community, room } => {
+                if !self.session.in_room(&community, &room)? {
+                    return Err(Error::InvalidRoom);
+                }
+
+                let record = db.get_room(room).await?.ok_or(Error::InvalidRoom)?;
+                NewReport {
+                    kind: ReportKind::Room,
+                    reported_user: None,
+                    community: Some(community),
+                    room: Some(room),
+                    message: None,
+                    snapshot: format!("Name: {}", record.name),
+                }
+            }
+            ReportTarget::Community(community) => {
+                if !self.session.in_community(&community)? {
+                    return Err(Error::InvalidCommunity);
+                }
+
+                let record = db
+                    .get_community_metadata(community)
+                    .await?
+                    .ok_or(Error::InvalidCommunity)?;
+                NewReport {
+                    kind: ReportKind::Community,
+                    reported_user: None,
+                    community: Some(community),
+                    room: None,
+                    message: None,
+                    snapshot: format!(
+                        "Name: {}\nDescription: {}",
+                        record.name,
+                        record.description.unwrap_or_default()
+                    ),
+                }
+            }
+        };
+
+        Ok(report)
+    }
+
+    /// Mutes which have expired but have not yet been swept are ignored
+    fn check_not_muted(&self) -> Result<(), Error> {
+        let user = manager::get_active_user(self.user)?;
+        match &user.muted {
+            Some(mute) if mute.is_active() => Err(Error::Muted(mute.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    fn can_manage_bots(&self) -> bool {
+        !self.bot && self.perms.has_perms(TokenPermissionFlags::MANAGE_BOTS)
+    }
+
+    /// Checks that the given user is a bot that is owned by this user.
+    async fn check_owns_bot(&self, bot: UserId) -> Result<(), Error> {
+        let db = &self.session.global.database;
+        let record = match db.get_user_by_id(bot).await? {
+            Some(record) if record.bot => record,
+            _ => return Err(Error::InvalidUser),
+        };
+
+        if record.bot_owner == Some(self.user) {
+            Ok(())
+        } else {
+            Err(Error::AccessDenied)
+        }
+    }
+
+    async fn create_bot(
+        self,
+        username: String,
+        display_name: String,
+    ) -> Result<OkResponse, Error> {
+        if !self.can_manage_bots() {
+            return Err(Error::AccessDenied);
+        }
+
+        let config = self.session.global.config();
+        let username = match auth::prepare_username(&username, &config) {
+            Ok(name) => name,
+            Err(auth::TooShort) => return Err(Error::InvalidUsername),
+        };
+
+        if !auth::valid_display_name(&display_name, &config) {
+            return Err(Error::InvalidDisplayName);
+        }
+
+        let db = &self.session.global.database;
+        if db.count_bots_owned_by(self.user).await? >= config.max_bots_per_user as i64 {
+            return Err(Error::TooManyBots);
+        }
+
+        let bot = UserRecord::new_bot(username, display_name, self.user);
+        let id = bot.id;
+
+        match db.create_user(bot).await? {
+            Ok(()) => Ok(OkResponse::UserId(id)),
+            Err(UsernameConflict) => Err(Error::UsernameAlreadyExists),
+        }
+    }
+
+    async fn create_bot_token(
+        self,
+        bot: UserId,
+        options: TokenCreationOptions,
+    ) -> Result<OkResponse, Error> {
+        if !self.can_manage_bots() {
+            return Err(Error::AccessDenied);
+        }
+
+        self.check_owns_bot(bot).await?;
+
+        let options = TokenCreationOptions {
+            permission_flags: options.permission_flags.scoped_for_bot(),
+            ..options
+        };
+
+        let authenticator = Authenticator {
+            global: self.session.global.clone(),
+        };
+        let token = authenticator.issue_token(bot, options).await?;
+
+        Ok(OkResponse::NewToken(token))
+    }
+
+    async fn install_bot(self, bot: UserId, id: CommunityId) -> Result<OkResponse, Error> {
+        if !self.can_manage_bots() {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_community(&id)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        self.check_owns_bot(bot).await?;
+
+        let community = community::address_of(id)?;
+        let res = community
+            .send(InstallBot { bot })
+            .await
+            .map_err(handle_disconnected("Community"))??;
+
+        let structure = match res {
+            Ok(structure) => structure,
+            Err(AddToCommunityError::AlreadyInCommunity) => return Err(Error::AlreadyInCommunity),
+            Err(AddToCommunityError::InvalidCommunity) => return Err(Error::InvalidCommunity),
+            Err(AddToCommunityError::InvalidUser) => return Err(Error::InvalidUser),
+        };
+
+        let event = AuditEvent {
+            target_user: Some(bot),
+            ..self.community_event(AuditAction::InstallBot, id)
+        };
+        self.audit(event).await?;
+
+        // Let any online sessions of the bot know that it has been added to the community
+        if manager::get_active_user(bot).is_ok() {
+            let db = &self.session.global.database;
+            let bot_community = UserCommunity::load(db, bot, id).await?;
+
+            if let Ok(mut active_bot) = manager::get_active_user_mut(bot) {
+                active_bot.communities.insert(id, bot_community);
+
+                let send = ServerMessage::Event(ServerEvent::AddCommunity(structure));
+                active_bot
+                    .sessions
+                    .values()
+                    .filter_map(Session::as_active_actor)
+                    .for_each(|session| {
+                        let _ = session.send(send.clone());
+                    });
+            }
+        }
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn create_webhook(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        name: String,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_WEBHOOKS;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        if !auth::valid_display_name(&name, &self.session.global.config()) {
+            return Err(Error::InvalidDisplayName);
+        }
+
+        // Webhooks post plaintext, which encrypted rooms do not accept
+        let rooms = community::address_of(community)?
+            .send(GetRoomInfo)
+            .await
+            .map_err(handle_disconnected("Community"))?;
+        if rooms.iter().any(|info| info.id == room && info.encrypted) {
+            return Err(Error::EncryptedRoom);
+        }
+
+        let db = &self.session.global.database;
+        let user = UserRecord::new_webhook_user(name.clone());
+        let user_id = user.id;
+        if db.create_user(user).await?.is_err() {
+            // The username is derived from a new UUID, so this should never happen
+            panic_error!("Newly generated webhook username conflicts with another!");
         }
+
+        let mut token_bytes: [u8; 32] = [0; 32]; // 256 bits
+        rand::thread_rng().fill_bytes(&mut token_bytes);
+
+        // The token is part of the URL, so it must be URL safe
+        let token = base64::encode_config(&token_bytes, base64::URL_SAFE_NO_PAD);
+        let (token_hash, hash_scheme_version) = auth::hash(token.clone()).await;
+
+        let webhook = Webhook {
+            id: WebhookId(Uuid::new_v4()),
+            room,
+            user: user_id,
+            name,
+        };
+        let record = WebhookRecord {
+            id: webhook.id,
+            community,
+            room,
+            user: user_id,
+            name: webhook.name.clone(),
+            token_hash,
+            hash_scheme_version,
+        };
+
+        db.create_webhook(record).await?;
+
+        let event = AuditEvent {
+            target: Some(webhook.id.0.to_string()),
+            parameters: serde_json::json!({ "name": webhook.name, "room": room }),
+            ..self.community_event(AuditAction::CreateWebhook, community)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::NewWebhook(NewWebhook { webhook, token }))
+    }
+
+    async fn list_webhooks(self, community: CommunityId) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_WEBHOOKS;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        let webhooks = db
+            .get_webhooks_in_community(community)
+            .await?
+            .map_ok(Into::into)
+            .try_collect()
+            .await?;
+
+        Ok(OkResponse::Webhooks(webhooks))
+    }
+
+    async fn revoke_webhook(
+        self,
+        community: CommunityId,
+        webhook: WebhookId,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_WEBHOOKS;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        if let Err(NonexistentWebhook) = db.delete_webhook(community, webhook).await? {
+            return Err(Error::InvalidWebhook);
+        }
+
+        let event = AuditEvent {
+            target: Some(webhook.0.to_string()),
+            ..self.community_event(AuditAction::RevokeWebhook, community)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn create_outgoing_webhook(
+        self,
+        community: CommunityId,
+        url: String,
+        events: WebhookEventFlags,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_WEBHOOKS;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        if url.len() > MAX_WEBHOOK_URL_LEN {
+            return Err(Error::InvalidUrl);
+        }
+
+        let allow_private = self.session.global.config().outgoing_webhook_private_addresses;
+        if !outgoing_webhook::valid_url(&url, allow_private) {
+            return Err(Error::InvalidUrl);
+        }
+
+        let mut secret_bytes: [u8; 32] = [0; 32]; // 256 bits
+        rand::thread_rng().fill_bytes(&mut secret_bytes);
+        let secret = base64::encode_config(&secret_bytes, base64::URL_SAFE_NO_PAD);
+
+        let record = OutgoingWebhookRecord {
+            id: WebhookId(Uuid::new_v4()),
+            community,
+            url,
+            events,
+            secret: secret.clone(),
+        };
+
+        let db = &self.session.global.database;
+        db.create_outgoing_webhook(record.clone()).await?;
+
+        let event = AuditEvent {
+            target: Some(record.id.0.to_string()),
+            parameters: serde_json::json!({ "url": record.url, "events": record.events.bits() }),
+            ..self.community_event(AuditAction::CreateOutgoingWebhook, community)
+        };
+        self.audit(event).await?;
+
+        let webhook = record.clone().into();
+        community::address_of(community)?
+            .send(AddOutgoingWebhook(record))
+            .await
+            .map_err(handle_disconnected("Community"))?;
+
+        Ok(OkResponse::NewOutgoingWebhook(NewOutgoingWebhook {
+            webhook,
+            secret,
+        }))
+    }
+
+    async fn list_outgoing_webhooks(self, community: CommunityId) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_WEBHOOKS;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        let webhooks = db
+            .get_outgoing_webhooks_in_community(community)
+            .await?
+            .map_ok(Into::into)
+            .try_collect()
+            .await?;
+
+        Ok(OkResponse::OutgoingWebhooks(webhooks))
+    }
+
+    async fn delete_outgoing_webhook(
+        self,
+        community: CommunityId,
+        webhook: WebhookId,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_WEBHOOKS;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        if let Err(NonexistentWebhook) = db.delete_outgoing_webhook(community, webhook).await? {
+            return Err(Error::InvalidWebhook);
+        }
+
+        community::address_of(community)?
+            .send(RemoveOutgoingWebhook(webhook))
+            .await
+            .map_err(handle_disconnected("Community"))?;
+
+        let event = AuditEvent {
+            target: Some(webhook.0.to_string()),
+            ..self.community_event(AuditAction::DeleteOutgoingWebhook, community)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn get_webhook_deliveries(
+        self,
+        community: CommunityId,
+        webhook: WebhookId,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_WEBHOOKS;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        let deliveries = db
+            .get_webhook_deliveries(community, webhook)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(OkResponse::WebhookDeliveries(deliveries))
+    }
+
+    async fn register_commands(
+        self,
+        community: CommunityId,
+        commands: Vec<BotCommand>,
+    ) -> Result<OkResponse, Error> {
+        if !self.bot {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_community(&community)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        if commands.len() > MAX_COMMANDS_PER_BOT {
+            return Err(Error::TooLong);
+        }
+
+        let mut names = HashSet::new();
+        let all_valid = commands
+            .iter()
+            .all(|command| valid_command(command) && names.insert(command.name.as_str()));
+
+        if !all_valid {
+            return Err(Error::InvalidCommand);
+        }
+
+        let db = &self.session.global.database;
+        db.set_bot_commands(community, self.user, commands).await?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn list_commands(self, community: CommunityId) -> Result<OkResponse, Error> {
+        if !self.session.in_community(&community)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let db = &self.session.global.database;
+        let commands = db
+            .get_commands_in_community(community)
+            .await?
+            .try_collect()
+            .await?;
+
+        Ok(OkResponse::Commands(commands))
+    }
+
+    async fn invoke_command(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        bot: UserId,
+        command: String,
+        arguments: Vec<String>,
+    ) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        let max_len = self.session.global.config().max_message_len as usize;
+        if arguments.iter().map(String::len).sum::<usize>() > max_len {
+            return Err(Error::MessageTooLong);
+        }
+
+        let db = &self.session.global.database;
+        let declared = match db.get_bot_command(community, bot, command.clone()).await? {
+            Some(declared) => declared,
+            None => return Err(Error::InvalidCommand),
+        };
+
+        if !declared.arguments_valid(&arguments) {
+            return Err(Error::InvalidCommand);
+        }
+
+        let invocation = CommandInvocation {
+            community,
+            room,
+            invoker: self.user,
+            command,
+            arguments,
+        };
+
+        // If the bot is offline, the invocation is dropped
+        if let Ok(active_bot) = manager::get_active_user(bot) {
+            let send = ServerMessage::Event(ServerEvent::CommandInvoked(invocation));
+            active_bot
+                .sessions
+                .values()
+                .filter_map(Session::as_active_actor)
+                .for_each(|session| {
+                    let _ = session.send(send.clone());
+                });
+        }
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn publish_device_keys(
+        self,
+        identity_key: Vec<u8>,
+        one_time_prekeys: Vec<Vec<u8>>,
+    ) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
+
+        let valid_key = |key: &Vec<u8>| !key.is_empty() && key.len() <= MAX_DEVICE_KEY_LEN;
+        if !valid_key(&identity_key) || !one_time_prekeys.iter().all(valid_key) {
+            return Err(Error::InvalidMessage);
+        }
+
+        if one_time_prekeys.len() > MAX_PREKEYS_PER_DEVICE as usize {
+            return Err(Error::TooLong);
+        }
+
+        let db = &self.session.global.database;
+        let remaining = db
+            .publish_device_keys(self.user, self.device, identity_key, one_time_prekeys)
+            .await?;
+
+        Ok(OkResponse::RemainingPrekeys(remaining))
+    }
+
+    async fn get_device_keys(self, user: UserId) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        Ok(OkResponse::DeviceKeys(db.get_device_keys(user).await?))
+    }
+
+    async fn claim_device_keys(
+        self,
+        community: CommunityId,
+        except_devices: Vec<DeviceId>,
+    ) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
+
+        // Devices in remote communities are not known here, so they cannot be claimed yet
+        if !self.session.in_community(&community)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let db = &self.session.global.database;
+        let bundles = db
+            .claim_device_keys(community, self.device, except_devices)
+            .await?;
+
+        let claimed: Vec<DeviceId> = bundles
+            .iter()
+            .filter(|bundle| bundle.one_time_prekey.is_some())
+            .map(|bundle| bundle.device)
+            .collect();
+
+        if !claimed.is_empty() {
+            let low = db.devices_low_on_prekeys(&claimed, LOW_PREKEYS).await?;
+            for (user, device, remaining) in low {
+                notify_prekeys_low(user, device, remaining);
+            }
+        }
+
+        Ok(OkResponse::DeviceKeys(bundles))
+    }
+
+    /// Records an action taken in a community in the audit log
+    async fn audit(&self, event: AuditEvent) -> Result<(), Error> {
+        Ok(self.session.global.database.log_audit_event(event).await?)
+    }
+
+    fn community_event(&self, action: AuditAction, community: CommunityId) -> AuditEvent {
+        AuditEvent {
+            community: Some(community),
+            ..AuditEvent::new(Some(self.user), action)
+        }
+    }
+
+    async fn set_retention(
+        self,
+        community: CommunityId,
+        room: Option<RoomId>,
+        days: Option<u32>,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_RETENTION;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        if !community::valid_retention(days) {
+            return Err(Error::InvalidRetentionPeriod);
+        }
+
+        let db = &self.session.global.database;
+        match room {
+            Some(room) => {
+                if !self.session.in_room(&community, &room)? {
+                    return Err(Error::InvalidRoom);
+                }
+
+                db.set_room_retention(room, days).await?;
+            }
+            None => db.set_community_retention(community, days).await?,
+        }
+
+        let event = AuditEvent {
+            parameters: serde_json::json!({ "room": room, "days": days }),
+            ..self.community_event(AuditAction::SetRetention, community)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn set_room_settings(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        settings: RoomSettings,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_ROOMS;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        if !community::valid_room_settings(&settings, &self.session.global.config()) {
+            return Err(Error::InvalidRoomSettings);
+        }
+
+        let db = &self.session.global.database;
+        db.set_room_settings(room, &settings).await?;
+
+        let parameters = serde_json::json!({
+            "slow_mode_secs": settings.slow_mode_secs,
+            "read_only": settings.read_only,
+            "max_message_len": settings.max_message_len,
+        });
+
+        community::address_of(community)?
+            .send(SetRoomSettings { room, settings })
+            .await
+            .map_err(handle_disconnected("Community"))??;
+
+        let event = AuditEvent {
+            target: Some(room.0.to_string()),
+            parameters,
+            ..self.community_event(AuditAction::SetRoomSettings, community)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn export_community(
+        self,
+        community: CommunityId,
+        include_invite_codes: bool,
+    ) -> Result<OkResponse, Error> {
+        if !self
+            .session
+            .has_community_perms(&community, CommunityPermissionFlags::ALL)?
+        {
+            return Err(Error::AccessDenied);
+        }
+
+        let global = &self.session.global;
+        let config = global.config();
+        let archive =
+            archive::export(&global.database, &config, community, include_invite_codes).await?;
+
+        let event = AuditEvent {
+            parameters: serde_json::json!({ "include_invite_codes": include_invite_codes }),
+            ..self.community_event(AuditAction::ExportCommunity, community)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::CommunityArchive(archive.to_bytes()))
+    }
+
+    async fn import_community(self, archive: Vec<u8>) -> Result<OkResponse, Error> {
+        if !self
+            .perms
+            .has_perms(TokenPermissionFlags::CREATE_COMMUNITIES)
+        {
+            return Err(Error::AccessDenied);
+        }
+
+        let archive = archive::CommunityArchive::from_bytes(&archive)?;
+        let db = &self.session.global.database;
+        let config = self.session.global.config();
+        let record = archive::import(db, &config, archive).await?;
+        let id = record.id;
+
+        CommunityActor::load_and_spawn(record, self.session.global.clone()).await?;
+        self.audit(self.community_event(AuditAction::ImportCommunity, id)).await?;
+
+        self.join_community_by_id(id, CommunityPermissionFlags::ALL).await
+    }
+
+    async fn add_automod_rule(
+        self,
+        community: CommunityId,
+        name: String,
+        trigger: AutomodTrigger,
+        action: AutomodAction,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_AUTOMOD;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        automod::validate(&name, &trigger, &action)?;
+
+        let db = &self.session.global.database;
+        if db.get_automod_rules(community).await?.len() >= automod::MAX_RULES_PER_COMMUNITY {
+            return Err(Error::TooLong);
+        }
+
+        let rule = db.create_automod_rule(community, name, trigger, action).await?;
+
+        let event = AuditEvent {
+            target: Some(rule.id.to_string()),
+            parameters: serde_json::json!({
+                "name": rule.name,
+                "trigger": rule.trigger,
+                "action": rule.action,
+            }),
+            ..self.community_event(AuditAction::AddAutomodRule, community)
+        };
+        self.audit(event).await?;
+
+        community::address_of(community)?
+            .send(AddAutomodRule(rule.clone()))
+            .await
+            .map_err(handle_disconnected("Community"))?;
+
+        Ok(OkResponse::AutomodRule(rule))
+    }
+
+    async fn list_automod_rules(self, community: CommunityId) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_AUTOMOD;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        let rules = db.get_automod_rules(community).await?;
+
+        Ok(OkResponse::AutomodRules(rules))
+    }
+
+    async fn delete_automod_rule(
+        self,
+        community: CommunityId,
+        rule: i32,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_AUTOMOD;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        if let Err(NonexistentAutomodRule) = db.delete_automod_rule(community, rule).await? {
+            return Err(Error::InvalidAutomodRule);
+        }
+
+        community::address_of(community)?
+            .send(RemoveAutomodRule(rule))
+            .await
+            .map_err(handle_disconnected("Community"))?;
+
+        let event = AuditEvent {
+            target: Some(rule.to_string()),
+            ..self.community_event(AuditAction::DeleteAutomodRule, community)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn get_automod_hits(self, community: CommunityId) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_AUTOMOD;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        let hits = db.get_automod_hits(community).await?.try_collect().await?;
+
+        Ok(OkResponse::AutomodHits(hits))
+    }
+
+    async fn list_invites(self, community: CommunityId) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_INVITES;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        let invites = db.get_invites_in_community(community).await?;
+
+        Ok(OkResponse::Invites(invites))
+    }
+
+    async fn revoke_invite(
+        self,
+        community: CommunityId,
+        code: InviteCode,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_INVITES;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        let target = code.0.clone();
+        if let Err(NonexistentInviteCode) = db.delete_invite_code(community, code).await? {
+            return Err(Error::InvalidInviteCode);
+        }
+
+        let event = AuditEvent {
+            target: Some(target),
+            ..self.community_event(AuditAction::RevokeInvite, community)
+        };
+        self.audit(event).await?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn get_invite_uses(
+        self,
+        community: CommunityId,
+        code: InviteCode,
+    ) -> Result<OkResponse, Error> {
+        let perms = CommunityPermissionFlags::MANAGE_INVITES;
+        if !self.session.has_community_perms(&community, perms)? {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        match db.get_invite_uses(community, code).await? {
+            Ok(uses) => Ok(OkResponse::InviteUses(uses)),
+            Err(NonexistentInviteCode) => Err(Error::InvalidInviteCode),
+        }
+    }
+}
+
+fn valid_command(command: &BotCommand) -> bool {
+    let valid_name = |name: &str| {
+        !name.is_empty()
+            && name.len() <= MAX_COMMAND_NAME_LEN
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+    };
+
+    let args = &command.arguments;
+    let args_valid = args.len() <= MAX_COMMAND_ARGUMENTS
+        && args.iter().enumerate().all(|(idx, arg)| {
+            let is_last = idx == args.len() - 1;
+            let follows_optional = idx > 0 && !args[idx - 1].required;
+
+            valid_name(&arg.name)
+                && arg.description.len() <= MAX_COMMAND_DESCRIPTION_LEN
+                && (arg.kind != CommandArgumentKind::Rest || is_last)
+                && !(arg.required && follows_optional)
+        });
+
+    valid_name(&command.name)
+        && !vertex::is_builtin_command(&command.name)
+        && command.description.len() <= MAX_COMMAND_DESCRIPTION_LEN
+        && args_valid
+}
+
+/// Tells the device that its prekeys are running low, if it is online. Devices that are offline
+/// top up their prekeys when they next connect.
+fn notify_prekeys_low(user: UserId, device: DeviceId, remaining: u32) {
+    let session = manager::get_active_user(user)
+        .ok()
+        .and_then(|active| active.sessions.get(&device).and_then(Session::as_active_actor));
+
+    if let Some(session) = session {
+        let _ = session.send(ServerMessage::Event(ServerEvent::PrekeysLow(remaining)));
     }
 }
@@ -1,8 +1,16 @@
-use crate::client::session::{AddRoom, ForwardMessage};
+use crate::automod::Automod;
+use crate::client::session::{self, AddRoom, ForwardMessage};
 use crate::client::{self, ActiveSession, Session};
-use crate::database::{AddToCommunityError, CommunityRecord, Database, DbResult};
-use crate::{handle_disconnected, IdentifiedMessage};
-use chrono::Utc;
+use crate::database::{
+    AddToCommunityError, AutomodHitRecord, CommunityRecord, Database, DbResult, NewReport,
+    OutgoingWebhookRecord,
+};
+use crate::config::Config;
+use crate::outgoing_webhook::{self, OutgoingEvent};
+use crate::webhook::WebhookSentMessage;
+use crate::{federation, handle_disconnected, metrics, Global, IdentifiedMessage};
+use chrono::{DateTime, Duration, Utc};
+use log::error;
 use dashmap::mapref::one::{Ref, RefMut};
 use dashmap::DashMap;
 use futures::TryStreamExt;
@@ -14,6 +22,11 @@ use xtra::prelude::*;
 use xtra::Disconnected;
 use async_trait::async_trait;
 
+/// The longest that slow mode can make members wait between messages
+const MAX_SLOW_MODE_SECS: u32 = 6 * 60 * 60;
+/// The longest that messages can be kept for by a retention policy, which is stored as an INTEGER
+const MAX_RETENTION_DAYS: u32 = 36500;
+
 lazy_static! {
     pub static ref COMMUNITIES: DashMap<CommunityId, Community> = DashMap::new();
 }
@@ -30,12 +43,30 @@ pub fn address_of(id: CommunityId) -> Result<Address<CommunityActor>, Error> {
     get(id).map(|c| c.actor.clone())
 }
 
+/// Checks that room settings are in range. A room's maximum message length can only be lowered
+/// from the server's.
+pub fn valid_room_settings(settings: &RoomSettings, config: &Config) -> bool {
+    let valid_max_len = match settings.max_message_len {
+        Some(max) => max > 0 && max <= config.max_message_len,
+        None => true,
+    };
+
+    settings.slow_mode_secs <= MAX_SLOW_MODE_SECS && valid_max_len
+}
+
+/// Checks that a retention policy keeps messages for at least a day, and at most a hundred years
+pub fn valid_retention(days: Option<u32>) -> bool {
+    days.map(|days| days >= 1 && days <= MAX_RETENTION_DAYS).unwrap_or(true)
+}
+
 /// Community info that is just read/updated very quickly (no logic like in the actor). Used to avoid
 /// calls back and forth to the actor for simple things like getting the community name.
 pub struct Community {
     pub actor: Address<CommunityActor>,
     pub name: String,
     pub description: Option<String>,
+    /// Whether the community is listed in the public directory
+    pub public: bool,
 }
 
 impl Community {
@@ -67,21 +98,103 @@ pub struct Join {
     pub user: UserId,
     pub device_id: DeviceId,
     pub session: ActiveSession,
+    pub permissions: CommunityPermissionFlags,
 }
 
 impl xtra::Message for Join {
     type Result = DbResult<Result<CommunityStructure, AddToCommunityError>>;
 }
 
+/// A user of another server joins the community through their home server
+pub struct JoinRemote {
+    /// The local stand-in for the user
+    pub user: UserId,
+    pub server: String,
+}
+
+impl xtra::Message for JoinRemote {
+    type Result = DbResult<Result<CommunityStructure, AddToCommunityError>>;
+}
+
+/// Install a bot into the community. The bot is added as a member without needing an invite.
+pub struct InstallBot {
+    pub bot: UserId,
+}
+
+impl xtra::Message for InstallBot {
+    type Result = DbResult<Result<CommunityStructure, AddToCommunityError>>;
+}
+
 pub struct CreateRoom {
     pub creator: DeviceId,
     pub name: String,
+    pub encrypted: bool,
 }
 
 impl xtra::Message for CreateRoom {
     type Result = DbResult<RoomId>;
 }
 
+/// Notify the community that an outgoing webhook was created, so that events are delivered to it
+pub struct AddOutgoingWebhook(pub OutgoingWebhookRecord);
+
+impl xtra::Message for AddOutgoingWebhook {
+    type Result = ();
+}
+
+pub struct RemoveOutgoingWebhook(pub WebhookId);
+
+impl xtra::Message for RemoveOutgoingWebhook {
+    type Result = ();
+}
+
+/// Notify the community that an automod rule was created, so that messages are checked against it
+pub struct AddAutomodRule(pub AutomodRule);
+
+impl xtra::Message for AddAutomodRule {
+    type Result = ();
+}
+
+pub struct RemoveAutomodRule(pub i32);
+
+impl xtra::Message for RemoveAutomodRule {
+    type Result = ();
+}
+
+/// Notify the community that a room's settings were changed, so that they are enforced and its
+/// online members are told
+pub struct SetRoomSettings {
+    pub room: RoomId,
+    pub settings: RoomSettings,
+}
+
+impl xtra::Message for SetRoomSettings {
+    type Result = Result<(), Error>;
+}
+
+/// Notify the community that some of its messages were deleted, e.g because they expired
+pub struct MessagesDeleted(pub Vec<Delete>);
+
+impl xtra::Message for MessagesDeleted {
+    type Result = ();
+}
+
+/// Tells each community about its messages which were deleted, so that it can tell its online
+/// members and webhooks
+pub fn notify_deleted(deleted: Vec<Delete>) {
+    let mut by_community: HashMap<CommunityId, Vec<Delete>> = HashMap::new();
+    for delete in deleted {
+        by_community.entry(delete.community).or_default().push(delete);
+    }
+
+    for (community, deleted) in by_community {
+        // If the community is not loaded yet, then nobody is connected to it to be told
+        if let Ok(addr) = address_of(community) {
+            let _ = addr.do_send(MessagesDeleted(deleted));
+        }
+    }
+}
+
 pub struct GetRoomInfo;
 
 impl xtra::Message for GetRoomInfo {
@@ -91,56 +204,104 @@ impl xtra::Message for GetRoomInfo {
 pub struct RoomInfo {
     pub id: RoomId,
     pub name: String,
+    pub encrypted: bool,
+    pub settings: RoomSettings,
 }
 
 /// A community is a collection (or "house", if you will) of rooms, as well as some metadata.
 /// It is similar to a "server" in Discord.
 pub struct CommunityActor {
     id: CommunityId,
+    global: Global,
     database: Database,
     rooms: HashMap<RoomId, Room>,
     /// BTreeSet gives us efficient iteration and checking, compared to HashSet which has O(capacity)
     /// iteration.
     online_members: BTreeSet<UserId>,
+    outgoing_webhooks: Vec<OutgoingWebhookRecord>,
+    /// Other servers with members in this community, which events are fanned out to
+    remote_servers: BTreeSet<String>,
+    automod: Automod,
+    /// Members muted by the automod. Expired mutes are removed when they are next checked.
+    mutes: HashMap<UserId, Restriction>,
 }
 
 impl Actor for CommunityActor {}
 
 impl CommunityActor {
-    pub fn new(id: CommunityId, database: Database, creator: UserId) -> CommunityActor {
+    pub fn new(id: CommunityId, global: Global, creator: UserId) -> CommunityActor {
         let mut online_members = BTreeSet::new();
         online_members.insert(creator);
 
         CommunityActor {
             id,
-            database,
+            database: global.database.clone(),
+            global,
             rooms: HashMap::new(),
             online_members,
+            outgoing_webhooks: Vec::new(),
+            remote_servers: BTreeSet::new(),
+            automod: Automod::default(),
+            mutes: HashMap::new(),
         }
     }
 
-    pub fn create_and_spawn(name: String, id: CommunityId, database: Database, creator: UserId) {
-        let addr = CommunityActor::new(id, database, creator).spawn();
+    pub fn create_and_spawn(name: String, id: CommunityId, global: Global, creator: UserId) {
+        let addr = CommunityActor::new(id, global, creator).spawn();
         let community = Community {
             actor: addr,
             name,
             description: None,
+            public: false,
         };
         COMMUNITIES.insert(id, community);
     }
 
-    pub async fn load_and_spawn(record: CommunityRecord, database: Database) -> DbResult<()> {
+    pub async fn load_and_spawn(record: CommunityRecord, global: Global) -> DbResult<()> {
+        let database = global.database.clone();
         let rooms = database.get_rooms_in_community(record.id).await?;
         let rooms = rooms
-            .map_ok(|record| (record.id, Room { name: record.name }))
+            .map_ok(|record| {
+                let room = Room {
+                    name: record.name,
+                    encrypted: record.encrypted,
+                    settings: record.settings,
+                    last_sent: HashMap::new(),
+                };
+                (record.id, room)
+            })
+            .try_collect()
+            .await?;
+
+        let outgoing_webhooks = database
+            .get_outgoing_webhooks_in_community(record.id)
+            .await?
             .try_collect()
             .await?;
 
+        let remote_servers = database
+            .get_remote_servers_in_community(record.id)
+            .await?
+            .into_iter()
+            .collect();
+
+        let automod = Automod::new(database.get_automod_rules(record.id).await?);
+        let mutes = database
+            .get_community_mutes(record.id)
+            .await?
+            .into_iter()
+            .collect();
+
         let addr = CommunityActor {
             id: record.id,
+            global,
             database,
             rooms,
             online_members: BTreeSet::new(),
+            outgoing_webhooks,
+            remote_servers,
+            automod,
+            mutes,
         }
         .spawn();
 
@@ -148,6 +309,7 @@ impl CommunityActor {
             actor: addr,
             name: record.name,
             description: record.description,
+            public: record.public,
         };
 
         COMMUNITIES.insert(record.id, community);
@@ -155,6 +317,170 @@ impl CommunityActor {
         Ok(())
     }
 
+    fn structure(&self) -> Result<CommunityStructure, Error> {
+        let info = get_mut(self.id)?;
+        let description = info.description();
+
+        Ok(CommunityStructure {
+            id: self.id,
+            name: info.name.clone(),
+            description,
+            rooms: self
+                .rooms
+                .iter()
+                .map(|(id, room)| RoomStructure {
+                    id: *id,
+                    name: room.name.clone(),
+                    unread: true,
+                    encrypted: room.encrypted,
+                    settings: room.settings.clone(),
+                })
+                .collect(),
+            public: info.public,
+        })
+    }
+
+    fn room(&self, id: RoomId) -> Result<&Room, Error> {
+        self.rooms.get(&id).ok_or(Error::InvalidRoom)
+    }
+
+    fn room_mut(&mut self, id: RoomId) -> Result<&mut Room, Error> {
+        self.rooms.get_mut(&id).ok_or(Error::InvalidRoom)
+    }
+
+    /// Checks that the author may post in the room now, given whether it is read-only and in slow
+    /// mode
+    async fn check_can_post(
+        &self,
+        author: UserId,
+        room: RoomId,
+        time_sent: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let room = self.room(room)?;
+        let settings = &room.settings;
+        if !settings.read_only && settings.slow_mode_secs == 0 {
+            return Ok(());
+        }
+
+        // Only webhooks post without being members. They are set up by the community's
+        // moderators to post to a particular room, so they are exempt.
+        let membership = self
+            .database
+            .get_community_membership(self.id, author)
+            .await?;
+        let perms = match membership {
+            Some(member) => member.permissions,
+            None => return Ok(()),
+        };
+
+        let can_post = CommunityPermissionFlags::POST_IN_READ_ONLY_ROOMS;
+        if settings.read_only && !perms.has_perms(can_post) {
+            return Err(Error::ReadOnlyRoom);
+        }
+
+        if settings.slow_mode_secs == 0 || perms.has_perms(CommunityPermissionFlags::MANAGE_ROOMS)
+        {
+            return Ok(());
+        }
+
+        let slow_mode = Duration::seconds(settings.slow_mode_secs as i64);
+        match room.last_sent.get(&author) {
+            Some(last) if *last + slow_mode > time_sent => {
+                let wait = *last + slow_mode - time_sent;
+                // Rounded up, so that trying again after this long always succeeds
+                let retry_after_secs = ((wait.num_milliseconds() + 999) / 1000) as u32;
+                Err(Error::SlowMode { retry_after_secs })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the member's automod mute, if it has not yet expired
+    fn active_mute(&mut self, user: UserId) -> Option<Restriction> {
+        let mute = self.mutes.get(&user).cloned()?;
+        if mute.is_active() {
+            Some(mute)
+        } else {
+            self.mutes.remove(&user);
+            None
+        }
+    }
+
+    /// Records that a message triggered an automod rule, and carries out the rule's action. Returns
+    /// an error if the message should not be sent.
+    async fn enforce_automod(
+        &mut self,
+        rule: &AutomodRule,
+        author: UserId,
+        room: RoomId,
+        text: &str,
+        time_sent: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let hit = AutomodHitRecord {
+            datetime: time_sent,
+            community: self.id,
+            rule: rule.clone(),
+            author,
+            room,
+            message_text: text.to_string(),
+        };
+        self.database.record_automod_hit(hit).await?;
+
+        match rule.action {
+            AutomodAction::Block => Err(Error::AutomodBlocked),
+            AutomodAction::Flag => Ok(()),
+            AutomodAction::Mute { minutes } => {
+                let mute = Restriction {
+                    reason: format!("Automod rule \"{}\"", rule.name),
+                    expires: Some(time_sent + Duration::minutes(minutes as i64)),
+                };
+
+                self.database
+                    .set_community_mute(self.id, author, Some(mute.clone()))
+                    .await?;
+                self.mutes.insert(author, mute.clone());
+                Err(Error::Muted(mute))
+            }
+        }
+    }
+
+    /// Reports a message which was flagged by the automod to the server's administrators. This is
+    /// done in the background, so that sending the message is not held up.
+    fn flag(&self, rule: &AutomodRule, author: UserId, room: RoomId, message: ReportMessage) {
+        let report = NewReport {
+            kind: ReportKind::Message,
+            reported_user: Some(author),
+            community: Some(self.id),
+            room: Some(room),
+            message: Some(message),
+            snapshot: String::new(),
+        };
+        let short_desc = format!("Flagged by automod rule \"{}\"", rule.name);
+        let global = self.global.clone();
+
+        tokio::spawn(async move {
+            let context_messages = global.config().report_context_messages;
+            let db = &global.database;
+            let res = db
+                .report(None, report, &short_desc, "", context_messages)
+                .await;
+
+            match res {
+                Ok(Ok(id)) => {
+                    if let Ok(Some(report)) = db.get_report(id).await {
+                        session::notify_admins_of_report(report);
+                    }
+                }
+                Ok(Err(_)) => {} // The message or its author was deleted in the meantime
+                Err(e) => error!("Database error while flagging message: {:?}", e),
+            }
+        });
+    }
+
+    fn dispatch_to_webhooks(&self, event: OutgoingEvent) {
+        outgoing_webhook::dispatch(&self.global, &self.outgoing_webhooks, event);
+    }
+
     fn for_each_online_device_except<F>(&mut self, mut f: F, except: Option<DeviceId>)
     where
         F: FnMut(&ActiveSession) -> Result<(), Disconnected>,
@@ -211,12 +537,71 @@ impl Handler<IdentifiedMessage<ClientSentMessage>> for CommunityActor {
         identified: IdentifiedMessage<ClientSentMessage>,
         _: &mut Context<Self>,
     ) -> Result<MessageConfirmation, Error> {
-        let id = MessageId(Uuid::new_v4());
+        let IdentifiedMessage { user, device, message } = identified;
+        self.send_message(user, device, message, None).await
+    }
+}
+
+#[async_trait]
+impl Handler<IdentifiedMessage<WebhookSentMessage>> for CommunityActor {
+    async fn handle(
+        &mut self,
+        identified: IdentifiedMessage<WebhookSentMessage>,
+        _: &mut Context<Self>,
+    ) -> Result<MessageConfirmation, Error> {
+        let IdentifiedMessage { user, device, message } = identified;
+        let WebhookSentMessage { message, display_name } = message;
+        self.send_message(user, device, message, display_name).await
+    }
+}
 
-        let message = identified.message;
-        let author = identified.user;
+impl CommunityActor {
+    /// Posts a message to a room. The author's display name may be overridden for this message
+    /// only, e.g by a webhook.
+    async fn send_message(
+        &mut self,
+        author: UserId,
+        from_device: DeviceId,
+        message: ClientSentMessage,
+        author_display_name: Option<String>,
+    ) -> Result<MessageConfirmation, Error> {
+        let id = MessageId(Uuid::new_v4());
         let time_sent = Utc::now();
 
+        if let Some(mute) = self.active_mute(author) {
+            return Err(Error::Muted(mute));
+        }
+
+        // Encrypted rooms only take ciphertext, so that plaintext is never stored by mistake
+        let room = self.room(message.to_room)?;
+        let max_len = room.settings.max_message_len;
+        let (content, ciphertext) = match (room.encrypted, message.ciphertext) {
+            (true, Some(ciphertext)) if message.content.is_empty() => (None, Some(ciphertext)),
+            (false, None) => (Some(message.content), None),
+            _ => return Err(Error::EncryptedRoom),
+        };
+
+        if let (Some(max_len), Some(text)) = (max_len, &content) {
+            if text.len() > max_len as usize {
+                return Err(Error::MessageTooLong);
+            }
+        }
+
+        self.check_can_post(author, message.to_room, time_sent).await?;
+
+        // Encrypted messages can't be checked, since the server can't read them
+        let flagged_by = match &content {
+            Some(text) => match self.automod.check(author, text) {
+                Some(rule) => {
+                    self.enforce_automod(&rule, author, message.to_room, text, time_sent)
+                        .await?;
+                    Some(rule)
+                }
+                None => None,
+            },
+            None => None,
+        };
+
         let (_ord, profile_version) = self
             .database
             .create_message(
@@ -225,11 +610,42 @@ impl Handler<IdentifiedMessage<ClientSentMessage>> for CommunityActor {
                 message.to_community,
                 message.to_room,
                 time_sent,
-                message.content.clone(),
+                content.clone(),
+                ciphertext.clone(),
+                author_display_name.clone(),
             )
             .await?;
 
-        let from_device = identified.device;
+        metrics::MESSAGES_SENT.inc();
+
+        let room = self.room_mut(message.to_room)?;
+        if room.settings.slow_mode_secs > 0 {
+            let since = time_sent - Duration::seconds(room.settings.slow_mode_secs as i64);
+            room.last_sent.retain(|_, sent| *sent > since);
+            room.last_sent.insert(author, time_sent);
+        }
+
+        if let (Some(rule), Some(text)) = (flagged_by, &content) {
+            let reported = ReportMessage {
+                id: Some(id),
+                sent_at: time_sent,
+                text: text.clone(),
+            };
+            self.flag(&rule, author, message.to_room, reported);
+        }
+
+        // Webhooks are not told about encrypted messages, since they could not read them anyway
+        if let Some(content) = &content {
+            self.dispatch_to_webhooks(OutgoingEvent::AddMessage {
+                community: message.to_community.0,
+                room: message.to_room.0,
+                message: id.0,
+                author: author.0,
+                time_sent,
+                content: content.clone(),
+            });
+        }
+
         let send = ForwardMessage {
             community: message.to_community,
             room: message.to_room,
@@ -238,10 +654,22 @@ impl Handler<IdentifiedMessage<ClientSentMessage>> for CommunityActor {
                 author,
                 author_profile_version: profile_version,
                 time_sent,
-                content: Some(message.content),
+                content,
+                ciphertext,
+                author_display_name,
             },
         };
 
+        if !self.remote_servers.is_empty() {
+            federation::fan_out_message(
+                self.remote_servers.iter().cloned().collect(),
+                send.community,
+                send.room,
+                send.message.clone(),
+                from_device,
+            );
+        }
+
         self.for_each_online_device_except(
             |session| {
                 let _ = session.forward_message(send.clone());
@@ -254,9 +682,53 @@ impl Handler<IdentifiedMessage<ClientSentMessage>> for CommunityActor {
     }
 }
 
-impl SyncHandler<IdentifiedMessage<Edit>> for CommunityActor {
-    fn handle(&mut self, m: IdentifiedMessage<Edit>, _: &mut Context<Self>) -> Result<(), Error> {
+#[async_trait]
+impl Handler<IdentifiedMessage<Edit>> for CommunityActor {
+    async fn handle(
+        &mut self,
+        m: IdentifiedMessage<Edit>,
+        _: &mut Context<Self>,
+    ) -> Result<(), Error> {
+        if let Some(mute) = self.active_mute(m.user) {
+            return Err(Error::Muted(mute));
+        }
+
         let from_device = m.device;
+        let edit = &m.message;
+
+        // Edits are sent as plaintext, so they would leak the content of an encrypted room
+        let room = self.room(edit.room)?;
+        if room.encrypted {
+            return Err(Error::EncryptedRoom);
+        }
+
+        if let Some(max_len) = room.settings.max_message_len {
+            if edit.new_content.len() > max_len as usize {
+                return Err(Error::MessageTooLong);
+            }
+        }
+
+        // Otherwise, rules could be got around by sending an innocent message and then editing it
+        if let Some(rule) = self.automod.check_edit(&edit.new_content) {
+            let time_edited = Utc::now();
+            self.enforce_automod(&rule, m.user, edit.room, &edit.new_content, time_edited)
+                .await?;
+
+            let reported = ReportMessage {
+                id: Some(edit.message),
+                sent_at: time_edited,
+                text: edit.new_content.clone(),
+            };
+            self.flag(&rule, m.user, edit.room, reported);
+        }
+
+        self.dispatch_to_webhooks(OutgoingEvent::Edit {
+            community: edit.community.0,
+            room: edit.room.0,
+            message: edit.message.0,
+            new_content: edit.new_content.clone(),
+        });
+
         let send = ServerMessage::Event(ServerEvent::Edit(m.message));
 
         self.for_each_online_device_except(
@@ -278,32 +750,77 @@ impl Handler<Join> for CommunityActor {
         join: Join,
         _: &mut Context<Self>
     ) -> DbResult<Result<CommunityStructure, AddToCommunityError>> {
-        if let Err(e) = self.database.add_to_community(self.id, join.user).await? {
+        let db = &self.database;
+        if let Err(e) = db.add_to_community(self.id, join.user, join.permissions).await? {
             return Ok(Err(e)); // TODO(banning): check if user is not banned
         }
 
         self.online_members.insert(join.user);
+        self.dispatch_to_webhooks(OutgoingEvent::MemberJoin {
+            community: self.id.0,
+            user: join.user.0,
+        });
+
+        match self.structure() {
+            Ok(structure) => Ok(Ok(structure)),
+            Err(_) => Ok(Err(AddToCommunityError::InvalidCommunity)),
+        }
+    }
+}
 
-        let info = match get_mut(self.id) {
-            Ok(i) => i,
-            Err(_) => return Ok(Err(AddToCommunityError::InvalidCommunity)),
-        };
-        let description = info.description();
+#[async_trait]
+impl Handler<JoinRemote> for CommunityActor {
+    async fn handle(
+        &mut self,
+        join: JoinRemote,
+        _: &mut Context<Self>
+    ) -> DbResult<Result<CommunityStructure, AddToCommunityError>> {
+        let db = &self.database;
+        let perms = CommunityPermissionFlags::empty();
+        if let Err(e) = db.add_to_community(self.id, join.user, perms).await? {
+            return Ok(Err(e));
+        }
 
-        Ok(Ok(CommunityStructure {
-            id: self.id,
-            name: info.name.clone(),
-            description,
-            rooms: self
-                .rooms
-                .iter()
-                .map(|(id, room)| RoomStructure {
-                    id: *id,
-                    name: room.name.clone(),
-                    unread: true,
-                })
-                .collect(),
-        }))
+        self.remote_servers.insert(join.server);
+        self.dispatch_to_webhooks(OutgoingEvent::MemberJoin {
+            community: self.id.0,
+            user: join.user.0,
+        });
+
+        match self.structure() {
+            Ok(structure) => Ok(Ok(structure)),
+            Err(_) => Ok(Err(AddToCommunityError::InvalidCommunity)),
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<InstallBot> for CommunityActor {
+    async fn handle(
+        &mut self,
+        install: InstallBot,
+        _: &mut Context<Self>
+    ) -> DbResult<Result<CommunityStructure, AddToCommunityError>> {
+        let db = &self.database;
+        let perms = CommunityPermissionFlags::empty();
+        if let Err(e) = db.add_to_community(self.id, install.bot, perms).await? {
+            return Ok(Err(e));
+        }
+
+        // If the bot is not online, it will be marked as online when it connects
+        if client::session::get_active_user(install.bot).is_ok() {
+            self.online_members.insert(install.bot);
+        }
+
+        self.dispatch_to_webhooks(OutgoingEvent::MemberJoin {
+            community: self.id.0,
+            user: install.bot.0,
+        });
+
+        match self.structure() {
+            Ok(structure) => Ok(Ok(structure)),
+            Err(_) => Ok(Err(AddToCommunityError::InvalidCommunity)),
+        }
     }
 }
 
@@ -311,7 +828,9 @@ impl Handler<Join> for CommunityActor {
 impl Handler<CreateRoom> for CommunityActor {
     async fn handle(&mut self, create: CreateRoom, _: &mut Context<Self>) -> DbResult<RoomId> {
         let db = &self.database;
-        let id = db.create_room(self.id, create.name.clone()).await?;
+        let id = db
+            .create_room(self.id, create.name.clone(), create.encrypted)
+            .await?;
 
         db.create_default_user_room_states_for_room(self.id, id)
             .await?
@@ -321,15 +840,26 @@ impl Handler<CreateRoom> for CommunityActor {
             id,
             Room {
                 name: create.name.clone(),
+                encrypted: create.encrypted,
+                settings: RoomSettings::default(),
+                last_sent: HashMap::new(),
             },
         );
 
+        self.dispatch_to_webhooks(OutgoingEvent::AddRoom {
+            community: self.id.0,
+            room: id.0,
+            name: create.name.clone(),
+        });
+
         let send = AddRoom {
             community: self.id,
             structure: RoomStructure {
                 id,
                 name: create.name.clone(),
                 unread: false,
+                encrypted: create.encrypted,
+                settings: RoomSettings::default(),
             },
         };
 
@@ -345,6 +875,76 @@ impl Handler<CreateRoom> for CommunityActor {
     }
 }
 
+impl SyncHandler<AddOutgoingWebhook> for CommunityActor {
+    fn handle(&mut self, add: AddOutgoingWebhook, _: &mut Context<Self>) {
+        self.outgoing_webhooks.push(add.0);
+    }
+}
+
+impl SyncHandler<RemoveOutgoingWebhook> for CommunityActor {
+    fn handle(&mut self, remove: RemoveOutgoingWebhook, _: &mut Context<Self>) {
+        self.outgoing_webhooks.retain(|webhook| webhook.id != remove.0);
+    }
+}
+
+impl SyncHandler<AddAutomodRule> for CommunityActor {
+    fn handle(&mut self, add: AddAutomodRule, _: &mut Context<Self>) {
+        self.automod.add(add.0);
+    }
+}
+
+impl SyncHandler<RemoveAutomodRule> for CommunityActor {
+    fn handle(&mut self, remove: RemoveAutomodRule, _: &mut Context<Self>) {
+        self.automod.remove(remove.0);
+    }
+}
+
+impl SyncHandler<SetRoomSettings> for CommunityActor {
+    fn handle(&mut self, set: SetRoomSettings, _: &mut Context<Self>) -> Result<(), Error> {
+        let room = self.room_mut(set.room)?;
+        room.settings = set.settings.clone();
+        if room.settings.slow_mode_secs == 0 {
+            room.last_sent.clear();
+        }
+
+        let send = ServerMessage::Event(ServerEvent::RoomSettingsChanged {
+            community: self.id,
+            room: set.room,
+            settings: set.settings,
+        });
+        self.for_each_online_device_except(
+            |session| {
+                let _ = session.send(send.clone());
+                Ok(())
+            },
+            None,
+        );
+
+        Ok(())
+    }
+}
+
+impl SyncHandler<MessagesDeleted> for CommunityActor {
+    fn handle(&mut self, deleted: MessagesDeleted, _: &mut Context<Self>) {
+        for delete in deleted.0 {
+            self.dispatch_to_webhooks(OutgoingEvent::Delete {
+                community: delete.community.0,
+                room: delete.room.0,
+                message: delete.message.0,
+            });
+
+            let send = ServerMessage::Event(ServerEvent::Delete(delete));
+            self.for_each_online_device_except(
+                |session| {
+                    let _ = session.send(send.clone());
+                    Ok(())
+                },
+                None,
+            );
+        }
+    }
+}
+
 impl SyncHandler<GetRoomInfo> for CommunityActor {
     fn handle(&mut self, _get: GetRoomInfo, _: &mut Context<Self>) -> Vec<RoomInfo> {
         self.rooms
@@ -352,6 +952,8 @@ impl SyncHandler<GetRoomInfo> for CommunityActor {
             .map(move |(id, room)| RoomInfo {
                 id: *id,
                 name: room.name.clone(),
+                encrypted: room.encrypted,
+                settings: room.settings.clone(),
             })
             .collect()
     }
@@ -361,4 +963,24 @@ impl SyncHandler<GetRoomInfo> for CommunityActor {
 #[derive(Debug)]
 struct Room {
     name: String,
+    encrypted: bool,
+    settings: RoomSettings,
+    /// When each member last sent a message to the room, while it is in slow mode. Members who
+    /// have waited out slow mode are removed whenever a message is sent.
+    last_sent: HashMap<UserId, DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retention_bounds() {
+        assert!(valid_retention(None));
+        assert!(valid_retention(Some(1)));
+        assert!(valid_retention(Some(MAX_RETENTION_DAYS)));
+        assert!(!valid_retention(Some(0)));
+        assert!(!valid_retention(Some(MAX_RETENTION_DAYS + 1)));
+        assert!(!valid_retention(Some(u32::MAX)));
+    }
 }
@@ -1,18 +1,27 @@
 // configuration framework rewrite time. very epic
 
+use crate::community;
 use directories_next::ProjectDirs;
 use log::Level;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs::{self, File};
 use std::io::ErrorKind;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 
+/// The most messages on each side of a reported message that can be kept alongside the report
+pub const MAX_REPORT_CONTEXT_MESSAGES: u32 = 50;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default = "max_message_len")]
     pub max_message_len: u32,
+    /// The maximum size in bytes of an encrypted message. This is larger than the maximum message
+    /// length, since the ciphertext carries the message key encrypted for every recipient device.
+    #[serde(default = "max_ciphertext_len")]
+    pub max_ciphertext_len: u32,
     #[serde(default = "max_community_name_len")]
     pub max_community_name_len: u16,
     #[serde(default = "max_community_description_len")]
@@ -39,18 +48,99 @@ pub struct Config {
     pub max_invite_codes_per_community: u32,
     #[serde(default = "invite_codes_sweep_interval_secs")]
     pub invite_codes_sweep_interval_secs: u64,
+    /// How many days messages are kept for, unless their community or room sets its own policy.
+    /// Messages are kept forever if this is unset.
+    #[serde(default = "message_retention_days")]
+    pub message_retention_days: Option<u32>,
+    #[serde(default = "messages_sweep_interval_secs")]
+    pub messages_sweep_interval_secs: u64,
+    /// How often expired bans, locks and mutes are lifted
+    #[serde(default = "restrictions_sweep_interval_secs")]
+    pub restrictions_sweep_interval_secs: u64,
+    #[serde(default = "max_bots_per_user")]
+    pub max_bots_per_user: u32,
+    /// How many messages before and after a reported message are kept alongside the report. This
+    /// is clamped to at most `MAX_REPORT_CONTEXT_MESSAGES` when the config is loaded.
+    #[serde(default = "report_context_messages")]
+    pub report_context_messages: u32,
+    #[serde(default = "ratelimit_burst_per_min")]
+    pub ratelimit_burst_per_min: u32,
+    #[serde(default = "bot_ratelimit_burst_per_min")]
+    pub bot_ratelimit_burst_per_min: u32,
+    #[serde(default = "message_ratelimit_per_min")]
+    pub message_ratelimit_per_min: u32,
+    #[serde(default = "invite_ratelimit_per_min")]
+    pub invite_ratelimit_per_min: u32,
+    #[serde(default = "room_creation_ratelimit_per_min")]
+    pub room_creation_ratelimit_per_min: u32,
+    /// Applies to the unauthenticated auth endpoints, per IP address
+    #[serde(default = "auth_ratelimit_per_min")]
+    pub auth_ratelimit_per_min: u32,
+    /// Applies to requests from other servers that make this server fetch the public key of a
+    /// server it has not seen before, per IP address
+    #[serde(default = "federation_key_fetch_ratelimit_per_min")]
+    pub federation_key_fetch_ratelimit_per_min: u32,
     #[serde(default = "log_level")]
     pub log_level: String,
     #[serde(default = "https")]
     pub https: bool,
     #[serde(default = "ip")]
     pub ip: SocketAddr,
+    /// Where to serve Prometheus metrics. They are not served if this is unset.
+    #[serde(default = "metrics_ip")]
+    pub metrics_ip: Option<SocketAddr>,
+    /// How long to wait for requests and sessions to finish when shutting down
+    #[serde(default = "shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Whether to federate with other Vertex servers
+    #[serde(default = "federation")]
+    pub federation: bool,
+    /// The name that other servers reach this server by, as `host` or `host:port`. This is the
+    /// part after the `@` in qualified usernames and invite codes.
+    #[serde(default = "server_name")]
+    pub server_name: String,
+    /// Whether to contact other servers over HTTPS. This should only be disabled for testing.
+    #[serde(default = "federation_https")]
+    pub federation_https: bool,
+    /// If not empty, the only other servers that this server federates with
+    #[serde(default = "federation_allowlist")]
+    pub federation_allowlist: Vec<String>,
+    /// Whether other servers may be reached at loopback or private network addresses. This should
+    /// only be enabled for testing, e.g to run more than one server on a machine.
+    #[serde(default = "federation_private_addresses")]
+    pub federation_private_addresses: bool,
+    /// Whether outgoing webhooks may deliver to loopback or private network addresses. This should
+    /// only be enabled for testing, or if every community moderator is trusted with access to the
+    /// server's network.
+    #[serde(default = "outgoing_webhook_private_addresses")]
+    pub outgoing_webhook_private_addresses: bool,
+    /// Who may create an account through the register endpoint
+    #[serde(default = "registration_mode")]
+    pub registration_mode: RegistrationMode,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistrationMode {
+    /// Anyone may register
+    Open,
+    /// Nobody may register. Existing users can still log in.
+    Closed,
+    /// Only people given a registration token by an administrator may register
+    ServerInviteToken,
+    /// Anyone may register, but cannot log in until an administrator approves their account.
+    /// Registering with a registration token skips approval.
+    AdminApproval,
 }
 
 fn max_message_len() -> u32 {
     2500
 }
 
+fn max_ciphertext_len() -> u32 {
+    65536
+}
+
 fn max_community_name_len() -> u16 {
     50
 }
@@ -83,6 +173,34 @@ fn max_display_name_len() -> u16 {
     64
 }
 
+fn ratelimit_burst_per_min() -> u32 {
+    vertex::RATELIMIT_BURST_PER_MIN
+}
+
+fn bot_ratelimit_burst_per_min() -> u32 {
+    vertex::BOT_RATELIMIT_BURST_PER_MIN
+}
+
+fn message_ratelimit_per_min() -> u32 {
+    60
+}
+
+fn invite_ratelimit_per_min() -> u32 {
+    10
+}
+
+fn room_creation_ratelimit_per_min() -> u32 {
+    10
+}
+
+fn auth_ratelimit_per_min() -> u32 {
+    10
+}
+
+fn federation_key_fetch_ratelimit_per_min() -> u32 {
+    10
+}
+
 fn https() -> bool {
     true
 }
@@ -91,6 +209,42 @@ fn ip() -> SocketAddr {
     "127.0.0.1:8443".parse().unwrap()
 }
 
+fn metrics_ip() -> Option<SocketAddr> {
+    None
+}
+
+fn shutdown_timeout_secs() -> u64 {
+    30
+}
+
+fn federation() -> bool {
+    false
+}
+
+fn server_name() -> String {
+    "localhost:8443".to_string()
+}
+
+fn federation_https() -> bool {
+    true
+}
+
+fn federation_allowlist() -> Vec<String> {
+    Vec::new()
+}
+
+fn federation_private_addresses() -> bool {
+    false
+}
+
+fn outgoing_webhook_private_addresses() -> bool {
+    false
+}
+
+fn registration_mode() -> RegistrationMode {
+    RegistrationMode::Open
+}
+
 fn tokens_sweep_interval_secs() -> u64 {
     1800 // 30min
 }
@@ -111,16 +265,45 @@ fn invite_codes_sweep_interval_secs() -> u64 {
     1800 // 30min
 }
 
+fn message_retention_days() -> Option<u32> {
+    None
+}
+
+fn messages_sweep_interval_secs() -> u64 {
+    3600 // 1h
+}
+
+fn restrictions_sweep_interval_secs() -> u64 {
+    300 // 5min
+}
+
 fn max_invite_codes_per_community() -> u32 {
     100
 }
 
+fn max_bots_per_user() -> u32 {
+    10
+}
+
+fn report_context_messages() -> u32 {
+    5
+}
+
+/// The directory that the config files are kept in. This can be overridden with the
+/// `VERTEX_SERVER_CONFIG_DIR` environment variable, e.g to run more than one server on a machine.
+pub fn config_dir() -> PathBuf {
+    match std::env::var_os("VERTEX_SERVER_CONFIG_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => ProjectDirs::from("", "vertex_chat", "vertex_server")
+            .expect("Error getting project directories")
+            .config_dir()
+            .to_path_buf(),
+    }
+}
+
 pub fn db_config() -> tokio_postgres::Config {
     const DEFAULT: &str = "host=localhost user=postgres password=postgres dbname=vertex";
-    let path = ProjectDirs::from("", "vertex_chat", "vertex_server")
-        .expect("Error getting project directories")
-        .config_dir()
-        .join("db.conf");
+    let path = config_dir().join("db.conf");
 
     fs::read_to_string(path)
         .unwrap_or_else(|_| DEFAULT.to_string())
@@ -128,99 +311,162 @@ pub fn db_config() -> tokio_postgres::Config {
         .expect("Invalid db.conf!")
 }
 
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(
+                f,
+                "Error reading config file. It is expected to be here: {}. Error: {:?}",
+                path.to_string_lossy(),
+                e,
+            ),
+            ConfigError::Parse(e) => write!(f, "Invalid config file: {}", e),
+            ConfigError::Invalid(reason) => write!(f, "Invalid config: {}", reason),
+        }
+    }
+}
+
+/// Loads the config at startup, creating an empty config file if there is none
 pub fn load_config() -> Config {
-    let dirs = ProjectDirs::from("", "vertex_chat", "vertex_server")
-        .expect("Error getting project directories");
-    let config_dir = dirs.config_dir();
-    let config_file = config_dir.join("config.toml");
+    read_config().unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Reads and validates the config file
+pub fn read_config() -> Result<Config, ConfigError> {
+    let dir = config_dir();
+    let config_file = dir.join("config.toml");
     let res = fs::read_to_string(&config_file);
 
     let config_str = match res {
         Ok(s) => s,
         Err(ref e) if e.kind() == ErrorKind::NotFound => {
-            fs::create_dir_all(config_dir).unwrap_or_else(|_| {
-                panic!(
-                    "Error creating config dirs ({})",
-                    config_dir.to_string_lossy(),
-                )
-            });
-
-            File::create(&config_file).unwrap_or_else(|_| {
-                panic!(
-                    "Error creating config file ({})",
-                    config_file.to_string_lossy(),
-                )
-            });
-
-            fs::read_to_string(&config_file).unwrap_or_else(|_| {
-                panic!(
-                    "Error reading config file ({}). Error",
-                    config_file.to_string_lossy(),
-                )
-            })
+            fs::create_dir_all(&dir).map_err(|e| ConfigError::Read(dir, e))?;
+            File::create(&config_file).map_err(|e| ConfigError::Read(config_file.clone(), e))?;
+            fs::read_to_string(&config_file)
+                .map_err(|e| ConfigError::Read(config_file.clone(), e))?
         }
-        Err(e) => panic!(
-            "Error reading config file. It is expected to be here: {}. Error: {:?}",
-            config_file.to_string_lossy(),
-            e,
-        ),
+        Err(e) => return Err(ConfigError::Read(config_file, e)),
     };
 
-    let config: Config = toml::from_str(&config_str).expect("Invalid config file");
+    let mut config: Config = toml::from_str(&config_str).map_err(ConfigError::Parse)?;
+    validate(&config).map_err(ConfigError::Invalid)?;
+
+    // Context positions are stored as SMALLINTs, and each message is copied into every report
+    if config.report_context_messages > MAX_REPORT_CONTEXT_MESSAGES {
+        log::warn!(
+            "report_context_messages is {}, but at most {} are kept; using {}",
+            config.report_context_messages,
+            MAX_REPORT_CONTEXT_MESSAGES,
+            MAX_REPORT_CONTEXT_MESSAGES,
+        );
+        config.report_context_messages = MAX_REPORT_CONTEXT_MESSAGES;
+    }
+
+    Ok(config)
+}
 
-    // Validate config
+fn validate(config: &Config) -> Result<(), &'static str> {
     if config.min_password_len < 8 {
-        panic!("Minimum password length must be greater than 8");
+        return Err("Minimum password length must be greater than 8");
     }
 
     if config.max_password_len < config.min_password_len {
-        panic!("Maximum password length must be greater or equal to than minimum password length");
+        return Err(
+            "Maximum password length must be greater or equal to than minimum password length",
+        );
     }
 
     if config.min_username_len < 1 {
-        panic!("Minimum username length must be greater than or equal to 1");
+        return Err("Minimum username length must be greater than or equal to 1");
     }
 
     if config.max_username_len < config.min_username_len {
-        panic!("Maximum username length must be greater than or equal to minimum username length");
+        return Err(
+            "Maximum username length must be greater than or equal to minimum username length",
+        );
     }
 
     if config.max_display_name_len < config.min_username_len {
-        panic!("Maximum display name length must be greater than or equal to minimum display name length");
+        return Err("Maximum display name length must be greater than or equal to minimum \
+            display name length");
     }
 
     if config.tokens_sweep_interval_secs < 60 {
-        panic!("Tokens sweep interval must be greater than 1 minute!");
+        return Err("Tokens sweep interval must be greater than 1 minute!");
+    }
+
+    if config.invite_codes_sweep_interval_secs < 60 {
+        return Err("Invite codes sweep interval must be greater than 1 minute!");
+    }
+
+    if config.messages_sweep_interval_secs < 60 {
+        return Err("Messages sweep interval must be greater than 1 minute!");
+    }
+
+    if config.restrictions_sweep_interval_secs < 60 {
+        return Err("Restrictions sweep interval must be greater than 1 minute!");
+    }
+
+    if !community::valid_retention(config.message_retention_days) {
+        return Err("Message retention must be between 1 and 36500 days");
     }
 
     if config.max_message_len < 1 {
-        panic!("Maximum message length must be greater than or equal to 1");
+        return Err("Maximum message length must be greater than or equal to 1");
+    }
+
+    if config.max_ciphertext_len < config.max_message_len {
+        return Err("Maximum ciphertext length must be greater than or equal to maximum message \
+            length");
     }
 
     if config.max_community_name_len < 1 {
-        panic!("Maximum community name length must be greater than or equal to 1");
+        return Err("Maximum community name length must be greater than or equal to 1");
     }
 
     if config.max_community_description_len < 1 {
-        panic!("Maximum community description length must be greater than or equal to 1");
+        return Err("Maximum community description length must be greater than or equal to 1");
     }
 
     if config.max_channel_name_len < 1 {
-        panic!("Maximum channel length must be greater than or equal to 1");
+        return Err("Maximum channel length must be greater than or equal to 1");
+    }
+
+    let quotas = [
+        config.ratelimit_burst_per_min,
+        config.bot_ratelimit_burst_per_min,
+        config.message_ratelimit_per_min,
+        config.invite_ratelimit_per_min,
+        config.room_creation_ratelimit_per_min,
+        config.auth_ratelimit_per_min,
+        config.federation_key_fetch_ratelimit_per_min,
+    ];
+
+    if quotas.iter().any(|&quota| quota < 1) {
+        return Err("Ratelimit quotas must be greater than or equal to 1");
+    }
+
+    if config.server_name.is_empty() || config.server_name.contains(&['@', '/'][..]) {
+        return Err("Server name must be a non-empty host name, optionally with a port");
     }
 
     if Level::from_str(&config.log_level).is_err() {
-        panic!("Invalid log level! It should be 'trace', 'debug', 'info', 'warn', or 'error'")
+        return Err("Invalid log level! It should be 'trace', 'debug', 'info', 'warn', or 'error'");
     }
 
-    config
+    Ok(())
 }
 
 /// Returns (cert path, key path)
 pub fn ssl_config() -> (PathBuf, PathBuf) {
-    let dirs = ProjectDirs::from("", "vertex_chat", "vertex_server")
-        .expect("Error getting project directories");
-    let dir = dirs.config_dir();
+    let dir = config_dir();
 
     let cert_path = dir.join("cert.pem");
     let key_path = dir.join("key.pem");
@@ -28,7 +28,7 @@ impl Database {
         ";
         const DELETE: &str = "DELETE FROM administrators WHERE user_id = $1";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let res = if permissions == AdminPermissionFlags::from_bits_truncate(0) {
             conn.client.execute(DELETE, &[&user.0]).await
         } else {
@@ -64,7 +64,7 @@ impl Database {
     pub async fn get_admin_permissions(&self, user: UserId) -> DbResult<AdminPermissionFlags> {
         const QUERY: &str = "SELECT permission_flags FROM administrators WHERE user_id = $1";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let opt = conn.client.query_opt(QUERY, &[&user.0]).await?;
 
         if let Some(row) = opt {
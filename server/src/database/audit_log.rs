@@ -0,0 +1,166 @@
+use crate::database::{Database, DbResult};
+use futures::{future, Stream, StreamExt, TryStreamExt};
+use std::convert::TryFrom;
+use tokio_postgres::Row;
+use vertex::prelude::*;
+
+/// How many entries are returned from a search of the audit log at most
+const MAX_AUDIT_LOG_RESULTS: i64 = 200;
+
+// There are deliberately no foreign keys here, so that entries outlive the users and communities
+// that they mention. Their names at the time of the action are kept for the same reason.
+pub(super) const CREATE_ADMIN_AUDIT_LOG_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS admin_audit_log (
+        id               BIGSERIAL PRIMARY KEY,
+        datetime         TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+        actor            UUID,
+        actor_username   VARCHAR,
+        action           "char" NOT NULL,
+        target_user      UUID,
+        target_username  VARCHAR,
+        community        UUID,
+        community_name   VARCHAR,
+        target           VARCHAR,
+        parameters       VARCHAR NOT NULL
+    )"#;
+
+/// An action to be recorded in the audit log
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    /// Unset for actions taken from the command line
+    pub actor: Option<UserId>,
+    pub action: AuditAction,
+    pub target_user: Option<UserId>,
+    pub community: Option<CommunityId>,
+    pub target: Option<String>,
+    pub parameters: serde_json::Value,
+}
+
+impl AuditEvent {
+    pub fn new(actor: Option<UserId>, action: AuditAction) -> Self {
+        AuditEvent {
+            actor,
+            action,
+            target_user: None,
+            community: None,
+            target: None,
+            parameters: serde_json::json!({}),
+        }
+    }
+}
+
+/// Returns `None` for actions that this version of the server does not know of
+fn row_to_entry(row: &Row) -> Result<Option<AuditLogEntry>, tokio_postgres::Error> {
+    let action = match AuditAction::try_from(row.try_get::<_, i8>("action")?) {
+        Ok(action) => action,
+        Err(_) => return Ok(None),
+    };
+
+    let user = |id: &str, name: &str| -> Result<Option<ReportUser>, tokio_postgres::Error> {
+        row.try_get::<_, Option<_>>(id)?
+            .map(|id| {
+                Ok(ReportUser {
+                    id: UserId(id),
+                    username: row
+                        .try_get::<_, Option<String>>(name)?
+                        .unwrap_or_else(|| "<deleted user>".to_string()),
+                })
+            })
+            .transpose()
+    };
+
+    Ok(Some(AuditLogEntry {
+        id: row.try_get("id")?,
+        datetime: row.try_get("datetime")?,
+        actor: user("actor", "actor_username")?,
+        action,
+        target_user: user("target_user", "target_username")?,
+        community: row
+            .try_get::<_, Option<_>>("community")?
+            .map(|id| {
+                Ok(ReportCommunity {
+                    id: CommunityId(id),
+                    name: row
+                        .try_get::<_, Option<String>>("community_name")?
+                        .unwrap_or_else(|| "<deleted community>".to_string()),
+                })
+            })
+            .transpose()?,
+        target: row.try_get("target")?,
+        parameters: row.try_get("parameters")?,
+    }))
+}
+
+impl Database {
+    /// Appends an action to the audit log. The names of the users and community involved are
+    /// looked up and kept alongside their IDs.
+    pub async fn log_audit_event(&self, event: AuditEvent) -> DbResult<()> {
+        const STMT: &str = "
+            INSERT INTO admin_audit_log
+            (
+                actor, actor_username, action, target_user, target_username, community,
+                community_name, target, parameters
+            )
+            VALUES (
+                $1, (SELECT username FROM users WHERE id = $1),
+                $2,
+                $3, (SELECT username FROM users WHERE id = $3),
+                $4, (SELECT name FROM communities WHERE id = $4),
+                $5, $6
+            )";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        conn.client
+            .execute(
+                &stmt,
+                &[
+                    &event.actor.map(|id| id.0),
+                    &(event.action as i8),
+                    &event.target_user.map(|id| id.0),
+                    &event.community.map(|id| id.0),
+                    &event.target,
+                    &event.parameters.to_string(),
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Searches the audit log, newest first
+    pub async fn search_audit_log(
+        &self,
+        criteria: AuditLogCriteria,
+    ) -> DbResult<impl Stream<Item = DbResult<AuditLogEntry>>> {
+        const SELECT_QUERY: &str = "
+            SELECT * FROM admin_audit_log
+            %where%
+            ORDER BY id DESC
+            LIMIT %limit%";
+
+        build_where_clause! {
+            let (mut args, where_clause) = criteria: {
+                actor => "actor_username = LOWER(${n})",
+                target_user => "target_username = LOWER(${n})",
+                action as i8 => "action = ${n}",
+                community => "community_name % ${n}",
+                before_date => "datetime < ${n}",
+                after_date => "datetime > ${n}",
+            }
+        };
+
+        args.push(&MAX_AUDIT_LOG_RESULTS);
+        let query = SELECT_QUERY
+            .replace("%where%", &where_clause)
+            .replace("%limit%", &format!("${}", args.len()));
+
+        let stream = self.query_stream(&query, &args).await?;
+        let stream = stream
+            .map(|row| row_to_entry(&row?))
+            .map_err(|e: tokio_postgres::Error| e.into())
+            .try_filter_map(future::ok);
+
+        Ok(stream)
+    }
+}
@@ -0,0 +1,209 @@
+use crate::database::{Database, DatabaseError, DbResult};
+use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt};
+use log::warn;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+use vertex::prelude::*;
+
+/// Max number of automod hits kept in the log per community
+const MAX_HITS_LOGGED: i64 = 1000;
+
+// Triggers and actions are stored as JSON, since their shape depends on their kind
+pub(super) const CREATE_AUTOMOD_RULES_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS automod_rules (
+        id         SERIAL PRIMARY KEY,
+        community  UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
+        name       VARCHAR NOT NULL,
+        trigger    VARCHAR NOT NULL,
+        action     VARCHAR NOT NULL
+    )";
+
+// The rule's name is kept so that hits still make sense once the rule is deleted
+pub(super) const CREATE_AUTOMOD_HITS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS automod_hits (
+        id            BIGSERIAL PRIMARY KEY,
+        datetime      TIMESTAMP WITH TIME ZONE NOT NULL,
+        community     UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
+        rule          INTEGER REFERENCES automod_rules(id) ON DELETE SET NULL,
+        rule_name     VARCHAR NOT NULL,
+        author        UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        room          UUID REFERENCES rooms(id) ON DELETE SET NULL,
+        message_text  VARCHAR NOT NULL,
+        action        VARCHAR NOT NULL
+    )";
+
+/// Returns `None` if the rule's trigger or action could not be decoded
+fn rule_from_row(row: &Row) -> Result<Option<AutomodRule>, tokio_postgres::Error> {
+    let id = row.try_get("id")?;
+    let trigger = serde_json::from_str(row.try_get("trigger")?);
+    let action = serde_json::from_str(row.try_get("action")?);
+
+    match (trigger, action) {
+        (Ok(trigger), Ok(action)) => Ok(Some(AutomodRule {
+            id,
+            name: row.try_get("name")?,
+            trigger,
+            action,
+        })),
+        _ => {
+            warn!("Automod rule {} is malformed, ignoring", id);
+            Ok(None)
+        }
+    }
+}
+
+/// Returns `None` if the hit's action could not be decoded
+fn hit_from_row(row: &Row) -> Result<Option<AutomodHit>, tokio_postgres::Error> {
+    let action = match serde_json::from_str(row.try_get("action")?) {
+        Ok(action) => action,
+        Err(_) => return Ok(None),
+    };
+
+    Ok(Some(AutomodHit {
+        id: row.try_get("id")?,
+        datetime: row.try_get("datetime")?,
+        rule: row.try_get("rule")?,
+        rule_name: row.try_get("rule_name")?,
+        user: UserId(row.try_get("author")?),
+        room: row.try_get::<_, Option<_>>("room")?.map(RoomId),
+        message_text: row.try_get("message_text")?,
+        action,
+    }))
+}
+
+pub struct AutomodHitRecord {
+    pub datetime: DateTime<Utc>,
+    pub community: CommunityId,
+    pub rule: AutomodRule,
+    pub author: UserId,
+    pub room: RoomId,
+    pub message_text: String,
+}
+
+pub struct NonexistentAutomodRule;
+
+impl Database {
+    pub async fn create_automod_rule(
+        &self,
+        community: CommunityId,
+        name: String,
+        trigger: AutomodTrigger,
+        action: AutomodAction,
+    ) -> DbResult<AutomodRule> {
+        const STMT: &str = "
+            INSERT INTO automod_rules (community, name, trigger, action)
+                VALUES ($1, $2, $3, $4)
+            RETURNING id";
+
+        // Neither contains anything which cannot be represented as JSON
+        let trigger_json = serde_json::to_string(&trigger).unwrap();
+        let action_json = serde_json::to_string(&action).unwrap();
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let args: &[&(dyn ToSql + Sync)] = &[&community.0, &name, &trigger_json, &action_json];
+        let row = conn.client.query_one(&stmt, args).await?;
+
+        Ok(AutomodRule {
+            id: row.try_get("id")?,
+            name,
+            trigger,
+            action,
+        })
+    }
+
+    /// Gets the community's automod rules, in the order that they were created. Malformed rules
+    /// are left out.
+    pub async fn get_automod_rules(&self, community: CommunityId) -> DbResult<Vec<AutomodRule>> {
+        const QUERY: &str = "SELECT * FROM automod_rules WHERE community = $1 ORDER BY id ASC";
+
+        let conn = self.connection().await?;
+        let rows = conn.client.query(QUERY, &[&community.0]).await?;
+
+        let mut rules = Vec::with_capacity(rows.len());
+        for row in rows {
+            if let Some(rule) = rule_from_row(&row)? {
+                rules.push(rule);
+            }
+        }
+
+        Ok(rules)
+    }
+
+    pub async fn delete_automod_rule(
+        &self,
+        community: CommunityId,
+        id: i32,
+    ) -> DbResult<Result<(), NonexistentAutomodRule>> {
+        const STMT: &str = "DELETE FROM automod_rules WHERE community = $1 AND id = $2";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let res = conn.client.execute(&stmt, &[&community.0, &id]).await?;
+
+        Ok(if res == 1 {
+            Ok(())
+        } else {
+            Err(NonexistentAutomodRule)
+        })
+    }
+
+    /// Records a message triggering an automod rule, pruning the oldest entries in the
+    /// community's log if it is full.
+    pub async fn record_automod_hit(&self, hit: AutomodHitRecord) -> DbResult<()> {
+        const INSERT: &str = "
+            INSERT INTO automod_hits
+                (datetime, community, rule, rule_name, author, room, message_text, action)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)";
+        const PRUNE: &str = "
+            DELETE FROM automod_hits
+                WHERE community = $1 AND id NOT IN (
+                    SELECT id FROM automod_hits
+                        WHERE community = $1
+                        ORDER BY id DESC
+                        LIMIT $2
+                )";
+
+        let action_json = serde_json::to_string(&hit.rule.action).unwrap();
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(INSERT).await?;
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &hit.datetime,
+            &hit.community.0,
+            &hit.rule.id,
+            &hit.rule.name,
+            &hit.author.0,
+            &hit.room.0,
+            &hit.message_text,
+            &action_json,
+        ];
+        conn.client.execute(&stmt, args).await?;
+
+        let stmt = conn.client.prepare(PRUNE).await?;
+        conn.client
+            .execute(&stmt, &[&hit.community.0, &MAX_HITS_LOGGED])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gets the most recent automod hits in the community, newest first
+    pub async fn get_automod_hits(
+        &self,
+        community: CommunityId,
+    ) -> DbResult<impl Stream<Item = DbResult<AutomodHit>>> {
+        const QUERY: &str = "
+            SELECT * FROM automod_hits
+                WHERE community = $1
+                ORDER BY id DESC";
+
+        let stream = self.query_stream(QUERY, &[&community.0]).await?;
+        let stream = stream
+            .map_err(DatabaseError::from)
+            .try_filter_map(|row| async move { Ok(hit_from_row(&row)?) });
+
+        Ok(stream)
+    }
+}
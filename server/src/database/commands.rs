@@ -0,0 +1,116 @@
+use futures::{Stream, TryStreamExt};
+use log::warn;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+
+use crate::database::{Database, DatabaseError, DbResult};
+use vertex::prelude::*;
+
+pub(super) const CREATE_BOT_COMMANDS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS bot_commands (
+        community    UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
+        bot          UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        name         VARCHAR NOT NULL,
+        description  VARCHAR NOT NULL,
+        arguments    VARCHAR NOT NULL,
+
+        PRIMARY KEY (community, bot, name)
+    )";
+
+/// Converts a row into a command. The arguments are stored as JSON, since they are only ever read
+/// and written along with the command itself. Returns `None` if they could not be decoded.
+fn command_from_row(row: &Row) -> Result<Option<BotCommand>, tokio_postgres::Error> {
+    let name: String = row.try_get("name")?;
+    let arguments = match serde_json::from_str(row.try_get("arguments")?) {
+        Ok(arguments) => arguments,
+        Err(_) => {
+            warn!("Arguments of bot command /{} are malformed, ignoring", name);
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(BotCommand {
+        name,
+        description: row.try_get("description")?,
+        arguments,
+    }))
+}
+
+/// Returns `None` if the command's arguments could not be decoded
+fn registered_command_from_row(
+    row: &Row,
+) -> Result<Option<RegisteredCommand>, tokio_postgres::Error> {
+    let bot = UserId(row.try_get("bot")?);
+    Ok(command_from_row(row)?.map(|command| RegisteredCommand { bot, command }))
+}
+
+impl Database {
+    /// Replaces the commands that the bot has registered in the community
+    pub async fn set_bot_commands(
+        &self,
+        community: CommunityId,
+        bot: UserId,
+        commands: Vec<BotCommand>,
+    ) -> DbResult<()> {
+        const DELETE: &str = "DELETE FROM bot_commands WHERE community = $1 AND bot = $2";
+        const INSERT: &str = "
+            INSERT INTO bot_commands (community, bot, name, description, arguments)
+                VALUES ($1, $2, $3, $4, $5)";
+
+        let mut conn = self.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let delete = transaction.prepare(DELETE).await?;
+        transaction
+            .execute(&delete, &[&community.0, &bot.0])
+            .await?;
+
+        let insert = transaction.prepare(INSERT).await?;
+        for command in commands {
+            let arguments = serde_json::to_string(&command.arguments)
+                .expect("Error serializing command arguments");
+            let args: &[&(dyn ToSql + Sync)] = &[
+                &community.0,
+                &bot.0,
+                &command.name,
+                &command.description,
+                &arguments,
+            ];
+
+            transaction.execute(&insert, args).await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_commands_in_community(
+        &self,
+        community: CommunityId,
+    ) -> DbResult<impl Stream<Item = DbResult<RegisteredCommand>>> {
+        const QUERY: &str = "SELECT * FROM bot_commands WHERE community = $1 ORDER BY name";
+
+        let stream = self.query_stream(QUERY, &[&community.0]).await?;
+        let stream = stream
+            .map_err(DatabaseError::from)
+            .try_filter_map(|row| async move { Ok(registered_command_from_row(&row)?) });
+
+        Ok(stream)
+    }
+
+    pub async fn get_bot_command(
+        &self,
+        community: CommunityId,
+        bot: UserId,
+        name: String,
+    ) -> DbResult<Option<BotCommand>> {
+        const QUERY: &str = "
+            SELECT * FROM bot_commands WHERE community = $1 AND bot = $2 AND name = $3";
+
+        let opt = self.query_opt(QUERY, &[&community.0, &bot.0, &name]).await?;
+        match opt {
+            Some(row) => Ok(command_from_row(&row)?),
+            None => Ok(None),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::database::{Database, DbResult};
 use futures::{Stream, TryStreamExt};
 use std::convert::TryFrom;
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
 use uuid::Uuid;
 use vertex::prelude::*;
@@ -17,6 +18,10 @@ pub struct CommunityRecord {
     pub id: CommunityId,
     pub name: String,
     pub description: Option<String>,
+    /// How many days messages are kept for, if the community has its own retention policy
+    pub retention_days: Option<u32>,
+    /// Whether the community is listed in the public directory
+    pub public: bool,
 }
 
 impl TryFrom<Row> for CommunityRecord {
@@ -27,10 +32,38 @@ impl TryFrom<Row> for CommunityRecord {
             id: CommunityId(row.try_get("id")?),
             name: row.try_get("name")?,
             description: row.try_get("description")?,
+            retention_days: row
+                .try_get::<&str, Option<i32>>("retention_days")?
+                .map(|days| days as u32),
+            public: row.try_get("public")?,
         })
     }
 }
 
+/// A community in the public directory, along with how many members it has
+#[derive(Debug, Clone)]
+pub struct PublicCommunityRecord {
+    pub id: CommunityId,
+    pub name: String,
+    pub description: Option<String>,
+    pub members: u32,
+}
+
+impl TryFrom<Row> for PublicCommunityRecord {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<PublicCommunityRecord, tokio_postgres::Error> {
+        Ok(PublicCommunityRecord {
+            id: CommunityId(row.try_get("id")?),
+            name: row.try_get("name")?,
+            description: row.try_get("description")?,
+            members: row.try_get::<&str, i64>("members")? as u32,
+        })
+    }
+}
+
+pub struct NonexistentCommunity;
+
 impl Database {
     pub async fn get_community_metadata(
         &self,
@@ -49,7 +82,7 @@ impl Database {
     pub async fn create_community(&self, name: String) -> DbResult<CommunityId> {
         const STMT: &str = "INSERT INTO communities (id, name, description) VALUES ($1, $2, NULL)";
         let id = Uuid::new_v4();
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         conn.client.execute(&stmt, &[&id, &name]).await?;
         Ok(CommunityId(id))
@@ -71,7 +104,7 @@ impl Database {
         new_description: String,
     ) -> DbResult<()> {
         const STMT: &str = "UPDATE communities SET description = $1 WHERE id = $2";
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         conn.client
             .execute(&stmt, &[&new_description, &id.0])
@@ -81,9 +114,80 @@ impl Database {
 
     pub async fn change_community_name(&self, id: CommunityId, new_name: String) -> DbResult<()> {
         const STMT: &str = "UPDATE communities SET name = $1 WHERE id = $2";
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         conn.client.execute(&stmt, &[&new_name, &id.0]).await?;
         Ok(())
     }
+
+    /// Sets how many days messages in the community are kept for. If unset, the server's default
+    /// applies.
+    pub async fn set_community_retention(
+        &self,
+        id: CommunityId,
+        days: Option<u32>,
+    ) -> DbResult<()> {
+        const STMT: &str = "UPDATE communities SET retention_days = $1 WHERE id = $2";
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let days = days.map(|days| days as i32);
+        conn.client.execute(&stmt, &[&days, &id.0]).await?;
+        Ok(())
+    }
+
+    pub async fn set_community_public(&self, id: CommunityId, public: bool) -> DbResult<()> {
+        const STMT: &str = "UPDATE communities SET public = $1 WHERE id = $2";
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        conn.client.execute(&stmt, &[&public, &id.0]).await?;
+        Ok(())
+    }
+
+    /// Searches the public directory by community name. If the query is empty, all public
+    /// communities are listed, those with the most members first.
+    pub async fn search_public_communities(
+        &self,
+        query: &str,
+        offset: u32,
+        limit: u32,
+    ) -> DbResult<Vec<PublicCommunityRecord>> {
+        const QUERY: &str = "
+            SELECT communities.id, communities.name, communities.description,
+                    COUNT(community_membership.user_id) AS members
+                FROM communities
+                LEFT JOIN community_membership
+                    ON community_membership.community = communities.id
+            WHERE communities.public AND ($1 = '' OR $1 % communities.name)
+            GROUP BY communities.id
+            ORDER BY SIMILARITY($1, communities.name) DESC, members DESC, communities.name ASC
+            LIMIT $2 OFFSET $3";
+
+        let (limit, offset) = (limit as i64, offset as i64);
+        let args: &[&(dyn ToSql + Sync)] = &[&query, &limit, &offset];
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(QUERY).await?;
+        let rows = conn.client.query(&stmt, args).await?;
+
+        rows.into_iter()
+            .map(|row| Ok(PublicCommunityRecord::try_from(row)?))
+            .collect()
+    }
+
+    /// Deletes the community along with its rooms and messages. Reports about its messages are
+    /// kept.
+    pub async fn delete_community(
+        &self,
+        id: CommunityId,
+    ) -> DbResult<Result<(), NonexistentCommunity>> {
+        const STMT: &str = "DELETE FROM communities WHERE id = $1";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let res = conn.client.execute(&stmt, &[&id.0]).await?;
+        Ok(if res == 1 {
+            Ok(())
+        } else {
+            Err(NonexistentCommunity)
+        })
+    }
 }
@@ -16,6 +16,7 @@ pub(super) const CREATE_COMMUNITY_MEMBERSHIP_TABLE: &str = r#"
 
 pub struct CommunityMember {
     pub community: CommunityId,
+    pub permissions: CommunityPermissionFlags,
 }
 
 impl TryFrom<Row> for CommunityMember {
@@ -24,6 +25,9 @@ impl TryFrom<Row> for CommunityMember {
     fn try_from(row: Row) -> Result<CommunityMember, tokio_postgres::Error> {
         Ok(CommunityMember {
             community: CommunityId(row.try_get("community")?),
+            permissions: CommunityPermissionFlags::from_bits_truncate(
+                row.try_get("permission_flags")?,
+            ),
         })
     }
 }
@@ -49,6 +53,28 @@ impl Database {
         Ok(stream)
     }
 
+    /// Gets every member of the community along with their permissions in it
+    pub async fn get_community_members(
+        &self,
+        community: CommunityId,
+    ) -> DbResult<Vec<(UserId, CommunityPermissionFlags)>> {
+        const QUERY: &str = "SELECT * from community_membership WHERE community = $1";
+
+        let conn = self.connection().await?;
+        let rows = conn.client.query(QUERY, &[&community.0]).await?;
+
+        let mut members = Vec::with_capacity(rows.len());
+        for row in rows {
+            let permissions = row.try_get("permission_flags")?;
+            members.push((
+                UserId(row.try_get("user_id")?),
+                CommunityPermissionFlags::from_bits_truncate(permissions),
+            ));
+        }
+
+        Ok(members)
+    }
+
     pub async fn get_community_membership(
         &self,
         community: CommunityId,
@@ -70,16 +96,18 @@ impl Database {
         &self,
         community: CommunityId,
         user: UserId,
+        permissions: CommunityPermissionFlags,
     ) -> DbResult<Result<(), AddToCommunityError>> {
         const STMT: &str = "
-            INSERT INTO community_membership (community, user_id)
-                VALUES ($1, $2)
+            INSERT INTO community_membership (community, user_id, permission_flags)
+                VALUES ($1, $2, $3)
                 ON CONFLICT DO NOTHING
         ";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let query = conn.client.prepare(STMT).await?;
-        let res = conn.client.execute(&query, &[&community.0, &user.0]).await;
+        let args: &[&(dyn ToSql + Sync)] = &[&community.0, &user.0, &permissions.bits()];
+        let res = conn.client.execute(&query, args).await;
 
         match res {
             Ok(1) => {
@@ -122,4 +150,53 @@ impl Database {
             }
         }
     }
+
+    /// Mutes or unmutes a member within the community. Community mutes always expire.
+    pub async fn set_community_mute(
+        &self,
+        community: CommunityId,
+        user: UserId,
+        mute: Option<Restriction>,
+    ) -> DbResult<()> {
+        const STMT: &str = "
+            UPDATE community_membership SET mute_reason = $3, mute_expires = $4
+                WHERE community = $1 AND user_id = $2";
+
+        let (reason, expires) = match mute {
+            Some(mute) => (Some(mute.reason), mute.expires),
+            None => (None, None),
+        };
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let args: &[&(dyn ToSql + Sync)] = &[&community.0, &user.0, &reason, &expires];
+        conn.client.execute(&stmt, args).await?;
+
+        Ok(())
+    }
+
+    /// Gets the members of the community whose mutes have not yet expired
+    pub async fn get_community_mutes(
+        &self,
+        community: CommunityId,
+    ) -> DbResult<Vec<(UserId, Restriction)>> {
+        const QUERY: &str = "
+            SELECT user_id, mute_reason, mute_expires FROM community_membership
+                WHERE community = $1 AND mute_expires > NOW()";
+
+        let conn = self.connection().await?;
+        let rows = conn.client.query(QUERY, &[&community.0]).await?;
+
+        let mut mutes = Vec::with_capacity(rows.len());
+        for row in rows {
+            let reason: Option<String> = row.try_get("mute_reason")?;
+            let mute = Restriction {
+                reason: reason.unwrap_or_default(),
+                expires: row.try_get("mute_expires")?,
+            };
+            mutes.push((UserId(row.try_get("user_id")?), mute));
+        }
+
+        Ok(mutes)
+    }
 }
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use futures::TryStreamExt;
+use tokio_postgres::types::ToSql;
+use uuid::Uuid;
+
+use crate::database::{Database, DbResult};
+use vertex::prelude::*;
+
+/// The identity keys that devices publish for end-to-end encryption. These are deleted along with
+/// the device's token when it logs out.
+pub(super) const CREATE_DEVICE_KEYS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS device_keys (
+        device        UUID PRIMARY KEY REFERENCES login_tokens(device) ON DELETE CASCADE,
+        user_id       UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        identity_key  BYTEA NOT NULL
+    )";
+
+/// One-time prekeys, which are deleted as they are claimed so that each is only used once
+pub(super) const CREATE_ONE_TIME_PREKEYS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS one_time_prekeys (
+        id      BIGSERIAL PRIMARY KEY,
+        device  UUID NOT NULL REFERENCES device_keys(device) ON DELETE CASCADE,
+        key     BYTEA NOT NULL
+    )";
+
+/// When each device last claimed another's prekeys. A device may only claim one prekey of each
+/// other device a day, so that claiming repeatedly cannot use up everyone's prekeys.
+pub(super) const CREATE_PREKEY_CLAIMS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS prekey_claims (
+        claimer     UUID NOT NULL REFERENCES login_tokens(device) ON DELETE CASCADE,
+        device      UUID NOT NULL REFERENCES device_keys(device) ON DELETE CASCADE,
+        claimed_at  TIMESTAMP WITH TIME ZONE NOT NULL,
+        PRIMARY KEY (claimer, device)
+    )";
+
+impl Database {
+    /// Publishes the device's identity key and adds the given one-time prekeys. If the identity
+    /// key has changed, the prekeys published with the old one are discarded. Returns the number
+    /// of prekeys the device now has.
+    pub async fn publish_device_keys(
+        &self,
+        user: UserId,
+        device: DeviceId,
+        identity_key: Vec<u8>,
+        one_time_prekeys: Vec<Vec<u8>>,
+    ) -> DbResult<u32> {
+        const GET_IDENTITY: &str = "SELECT identity_key FROM device_keys WHERE device = $1";
+        const CLEAR_PREKEYS: &str = "DELETE FROM one_time_prekeys WHERE device = $1";
+        const UPSERT_IDENTITY: &str = "
+            INSERT INTO device_keys (device, user_id, identity_key) VALUES ($1, $2, $3)
+            ON CONFLICT (device) DO UPDATE SET identity_key = $3";
+        const ADD_PREKEY: &str = "INSERT INTO one_time_prekeys (device, key) VALUES ($1, $2)";
+        const COUNT_PREKEYS: &str =
+            "SELECT COUNT(*) AS remaining FROM one_time_prekeys WHERE device = $1";
+
+        let mut conn = self.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let old = transaction.query_opt(GET_IDENTITY, &[&device.0]).await?;
+        if let Some(old) = old {
+            if old.try_get::<&str, Vec<u8>>("identity_key")? != identity_key {
+                transaction.execute(CLEAR_PREKEYS, &[&device.0]).await?;
+            }
+        }
+
+        let args: &[&(dyn ToSql + Sync)] = &[&device.0, &user.0, &identity_key];
+        transaction.execute(UPSERT_IDENTITY, args).await?;
+
+        let add_prekey = transaction.prepare(ADD_PREKEY).await?;
+        for key in &one_time_prekeys {
+            transaction.execute(&add_prekey, &[&device.0, key]).await?;
+        }
+
+        let row = transaction.query_one(COUNT_PREKEYS, &[&device.0]).await?;
+        transaction.commit().await?;
+
+        Ok(row.try_get::<&str, i64>("remaining")? as u32)
+    }
+
+    /// Gets the identity keys of all of the user's devices, without claiming any prekeys
+    pub async fn get_device_keys(&self, user: UserId) -> DbResult<Vec<DeviceKeyBundle>> {
+        const QUERY: &str = "SELECT * FROM device_keys WHERE user_id = $1";
+
+        let stream = self.query_stream(QUERY, &[&user.0]).await?;
+        stream
+            .and_then(|row| async move {
+                Ok(DeviceKeyBundle {
+                    user: UserId(row.try_get("user_id")?),
+                    device: DeviceId(row.try_get("device")?),
+                    identity_key: row.try_get("identity_key")?,
+                    one_time_prekey: None,
+                })
+            })
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Claims a key bundle for every device of every member of the community. Each bundle has a
+    /// one-time prekey if the device had any left, and that prekey is deleted so that it will not
+    /// be handed out again. Devices that the claimer already has sessions with are left out of the
+    /// prekeys claimed, so that prekeys are only used up when a session is set up, as are devices
+    /// that the claimer has claimed a prekey from in the last day.
+    pub async fn claim_device_keys(
+        &self,
+        community: CommunityId,
+        claimer: DeviceId,
+        except_devices: Vec<DeviceId>,
+    ) -> DbResult<Vec<DeviceKeyBundle>> {
+        const IDENTITIES: &str = "
+            SELECT device_keys.* FROM device_keys
+            INNER JOIN community_membership ON device_keys.user_id = community_membership.user_id
+            WHERE community_membership.community = $1";
+
+        // If two claims race for the same prekey, only one of them deletes it. The other gets no
+        // prekey for that device, and falls back to its identity key.
+        const CLAIM_PREKEYS: &str = "
+            DELETE FROM one_time_prekeys WHERE id IN (
+                SELECT DISTINCT ON (one_time_prekeys.device) one_time_prekeys.id
+                FROM one_time_prekeys
+                INNER JOIN device_keys ON one_time_prekeys.device = device_keys.device
+                INNER JOIN community_membership
+                    ON device_keys.user_id = community_membership.user_id
+                WHERE community_membership.community = $1
+                    AND NOT (one_time_prekeys.device = ANY($2))
+                    AND one_time_prekeys.device NOT IN (
+                        SELECT device FROM prekey_claims
+                        WHERE claimer = $3 AND claimed_at > NOW() - INTERVAL '1 day'
+                    )
+                ORDER BY one_time_prekeys.device, one_time_prekeys.id
+            )
+            RETURNING device, key";
+
+        // Claims older than a day no longer limit anything, so they are cleared out as we go
+        const FORGET_OLD_CLAIMS: &str = "
+            DELETE FROM prekey_claims
+            WHERE claimer = $1 AND claimed_at <= NOW() - INTERVAL '1 day'";
+        const RECORD_CLAIMS: &str = "
+            INSERT INTO prekey_claims (claimer, device, claimed_at)
+                SELECT $1, device, NOW() FROM UNNEST($2::UUID[]) AS device
+            ON CONFLICT (claimer, device) DO UPDATE SET claimed_at = NOW()";
+
+        let except_devices: Vec<Uuid> = except_devices.into_iter().map(|device| device.0).collect();
+
+        let mut conn = self.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let args: &[&(dyn ToSql + Sync)] = &[&community.0, &except_devices, &claimer.0];
+        let rows = transaction.query(CLAIM_PREKEYS, args).await?;
+        let mut prekeys = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let key: Vec<u8> = row.try_get("key")?;
+            prekeys.insert(DeviceId(row.try_get("device")?), key);
+        }
+
+        let claimed: Vec<Uuid> = prekeys.keys().map(|device| device.0).collect();
+        transaction
+            .execute(FORGET_OLD_CLAIMS, &[&claimer.0])
+            .await?;
+        transaction
+            .execute(RECORD_CLAIMS, &[&claimer.0, &claimed])
+            .await?;
+
+        let rows = transaction.query(IDENTITIES, &[&community.0]).await?;
+        transaction.commit().await?;
+
+        let mut bundles = Vec::with_capacity(rows.len());
+        for row in rows {
+            let device = DeviceId(row.try_get("device")?);
+            bundles.push(DeviceKeyBundle {
+                user: UserId(row.try_get("user_id")?),
+                device,
+                identity_key: row.try_get("identity_key")?,
+                one_time_prekey: prekeys.remove(&device),
+            });
+        }
+
+        Ok(bundles)
+    }
+
+    /// Gets how many one-time prekeys each of the given devices has left, for those which have
+    /// fewer than `below` left
+    pub async fn devices_low_on_prekeys(
+        &self,
+        devices: &[DeviceId],
+        below: u32,
+    ) -> DbResult<Vec<(UserId, DeviceId, u32)>> {
+        const QUERY: &str = "
+            SELECT device_keys.user_id, device_keys.device, COUNT(one_time_prekeys.id) AS remaining
+            FROM device_keys
+            LEFT JOIN one_time_prekeys ON device_keys.device = one_time_prekeys.device
+            WHERE device_keys.device = ANY($1)
+            GROUP BY device_keys.device
+            HAVING COUNT(one_time_prekeys.id) < $2";
+
+        let devices: Vec<Uuid> = devices.iter().map(|device| device.0).collect();
+        let below = below as i64;
+
+        let args: &[&(dyn ToSql + Sync)] = &[&devices, &below];
+        let stream = self.query_stream(QUERY, args).await?;
+        stream
+            .and_then(|row| async move {
+                Ok((
+                    UserId(row.try_get("user_id")?),
+                    DeviceId(row.try_get("device")?),
+                    row.try_get::<&str, i64>("remaining")? as u32,
+                ))
+            })
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+}
@@ -0,0 +1,244 @@
+use futures::TryStreamExt;
+use tokio_postgres::types::ToSql;
+use uuid::Uuid;
+
+use crate::database::user::insert_user;
+use crate::database::{Database, DbResult, UserRecord, UsernameConflict};
+use vertex::prelude::*;
+
+pub(super) const CREATE_FEDERATED_SERVERS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS federated_servers (
+        name        VARCHAR PRIMARY KEY,
+        public_key  BYTEA NOT NULL,
+        first_seen  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+    )";
+
+/// Maps the local stand-in users for users of other servers to their ID on their home server
+pub(super) const CREATE_REMOTE_USERS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS remote_users (
+        id         UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+        server     VARCHAR NOT NULL,
+        remote_id  UUID NOT NULL,
+
+        UNIQUE(server, remote_id)
+    )";
+
+/// Which communities on other servers local users have joined
+pub(super) const CREATE_REMOTE_COMMUNITY_MEMBERSHIP_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS remote_community_membership (
+        user_id    UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        server     VARCHAR NOT NULL,
+        community  UUID NOT NULL,
+
+        PRIMARY KEY (user_id, community)
+    )";
+
+/// A user of another server, as known to this server
+#[derive(Debug, Clone)]
+pub struct RemoteUser {
+    /// The ID of the local stand-in user
+    pub id: UserId,
+    pub server: String,
+    /// The ID of the user on their home server
+    pub remote_id: UserId,
+}
+
+impl Database {
+    /// Returns the public key that the server was first seen with, if it has been seen before
+    pub async fn get_server_key(&self, server: &str) -> DbResult<Option<Vec<u8>>> {
+        const QUERY: &str = "SELECT public_key FROM federated_servers WHERE name = $1";
+
+        match self.query_opt(QUERY, &[&server]).await? {
+            Some(row) => Ok(Some(row.try_get("public_key")?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the server's public key. If a key is already known for the server, it is kept.
+    pub async fn add_server_key(&self, server: &str, public_key: &[u8]) -> DbResult<()> {
+        const STMT: &str = "
+            INSERT INTO federated_servers (name, public_key) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        conn.client.execute(&stmt, &[&server, &public_key]).await?;
+        Ok(())
+    }
+
+    pub async fn get_remote_user(&self, id: UserId) -> DbResult<Option<RemoteUser>> {
+        const QUERY: &str = "SELECT * FROM remote_users WHERE id = $1";
+
+        match self.query_opt(QUERY, &[&id.0]).await? {
+            Some(row) => Ok(Some(RemoteUser {
+                id,
+                server: row.try_get("server")?,
+                remote_id: UserId(row.try_get("remote_id")?),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn get_remote_user_by_remote_id(
+        &self,
+        server: &str,
+        remote_id: UserId,
+    ) -> DbResult<Option<UserId>> {
+        const QUERY: &str = "SELECT id FROM remote_users WHERE server = $1 AND remote_id = $2";
+
+        match self.query_opt(QUERY, &[&server, &remote_id.0]).await? {
+            Some(row) => Ok(Some(UserId(row.try_get("id")?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the local stand-in for a user of another server, creating it if this is the first time
+    /// the user has been seen. Its display name is kept up to date with the one given.
+    ///
+    /// Fails if the stand-in's username is taken. This happens if the user has been renamed on
+    /// their server and their old name has since been reused, which we don't support yet.
+    pub async fn get_or_create_remote_user(
+        &self,
+        server: &str,
+        remote_id: UserId,
+        username: &str,
+        display_name: String,
+    ) -> DbResult<Result<UserId, UsernameConflict>> {
+        const STMT: &str = "
+            INSERT INTO remote_users (id, server, remote_id) VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING";
+
+        if let Some(id) = self.get_remote_user_by_remote_id(server, remote_id).await? {
+            let profile = self.get_user_profile(id).await?;
+            if profile.map(|p| p.display_name != display_name).unwrap_or(false) {
+                let _ = self.change_display_name(id, display_name).await?;
+            }
+
+            return Ok(Ok(id));
+        }
+
+        let user = UserRecord::new_remote(username, server, display_name);
+        let id = user.id;
+
+        // The stand-in and its mapping are created together, so that there are never stand-ins
+        // which aren't mapped to anyone. The transaction is rolled back if it isn't committed.
+        let created = {
+            let mut conn = self.connection().await?;
+            let transaction = conn.client.transaction().await?;
+
+            let created = match insert_user(&transaction, &user).await? {
+                Ok(()) => {
+                    let stmt = transaction.prepare(STMT).await?;
+                    let args: &[&(dyn ToSql + Sync)] = &[&id.0, &server, &remote_id.0];
+                    transaction.execute(&stmt, args).await? == 1
+                }
+                Err(UsernameConflict) => false,
+            };
+
+            if created {
+                transaction.commit().await?;
+            }
+
+            created
+        };
+
+        if created {
+            return Ok(Ok(id));
+        }
+
+        // We may have raced with another request to create the same user
+        match self.get_remote_user_by_remote_id(server, remote_id).await? {
+            Some(id) => Ok(Ok(id)),
+            None => {
+                log::warn!("Remote user {}@{} conflicts with a local username", username, server);
+                Ok(Err(UsernameConflict))
+            }
+        }
+    }
+
+    /// The servers which have users in the given local community
+    pub async fn get_remote_servers_in_community(
+        &self,
+        community: CommunityId,
+    ) -> DbResult<Vec<String>> {
+        const QUERY: &str = "
+            SELECT DISTINCT remote_users.server FROM community_membership
+            INNER JOIN remote_users ON community_membership.user_id = remote_users.id
+            WHERE community_membership.community = $1";
+
+        let stream = self.query_stream(QUERY, &[&community.0]).await?;
+        stream
+            .and_then(|row| async move { row.try_get("server") })
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn add_remote_community_membership(
+        &self,
+        user: UserId,
+        server: &str,
+        community: CommunityId,
+    ) -> DbResult<()> {
+        const STMT: &str = "
+            INSERT INTO remote_community_membership (user_id, server, community)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        conn.client
+            .execute(&stmt, &[&user.0, &server, &community.0])
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the server that hosts the community, if the user is a member of it
+    pub async fn get_remote_community_membership(
+        &self,
+        user: UserId,
+        community: CommunityId,
+    ) -> DbResult<Option<String>> {
+        const QUERY: &str = "
+            SELECT server FROM remote_community_membership WHERE user_id = $1 AND community = $2";
+
+        match self.query_opt(QUERY, &[&user.0, &community.0]).await? {
+            Some(row) => Ok(Some(row.try_get("server")?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns (server, community) for every remote community the user is a member of
+    pub async fn get_remote_communities(
+        &self,
+        user: UserId,
+    ) -> DbResult<Vec<(String, CommunityId)>> {
+        const QUERY: &str = "SELECT * FROM remote_community_membership WHERE user_id = $1";
+
+        let stream = self.query_stream(QUERY, &[&user.0]).await?;
+        stream
+            .and_then(|row| async move {
+                Ok((row.try_get("server")?, CommunityId(row.try_get("community")?)))
+            })
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// The local users which are members of the community on the given server
+    pub async fn get_remote_community_members(
+        &self,
+        server: &str,
+        community: CommunityId,
+    ) -> DbResult<Vec<UserId>> {
+        const QUERY: &str = "
+            SELECT user_id FROM remote_community_membership WHERE server = $1 AND community = $2";
+
+        let stream = self.query_stream(QUERY, &[&server, &community.0]).await?;
+        stream
+            .and_then(|row| async move { row.try_get::<&str, Uuid>("user_id").map(UserId) })
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+}
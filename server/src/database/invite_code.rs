@@ -4,7 +4,8 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use chrono::{DateTime, Utc};
 use rand::Rng;
 use tokio_postgres::types::ToSql;
-use tokio_postgres::IsolationLevel;
+use tokio_postgres::{IsolationLevel, Row};
+use uuid::Uuid;
 
 use vertex::prelude::*;
 
@@ -17,6 +18,20 @@ pub(super) const CREATE_INVITE_CODES_TABLE: &str = "
         expiration_date TIMESTAMP WITH TIME ZONE
     )";
 
+// A user may join through the same code more than once if they leave in between
+pub(super) const CREATE_INVITE_USES_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS invite_uses (
+        id         BIGSERIAL PRIMARY KEY,
+        code       BIGINT NOT NULL REFERENCES invite_codes(id) ON DELETE CASCADE,
+        user_id    UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        joined_at  TIMESTAMP WITH TIME ZONE NOT NULL
+    )";
+
+/// Only codes which have not expired or been used up can be used to join
+const USABLE: &str = "
+    (expiration_date IS NULL OR expiration_date > NOW())
+    AND (max_uses IS NULL OR uses < max_uses)";
+
 #[derive(Copy, Clone, Debug)]
 pub struct MalformedInviteCode;
 
@@ -24,9 +39,15 @@ pub struct MalformedInviteCode;
 pub struct InviteCodeRecord {
     pub id: i64,
     pub expiration_date: Option<DateTime<Utc>>,
+    pub max_uses: Option<u32>,
+    pub uses: u32,
 }
 
 impl InviteCodeRecord {
+    fn encode_id(id: i64) -> InviteCode {
+        InviteCode(base64::encode_config(&id.to_le_bytes(), base64::URL_SAFE_NO_PAD))
+    }
+
     fn parse_id(code: InviteCode) -> Result<i64, MalformedInviteCode> {
         let bytes = base64::decode_config(&code.0, base64::URL_SAFE_NO_PAD)
             .map_err(|_| MalformedInviteCode)?;
@@ -39,26 +60,56 @@ impl InviteCodeRecord {
 
 impl Into<String> for InviteCodeRecord {
     fn into(self) -> String {
-        base64::encode_config(&self.id.to_le_bytes(), base64::URL_SAFE_NO_PAD)
+        InviteCodeRecord::encode_id(self.id).0
     }
 }
 
+fn invite_user(
+    row: &Row,
+    id: &str,
+    username: &str,
+) -> Result<Option<InviteUser>, tokio_postgres::Error> {
+    let id: Option<Uuid> = row.try_get(id)?;
+    let username: Option<String> = row.try_get(username)?;
+
+    Ok(match (id, username) {
+        (Some(id), Some(username)) => Some(InviteUser {
+            id: UserId(id),
+            username,
+        }),
+        _ => None,
+    })
+}
+
 pub struct TooManyInviteCodes;
 
+pub struct NonexistentInviteCode;
+
+/// A use of an invite code which has been counted, but not yet recorded. It must be either
+/// recorded or released, depending on whether the user could join the community.
+pub struct ClaimedInvite {
+    pub id: i64,
+    pub community: CommunityId,
+}
+
 impl Database {
     pub async fn create_invite_code(
         &self,
         community: CommunityId,
+        creator: UserId,
         expiration_date: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
         max_per_community: i64,
     ) -> DbResult<Result<InviteCode, TooManyInviteCodes>> {
         // From https://stackoverflow.com/a/26448803/4871468
         const INSERT: &str = "
-            INSERT INTO invite_codes (id, community, expiration_date)
+            INSERT INTO invite_codes (id, community, expiration_date, creator, max_uses)
             SELECT
               $1 AS id,
               $2 AS community,
-              $3 AS expiration_date
+              $3 AS expiration_date,
+              $5 AS creator,
+              $6 AS max_uses
             FROM
               invite_codes
             WHERE community = $2
@@ -68,12 +119,19 @@ impl Database {
         ";
         const COUNT: &str = "SELECT COUNT(*) FROM invite_codes WHERE community = $1;";
 
-        let mut conn = self.pool.connection().await?;
+        let mut conn = self.connection().await?;
+        let max_uses = max_uses.map(|max| max as i32);
 
         let id = loop {
             let id = rand::thread_rng().gen::<i64>();
-            let args: &[&(dyn ToSql + Sync)] =
-                &[&id, &community.0, &expiration_date, &max_per_community];
+            let args: &[&(dyn ToSql + Sync)] = &[
+                &id,
+                &community.0,
+                &expiration_date,
+                &max_per_community,
+                &creator.0,
+                &max_uses,
+            ];
 
             let builder = conn.client.build_transaction();
             let insert_transaction = builder
@@ -107,31 +165,222 @@ impl Database {
             }
         };
 
-        let record = InviteCodeRecord {
-            id,
-            expiration_date,
-        };
-        Ok(Ok(InviteCode(record.into())))
+        Ok(Ok(InviteCodeRecord::encode_id(id)))
     }
 
+    /// Gets the community that the invite code is to, if the code can still be used
     pub async fn get_community_from_invite_code(
         &self,
         code: InviteCode,
     ) -> DbResult<Result<Option<CommunityId>, MalformedInviteCode>> {
-        const QUERY: &str = "
-            SELECT community FROM invite_codes WHERE id=$1
-        ";
+        let query = format!("SELECT community FROM invite_codes WHERE id = $1 AND {}", USABLE);
 
         let id = match InviteCodeRecord::parse_id(code) {
             Ok(id) => id,
             Err(e) => return Ok(Err(e)),
         };
 
-        let community = match self.query_opt(QUERY, &[&id]).await? {
+        let community = match self.query_opt(&query, &[&id]).await? {
             Some(row) => Some(CommunityId(row.try_get("community")?)),
             None => None,
         };
 
         Ok(Ok(community))
     }
+
+    /// Counts a use of the invite code, if it can still be used. This is done before the user
+    /// joins, so that a code can't be used more times than it allows by joining at the same time.
+    pub async fn claim_invite_code(
+        &self,
+        code: InviteCode,
+    ) -> DbResult<Result<Option<ClaimedInvite>, MalformedInviteCode>> {
+        let stmt = format!(
+            "UPDATE invite_codes SET uses = uses + 1 WHERE id = $1 AND {} RETURNING community",
+            USABLE
+        );
+
+        let id = match InviteCodeRecord::parse_id(code) {
+            Ok(id) => id,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(&stmt).await?;
+        let claimed = match conn.client.query_opt(&stmt, &[&id]).await? {
+            Some(row) => Some(ClaimedInvite {
+                id,
+                community: CommunityId(row.try_get("community")?),
+            }),
+            None => None,
+        };
+
+        Ok(Ok(claimed))
+    }
+
+    /// Records that the user joined the community through the claimed invite code
+    pub async fn record_invite_use(&self, claimed: ClaimedInvite, user: UserId) -> DbResult<()> {
+        const STMT: &str = "
+            INSERT INTO invite_uses (code, user_id, joined_at) VALUES ($1, $2, NOW())
+            ON CONFLICT DO NOTHING";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        conn.client.execute(&stmt, &[&claimed.id, &user.0]).await?;
+        Ok(())
+    }
+
+    /// Gives back a claimed use of an invite code, because the user could not join through it
+    pub async fn release_invite_code(&self, claimed: ClaimedInvite) -> DbResult<()> {
+        const STMT: &str = "UPDATE invite_codes SET uses = uses - 1 WHERE id = $1 AND uses > 0";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        conn.client.execute(&stmt, &[&claimed.id]).await?;
+        Ok(())
+    }
+
+    /// Gets the invite codes to the community, newest first
+    pub async fn get_invites_in_community(&self, community: CommunityId) -> DbResult<Vec<Invite>> {
+        const QUERY: &str = "
+            SELECT invite_codes.*, users.username AS creator_username
+                FROM invite_codes
+                LEFT JOIN users ON users.id = invite_codes.creator
+            WHERE invite_codes.community = $1
+            ORDER BY invite_codes.created_at DESC";
+
+        let conn = self.connection().await?;
+        let rows = conn.client.query(QUERY, &[&community.0]).await?;
+
+        let mut invites = Vec::with_capacity(rows.len());
+        for row in rows {
+            invites.push(Invite {
+                code: InviteCodeRecord::encode_id(row.try_get("id")?),
+                creator: invite_user(&row, "creator", "creator_username")?,
+                created_at: row.try_get("created_at")?,
+                expiration_date: row.try_get("expiration_date")?,
+                max_uses: row
+                    .try_get::<&str, Option<i32>>("max_uses")?
+                    .map(|max| max as u32),
+                uses: row.try_get::<&str, i32>("uses")? as u32,
+            });
+        }
+
+        Ok(invites)
+    }
+
+    pub async fn delete_invite_code(
+        &self,
+        community: CommunityId,
+        code: InviteCode,
+    ) -> DbResult<Result<(), NonexistentInviteCode>> {
+        const STMT: &str = "DELETE FROM invite_codes WHERE community = $1 AND id = $2";
+
+        let id = match InviteCodeRecord::parse_id(code) {
+            Ok(id) => id,
+            Err(MalformedInviteCode) => return Ok(Err(NonexistentInviteCode)),
+        };
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let res = conn.client.execute(&stmt, &[&community.0, &id]).await?;
+
+        Ok(if res == 1 {
+            Ok(())
+        } else {
+            Err(NonexistentInviteCode)
+        })
+    }
+
+    /// Gets who joined the community through the invite code, oldest first. Users who have since
+    /// been deleted are left out.
+    pub async fn get_invite_uses(
+        &self,
+        community: CommunityId,
+        code: InviteCode,
+    ) -> DbResult<Result<Vec<InviteUse>, NonexistentInviteCode>> {
+        const EXISTS: &str = "SELECT 1 FROM invite_codes WHERE community = $1 AND id = $2";
+        const QUERY: &str = "
+            SELECT invite_uses.joined_at, users.id AS user_id, users.username
+                FROM invite_uses
+                INNER JOIN users ON users.id = invite_uses.user_id
+            WHERE invite_uses.code = $1
+            ORDER BY invite_uses.joined_at ASC";
+
+        let id = match InviteCodeRecord::parse_id(code) {
+            Ok(id) => id,
+            Err(MalformedInviteCode) => return Ok(Err(NonexistentInviteCode)),
+        };
+
+        if self.query_opt(EXISTS, &[&community.0, &id]).await?.is_none() {
+            return Ok(Err(NonexistentInviteCode));
+        }
+
+        let conn = self.connection().await?;
+        let rows = conn.client.query(QUERY, &[&id]).await?;
+        let mut uses = Vec::with_capacity(rows.len());
+        for row in rows {
+            if let Some(user) = invite_user(&row, "user_id", "username")? {
+                uses.push(InviteUse {
+                    user,
+                    joined_at: row.try_get("joined_at")?,
+                });
+            }
+        }
+
+        Ok(Ok(uses))
+    }
+
+    pub async fn get_invite_codes_in_community(
+        &self,
+        community: CommunityId,
+    ) -> DbResult<Vec<InviteCodeRecord>> {
+        const QUERY: &str = "SELECT * FROM invite_codes WHERE community = $1";
+
+        let conn = self.connection().await?;
+        let rows = conn.client.query(QUERY, &[&community.0]).await?;
+
+        let mut codes = Vec::with_capacity(rows.len());
+        for row in rows {
+            codes.push(InviteCodeRecord {
+                id: row.try_get("id")?,
+                expiration_date: row.try_get("expiration_date")?,
+                max_uses: row
+                    .try_get::<&str, Option<i32>>("max_uses")?
+                    .map(|max| max as u32),
+                uses: row.try_get::<&str, i32>("uses")? as u32,
+            });
+        }
+
+        Ok(codes)
+    }
+
+    /// Adds an existing invite code to the community, e.g when importing it from another server.
+    /// If the code is already in use, it is not added.
+    pub async fn add_invite_code(
+        &self,
+        community: CommunityId,
+        code: InviteCode,
+        expiration_date: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
+        uses: u32,
+    ) -> DbResult<Result<(), MalformedInviteCode>> {
+        const STMT: &str = "
+            INSERT INTO invite_codes (id, community, expiration_date, max_uses, uses)
+                VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO NOTHING";
+
+        let id = match InviteCodeRecord::parse_id(code) {
+            Ok(id) => id,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let max_uses = max_uses.map(|max| max as i32);
+        let uses = uses as i32;
+        let args: &[&(dyn ToSql + Sync)] = &[&id, &community.0, &expiration_date, &max_uses, &uses];
+        conn.client.execute(&stmt, args).await?;
+
+        Ok(Ok(()))
+    }
 }
@@ -2,6 +2,7 @@ use std::convert::TryFrom;
 
 use chrono::{DateTime, Utc};
 use futures::{Stream, TryStream, TryStreamExt};
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
 
 use crate::database::{Database, DatabaseError, DbResult};
@@ -37,6 +38,10 @@ pub struct MessageRecord {
     pub room: RoomId,
     pub date: DateTime<Utc>,
     pub content: Option<String>,
+    /// Set instead of the content for messages in encrypted rooms
+    pub ciphertext: Option<Vec<u8>>,
+    /// Shown instead of the author's display name for this message only
+    pub author_display_name: Option<String>,
 }
 
 impl TryFrom<Row> for MessageRecord {
@@ -51,6 +56,8 @@ impl TryFrom<Row> for MessageRecord {
             room: RoomId(row.try_get("room")?),
             date: row.try_get("date")?,
             content: row.try_get("content")?,
+            ciphertext: row.try_get("ciphertext")?,
+            author_display_name: row.try_get("author_display_name")?,
         })
     }
 }
@@ -63,12 +70,16 @@ impl Database {
         community: CommunityId,
         room: RoomId,
         date: DateTime<Utc>,
-        content: String,
+        content: Option<String>,
+        ciphertext: Option<Vec<u8>>,
+        author_display_name: Option<String>,
     ) -> DbResult<(MessageOrdinal, ProfileVersion)> {
         const QUERY: &str = "
             WITH inserted AS
-                (INSERT INTO messages (id, author, community, room, date, content)
-                    VALUES ($1, $2, $3, $4, $5, $6)
+                (INSERT INTO messages (
+                    id, author, community, room, date, content, ciphertext, author_display_name
+                )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
                     RETURNING ord, author
                 )
             SELECT inserted.ord, users.profile_version FROM inserted
@@ -84,7 +95,9 @@ impl Database {
                     &community.0,
                     &room.0,
                     &date,
-                    &Some(content),
+                    &content,
+                    &ciphertext,
+                    &author_display_name,
                 ],
             )
             .await?;
@@ -116,6 +129,20 @@ impl Database {
         }
     }
 
+    /// Gets every message in the room, oldest first
+    pub async fn get_all_messages_in_room(
+        &self,
+        room: RoomId,
+    ) -> DbResult<impl Stream<Item = DbResult<MessageRecord>>> {
+        const QUERY: &str = "SELECT * FROM messages WHERE room = $1 ORDER BY ord ASC";
+
+        let stream = self.query_stream(QUERY, &[&room.0]).await?;
+        let stream = stream
+            .and_then(|row| async move { MessageRecord::try_from(row) })
+            .map_err(|e| e.into());
+        Ok(stream)
+    }
+
     pub async fn get_message_by_id(&self, id: MessageId) -> DbResult<Option<MessageRecord>> {
         const QUERY: &str = "SELECT * FROM messages WHERE id = $1";
         match self.query_opt(QUERY, &[&id.0]).await? {
@@ -142,6 +169,38 @@ impl Database {
         }
     }
 
+    /// Returns `None` if the message did not exist
+    pub async fn delete_message(&self, id: MessageId) -> DbResult<Option<Delete>> {
+        const STMT: &str = "DELETE FROM messages WHERE id = $1 RETURNING community, room";
+        match self.query_opt(STMT, &[&id.0]).await? {
+            Some(row) => Ok(Some(Delete {
+                message: id,
+                community: CommunityId(row.try_get("community")?),
+                room: RoomId(row.try_get("room")?),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes the message if it was sent by the user to the given room. Returns `None` if there
+    /// was no such message.
+    pub async fn delete_own_message(
+        &self,
+        author: UserId,
+        delete: Delete,
+    ) -> DbResult<Option<Delete>> {
+        const STMT: &str = "
+            DELETE FROM messages
+                WHERE id = $1 AND author = $2 AND community = $3 AND room = $4";
+
+        let args: &[&(dyn ToSql + Sync)] =
+            &[&delete.message.0, &author.0, &delete.community.0, &delete.room.0];
+        let conn = self.connection().await?;
+        let deleted = conn.client.execute(STMT, args).await?;
+
+        Ok(if deleted == 1 { Some(delete) } else { None })
+    }
+
     pub async fn get_messages(
         &self,
         community: CommunityId,
@@ -227,15 +286,17 @@ where
         Self: Sized,
     {
         self.try_filter_map(|(profile_version, record)| async move {
-            match record.content {
-                Some(content) => Ok(Some(Message {
+            match (record.content, record.ciphertext) {
+                (None, None) => Ok(None),
+                (content, ciphertext) => Ok(Some(Message {
                     id: record.id,
                     author: record.author,
                     author_profile_version: profile_version,
                     time_sent: record.date,
-                    content: Some(content),
+                    content,
+                    ciphertext,
+                    author_display_name: record.author_display_name,
                 })),
-                None => Ok(None),
             }
         })
     }
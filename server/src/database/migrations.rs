@@ -0,0 +1,392 @@
+//! Versioned schema migrations. Each migration is applied at most once, in order, inside a
+//! transaction, and the version of the newest applied migration is recorded in the
+//! `schema_version` table.
+//!
+//! Migrations must never be edited once released - to change the schema, append a new migration
+//! to the end of `MIGRATIONS`. This includes the `CREATE_*_TABLE` constants which make up the
+//! initial schema.
+
+use std::fmt::{self, Display, Formatter};
+
+use log::info;
+
+use super::*;
+
+const CREATE_SCHEMA_VERSION_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS schema_version (
+        version     INTEGER PRIMARY KEY,
+        name        VARCHAR NOT NULL,
+        applied_at  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+    )";
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    statements: &'static [&'static str],
+}
+
+/// All migrations, ordered by version. Versions must be consecutive and start at 1.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial schema",
+        // These use CREATE TABLE IF NOT EXISTS so that databases created before migrations were
+        // introduced can be brought under version control.
+        statements: &[
+            CREATE_USERS_TABLE,
+            CREATE_TOKENS_TABLE,
+            CREATE_COMMUNITIES_TABLE,
+            CREATE_COMMUNITY_MEMBERSHIP_TABLE,
+            CREATE_ROOMS_TABLE,
+            CREATE_INVITE_CODES_TABLE,
+            CREATE_MESSAGES_TABLE,
+            CREATE_USER_ROOM_STATES_TABLE,
+            CREATE_ADMINISTRATORS_TABLE,
+            CREATE_REPORTS_TABLE,
+            "CREATE EXTENSION IF NOT EXISTS pg_trgm", // Allow fuzzy searching
+        ],
+    },
+    Migration {
+        version: 2,
+        name: "foreign key indexes",
+        statements: &[
+            "CREATE INDEX IF NOT EXISTS rooms_community ON rooms (community)",
+            "CREATE INDEX IF NOT EXISTS messages_room_ord ON messages (room, ord)",
+            "CREATE INDEX IF NOT EXISTS community_membership_community
+                ON community_membership (community)",
+            "CREATE INDEX IF NOT EXISTS user_room_states_room ON user_room_states (room)",
+            "CREATE INDEX IF NOT EXISTS login_tokens_user_id ON login_tokens (user_id)",
+            "CREATE INDEX IF NOT EXISTS invite_codes_community ON invite_codes (community)",
+        ],
+    },
+    Migration {
+        version: 3,
+        name: "federation",
+        statements: &[
+            CREATE_FEDERATED_SERVERS_TABLE,
+            CREATE_REMOTE_USERS_TABLE,
+            CREATE_REMOTE_COMMUNITY_MEMBERSHIP_TABLE,
+            "CREATE INDEX IF NOT EXISTS remote_community_membership_community
+                ON remote_community_membership (server, community)",
+        ],
+    },
+    Migration {
+        version: 4,
+        name: "end-to-end encryption",
+        statements: &[
+            "ALTER TABLE rooms ADD COLUMN IF NOT EXISTS encrypted BOOLEAN NOT NULL DEFAULT FALSE",
+            "ALTER TABLE messages ADD COLUMN IF NOT EXISTS ciphertext BYTEA",
+            CREATE_DEVICE_KEYS_TABLE,
+            CREATE_ONE_TIME_PREKEYS_TABLE,
+            "CREATE INDEX IF NOT EXISTS device_keys_user_id ON device_keys (user_id)",
+            "CREATE INDEX IF NOT EXISTS one_time_prekeys_device ON one_time_prekeys (device)",
+        ],
+    },
+    Migration {
+        version: 5,
+        name: "message retention",
+        statements: &[
+            "ALTER TABLE communities ADD COLUMN IF NOT EXISTS retention_days INTEGER",
+            "ALTER TABLE rooms ADD COLUMN IF NOT EXISTS retention_days INTEGER",
+            "CREATE INDEX IF NOT EXISTS messages_date ON messages (date)",
+            "CREATE INDEX IF NOT EXISTS reports_message_id ON reports (message_id)",
+        ],
+    },
+    Migration {
+        version: 6,
+        name: "admin audit log",
+        statements: &[
+            CREATE_ADMIN_AUDIT_LOG_TABLE,
+            "CREATE INDEX IF NOT EXISTS admin_audit_log_datetime ON admin_audit_log (datetime)",
+            // The audit log is append-only
+            "CREATE OR REPLACE RULE admin_audit_log_no_update AS
+                ON UPDATE TO admin_audit_log DO INSTEAD NOTHING",
+            "CREATE OR REPLACE RULE admin_audit_log_no_delete AS
+                ON DELETE TO admin_audit_log DO INSTEAD NOTHING",
+        ],
+    },
+    Migration {
+        version: 7,
+        name: "report moderation",
+        statements: &[
+            "ALTER TABLE reports
+                ADD COLUMN IF NOT EXISTS assignee UUID REFERENCES users(id) ON DELETE SET NULL",
+            CREATE_REPORT_NOTES_TABLE,
+            "CREATE INDEX IF NOT EXISTS report_notes_report ON report_notes (report)",
+        ],
+    },
+    Migration {
+        version: 8,
+        name: "report users, rooms and communities",
+        statements: &[
+            // Only reports of messages have a message, and only reports of messages and users have
+            // a reported user
+            r#"ALTER TABLE reports
+                ALTER COLUMN reported_user DROP NOT NULL,
+                ALTER COLUMN message_text DROP NOT NULL,
+                ALTER COLUMN msg_sent_at DROP NOT NULL,
+                ADD COLUMN IF NOT EXISTS kind "char" NOT NULL DEFAULT 0::"char",
+                ADD COLUMN IF NOT EXISTS snapshot VARCHAR NOT NULL DEFAULT ''"#,
+        ],
+    },
+    Migration {
+        version: 9,
+        name: "report context",
+        statements: &[
+            CREATE_REPORT_CONTEXT_TABLE,
+            // The context is a snapshot, so it must not change after the report is made. It is
+            // still deleted along with its report.
+            "CREATE OR REPLACE RULE report_context_no_update AS
+                ON UPDATE TO report_context DO INSTEAD NOTHING",
+        ],
+    },
+    Migration {
+        version: 10,
+        name: "temporary restrictions",
+        statements: &[
+            // Expiry is NULL for permanent restrictions
+            "ALTER TABLE users
+                ADD COLUMN IF NOT EXISTS ban_reason VARCHAR,
+                ADD COLUMN IF NOT EXISTS ban_expires TIMESTAMP WITH TIME ZONE,
+                ADD COLUMN IF NOT EXISTS lock_reason VARCHAR,
+                ADD COLUMN IF NOT EXISTS lock_expires TIMESTAMP WITH TIME ZONE,
+                ADD COLUMN IF NOT EXISTS muted BOOLEAN NOT NULL DEFAULT FALSE,
+                ADD COLUMN IF NOT EXISTS mute_reason VARCHAR,
+                ADD COLUMN IF NOT EXISTS mute_expires TIMESTAMP WITH TIME ZONE",
+        ],
+    },
+    Migration {
+        version: 11,
+        name: "automod",
+        statements: &[
+            CREATE_AUTOMOD_RULES_TABLE,
+            CREATE_AUTOMOD_HITS_TABLE,
+            // Members are only muted within a community by its automod, so these always expire
+            "ALTER TABLE community_membership
+                ADD COLUMN IF NOT EXISTS mute_reason VARCHAR,
+                ADD COLUMN IF NOT EXISTS mute_expires TIMESTAMP WITH TIME ZONE",
+        ],
+    },
+    Migration {
+        version: 12,
+        name: "room settings",
+        statements: &[
+            // A NULL max message length means that the server's maximum applies
+            "ALTER TABLE rooms
+                ADD COLUMN IF NOT EXISTS slow_mode_secs INTEGER NOT NULL DEFAULT 0,
+                ADD COLUMN IF NOT EXISTS read_only BOOLEAN NOT NULL DEFAULT FALSE,
+                ADD COLUMN IF NOT EXISTS max_message_len INTEGER",
+        ],
+    },
+    Migration {
+        version: 13,
+        name: "invite management",
+        statements: &[
+            // Codes created before this migration are treated as created when it was applied, and
+            // as having no known creator
+            "ALTER TABLE invite_codes
+                ADD COLUMN IF NOT EXISTS creator UUID REFERENCES users(id) ON DELETE SET NULL,
+                ADD COLUMN IF NOT EXISTS created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+                ADD COLUMN IF NOT EXISTS max_uses INTEGER,
+                ADD COLUMN IF NOT EXISTS uses INTEGER NOT NULL DEFAULT 0",
+            CREATE_INVITE_USES_TABLE,
+            "CREATE INDEX IF NOT EXISTS invite_uses_code ON invite_uses (code)",
+        ],
+    },
+    Migration {
+        version: 14,
+        name: "public community directory",
+        statements: &[
+            "ALTER TABLE communities
+                ADD COLUMN IF NOT EXISTS public BOOLEAN NOT NULL DEFAULT FALSE",
+            // Only public communities are ever searched by name
+            "CREATE INDEX IF NOT EXISTS communities_public_name
+                ON communities USING GIN (name gin_trgm_ops) WHERE public",
+        ],
+    },
+    Migration {
+        version: 15,
+        name: "registration controls",
+        statements: &[
+            CREATE_REGISTRATION_TOKENS_TABLE,
+            // Users registered before this migration are treated as registered when it was applied
+            "ALTER TABLE users
+                ADD COLUMN IF NOT EXISTS pending_approval BOOLEAN NOT NULL DEFAULT FALSE,
+                ADD COLUMN IF NOT EXISTS registered_at TIMESTAMP WITH TIME ZONE
+                    NOT NULL DEFAULT NOW()",
+            "CREATE INDEX IF NOT EXISTS users_pending_approval
+                ON users (registered_at) WHERE pending_approval",
+        ],
+    },
+    Migration {
+        version: 16,
+        name: "bots, webhooks and community permissions",
+        statements: &[
+            "ALTER TABLE users
+                ADD COLUMN IF NOT EXISTS bot BOOLEAN NOT NULL DEFAULT FALSE,
+                ADD COLUMN IF NOT EXISTS bot_owner UUID REFERENCES users(id) ON DELETE CASCADE",
+            "ALTER TABLE community_membership
+                ADD COLUMN IF NOT EXISTS permission_flags BIGINT NOT NULL DEFAULT 0",
+            CREATE_WEBHOOKS_TABLE,
+            CREATE_OUTGOING_WEBHOOKS_TABLE,
+            CREATE_WEBHOOK_DELIVERIES_TABLE,
+            CREATE_BOT_COMMANDS_TABLE,
+        ],
+    },
+    Migration {
+        version: 17,
+        name: "community permission backfill",
+        statements: &[
+            // Members who joined before community permissions existed were left without any. Give
+            // server administrators every permission (1 is ALL) in the communities they are in.
+            "UPDATE community_membership SET permission_flags = 1
+                FROM administrators
+                WHERE community_membership.user_id = administrators.user_id",
+            // Who created a community was never recorded, and every member could manage it back
+            // then, so communities that are still left without anyone to manage them keep that
+            "UPDATE community_membership SET permission_flags = 1
+                WHERE community NOT IN (
+                    SELECT community FROM community_membership WHERE permission_flags & 1 = 1
+                )",
+        ],
+    },
+    Migration {
+        version: 18,
+        name: "message display names",
+        statements: &[
+            // Set when a message is posted under another name than its author's, e.g by a webhook
+            "ALTER TABLE messages ADD COLUMN IF NOT EXISTS author_display_name VARCHAR",
+        ],
+    },
+    Migration {
+        version: 19,
+        name: "prekey claims",
+        statements: &[
+            CREATE_PREKEY_CLAIMS_TABLE,
+            "CREATE INDEX IF NOT EXISTS prekey_claims_device ON prekey_claims (device)",
+        ],
+    },
+];
+
+/// Whether pending migrations should actually be applied, or only reported
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MigrationMode {
+    Apply,
+    DryRun,
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(DatabaseError),
+    /// The database has been migrated by a newer version of the server, so this version cannot
+    /// safely use it.
+    SchemaTooNew { current: i32, supported: i32 },
+}
+
+impl From<DatabaseError> for MigrationError {
+    fn from(e: DatabaseError) -> Self {
+        MigrationError::Database(e)
+    }
+}
+
+impl From<tokio_postgres::Error> for MigrationError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        MigrationError::Database(e.into())
+    }
+}
+
+impl From<l337::Error<tokio_postgres::Error>> for MigrationError {
+    fn from(e: l337::Error<tokio_postgres::Error>) -> Self {
+        MigrationError::Database(e.into())
+    }
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Database(e) => write!(f, "database error: {:?}", e),
+            MigrationError::SchemaTooNew { current, supported } => write!(
+                f,
+                "database schema is at version {}, but this server only supports up to version {}",
+                current, supported,
+            ),
+        }
+    }
+}
+
+/// The outcome of a migration run
+pub struct MigrationReport {
+    /// The schema version before any migrations were applied
+    pub from: i32,
+    /// The migrations which were (or, in a dry run, would have been) applied
+    pub pending: Vec<&'static Migration>,
+}
+
+/// The newest schema version that this server knows about
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+impl Database {
+    /// Brings the schema up to date, refusing to continue if it is newer than this server
+    /// supports. All pending migrations are applied in a single transaction, so a failure leaves
+    /// the schema untouched.
+    pub async fn migrate(&self, mode: MigrationMode) -> Result<MigrationReport, MigrationError> {
+        let mut conn = self.connection().await?;
+        conn.client.batch_execute(CREATE_SCHEMA_VERSION_TABLE).await?;
+
+        let transaction = conn.client.transaction().await?;
+
+        // Stop two servers starting at once from both applying the same migrations
+        transaction
+            .batch_execute("LOCK TABLE schema_version IN EXCLUSIVE MODE")
+            .await?;
+
+        let row = transaction
+            .query_one("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version", &[])
+            .await?;
+        let current: i32 = row.try_get("version")?;
+
+        if current > latest_version() {
+            return Err(MigrationError::SchemaTooNew {
+                current,
+                supported: latest_version(),
+            });
+        }
+
+        let pending: Vec<&'static Migration> =
+            MIGRATIONS.iter().filter(|m| m.version > current).collect();
+
+        if mode == MigrationMode::DryRun {
+            return Ok(MigrationReport {
+                from: current,
+                pending,
+            });
+        }
+
+        const RECORD: &str = "INSERT INTO schema_version (version, name) VALUES ($1, $2)";
+        let record = transaction.prepare(RECORD).await?;
+
+        for migration in &pending {
+            info!(
+                "Applying database migration {}: {}",
+                migration.version, migration.name
+            );
+
+            for stmt in migration.statements {
+                transaction.batch_execute(stmt).await?;
+            }
+
+            transaction
+                .execute(&record, &[&migration.version, &migration.name])
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(MigrationReport {
+            from: current,
+            pending,
+        })
+    }
+}
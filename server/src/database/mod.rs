@@ -1,38 +1,94 @@
 use std::convert::TryFrom;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::{client, config};
+use crate::config::{self, Config};
+use crate::{client, community, metrics};
+use arc_swap::ArcSwap;
 use futures::{Stream, TryStreamExt};
+use l337::Conn;
 use l337_postgres::PostgresConnectionManager;
 use log::{error, warn};
 use tokio_postgres::types::ToSql;
 use tokio_postgres::{NoTls, Row, RowStream};
 use vertex::prelude::*;
 
+/// Builds a `WHERE` clause and its arguments out of the fields of search criteria which are set
+macro_rules! build_where_clause {
+    (let (mut $a:ident, $b:ident) = $criteria:ident: { $($field: ident $(as $ty:ty)? => $stmt:expr,)* }) => {
+        // Each field is moved out into a local first, so that casted values live as long as the
+        // arguments which borrow them
+        $(let $field = $criteria.$field$(.map(|f| f as $ty))?;)*
+        let (mut $a, $b) = {
+            let mut _where_clause = String::new();
+            let mut _cur_arg: usize = 0;
+            let mut _args: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = vec![];
+            $(if let Some(ref $field) = $field {
+                _cur_arg += 1;
+                let join = if _cur_arg == 1 {
+                    "WHERE"
+                } else {
+                    "AND"
+                };
+
+                _where_clause.push_str(
+                    &format!("{} {}\n", join, format_args!($stmt, n = _cur_arg))
+                );
+
+                _args.push($field);
+            })*
+
+            (_args, _where_clause)
+        };
+    }
+}
+
 mod administrators;
+mod audit_log;
+mod automod;
+mod commands;
 mod communities;
 mod community_membership;
+mod device_keys;
+mod federation;
 mod invite_code;
 mod message;
+mod migrations;
+mod outgoing_webhooks;
+mod registration_token;
 mod reports;
 mod rooms;
 mod token;
 mod user;
 mod user_room_states;
+mod webhooks;
 
 pub use administrators::*;
+pub use audit_log::*;
+pub use automod::*;
+pub use commands::*;
 pub use communities::*;
 pub use community_membership::*;
+pub use device_keys::*;
+pub use federation::*;
 pub use invite_code::*;
 pub use message::*;
+pub use migrations::*;
+pub use outgoing_webhooks::*;
+pub use registration_token::*;
 pub use reports::*;
 pub use rooms::*;
 pub use token::*;
 pub use user::*;
 pub use user_room_states::*;
+pub use webhooks::*;
 
 pub type DbResult<T> = Result<T, DatabaseError>;
 
+/// How many expired messages are deleted at once, so that a large backlog of them does not hold
+/// locks on the messages table for too long
+const MESSAGE_SWEEP_BATCH_SIZE: i64 = 1000;
+
 #[derive(Debug)]
 pub struct DatabaseError(l337::Error<tokio_postgres::Error>);
 
@@ -90,13 +146,25 @@ impl Database {
             .await
             .expect("Error creating database connection pool");
 
-        let db = Database { pool };
-        db.create_tables().await?;
-        Ok(db)
+        Ok(Database { pool })
+    }
+
+    /// Gets a connection from the pool, recording how long was spent waiting for it
+    async fn connection(
+        &self,
+    ) -> Result<Conn<PostgresConnectionManager<NoTls>>, l337::Error<tokio_postgres::Error>> {
+        let _timer = metrics::DB_POOL_WAIT.start_timer();
+        self.pool.connection().await
+    }
+
+    /// Checks that a connection can be made to the database
+    pub async fn ping(&self) -> DbResult<()> {
+        self.query_one("SELECT 1", &[]).await?;
+        Ok(())
     }
 
     pub async fn query_one(&self, query: &str, args: &[&(dyn ToSql + Sync)]) -> DbResult<Row> {
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let query = conn.client.prepare(query).await?;
         Ok(conn.client.query_one(&query, args).await?)
     }
@@ -106,7 +174,7 @@ impl Database {
         query: &str,
         args: &[&(dyn ToSql + Sync)],
     ) -> DbResult<Option<Row>> {
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let query = conn.client.prepare(query).await?;
         Ok(conn.client.query_opt(&query, args).await?)
     }
@@ -116,42 +184,18 @@ impl Database {
         query: &str,
         args: &[&(dyn ToSql + Sync)],
     ) -> DbResult<RowStream> {
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let query = conn.client.prepare(query).await?;
         Ok(conn.client.query_raw(&query, slice_iter(args)).await?)
     }
 
-    async fn create_tables(&self) -> DbResult<()> {
-        let conn = self.pool.connection().await?;
-        let cmds = [
-            CREATE_USERS_TABLE,
-            CREATE_TOKENS_TABLE,
-            CREATE_COMMUNITIES_TABLE,
-            CREATE_COMMUNITY_MEMBERSHIP_TABLE,
-            CREATE_ROOMS_TABLE,
-            CREATE_INVITE_CODES_TABLE,
-            CREATE_MESSAGES_TABLE,
-            CREATE_USER_ROOM_STATES_TABLE,
-            CREATE_ADMINISTRATORS_TABLE,
-            CREATE_REPORTS_TABLE,
-            "CREATE EXTENSION IF NOT EXISTS pg_trgm;", // Allow fuzzy searching
-        ];
-
-        for cmd in &cmds {
-            let stmt = conn.client.prepare(cmd).await?;
-            conn.client.execute(&stmt, &[]).await?;
-        }
-
-        Ok(())
-    }
-
-    pub async fn sweep_tokens_loop(self, token_expiry_days: u16, interval: Duration) {
-        let mut timer = tokio::time::interval(interval);
-
+    pub async fn sweep_tokens_loop(self, config: Arc<ArcSwap<Config>>) {
         loop {
-            timer.tick().await;
+            let current = config.load_full();
+            let interval = Duration::from_secs(current.tokens_sweep_interval_secs);
             let begin = Instant::now();
-            self.expired_tokens(token_expiry_days)
+            let sweep_timer = metrics::time_sweep("tokens");
+            self.expired_tokens(current.token_expiry_days)
                 .await
                 .expect("Database error while sweeping tokens")
                 .try_for_each(|(user, device)| async move {
@@ -161,6 +205,7 @@ impl Database {
                 .await
                 .expect("Database error while sweeping tokens");
 
+            sweep_timer.observe_duration();
             let time_taken = Instant::now().duration_since(begin);
             if time_taken > interval {
                 warn!(
@@ -169,6 +214,8 @@ impl Database {
                     interval.as_secs(),
                 );
             }
+
+            wait_for_next_sweep(interval, time_taken).await;
         }
     }
 
@@ -185,7 +232,7 @@ impl Database {
         let token_expiry_days = token_expiry_days as f64;
         let args = [token_expiry_days];
         let args = args.iter().map(|x| x as &dyn ToSql);
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(QUERY).await?;
 
         let stream = conn
@@ -202,16 +249,16 @@ impl Database {
         Ok(stream)
     }
 
-    pub async fn sweep_invite_codes_loop(self, interval: Duration) {
-        let mut timer = tokio::time::interval(interval);
-
+    pub async fn sweep_invite_codes_loop(self, config: Arc<ArcSwap<Config>>) {
         loop {
-            timer.tick().await;
+            let interval = Duration::from_secs(config.load().invite_codes_sweep_interval_secs);
             let begin = Instant::now();
+            let sweep_timer = metrics::time_sweep("invite_codes");
             self.delete_expired_invite_codes()
                 .await
                 .expect("Database error while sweeping invite codes");
 
+            sweep_timer.observe_duration();
             let time_taken = Instant::now().duration_since(begin);
             if time_taken > interval {
                 warn!(
@@ -220,17 +267,149 @@ impl Database {
                     interval.as_secs(),
                 );
             }
+
+            wait_for_next_sweep(interval, time_taken).await;
+        }
+    }
+
+    pub async fn sweep_restrictions_loop(self, config: Arc<ArcSwap<Config>>) {
+        loop {
+            let interval = Duration::from_secs(config.load().restrictions_sweep_interval_secs);
+            let begin = Instant::now();
+            let sweep_timer = metrics::time_sweep("restrictions");
+            match self.lift_expired_restrictions().await {
+                Ok(unmuted) => {
+                    for user in unmuted {
+                        client::session::set_muted(user, None);
+                    }
+                }
+                Err(err) => error!("Database error while sweeping restrictions: {:?}", err),
+            }
+
+            sweep_timer.observe_duration();
+            let time_taken = Instant::now().duration_since(begin);
+            if time_taken > interval {
+                warn!(
+                    "Took {}s to sweep the database for expired restrictions, but the interval is {}s!",
+                    time_taken.as_secs(),
+                    interval.as_secs(),
+                );
+            }
+
+            wait_for_next_sweep(interval, time_taken).await;
         }
     }
 
+    /// Deletes expired community invite codes, along with expired registration tokens, which are
+    /// invites to the server itself
     async fn delete_expired_invite_codes(&self) -> DbResult<()> {
         const STMT: &str = "DELETE FROM invite_codes WHERE expiration_date < NOW()::timestamp";
+        const REGISTRATION_TOKENS: &str =
+            "DELETE FROM registration_tokens WHERE expiration_date < NOW()::timestamp";
 
-        let conn = self.pool.connection().await?;
-        let stmt = conn.client.prepare(STMT).await?;
-        conn.client.execute(&stmt, &[]).await?;
+        let conn = self.connection().await?;
+        for stmt in &[STMT, REGISTRATION_TOKENS] {
+            let stmt = conn.client.prepare(stmt).await?;
+            conn.client.execute(&stmt, &[]).await?;
+        }
         Ok(())
     }
+
+    pub async fn sweep_messages_loop(self, config: Arc<ArcSwap<Config>>) {
+        loop {
+            let current = config.load_full();
+            let default_retention_days = current.message_retention_days;
+            let interval = Duration::from_secs(current.messages_sweep_interval_secs);
+            let begin = Instant::now();
+            let sweep_timer = metrics::time_sweep("messages");
+
+            loop {
+                let deleted = match self.delete_expired_messages(default_retention_days).await {
+                    Ok(deleted) => deleted,
+                    Err(err) => {
+                        error!("Database error while sweeping messages: {:?}", err);
+                        break;
+                    }
+                };
+                let finished = deleted.len() < MESSAGE_SWEEP_BATCH_SIZE as usize;
+                community::notify_deleted(deleted);
+
+                if finished {
+                    break;
+                }
+            }
+
+            sweep_timer.observe_duration();
+            let time_taken = Instant::now().duration_since(begin);
+            if time_taken > interval {
+                warn!(
+                    "Took {}s to sweep the database for expired messages, but the interval is {}s!",
+                    time_taken.as_secs(),
+                    interval.as_secs(),
+                );
+            }
+
+            wait_for_next_sweep(interval, time_taken).await;
+        }
+    }
+
+    /// Deletes a batch of messages which are older than the retention period of their room. The
+    /// room's period takes precedence over its community's, which takes precedence over the
+    /// server's default.
+    ///
+    /// Messages with an open report, whether or not it has been claimed, are kept until it has been
+    /// accepted or denied, so that whoever handles it can still act on the message. Once a report
+    /// is resolved its message may be deleted, but the report keeps its own copy of the message's
+    /// text and context as evidence.
+    async fn delete_expired_messages(
+        &self,
+        default_retention_days: Option<u32>,
+    ) -> DbResult<Vec<Delete>> {
+        const STMT: &str = "
+            DELETE FROM messages WHERE id IN (
+                SELECT messages.id FROM messages
+                INNER JOIN rooms ON messages.room = rooms.id
+                INNER JOIN communities ON messages.community = communities.id
+                WHERE messages.date < NOW() - MAKE_INTERVAL(
+                    days => COALESCE(rooms.retention_days, communities.retention_days, $1)
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM reports
+                        WHERE reports.message_id = messages.id AND reports.status = $2
+                )
+                LIMIT $3
+            )
+            RETURNING id, community, room";
+
+        let default_retention_days = default_retention_days.map(|days| days as i32);
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &default_retention_days,
+            &(ReportStatus::Opened as i8),
+            &MESSAGE_SWEEP_BATCH_SIZE,
+        ];
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let rows = conn.client.query(&stmt, args).await?;
+
+        let mut deleted = Vec::with_capacity(rows.len());
+        for row in rows {
+            deleted.push(Delete {
+                message: MessageId(row.try_get("id")?),
+                community: CommunityId(row.try_get("community")?),
+                room: RoomId(row.try_get("room")?),
+            });
+        }
+
+        Ok(deleted)
+    }
+}
+
+/// Waits until the next sweep is due. The interval is read from the config before each sweep, so
+/// that changes to it apply once the config is reloaded.
+async fn wait_for_next_sweep(interval: Duration, time_taken: Duration) {
+    let remaining = interval.checked_sub(time_taken).unwrap_or_default();
+    tokio::time::delay_for(remaining).await;
 }
 
 /// How the user was (or wasn't) added to a community or room. This is needed for the complicated (
@@ -0,0 +1,201 @@
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+
+use crate::database::{Database, DbResult, NonexistentWebhook};
+use vertex::prelude::*;
+
+/// Max number of delivery attempts kept in the log per webhook
+const MAX_DELIVERIES_LOGGED: i64 = 100;
+
+pub(super) const CREATE_OUTGOING_WEBHOOKS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS outgoing_webhooks (
+        id         UUID PRIMARY KEY,
+        community  UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
+        url        VARCHAR NOT NULL,
+        events     BIGINT NOT NULL,
+        secret     VARCHAR NOT NULL
+    )";
+
+pub(super) const CREATE_WEBHOOK_DELIVERIES_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS webhook_deliveries (
+        id            BIGSERIAL PRIMARY KEY,
+        webhook       UUID NOT NULL REFERENCES outgoing_webhooks(id) ON DELETE CASCADE,
+        event         VARCHAR NOT NULL,
+        attempted_at  TIMESTAMP WITH TIME ZONE NOT NULL,
+        attempt       INTEGER NOT NULL,
+        status_code   INTEGER,
+        error         VARCHAR,
+        success       BOOLEAN NOT NULL
+    )";
+
+#[derive(Debug, Clone)]
+pub struct OutgoingWebhookRecord {
+    pub id: WebhookId,
+    pub community: CommunityId,
+    pub url: String,
+    pub events: WebhookEventFlags,
+    /// The key that request bodies are signed with
+    pub secret: String,
+}
+
+impl TryFrom<Row> for OutgoingWebhookRecord {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<OutgoingWebhookRecord, tokio_postgres::Error> {
+        Ok(OutgoingWebhookRecord {
+            id: WebhookId(row.try_get("id")?),
+            community: CommunityId(row.try_get("community")?),
+            url: row.try_get("url")?,
+            events: WebhookEventFlags::from_bits_truncate(row.try_get("events")?),
+            secret: row.try_get("secret")?,
+        })
+    }
+}
+
+impl Into<OutgoingWebhook> for OutgoingWebhookRecord {
+    fn into(self) -> OutgoingWebhook {
+        OutgoingWebhook {
+            id: self.id,
+            url: self.url,
+            events: self.events,
+        }
+    }
+}
+
+pub struct WebhookDeliveryRecord {
+    pub webhook: WebhookId,
+    pub event: &'static str,
+    pub attempted_at: DateTime<Utc>,
+    pub attempt: u32,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub success: bool,
+}
+
+fn delivery_from_row(row: Row) -> Result<WebhookDelivery, tokio_postgres::Error> {
+    Ok(WebhookDelivery {
+        event: row.try_get("event")?,
+        attempted_at: row.try_get("attempted_at")?,
+        attempt: row.try_get::<&str, i32>("attempt")? as u32,
+        status_code: row
+            .try_get::<&str, Option<i32>>("status_code")?
+            .map(|code| code as u16),
+        error: row.try_get("error")?,
+        success: row.try_get("success")?,
+    })
+}
+
+impl Database {
+    pub async fn create_outgoing_webhook(&self, webhook: OutgoingWebhookRecord) -> DbResult<()> {
+        const STMT: &str = "
+            INSERT INTO outgoing_webhooks (id, community, url, events, secret)
+                VALUES ($1, $2, $3, $4, $5)";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &webhook.id.0,
+            &webhook.community.0,
+            &webhook.url,
+            &webhook.events.bits(),
+            &webhook.secret,
+        ];
+
+        conn.client.execute(&stmt, args).await?;
+        Ok(())
+    }
+
+    pub async fn get_outgoing_webhooks_in_community(
+        &self,
+        community: CommunityId,
+    ) -> DbResult<impl Stream<Item = DbResult<OutgoingWebhookRecord>>> {
+        const QUERY: &str = "SELECT * FROM outgoing_webhooks WHERE community = $1";
+
+        let stream = self.query_stream(QUERY, &[&community.0]).await?;
+        let stream = stream
+            .and_then(|row| async move { Ok(OutgoingWebhookRecord::try_from(row)?) })
+            .map_err(|e| e.into());
+
+        Ok(stream)
+    }
+
+    pub async fn delete_outgoing_webhook(
+        &self,
+        community: CommunityId,
+        id: WebhookId,
+    ) -> DbResult<Result<(), NonexistentWebhook>> {
+        const STMT: &str = "DELETE FROM outgoing_webhooks WHERE community = $1 AND id = $2";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let res = conn.client.execute(&stmt, &[&community.0, &id.0]).await?;
+
+        Ok(if res == 1 {
+            Ok(())
+        } else {
+            Err(NonexistentWebhook)
+        })
+    }
+
+    /// Records a delivery attempt, pruning the oldest entries in the webhook's log if it is full.
+    pub async fn record_webhook_delivery(&self, delivery: WebhookDeliveryRecord) -> DbResult<()> {
+        const INSERT: &str = "
+            INSERT INTO webhook_deliveries
+                (webhook, event, attempted_at, attempt, status_code, error, success)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)";
+        const PRUNE: &str = "
+            DELETE FROM webhook_deliveries
+                WHERE webhook = $1 AND id NOT IN (
+                    SELECT id FROM webhook_deliveries
+                        WHERE webhook = $1
+                        ORDER BY id DESC
+                        LIMIT $2
+                )";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(INSERT).await?;
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &delivery.webhook.0,
+            &delivery.event,
+            &delivery.attempted_at,
+            &(delivery.attempt as i32),
+            &delivery.status_code.map(|code| code as i32),
+            &delivery.error,
+            &delivery.success,
+        ];
+        conn.client.execute(&stmt, args).await?;
+
+        let stmt = conn.client.prepare(PRUNE).await?;
+        conn.client
+            .execute(&stmt, &[&delivery.webhook.0, &MAX_DELIVERIES_LOGGED])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gets the most recent delivery attempts for a webhook, newest first. The webhook must be in
+    /// the given community, or no deliveries will be returned.
+    pub async fn get_webhook_deliveries(
+        &self,
+        community: CommunityId,
+        webhook: WebhookId,
+    ) -> DbResult<impl Stream<Item = DbResult<WebhookDelivery>>> {
+        const QUERY: &str = "
+            SELECT webhook_deliveries.* FROM webhook_deliveries
+                INNER JOIN outgoing_webhooks
+                    ON outgoing_webhooks.id = webhook_deliveries.webhook
+                WHERE outgoing_webhooks.community = $1 AND outgoing_webhooks.id = $2
+                ORDER BY webhook_deliveries.id DESC";
+
+        let stream = self.query_stream(QUERY, &[&community.0, &webhook.0]).await?;
+        let stream = stream
+            .and_then(|row| async move { Ok(delivery_from_row(row)?) })
+            .map_err(|e| e.into());
+
+        Ok(stream)
+    }
+}
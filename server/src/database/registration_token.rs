@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+use vertex::prelude::*;
+
+use crate::database::{Database, DbResult};
+
+pub(super) const CREATE_REGISTRATION_TOKENS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS registration_tokens (
+        token            VARCHAR PRIMARY KEY,
+        creator          UUID REFERENCES users(id) ON DELETE SET NULL,
+        created_at       TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+        expiration_date  TIMESTAMP WITH TIME ZONE,
+        max_uses         INTEGER,
+        uses             INTEGER NOT NULL DEFAULT 0
+    )";
+
+/// Only tokens which have not expired or been used up can be registered with
+const USABLE: &str = "
+    (expiration_date IS NULL OR expiration_date > NOW())
+    AND (max_uses IS NULL OR uses < max_uses)";
+
+fn registration_token_from_row(row: &Row) -> Result<RegistrationToken, tokio_postgres::Error> {
+    let creator_id: Option<Uuid> = row.try_get("creator")?;
+    let creator_username: Option<String> = row.try_get("creator_username")?;
+    let creator = match (creator_id, creator_username) {
+        (Some(id), Some(username)) => Some(ReportUser {
+            id: UserId(id),
+            username,
+        }),
+        _ => None,
+    };
+
+    Ok(RegistrationToken {
+        token: row.try_get("token")?,
+        creator,
+        created_at: row.try_get("created_at")?,
+        expiration_date: row.try_get("expiration_date")?,
+        max_uses: row
+            .try_get::<&str, Option<i32>>("max_uses")?
+            .map(|max| max as u32),
+        uses: row.try_get::<&str, i32>("uses")? as u32,
+    })
+}
+
+pub struct NonexistentRegistrationToken;
+
+/// A use of a registration token which has been counted before the account is created. It must be
+/// released if the account could not be created.
+pub struct ClaimedRegistrationToken(String);
+
+impl Database {
+    /// Creates a new registration token. The creator is `None` for tokens created from the command
+    /// line.
+    pub async fn create_registration_token(
+        &self,
+        creator: Option<UserId>,
+        expiration_date: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
+    ) -> DbResult<RegistrationToken> {
+        const STMT: &str = "
+            WITH inserted AS (
+                INSERT INTO registration_tokens (token, creator, expiration_date, max_uses)
+                    VALUES ($1, $2, $3, $4)
+                ON CONFLICT DO NOTHING
+                RETURNING *
+            )
+            SELECT inserted.*, users.username AS creator_username
+                FROM inserted
+                LEFT JOIN users ON users.id = inserted.creator";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let creator = creator.map(|user| user.0);
+        let max_uses = max_uses.map(|max| max as i32);
+
+        loop {
+            let mut token_bytes: [u8; 16] = [0; 16]; // 128 bits
+            rand::thread_rng().fill_bytes(&mut token_bytes);
+            let token = base64::encode_config(&token_bytes, base64::URL_SAFE_NO_PAD);
+
+            let args: &[&(dyn ToSql + Sync)] = &[&token, &creator, &expiration_date, &max_uses];
+
+            // Nothing is returned if the token conflicts with an existing one, in which case
+            // another is generated
+            if let Some(row) = conn.client.query_opt(&stmt, args).await? {
+                return Ok(registration_token_from_row(&row)?);
+            }
+        }
+    }
+
+    /// Gets all registration tokens, newest first
+    pub async fn get_registration_tokens(&self) -> DbResult<Vec<RegistrationToken>> {
+        const QUERY: &str = "
+            SELECT registration_tokens.*, users.username AS creator_username
+                FROM registration_tokens
+                LEFT JOIN users ON users.id = registration_tokens.creator
+            ORDER BY registration_tokens.created_at DESC";
+
+        let conn = self.connection().await?;
+        let rows = conn.client.query(QUERY, &[]).await?;
+
+        let tokens = rows
+            .iter()
+            .map(registration_token_from_row)
+            .collect::<Result<_, _>>()?;
+        Ok(tokens)
+    }
+
+    pub async fn delete_registration_token(
+        &self,
+        token: String,
+    ) -> DbResult<Result<(), NonexistentRegistrationToken>> {
+        const STMT: &str = "DELETE FROM registration_tokens WHERE token = $1";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let res = conn.client.execute(&stmt, &[&token]).await?;
+
+        Ok(if res == 1 {
+            Ok(())
+        } else {
+            Err(NonexistentRegistrationToken)
+        })
+    }
+
+    /// Counts a use of the registration token, if it can still be used. This is done before the
+    /// account is created, so that a token can't be used more times than it allows by registering
+    /// at the same time.
+    pub async fn claim_registration_token(
+        &self,
+        token: String,
+    ) -> DbResult<Option<ClaimedRegistrationToken>> {
+        let stmt = format!(
+            "UPDATE registration_tokens SET uses = uses + 1 WHERE token = $1 AND {}",
+            USABLE
+        );
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(&stmt).await?;
+        let res = conn.client.execute(&stmt, &[&token]).await?;
+
+        Ok(if res == 1 {
+            Some(ClaimedRegistrationToken(token))
+        } else {
+            None
+        })
+    }
+
+    /// Gives back a claimed use of a registration token, because the account could not be created
+    pub async fn release_registration_token(
+        &self,
+        claimed: ClaimedRegistrationToken,
+    ) -> DbResult<()> {
+        const STMT: &str =
+            "UPDATE registration_tokens SET uses = uses - 1 WHERE token = $1 AND uses > 0";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        conn.client.execute(&stmt, &[&claimed.0]).await?;
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use crate::database::{Database, DbResult, MessageRecord};
 use chrono::{DateTime, Utc};
-use futures::{Stream, StreamExt, TryStreamExt};
+use futures::{StreamExt, TryStreamExt};
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::error::Error;
 use tokio_postgres::error::{DbError, SqlState};
@@ -26,6 +27,47 @@ pub(super) const CREATE_REPORTS_TABLE: &str = r#"
         status         "char" NOT NULL
     )"#;
 
+pub(super) const CREATE_REPORT_NOTES_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS report_notes (
+        id        SERIAL PRIMARY KEY,
+        report    INTEGER NOT NULL REFERENCES reports(id) ON DELETE CASCADE,
+        author    UUID REFERENCES users(id) ON DELETE SET NULL,
+        datetime  TIMESTAMP WITH TIME ZONE NOT NULL,
+        text      VARCHAR NOT NULL
+    )"#;
+
+/// The messages around a reported message at the time it was reported. The author is not a foreign
+/// key, since rows are never updated after insertion - their username is kept instead.
+pub(super) const CREATE_REPORT_CONTEXT_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS report_context (
+        report           INTEGER NOT NULL REFERENCES reports(id) ON DELETE CASCADE,
+        position         SMALLINT NOT NULL,
+        author           UUID NOT NULL,
+        author_username  VARCHAR NOT NULL,
+        sent_at          TIMESTAMP WITH TIME ZONE NOT NULL,
+        text             VARCHAR NOT NULL,
+
+        PRIMARY KEY (report, position)
+    )"#;
+
+const SELECT_REPORTS_QUERY: &str = "
+    SELECT
+        reports.id, reports.datetime, reports.kind, reports.message_text, reports.message_id,
+        reports.short_desc, reports.extended_desc, reports.status, msg_sent_at, reports.snapshot,
+        reports.reported_user, reported.username AS reported_username,
+        reports.reporter_user, reporter.username AS reporter_username,
+        reports.community, communities.name AS community_name,
+        reports.room, rooms.name AS room_name,
+        reports.assignee, assignee.username AS assignee_username
+    FROM reports
+    LEFT JOIN users reported ON reports.reported_user = reported.id
+    LEFT JOIN users reporter ON reports.reporter_user = reporter.id
+    LEFT JOIN users assignee ON reports.assignee = assignee.id
+    LEFT JOIN rooms ON reports.room = rooms.id
+    LEFT JOIN communities ON reports.community = communities.id
+    %where%
+    %order%";
+
 #[derive(Debug, Clone)]
 pub struct ReportRecord {
     pub id: i32,
@@ -35,32 +77,75 @@ pub struct ReportRecord {
 
 #[derive(Debug, Clone)]
 pub struct Report {
-    pub reported_user: UserId,
+    pub kind: ReportKind,
+    pub reported_user: Option<UserId>,
     pub reporter_user: Option<UserId>,
     pub community: Option<CommunityId>,
     pub room: Option<RoomId>,
     pub message_id: Option<MessageId>,
-    pub message_text: String,
+    /// Only set for reports of messages
+    pub message_text: Option<String>,
+    pub snapshot: String,
     pub short_desc: String,
     pub extended_desc: String,
     pub status: ReportStatus,
 }
 
+/// What is being reported, as it is at the time of the report
+#[derive(Debug, Clone)]
+pub struct NewReport {
+    pub kind: ReportKind,
+    pub reported_user: Option<UserId>,
+    pub community: Option<CommunityId>,
+    pub room: Option<RoomId>,
+    pub message: Option<ReportMessage>,
+    pub snapshot: String,
+}
+
+impl NewReport {
+    /// The message's text is kept alongside the report, so that the report is not lost when the
+    /// message is edited or deleted. Returns `None` if the message has no content.
+    pub fn message(msg: MessageRecord) -> Option<NewReport> {
+        // The server cannot read encrypted messages, so only the fact that it was encrypted is kept
+        let text = match (msg.content, &msg.ciphertext) {
+            (Some(content), _) => content,
+            (None, Some(_)) => "[encrypted message]".to_string(),
+            (None, None) => return None,
+        };
+
+        Some(NewReport {
+            kind: ReportKind::Message,
+            reported_user: Some(msg.author),
+            community: Some(msg.community),
+            room: Some(msg.room),
+            message: Some(ReportMessage {
+                id: Some(msg.id),
+                sent_at: msg.date,
+                text,
+            }),
+            snapshot: String::new(),
+        })
+    }
+}
+
 impl TryFrom<&Row> for ReportRecord {
     type Error = tokio_postgres::Error;
 
     fn try_from(row: &Row) -> Result<ReportRecord, tokio_postgres::Error> {
         let status: i8 = row.try_get("status")?;
+        let kind: i8 = row.try_get("kind")?;
         Ok(ReportRecord {
             id: row.try_get("id")?,
             datetime: row.try_get("datetime")?,
             report: Report {
-                reported_user: UserId(row.try_get("reported_user")?),
+                kind: kind.try_into().unwrap_or(ReportKind::Message),
+                reported_user: row.try_get::<_, Option<_>>("reported_user")?.map(UserId),
                 reporter_user: row.try_get::<_, Option<_>>("reporter_user")?.map(UserId),
                 community: row.try_get::<_, Option<_>>("community")?.map(CommunityId),
                 message_id: row.try_get::<_, Option<_>>("message_id")?.map(MessageId),
                 room: row.try_get::<_, Option<_>>("room")?.map(RoomId),
                 message_text: row.try_get("message_text")?,
+                snapshot: row.try_get("snapshot")?,
                 short_desc: row.try_get("short_desc")?,
                 extended_desc: row.try_get("extended_desc")?,
                 status: status.try_into().unwrap_or(ReportStatus::Opened),
@@ -69,44 +154,20 @@ impl TryFrom<&Row> for ReportRecord {
     }
 }
 
-macro_rules! build_where_clause {
-    (let (mut $a:ident, $b:ident) = $criteria:ident: { $($field: ident $(as $ty:ty)? => $stmt:expr,)* }) => {
-        let _casted: i8; // specific: there is one cast and it is to i8
-        let (mut $a, $b) = {
-            let mut _where_clause = String::new();
-            let mut _cur_arg: usize = 0;
-            let mut _args: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = vec![];
-            $(if let Some(ref $field) = $criteria.$field$(.map(|f| f as $ty))? {
-                _cur_arg += 1;
-                let join = if _cur_arg == 1 {
-                    "WHERE"
-                } else {
-                    "AND"
-                };
-
-                _where_clause.push_str(
-                    &format!("{} {}\n", join, format_args!($stmt, n = _cur_arg))
-                );
-
-                #[allow(unused_variables)]
-                let push = $field;
-                $(
-                    _casted = (*$field) as $ty;
-                    let push = &_casted;
-                )?
-                _args.push(push);
-            })*
-
-            (_args, _where_clause)
-        };
-    }
-}
-
 pub enum ReportUserError {
-    InvalidMessage,
+    /// The reported user, message, room or community does not exist
+    InvalidTarget,
     InvalidReporter,
 }
 
+pub struct NonexistentReport;
+
+pub enum ClaimReportError {
+    NonexistentReport,
+    /// The report is claimed by someone else
+    AlreadyClaimed,
+}
+
 fn row_to_report(row: &Row) -> Result<VertexReport, tokio_postgres::Error> {
     let record: ReportRecord = row.try_into()?;
     let report = record.report;
@@ -122,15 +183,26 @@ fn row_to_report(row: &Row) -> Result<VertexReport, tokio_postgres::Error> {
                 })
             })
             .transpose()?,
-        reported: ReportUser {
-            id: report.reported_user,
-            username: row.try_get("reported_username")?,
-        },
-        message: ReportMessage {
-            id: report.message_id,
-            text: report.message_text,
-            sent_at: row.try_get("msg_sent_at")?,
-        },
+        kind: report.kind,
+        reported: report
+            .reported_user
+            .map(|id| {
+                Ok(ReportUser {
+                    id,
+                    username: row.try_get("reported_username")?,
+                })
+            })
+            .transpose()?,
+        message: report
+            .message_text
+            .map(|text| {
+                Ok(ReportMessage {
+                    id: report.message_id,
+                    text,
+                    sent_at: row.try_get("msg_sent_at")?,
+                })
+            })
+            .transpose()?,
         room: report
             .room
             .map(|id| {
@@ -153,53 +225,93 @@ fn row_to_report(row: &Row) -> Result<VertexReport, tokio_postgres::Error> {
         short_desc: report.short_desc,
         extended_desc: report.extended_desc,
         status: report.status,
+        assignee: row
+            .try_get::<_, Option<_>>("assignee")?
+            .map(|id| {
+                Ok(ReportUser {
+                    id: UserId(id),
+                    username: row.try_get("assignee_username")?,
+                })
+            })
+            .transpose()?,
+        notes: Vec::new(), // Filled in afterwards
+        snapshot: report.snapshot,
     })
 }
 
 impl Database {
-    pub async fn report_message(
+    /// Makes a report. For reports of messages, up to `context_messages` messages before and after
+    /// the reported message are kept alongside it. The reporter is `None` for reports made by the
+    /// server itself, e.g when a message is flagged by a community's automod.
+    pub async fn report(
         &self,
-        reporter: UserId,
-        msg: MessageRecord,
+        reporter: Option<UserId>,
+        report: NewReport,
         short_desc: &str,
         extended_desc: &str,
-    ) -> DbResult<Result<(), ReportUserError>> {
+        context_messages: u32,
+    ) -> DbResult<Result<i32, ReportUserError>> {
         const STMT: &str = "
             INSERT INTO reports
             (
-                datetime, reported_user, reporter_user, community, message_id, room, message_text,
-                short_desc, extended_desc, msg_sent_at, status
+                datetime, kind, reported_user, reporter_user, community, room, message_id,
+                message_text, msg_sent_at, snapshot, short_desc, extended_desc, status
             )
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)";
+            VALUES (NOW(), $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            RETURNING id";
 
-        let conn = self.pool.connection().await?;
+        // Positions are negative before the reported message, 0 for it and positive after it
+        const INSERT_CONTEXT: &str = "
+            WITH reported AS (SELECT room, ord FROM messages WHERE id = $2),
+            context AS (
+                (SELECT messages.*, -ROW_NUMBER() OVER (ORDER BY messages.ord DESC) AS position
+                    FROM messages, reported
+                    WHERE messages.room = reported.room AND messages.ord < reported.ord
+                        AND (messages.content IS NOT NULL OR messages.ciphertext IS NOT NULL)
+                    ORDER BY messages.ord DESC
+                    LIMIT $3)
+                UNION ALL
+                (SELECT messages.*, ROW_NUMBER() OVER (ORDER BY messages.ord ASC) - 1 AS position
+                    FROM messages, reported
+                    WHERE messages.room = reported.room AND messages.ord >= reported.ord
+                        AND (messages.content IS NOT NULL OR messages.ciphertext IS NOT NULL)
+                    ORDER BY messages.ord ASC
+                    LIMIT $3 + 1)
+            )
+            INSERT INTO report_context (report, position, author, author_username, sent_at, text)
+                SELECT
+                    $1, context.position::SMALLINT, context.author, users.username, context.date,
+                    COALESCE(context.content, '[encrypted message]')
+                FROM context
+                INNER JOIN users ON context.author = users.id";
 
-        let stmt = conn.client.prepare(STMT).await?;
-        let res = conn
-            .client
-            .execute(
+        let message = report.message.as_ref();
+        let mut conn = self.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let stmt = transaction.prepare(STMT).await?;
+        let res = transaction
+            .query_one(
                 &stmt,
                 &[
-                    &msg.date,
-                    &msg.author.0,
-                    &reporter.0,
-                    &msg.community.0,
-                    &msg.id.0,
-                    &msg.room.0,
-                    &msg.content,
+                    &(report.kind as i8),
+                    &report.reported_user.map(|id| id.0),
+                    &reporter.map(|id| id.0),
+                    &report.community.map(|id| id.0),
+                    &report.room.map(|id| id.0),
+                    &message.and_then(|msg| msg.id).map(|id| id.0),
+                    &message.map(|msg| &msg.text),
+                    &message.map(|msg| msg.sent_at),
+                    &report.snapshot,
                     &short_desc,
                     &extended_desc,
-                    &msg.date,
                     &(ReportStatus::Opened as i8),
                 ],
             )
             .await;
 
-        match res {
-            Ok(1) => Ok(Ok(())), // 1 row modified = successfully added
-            Ok(_n) => {
-                panic_error!("db error: report user query returned more than one row modified!");
-            }
+        let id: i32 = match res {
+            Ok(row) => row.try_get("id")?,
             Err(err) => {
                 if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
                     let constraint = err
@@ -208,48 +320,90 @@ impl Database {
                         .and_then(|e| e.constraint());
 
                     match constraint {
-                        Some("reports_reporter_user_fkey")
-                        | Some("reports_reported_user_fkey")
+                        Some("reports_reporter_user_fkey") => {
+                            Ok(Err(ReportUserError::InvalidReporter))
+                        }
+                        Some("reports_reported_user_fkey")
                         | Some("reports_message_id_fkey")
                         | Some("reports_community_fkey")
-                        | Some("reports_room_fkey") => Ok(Err(ReportUserError::InvalidReporter)),
-                        Some(_) | None => Err(err.into()),
+                        | Some("reports_room_fkey") => {
+                            return Ok(Err(ReportUserError::InvalidTarget))
+                        }
+                        Some(_) | None => return Err(err.into()),
                     }
                 } else {
-                    Err(err.into())
+                    return Err(err.into());
                 }
             }
+        };
+
+        if let Some(message_id) = message.and_then(|msg| msg.id) {
+            let stmt = transaction.prepare(INSERT_CONTEXT).await?;
+            let count = context_messages as i64;
+            transaction.execute(&stmt, &[&id, &message_id.0, &count]).await?;
         }
+
+        transaction.commit().await?;
+        Ok(Ok(id))
+    }
+
+    /// Gets the messages kept around a reported message, oldest first. The author is `None` if
+    /// they have since been deleted. Returns `None` if the report does not exist.
+    pub async fn get_report_context(
+        &self,
+        id: i32,
+    ) -> DbResult<Option<Vec<ReportContextMessage>>> {
+        const QUERY: &str = "
+            SELECT report_context.*, users.id AS author_id
+            FROM report_context
+            LEFT JOIN users ON report_context.author = users.id
+            WHERE report = $1
+            ORDER BY position ASC";
+
+        let exists = self
+            .query_opt("SELECT 1 FROM reports WHERE id = $1", &[&id])
+            .await?
+            .is_some();
+
+        if !exists {
+            return Ok(None);
+        }
+
+        let rows = self.query_stream(QUERY, &[&id]).await?;
+        let messages = rows
+            .map(|row| {
+                let row = row?;
+                Ok(ReportContextMessage {
+                    author: row
+                        .try_get::<_, Option<_>>("author_id")?
+                        .map(|id| {
+                            Ok(ReportUser {
+                                id: UserId(id),
+                                username: row.try_get("author_username")?,
+                            })
+                        })
+                        .transpose()?,
+                    sent_at: row.try_get("sent_at")?,
+                    text: row.try_get("text")?,
+                    reported: row.try_get::<_, i16>("position")? == 0,
+                })
+            })
+            .map_err(|e: tokio_postgres::Error| e.into())
+            .try_collect()
+            .await?;
+
+        Ok(Some(messages))
     }
 
     pub async fn set_report_status(&self, id: i32, status: ReportStatus) -> DbResult<()> {
         const STMT: &str = "UPDATE reports SET status = $1 WHERE id = $2";
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         conn.client.execute(&stmt, &[&(status as i8), &id]).await?;
         Ok(())
     }
 
-    pub async fn search_reports(
-        &self,
-        criteria: SearchCriteria,
-    ) -> DbResult<impl Stream<Item = DbResult<VertexReport>>> {
-        const SELECT_QUERY: &str = "
-            SELECT
-                reports.id, reports.datetime, reports.message_text, reports.message_id,
-                reports.short_desc, reports.extended_desc, reports.status, msg_sent_at,
-                reports.reported_user, reported.username AS reported_username,
-                reports.reporter_user, reporter.username AS reporter_username,
-                reports.community, communities.name AS community_name,
-                reports.room, rooms.name AS room_name
-            FROM reports
-            INNER JOIN users reported ON reports.reported_user = reported.id
-            LEFT JOIN users reporter ON reports.reporter_user = reporter.id
-            LEFT JOIN rooms ON reports.room = rooms.id
-            LEFT JOIN communities ON reports.community = communities.id
-            %where%
-            %order%";
-
+    pub async fn search_reports(&self, criteria: SearchCriteria) -> DbResult<Vec<VertexReport>> {
         build_where_clause! {
             let (mut args, where_clause) = criteria: {
                 of_user => "reported.username = LOWER(${n})",
@@ -259,12 +413,13 @@ impl Database {
                 in_community => "communities.name % ${n}",
                 in_room => "rooms.name % ${n}",
                 status as i8 => "status = ${n}",
+                kind as i8 => "reports.kind = ${n}",
             }
         };
 
         let trimmed = &criteria.words.trim();
 
-        let query = SELECT_QUERY.replace("%where%", &where_clause);
+        let query = SELECT_REPORTS_QUERY.replace("%where%", &where_clause);
         let order = if trimmed.len() == 0 {
             "ORDER BY reports.id DESC".to_string()
         } else {
@@ -279,10 +434,127 @@ impl Database {
         };
         let query = query.replace("%order%", &order);
         let stream = self.query_stream(&query, &args).await?;
-        let stream = stream
+        let mut reports: Vec<VertexReport> = stream
             .map(|row| Ok(row_to_report(&row?)?))
-            .map_err(|e: tokio_postgres::Error| e.into());
+            .map_err(|e: tokio_postgres::Error| e.into())
+            .try_collect()
+            .await?;
+
+        self.fill_in_report_notes(&mut reports).await?;
+        Ok(reports)
+    }
+
+    pub async fn get_report(&self, id: i32) -> DbResult<Option<VertexReport>> {
+        let query = SELECT_REPORTS_QUERY
+            .replace("%where%", "WHERE reports.id = $1")
+            .replace("%order%", "");
+
+        let mut report = match self.query_opt(&query, &[&id]).await? {
+            Some(row) => row_to_report(&row)?,
+            None => return Ok(None),
+        };
+
+        self.fill_in_report_notes(std::slice::from_mut(&mut report)).await?;
+        Ok(Some(report))
+    }
+
+    async fn fill_in_report_notes(&self, reports: &mut [VertexReport]) -> DbResult<()> {
+        const QUERY: &str = "
+            SELECT report_notes.*, users.username AS author_username
+            FROM report_notes
+            LEFT JOIN users ON report_notes.author = users.id
+            WHERE report = ANY($1)
+            ORDER BY report_notes.id ASC";
+
+        let ids: Vec<i32> = reports.iter().map(|report| report.id).collect();
+        let rows = self.query_stream(QUERY, &[&ids]).await?;
+        let notes: Vec<(i32, ReportNote)> = rows
+            .map(|row| {
+                let row = row?;
+                let note = ReportNote {
+                    author: row
+                        .try_get::<_, Option<_>>("author")?
+                        .map(|id| {
+                            Ok(ReportUser {
+                                id: UserId(id),
+                                username: row.try_get("author_username")?,
+                            })
+                        })
+                        .transpose()?,
+                    datetime: row.try_get("datetime")?,
+                    text: row.try_get("text")?,
+                };
+
+                Ok((row.try_get("report")?, note))
+            })
+            .map_err(|e: tokio_postgres::Error| e.into())
+            .try_collect()
+            .await?;
+
+        let mut by_report: HashMap<i32, &mut VertexReport> =
+            reports.iter_mut().map(|report| (report.id, report)).collect();
+        for (id, note) in notes {
+            if let Some(report) = by_report.get_mut(&id) {
+                report.notes.push(note);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Assigns the report to the user, unless someone else has already claimed it. Passing `None`
+    /// as the assignee unclaims it, if the user had claimed it.
+    pub async fn set_report_assignee(
+        &self,
+        id: i32,
+        user: UserId,
+        assignee: Option<UserId>,
+    ) -> DbResult<Result<(), ClaimReportError>> {
+        const STMT: &str = "
+            UPDATE reports SET assignee = $1
+                WHERE id = $2 AND (assignee IS NULL OR assignee = $3)";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let assignee = assignee.map(|id| id.0);
+        let modified = conn.client.execute(&stmt, &[&assignee, &id, &user.0]).await?;
+
+        if modified == 1 {
+            return Ok(Ok(()));
+        }
+
+        let exists = self
+            .query_opt("SELECT 1 FROM reports WHERE id = $1", &[&id])
+            .await?
+            .is_some();
 
-        Ok(stream)
+        if exists {
+            Ok(Err(ClaimReportError::AlreadyClaimed))
+        } else {
+            Ok(Err(ClaimReportError::NonexistentReport))
+        }
+    }
+
+    pub async fn add_report_note(
+        &self,
+        id: i32,
+        author: UserId,
+        text: String,
+    ) -> DbResult<Result<(), NonexistentReport>> {
+        const STMT: &str = "
+            INSERT INTO report_notes (report, author, datetime, text)
+                VALUES ($1, $2, NOW(), $3)";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let res = conn.client.execute(&stmt, &[&id, &author.0, &text]).await;
+
+        match res {
+            Ok(_) => Ok(Ok(())),
+            Err(err) if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) => {
+                Ok(Err(NonexistentReport))
+            }
+            Err(err) => Err(err.into()),
+        }
     }
 }
@@ -1,6 +1,7 @@
 use crate::database::{Database, DbResult};
 use futures::{Stream, TryStreamExt};
 use std::convert::TryFrom;
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
 use uuid::Uuid;
 use vertex::prelude::*;
@@ -11,13 +12,16 @@ pub(super) const CREATE_ROOMS_TABLE: &str = "
         community  UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
         name       VARCHAR NOT NULL
     )";
-// TODO(sql): indexing
 
 #[derive(Debug, Clone)]
 pub struct RoomRecord {
     pub id: RoomId,
     pub community: CommunityId,
     pub name: String,
+    pub encrypted: bool,
+    /// How many days messages are kept for, if the room has its own retention policy
+    pub retention_days: Option<u32>,
+    pub settings: RoomSettings,
 }
 
 impl TryFrom<Row> for RoomRecord {
@@ -28,6 +32,17 @@ impl TryFrom<Row> for RoomRecord {
             id: RoomId(row.try_get("id")?),
             community: CommunityId(row.try_get("community")?),
             name: row.try_get("name")?,
+            encrypted: row.try_get("encrypted")?,
+            retention_days: row
+                .try_get::<&str, Option<i32>>("retention_days")?
+                .map(|days| days as u32),
+            settings: RoomSettings {
+                slow_mode_secs: row.try_get::<&str, i32>("slow_mode_secs")? as u32,
+                read_only: row.try_get("read_only")?,
+                max_message_len: row
+                    .try_get::<&str, Option<i32>>("max_message_len")?
+                    .map(|len| len as u32),
+            },
         })
     }
 }
@@ -44,13 +59,19 @@ impl Database {
         }
     }
 
-    pub async fn create_room(&self, community: CommunityId, name: String) -> DbResult<RoomId> {
-        const STMT: &str = "INSERT INTO rooms (id, community, name) VALUES ($1, $2, $3)";
+    pub async fn create_room(
+        &self,
+        community: CommunityId,
+        name: String,
+        encrypted: bool,
+    ) -> DbResult<RoomId> {
+        const STMT: &str =
+            "INSERT INTO rooms (id, community, name, encrypted) VALUES ($1, $2, $3, $4)";
         let id = Uuid::new_v4();
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         conn.client
-            .execute(&stmt, &[&id, &community.0, &name])
+            .execute(&stmt, &[&id, &community.0, &name, &encrypted])
             .await?;
         Ok(RoomId(id))
     }
@@ -67,4 +88,30 @@ impl Database {
             .map_err(|e| e.into());
         Ok(stream)
     }
+
+    /// Sets how many days messages in the room are kept for. If unset, the community's policy
+    /// applies.
+    pub async fn set_room_retention(&self, id: RoomId, days: Option<u32>) -> DbResult<()> {
+        const STMT: &str = "UPDATE rooms SET retention_days = $1 WHERE id = $2";
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let days = days.map(|days| days as i32);
+        conn.client.execute(&stmt, &[&days, &id.0]).await?;
+        Ok(())
+    }
+
+    pub async fn set_room_settings(&self, id: RoomId, settings: &RoomSettings) -> DbResult<()> {
+        const STMT: &str = "
+            UPDATE rooms SET slow_mode_secs = $1, read_only = $2, max_message_len = $3
+                WHERE id = $4";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let slow_mode_secs = settings.slow_mode_secs as i32;
+        let max_message_len = settings.max_message_len.map(|len| len as i32);
+        let args: &[&(dyn ToSql + Sync)] =
+            &[&slow_mode_secs, &settings.read_only, &max_message_len, &id.0];
+        conn.client.execute(&stmt, args).await?;
+        Ok(())
+    }
 }
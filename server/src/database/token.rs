@@ -58,7 +58,7 @@ impl Database {
     pub async fn get_token(&self, device: DeviceId) -> DbResult<Option<Token>> {
         const QUERY: &str = "SELECT * FROM login_tokens WHERE device=$1";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let query = conn.client.prepare(QUERY).await?;
         let opt = conn.client.query_opt(&query, &[&device.0]).await?;
 
@@ -84,7 +84,7 @@ impl Database {
                 )
             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT DO NOTHING";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         let args: &[&(dyn ToSql + Sync)] = &[
             &token.device.0,
@@ -112,7 +112,7 @@ impl Database {
         &self,
         device_id: DeviceId,
     ) -> DbResult<Result<(), NonexistentDevice>> {
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn
             .client
             .prepare("DELETE FROM login_tokens WHERE device = $1")
@@ -137,7 +137,7 @@ impl Database {
     ) -> DbResult<Result<(), NonexistentDevice>> {
         const STMT: &str = "UPDATE login_tokens SET last_used=NOW()::timestamp WHERE device = $1";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
 
         // Result will be 1 if the token existed
@@ -151,4 +151,13 @@ impl Database {
 
         res.map_err(Into::into)
     }
+
+    /// Revokes every token of the user, returning how many there were
+    pub async fn revoke_all_tokens(&self, user: UserId) -> DbResult<u64> {
+        const STMT: &str = "DELETE FROM login_tokens WHERE user_id = $1";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        Ok(conn.client.execute(&stmt, &[&user.0]).await?)
+    }
 }
@@ -1,7 +1,7 @@
 use super::*;
 use crate::auth::HashSchemeVersion;
 use std::convert::TryFrom;
-use tokio_postgres::{error::SqlState, row::Row, types::ToSql};
+use tokio_postgres::{error::SqlState, row::Row, types::ToSql, GenericClient};
 use uuid::Uuid;
 
 pub(super) const CREATE_USERS_TABLE: &str = "
@@ -25,8 +25,16 @@ pub struct UserRecord {
     pub password_hash: String,
     pub hash_scheme_version: HashSchemeVersion,
     pub compromised: bool,
-    pub locked: bool,
-    pub banned: bool,
+    pub locked: Option<Restriction>,
+    pub banned: Option<Restriction>,
+    /// Muted users can log in and read messages, but not send or edit them
+    pub muted: Option<Restriction>,
+    pub bot: bool,
+    /// The user that created and manages this bot. `None` for regular users.
+    pub bot_owner: Option<UserId>,
+    /// Users registered while registration needs approval cannot log in until an administrator
+    /// approves them
+    pub pending_approval: bool,
 }
 
 impl UserRecord {
@@ -44,8 +52,95 @@ impl UserRecord {
             password_hash,
             hash_scheme_version,
             compromised: false,
-            locked: false,
-            banned: false,
+            locked: None,
+            banned: None,
+            muted: None,
+            bot: false,
+            bot_owner: None,
+            pending_approval: false,
+        }
+    }
+
+    /// Creates the user that messages sent through a webhook are authored by. It is a bot with no
+    /// owner, and has no way to log in.
+    pub fn new_webhook_user(display_name: String) -> Self {
+        let id = Uuid::new_v4();
+        UserRecord {
+            id: UserId(id),
+            username: format!("webhook-{}", id),
+            display_name,
+            profile_version: ProfileVersion(0),
+            password_hash: String::new(),
+            hash_scheme_version: HashSchemeVersion::LATEST,
+            compromised: false,
+            locked: None,
+            banned: None,
+            muted: None,
+            bot: true,
+            bot_owner: None,
+            pending_approval: false,
+        }
+    }
+
+    /// Creates the stand-in for the author of messages in an imported community. It isn't linked
+    /// to any other user, whatever the archive claims they are, and has no way to log in.
+    pub fn new_imported(display_name: String) -> Self {
+        let id = Uuid::new_v4();
+        UserRecord {
+            id: UserId(id),
+            username: format!("imported-{}", id),
+            display_name,
+            profile_version: ProfileVersion(0),
+            password_hash: String::new(),
+            hash_scheme_version: HashSchemeVersion::LATEST,
+            compromised: false,
+            locked: None,
+            banned: None,
+            muted: None,
+            bot: false,
+            bot_owner: None,
+            pending_approval: false,
+        }
+    }
+
+    /// Creates the local stand-in for a user of another server in the federation, so that they can
+    /// be a member of local communities and author messages in them. Like a webhook user, it has
+    /// no way to log in.
+    pub fn new_remote(username: &str, server: &str, display_name: String) -> Self {
+        UserRecord {
+            id: UserId(Uuid::new_v4()),
+            username: format!("{}@{}", username, server),
+            display_name,
+            profile_version: ProfileVersion(0),
+            password_hash: String::new(),
+            hash_scheme_version: HashSchemeVersion::LATEST,
+            compromised: false,
+            locked: None,
+            banned: None,
+            muted: None,
+            bot: false,
+            bot_owner: None,
+            pending_approval: false,
+        }
+    }
+
+    /// Creates a new bot account. Bots have no password, and so cannot log in with credentials;
+    /// tokens for them are created by their owner.
+    pub fn new_bot(username: String, display_name: String, owner: UserId) -> Self {
+        UserRecord {
+            id: UserId(Uuid::new_v4()),
+            username,
+            display_name,
+            profile_version: ProfileVersion(0),
+            password_hash: String::new(),
+            hash_scheme_version: HashSchemeVersion::LATEST,
+            compromised: false,
+            locked: None,
+            banned: None,
+            muted: None,
+            bot: true,
+            bot_owner: Some(owner),
+            pending_approval: false,
         }
     }
 }
@@ -64,19 +159,40 @@ impl TryFrom<Row> for UserRecord {
                 row.try_get::<&str, i16>("hash_scheme_version")?,
             ),
             compromised: row.try_get("compromised")?,
-            locked: row.try_get("locked")?,
-            banned: row.try_get("banned")?,
+            locked: restriction_from_row(&row, "locked", "lock")?,
+            banned: restriction_from_row(&row, "banned", "ban")?,
+            muted: restriction_from_row(&row, "muted", "mute")?,
+            bot: row.try_get("bot")?,
+            bot_owner: row.try_get::<&str, Option<Uuid>>("bot_owner")?.map(UserId),
+            pending_approval: row.try_get("pending_approval")?,
         })
     }
 }
 
+/// Restrictions are stored as a flag along with a `<prefix>_reason` and `<prefix>_expires` column
+fn restriction_from_row(
+    row: &Row,
+    flag: &str,
+    prefix: &str,
+) -> Result<Option<Restriction>, tokio_postgres::Error> {
+    if !row.try_get::<&str, bool>(flag)? {
+        return Ok(None);
+    }
+
+    let reason: Option<String> = row.try_get(format!("{}_reason", prefix).as_str())?;
+    Ok(Some(Restriction {
+        reason: reason.unwrap_or_default(), // Unset for restrictions from before reasons were kept
+        expires: row.try_get(format!("{}_expires", prefix).as_str())?,
+    }))
+}
+
 impl Into<ServerUser> for UserRecord {
     fn into(self) -> ServerUser {
         ServerUser {
             username: self.username,
             display_name: self.display_name,
-            banned: self.banned,
-            locked: self.locked,
+            banned: self.banned.is_some(),
+            locked: self.locked.is_some(),
             compromised: self.compromised,
             latest_hash_scheme: self.hash_scheme_version == HashSchemeVersion::LATEST,
             id: self.id,
@@ -114,7 +230,7 @@ impl Database {
     }
 
     pub async fn get_user_profile(&self, id: UserId) -> DbResult<Option<Profile>> {
-        let query = "SELECT username, display_name, profile_version FROM users WHERE id=$1";
+        let query = "SELECT username, display_name, profile_version, bot FROM users WHERE id=$1";
         let opt = self.query_opt(query, &[&id.0]).await?;
         if let Some(row) = opt {
             // Can't opt::map because of ?
@@ -122,6 +238,7 @@ impl Database {
                 version: ProfileVersion(row.try_get::<&str, i32>("profile_version")? as u32),
                 username: row.try_get("username")?,
                 display_name: row.try_get("display_name")?,
+                bot: row.try_get("bot")?,
             }))
         } else {
             Ok(None)
@@ -131,46 +248,70 @@ impl Database {
     /// Creates a user, returning whether it was successful (i.e, if there were no conflicts with
     /// respect to the ID and username).
     pub async fn create_user(&self, user: UserRecord) -> DbResult<Result<(), UsernameConflict>> {
-        const STMT: &str = "
-            INSERT INTO users
-                (
-                    id,
-                    username,
-                    display_name,
-                    profile_version,
-                    password_hash,
-                    hash_scheme_version,
-                    compromised,
-                    locked,
-                    banned
-                )
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-            ON CONFLICT DO NOTHING";
-
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
+        insert_user(&conn.client, &user).await
+    }
+
+    /// Gets the users waiting to be approved, oldest first
+    pub async fn get_pending_users(&self) -> DbResult<Vec<PendingUser>> {
+        const QUERY: &str = "
+            SELECT id, username, display_name, registered_at FROM users
+                WHERE pending_approval
+                ORDER BY registered_at ASC";
+
+        let conn = self.connection().await?;
+        let rows = conn.client.query(QUERY, &[]).await?;
+
+        let mut users = Vec::with_capacity(rows.len());
+        for row in rows {
+            users.push(PendingUser {
+                id: UserId(row.try_get("id")?),
+                username: row.try_get("username")?,
+                display_name: row.try_get("display_name")?,
+                registered_at: row.try_get("registered_at")?,
+            });
+        }
+
+        Ok(users)
+    }
+
+    /// Lets a user waiting to be approved log in, returning whether they were waiting at all
+    pub async fn approve_user(&self, user: UserId) -> DbResult<Result<(), NonexistentUser>> {
+        const STMT: &str =
+            "UPDATE users SET pending_approval = FALSE WHERE id = $1 AND pending_approval";
+
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
-        let args: &[&(dyn ToSql + Sync)] = &[
-            &user.id.0,
-            &user.username,
-            &user.display_name,
-            &(user.profile_version.0 as i32),
-            &user.password_hash,
-            &(user.hash_scheme_version as i16),
-            &user.compromised,
-            &user.locked,
-            &user.banned,
-        ];
+        let res = conn.client.execute(&stmt, &[&user.0]).await?;
+        Ok(if res == 1 {
+            Ok(())
+        } else {
+            Err(NonexistentUser)
+        })
+    }
 
-        let ret = conn.client.execute(&stmt, args).await?;
+    /// Deletes a user waiting to be approved, freeing their username. Users who have already been
+    /// approved are never deleted.
+    pub async fn reject_user(&self, user: UserId) -> DbResult<Result<(), NonexistentUser>> {
+        const STMT: &str = "DELETE FROM users WHERE id = $1 AND pending_approval";
 
-        Ok(if ret == 1 {
-            // 1 item was inserted (insert was successful)
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let res = conn.client.execute(&stmt, &[&user.0]).await?;
+        Ok(if res == 1 {
             Ok(())
         } else {
-            Err(UsernameConflict)
+            Err(NonexistentUser)
         })
     }
 
+    pub async fn count_bots_owned_by(&self, owner: UserId) -> DbResult<i64> {
+        const QUERY: &str = "SELECT COUNT(*) FROM users WHERE bot_owner = $1";
+
+        let row = self.query_one(QUERY, &[&owner.0]).await?;
+        Ok(row.try_get(0)?)
+    }
+
     pub async fn change_username(
         &self,
         user: UserId,
@@ -182,7 +323,7 @@ impl Database {
                 WHERE id = $2
         ";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         let res = conn.client.execute(&stmt, &[&new_username, &user.0]).await;
 
@@ -218,7 +359,7 @@ impl Database {
                 WHERE id = $2
         ";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         let res = conn
             .client
@@ -243,7 +384,7 @@ impl Database {
                 SET password_hash = $1, hash_scheme_version = $2, compromised = $3
                 WHERE id = $4";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         let args: &[&(dyn ToSql + Sync)] = &[
             &new_password_hash,
@@ -260,35 +401,51 @@ impl Database {
         })
     }
 
+    /// Bans the user, or unbans them if `None` is given
     pub async fn set_banned(
         &self,
         user: UserId,
-        banned: bool,
+        ban: Option<Restriction>,
     ) -> DbResult<Result<(), NonexistentUser>> {
-        const STMT: &str = "UPDATE users SET banned = $1 WHERE id = $2";
-
-        let conn = self.pool.connection().await?;
-        let stmt = conn.client.prepare(STMT).await?;
-        let args: &[&(dyn ToSql + Sync)] = &[&banned, &user.0];
-
-        let res = conn.client.execute(&stmt, args).await?;
-        Ok(if res == 1 {
-            Ok(())
-        } else {
-            Err(NonexistentUser)
-        })
+        self.set_restriction(user, "banned", "ban", ban).await
     }
 
+    /// Locks the user, or unlocks them if `None` is given
     pub async fn set_locked(
         &self,
         user: UserId,
-        locked: bool,
+        lock: Option<Restriction>,
     ) -> DbResult<Result<(), NonexistentUser>> {
-        const STMT: &str = "UPDATE users SET locked = $1 WHERE id = $2";
+        self.set_restriction(user, "locked", "lock", lock).await
+    }
 
-        let conn = self.pool.connection().await?;
-        let stmt = conn.client.prepare(STMT).await?;
-        let args: &[&(dyn ToSql + Sync)] = &[&locked, &user.0];
+    /// Mutes the user, or unmutes them if `None` is given
+    pub async fn set_muted(
+        &self,
+        user: UserId,
+        mute: Option<Restriction>,
+    ) -> DbResult<Result<(), NonexistentUser>> {
+        self.set_restriction(user, "muted", "mute", mute).await
+    }
+
+    async fn set_restriction(
+        &self,
+        user: UserId,
+        flag: &str,
+        prefix: &str,
+        restriction: Option<Restriction>,
+    ) -> DbResult<Result<(), NonexistentUser>> {
+        let stmt = format!(
+            "UPDATE users SET {flag} = $1, {prefix}_reason = $2, {prefix}_expires = $3 WHERE id = $4",
+            flag = flag,
+            prefix = prefix,
+        );
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(&stmt).await?;
+        let reason = restriction.as_ref().map(|r| &r.reason);
+        let expires = restriction.as_ref().and_then(|r| r.expires);
+        let args: &[&(dyn ToSql + Sync)] = &[&restriction.is_some(), &reason, &expires, &user.0];
 
         let res = conn.client.execute(&stmt, args).await?;
         Ok(if res == 1 {
@@ -298,6 +455,35 @@ impl Database {
         })
     }
 
+    /// Lifts bans, locks and mutes which have expired, returning the users who were unmuted
+    pub async fn lift_expired_restrictions(&self) -> DbResult<Vec<UserId>> {
+        const UNBAN: &str = "
+            UPDATE users SET banned = FALSE, ban_reason = NULL, ban_expires = NULL
+                WHERE banned AND ban_expires < NOW()";
+        const UNLOCK: &str = "
+            UPDATE users SET locked = FALSE, lock_reason = NULL, lock_expires = NULL
+                WHERE locked AND lock_expires < NOW()";
+        const UNMUTE: &str = "
+            UPDATE users SET muted = FALSE, mute_reason = NULL, mute_expires = NULL
+                WHERE muted AND mute_expires < NOW()
+                RETURNING id";
+
+        let conn = self.connection().await?;
+        for stmt in &[UNBAN, UNLOCK] {
+            let stmt = conn.client.prepare(stmt).await?;
+            conn.client.execute(&stmt, &[]).await?;
+        }
+
+        let stmt = conn.client.prepare(UNMUTE).await?;
+        let rows = conn.client.query(&stmt, &[]).await?;
+        let unmuted = rows
+            .iter()
+            .map(|row| Ok(UserId(row.try_get("id")?)))
+            .collect::<Result<_, tokio_postgres::Error>>()?;
+
+        Ok(unmuted)
+    }
+
     pub async fn search_user(
         &self,
         name: String,
@@ -331,7 +517,7 @@ impl Database {
         const SET_COMPROMISED: &str = "UPDATE users SET compromised = $1";
         const DELETE_TOKENS: &str = "DELETE FROM login_tokens";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(SET_COMPROMISED).await?;
         conn.client.execute(&stmt, &[&true]).await?;
 
@@ -350,7 +536,7 @@ impl Database {
                 WHERE login_tokens.user_id = users.id
                 AND users.compromised;";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(SET_COMPROMISED).await?;
         conn.client
             .execute(&stmt, &[&true, &(HashSchemeVersion::LATEST as i16)])
@@ -362,3 +548,53 @@ impl Database {
         Ok(())
     }
 }
+
+/// Inserts the user with the given client, so that it can be done as part of a transaction
+pub(super) async fn insert_user<C: GenericClient>(
+    client: &C,
+    user: &UserRecord,
+) -> DbResult<Result<(), UsernameConflict>> {
+    const STMT: &str = "
+        INSERT INTO users
+            (
+                id,
+                username,
+                display_name,
+                profile_version,
+                password_hash,
+                hash_scheme_version,
+                compromised,
+                locked,
+                banned,
+                bot,
+                bot_owner,
+                pending_approval
+            )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+        ON CONFLICT DO NOTHING";
+
+    let stmt = client.prepare(STMT).await?;
+    let args: &[&(dyn ToSql + Sync)] = &[
+        &user.id.0,
+        &user.username,
+        &user.display_name,
+        &(user.profile_version.0 as i32),
+        &user.password_hash,
+        &(user.hash_scheme_version as i16),
+        &user.compromised,
+        &user.locked.is_some(),
+        &user.banned.is_some(),
+        &user.bot,
+        &user.bot_owner.map(|owner| owner.0),
+        &user.pending_approval,
+    ];
+
+    let ret = client.execute(&stmt, args).await?;
+
+    Ok(if ret == 1 {
+        // 1 item was inserted (insert was successful)
+        Ok(())
+    } else {
+        Err(UsernameConflict)
+    })
+}
@@ -82,7 +82,7 @@ impl Database {
                     WHERE rooms.community = $3
         ";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         let args: &[&(dyn ToSql + Sync)] =
             &[&user.0, &(WatchLevel::default() as u8 as i8), &community.0];
@@ -112,7 +112,7 @@ impl Database {
                     WHERE community_membership.community = $3
         ";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
         let args: &[&(dyn ToSql + Sync)] =
             &[&room.0, &(WatchLevel::default() as u8 as i8), &community.0];
@@ -136,7 +136,7 @@ impl Database {
                 WHERE user_id = $1 AND room = $2
             ";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
 
         let stmt = conn.client.prepare(STMT).await?;
         let args: &[&(dyn ToSql + Sync)] = &[&user.0, &room.0];
@@ -174,7 +174,7 @@ impl Database {
                 WHERE user_id = $1 AND room = $2
             ";
 
-        let conn = self.pool.connection().await?;
+        let conn = self.connection().await?;
 
         let stmt = conn.client.prepare(STMT).await?;
         let args: &[&(dyn ToSql + Sync)] = &[&user.0, &room.0, &(level as u8 as i8)];
@@ -0,0 +1,131 @@
+use std::convert::TryFrom;
+
+use futures::{Stream, TryStreamExt};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+
+use crate::auth::HashSchemeVersion;
+use crate::database::{Database, DbResult};
+use vertex::prelude::*;
+
+pub(super) const CREATE_WEBHOOKS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS webhooks (
+        id                   UUID PRIMARY KEY,
+        community            UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
+        room                 UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+        user_id              UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        name                 VARCHAR NOT NULL,
+        token_hash           VARCHAR NOT NULL,
+        hash_scheme_version  SMALLINT NOT NULL
+    )";
+
+#[derive(Debug)]
+pub struct WebhookRecord {
+    pub id: WebhookId,
+    pub community: CommunityId,
+    pub room: RoomId,
+    /// The user that messages sent through this webhook are authored by
+    pub user: UserId,
+    pub name: String,
+    pub token_hash: String,
+    pub hash_scheme_version: HashSchemeVersion,
+}
+
+impl TryFrom<Row> for WebhookRecord {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<WebhookRecord, tokio_postgres::Error> {
+        Ok(WebhookRecord {
+            id: WebhookId(row.try_get("id")?),
+            community: CommunityId(row.try_get("community")?),
+            room: RoomId(row.try_get("room")?),
+            user: UserId(row.try_get("user_id")?),
+            name: row.try_get("name")?,
+            token_hash: row.try_get("token_hash")?,
+            hash_scheme_version: HashSchemeVersion::from(
+                row.try_get::<&str, i16>("hash_scheme_version")?,
+            ),
+        })
+    }
+}
+
+impl Into<Webhook> for WebhookRecord {
+    fn into(self) -> Webhook {
+        Webhook {
+            id: self.id,
+            room: self.room,
+            user: self.user,
+            name: self.name,
+        }
+    }
+}
+
+pub struct NonexistentWebhook;
+
+impl Database {
+    pub async fn create_webhook(&self, webhook: WebhookRecord) -> DbResult<()> {
+        const STMT: &str = "
+            INSERT INTO webhooks
+                (id, community, room, user_id, name, token_hash, hash_scheme_version)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &webhook.id.0,
+            &webhook.community.0,
+            &webhook.room.0,
+            &webhook.user.0,
+            &webhook.name,
+            &webhook.token_hash,
+            &(webhook.hash_scheme_version as i16),
+        ];
+
+        conn.client.execute(&stmt, args).await?;
+        Ok(())
+    }
+
+    pub async fn get_webhook(&self, id: WebhookId) -> DbResult<Option<WebhookRecord>> {
+        const QUERY: &str = "SELECT * FROM webhooks WHERE id = $1";
+
+        let opt = self.query_opt(QUERY, &[&id.0]).await?;
+        if let Some(row) = opt {
+            Ok(Some(WebhookRecord::try_from(row)?)) // Can't opt::map because of ?
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn get_webhooks_in_community(
+        &self,
+        community: CommunityId,
+    ) -> DbResult<impl Stream<Item = DbResult<WebhookRecord>>> {
+        const QUERY: &str = "SELECT * FROM webhooks WHERE community = $1";
+
+        let stream = self.query_stream(QUERY, &[&community.0]).await?;
+        let stream = stream
+            .and_then(|row| async move { Ok(WebhookRecord::try_from(row)?) })
+            .map_err(|e| e.into());
+
+        Ok(stream)
+    }
+
+    /// Deletes the webhook. Its user is kept so that the messages it sent are not lost.
+    pub async fn delete_webhook(
+        &self,
+        community: CommunityId,
+        id: WebhookId,
+    ) -> DbResult<Result<(), NonexistentWebhook>> {
+        const STMT: &str = "DELETE FROM webhooks WHERE community = $1 AND id = $2";
+
+        let conn = self.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let res = conn.client.execute(&stmt, &[&community.0, &id.0]).await?;
+
+        Ok(if res == 1 {
+            Ok(())
+        } else {
+            Err(NonexistentWebhook)
+        })
+    }
+}
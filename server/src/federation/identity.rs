@@ -0,0 +1,60 @@
+//! This server's identity in the federation: an Ed25519 keypair which signs every request it makes
+//! to other servers. The secret key is kept in `identity.key` in the config directory, and is
+//! generated the first time that the server starts with federation enabled.
+
+use std::fs;
+use std::io::{self, ErrorKind};
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer};
+use rand::rngs::OsRng;
+
+use crate::config;
+
+pub struct ServerIdentity {
+    keypair: Keypair,
+}
+
+impl ServerIdentity {
+    pub fn load_or_generate() -> io::Result<ServerIdentity> {
+        let path = config::config_dir().join("identity.key");
+
+        match fs::read_to_string(&path) {
+            Ok(encoded) => {
+                let bytes = base64::decode(encoded.trim()).map_err(invalid_key)?;
+                let secret = SecretKey::from_bytes(&bytes).map_err(invalid_key)?;
+                let public = PublicKey::from(&secret);
+
+                Ok(ServerIdentity {
+                    keypair: Keypair { secret, public },
+                })
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let keypair = Keypair::generate(&mut OsRng);
+                fs::create_dir_all(config::config_dir())?;
+                fs::write(&path, base64::encode(keypair.secret.as_bytes()))?;
+
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+                }
+
+                log::info!("Generated new server identity key at {}", path.to_string_lossy());
+                Ok(ServerIdentity { keypair })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.keypair.public
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.keypair.sign(message)
+    }
+}
+
+fn invalid_key<E>(_: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "invalid identity.key")
+}
@@ -0,0 +1,271 @@
+//! Handlers for requests from other servers. The origin of each request has already been verified.
+
+use vertex::prelude::*;
+
+use super::{CommunityRequest, FederatedEvent, Federation, JoinRequest, MessageRequest};
+use crate::client;
+use crate::community::{self, GetRoomInfo, JoinRemote};
+use crate::database::AddToCommunityError;
+use crate::{auth, handle_disconnected, metrics, IdentifiedMessage};
+
+/// A user of the origin server joins a local community with an invite code
+pub async fn join(
+    federation: &Federation,
+    origin: String,
+    request: JoinRequest,
+) -> Result<CommunityStructure, Error> {
+    let user = request.user;
+    if user.server != origin {
+        return Err(Error::AccessDenied);
+    }
+
+    if request.invite_code.0.len() > 11 {
+        return Err(Error::InvalidInviteCode);
+    }
+
+    let db = &federation.global.database;
+    let claimed = match db.claim_invite_code(request.invite_code).await? {
+        Ok(Some(claimed)) => claimed,
+        Ok(None) | Err(_) => return Err(Error::InvalidInviteCode),
+    };
+
+    let community = claimed.community;
+    let res = async {
+        let local_user = db
+            .get_or_create_remote_user(&origin, user.id, &user.username, user.display_name)
+            .await?
+            .map_err(|_| Error::InvalidUser)?;
+        let structure = join_remote(community, local_user, origin).await?;
+        Ok::<_, Error>((local_user, structure))
+    };
+
+    match res.await {
+        Ok((local_user, structure)) => {
+            db.record_invite_use(claimed, local_user).await?;
+            Ok(structure)
+        }
+        Err(e) => {
+            db.release_invite_code(claimed).await?;
+            Err(e)
+        }
+    }
+}
+
+async fn join_remote(
+    community: CommunityId,
+    user: UserId,
+    server: String,
+) -> Result<CommunityStructure, Error> {
+    let res = community::address_of(community)?
+        .send(JoinRemote { user, server })
+        .await
+        .map_err(handle_disconnected("Community"))??;
+
+    match res {
+        Ok(structure) => Ok(structure),
+        Err(AddToCommunityError::AlreadyInCommunity) => Err(Error::AlreadyInCommunity),
+        Err(AddToCommunityError::InvalidCommunity) => Err(Error::InvalidCommunity),
+        Err(AddToCommunityError::InvalidUser) => Err(Error::InvalidUser),
+    }
+}
+
+/// A user of the origin server sends a message to a local community
+pub async fn message(
+    federation: &Federation,
+    origin: String,
+    request: MessageRequest,
+) -> Result<MessageConfirmation, Error> {
+    let global = &federation.global;
+    let user = member(federation, &origin, request.user, request.message.to_community).await?;
+
+    // Remote users are keyed by their local stand-in, since their devices are not known here
+    let ratelimited = global
+        .ratelimiters
+        .load()
+        .check_general(DeviceId(user.0), false);
+
+    if ratelimited.is_err() {
+        metrics::record_ratelimited(false);
+        return Err(Error::RateLimited);
+    }
+
+    // Stand-ins never have a session here, so their server-wide mute is looked up instead
+    let record = global.database.get_user_by_id(user).await?;
+    if let Some(mute) = record.and_then(|record| record.muted) {
+        if mute.is_active() {
+            return Err(Error::Muted(mute));
+        }
+    }
+
+    let message = request.message;
+    let config = global.config();
+    let ciphertext_len = message.ciphertext.as_ref().map(Vec::len).unwrap_or(0);
+    if message.content.len() > config.max_message_len as usize
+        || ciphertext_len > config.max_ciphertext_len as usize
+    {
+        return Err(Error::MessageTooLong);
+    }
+
+    let community = community::address_of(message.to_community)?;
+    let message = IdentifiedMessage {
+        user,
+        device: request.device,
+        message,
+    };
+
+    community
+        .send(message)
+        .await
+        .map_err(handle_disconnected("Community"))?
+}
+
+/// A user of the origin server fetches the structure of a local community that they are in
+pub async fn community(
+    federation: &Federation,
+    origin: String,
+    request: CommunityRequest,
+) -> Result<CommunityStructure, Error> {
+    member(federation, &origin, request.user, request.community).await?;
+
+    let rooms = community::address_of(request.community)?
+        .send(GetRoomInfo)
+        .await
+        .map_err(handle_disconnected("Community"))?
+        .into_iter()
+        .map(|info| RoomStructure {
+            id: info.id,
+            name: info.name,
+            unread: false, // Read state is not federated yet
+            encrypted: info.encrypted,
+            settings: info.settings,
+        })
+        .collect();
+
+    let info = community::get(request.community)?;
+    Ok(CommunityStructure {
+        id: request.community,
+        name: info.name.clone(),
+        description: info.description(),
+        rooms,
+        public: info.public,
+    })
+}
+
+/// The origin server sends an event from a community that it hosts to its members on this server
+pub async fn event(
+    federation: &Federation,
+    origin: String,
+    event: FederatedEvent,
+) -> Result<(), Error> {
+    let db = &federation.global.database;
+
+    match event {
+        FederatedEvent::AddMessage {
+            community,
+            room,
+            id,
+            author,
+            time_sent,
+            content,
+            ciphertext,
+            author_display_name,
+            except_device,
+        } => {
+            let members = db.get_remote_community_members(&origin, community).await?;
+            if members.is_empty() {
+                return Err(Error::InvalidCommunity);
+            }
+
+            let own_name = federation.global.config().server_name.clone();
+            let author = if author.server == own_name {
+                // The origin may only speak for our users who are actually in the community
+                if !members.contains(&author.id) {
+                    return Err(Error::InvalidUser);
+                }
+
+                author.id
+            } else if author.server == origin {
+                db.get_or_create_remote_user(
+                    &origin,
+                    author.id,
+                    &author.username,
+                    author.display_name,
+                )
+                .await?
+                .map_err(|_| Error::InvalidUser)?
+            } else {
+                // Nor may it speak for users of other servers, since it could make up anything
+                // about them
+                return Err(Error::InvalidUser);
+            };
+
+            let config = federation.global.config();
+            if let Some(name) = &author_display_name {
+                if !auth::valid_display_name(name, &config) {
+                    return Err(Error::InvalidDisplayName);
+                }
+            }
+
+            let author_profile_version = db
+                .get_user_profile(author)
+                .await?
+                .map(|profile| profile.version)
+                .unwrap_or(ProfileVersion(0));
+
+            let message = Message {
+                id,
+                author,
+                author_profile_version,
+                time_sent,
+                content,
+                ciphertext,
+                author_display_name,
+            };
+
+            let send = ServerMessage::Event(ServerEvent::AddMessage {
+                community,
+                room,
+                message,
+            });
+
+            for member in members {
+                let user = match client::session::get_active_user(member) {
+                    Ok(user) => user,
+                    Err(_) => continue, // Not online
+                };
+
+                let sessions = user
+                    .sessions
+                    .iter()
+                    .filter(|(device, _)| Some(**device) != except_device)
+                    .filter_map(|(_, session)| session.as_active_actor());
+
+                for session in sessions {
+                    let _ = session.send(send.clone());
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Looks up the local stand-in for a user of the origin server, checking that they are a member of
+/// the community
+async fn member(
+    federation: &Federation,
+    origin: &str,
+    remote_id: UserId,
+    community: CommunityId,
+) -> Result<UserId, Error> {
+    let db = &federation.global.database;
+    let user = db
+        .get_remote_user_by_remote_id(origin, remote_id)
+        .await?
+        .ok_or(Error::InvalidUser)?;
+
+    match db.get_community_membership(community, user).await? {
+        Some(_) => Ok(user),
+        None => Err(Error::InvalidCommunity),
+    }
+}
@@ -0,0 +1,352 @@
+//! Federation between Vertex servers. A user can join a community hosted on another server with an
+//! invite code qualified by that server's name (`code@server`). Their home server joins on their
+//! behalf, and from then on proxies the messages that they send in the community to the host. The
+//! host fans out every message sent in the community to the servers which have members in it, and
+//! those servers forward it on to their users.
+//!
+//! On the host, remote users are represented by local stand-in users named `user@server`, so that
+//! they can be members of communities and author messages like any other user. Likewise, authors
+//! from the host are represented by stand-ins on the receiving server. A server only speaks for
+//! its own users, so messages from users of a third server are not yet passed on.
+//!
+//! Only joining, sending messages, and receiving messages are federated so far. Other requests
+//! about remote communities (e.g editing messages or fetching history) are not yet supported.
+//!
+//! To try it out on one machine, run two servers with different `VERTEX_SERVER_CONFIG_DIR`s. Each
+//! needs its own database in `db.conf`, and a config with `federation = true`, `https = false`,
+//! `federation_https = false`, `federation_private_addresses = true`, and a distinct `ip` and
+//! matching `server_name` (e.g `localhost:8081` and `localhost:8082`).
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, Utc};
+use futures::future;
+use http::StatusCode;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use warp::Reply;
+
+use vertex::prelude::*;
+
+use crate::database::DbResult;
+use crate::Global;
+pub use identity::ServerIdentity;
+use transport::{ErrorResponse, FederationError, KeyResponse};
+pub use transport::{SignedRequest, DATE_HEADER, ORIGIN_HEADER, SIGNATURE_HEADER};
+
+mod identity;
+mod inbound;
+mod transport;
+
+lazy_static! {
+    /// Set at startup if federation is enabled
+    static ref FEDERATION: ArcSwapOption<Federation> = ArcSwapOption::empty();
+}
+
+pub struct Federation {
+    global: Global,
+    identity: ServerIdentity,
+}
+
+/// Enables federation, loading or generating this server's identity key
+pub fn enable(global: Global) -> std::io::Result<()> {
+    let identity = ServerIdentity::load_or_generate()?;
+    info!(
+        "Federating as {} with public key {}",
+        global.config().server_name,
+        base64::encode(identity.public_key().as_bytes())
+    );
+
+    FEDERATION.store(Some(Arc::new(Federation { global, identity })));
+    Ok(())
+}
+
+fn federation() -> Result<Arc<Federation>, FederationError> {
+    FEDERATION.load_full().ok_or(FederationError::Disabled)
+}
+
+/// A user as identified between servers, by their ID on their home server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FederatedUser {
+    pub id: UserId,
+    pub server: String,
+    pub username: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JoinRequest {
+    pub user: FederatedUser,
+    pub invite_code: InviteCode,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MessageRequest {
+    /// The author's ID on the origin server
+    pub user: UserId,
+    /// The device that the message was sent from, so that it is not echoed back to it
+    pub device: DeviceId,
+    pub message: ClientSentMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommunityRequest {
+    /// The member's ID on the origin server
+    pub user: UserId,
+    pub community: CommunityId,
+}
+
+/// An event in a community, sent by its host to the servers with members in it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FederatedEvent {
+    AddMessage {
+        community: CommunityId,
+        room: RoomId,
+        id: MessageId,
+        author: FederatedUser,
+        time_sent: DateTime<Utc>,
+        /// Unset for messages in encrypted rooms
+        content: Option<String>,
+        #[serde(default)]
+        ciphertext: Option<Vec<u8>>,
+        /// Shown instead of the author's display name for this message only
+        #[serde(default)]
+        author_display_name: Option<String>,
+        except_device: Option<DeviceId>,
+    },
+}
+
+/// Replies to a request for this server's public key
+pub fn key() -> Box<dyn Reply> {
+    match federation() {
+        Ok(federation) => Box::new(warp::reply::json(&KeyResponse {
+            server: federation.global.config().server_name.clone(),
+            public_key: base64::encode(federation.identity.public_key().as_bytes()),
+        })),
+        Err(_) => Box::new(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Handles a signed request from another server to the given endpoint
+pub async fn handle(
+    endpoint: String,
+    request: SignedRequest,
+    body: bytes::Bytes,
+) -> Box<dyn Reply> {
+    let federation = match federation() {
+        Ok(federation) => federation,
+        Err(_) => return Box::new(StatusCode::NOT_FOUND),
+    };
+
+    if let Err(e) = transport::verify(&federation, &request, &body).await {
+        warn!("Rejected federated request from {}: {:?}", request.origin, e);
+        return match e {
+            FederationError::NotAllowed => Box::new(StatusCode::FORBIDDEN),
+            FederationError::RateLimited => Box::new(StatusCode::TOO_MANY_REQUESTS),
+            _ => Box::new(StatusCode::UNAUTHORIZED),
+        };
+    }
+
+    let res = dispatch(&federation, &endpoint, request.origin, &body).await;
+    match res {
+        Ok(reply) => reply,
+        Err(error) => {
+            let status = match error {
+                Error::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+                Error::AccessDenied => StatusCode::FORBIDDEN,
+                Error::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+                _ => StatusCode::BAD_REQUEST,
+            };
+
+            let reply = warp::reply::json(&ErrorResponse { error });
+            Box::new(warp::reply::with_status(reply, status))
+        }
+    }
+}
+
+async fn dispatch(
+    federation: &Federation,
+    endpoint: &str,
+    origin: String,
+    body: &[u8],
+) -> Result<Box<dyn Reply>, Error> {
+    Ok(match endpoint {
+        "join" => reply_json(inbound::join(federation, origin, parse(body)?).await?),
+        "message" => reply_json(inbound::message(federation, origin, parse(body)?).await?),
+        "community" => reply_json(inbound::community(federation, origin, parse(body)?).await?),
+        "event" => reply_json(inbound::event(federation, origin, parse(body)?).await?),
+        _ => Box::new(StatusCode::NOT_FOUND),
+    })
+}
+
+fn parse<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T, Error> {
+    serde_json::from_slice(body).map_err(|_| Error::InvalidMessage)
+}
+
+fn reply_json<T: Serialize>(res: T) -> Box<dyn Reply> {
+    Box::new(warp::reply::json(&res))
+}
+
+/// Joins a community on another server on behalf of a local user
+pub async fn join(
+    global: &Global,
+    user: UserId,
+    server: &str,
+    invite_code: InviteCode,
+) -> Result<CommunityStructure, Error> {
+    let federation = federation().map_err(|_| Error::InvalidInviteCode)?;
+    let record = global
+        .database
+        .get_user_by_id(user)
+        .await?
+        .ok_or(Error::InvalidUser)?;
+
+    let request = JoinRequest {
+        user: FederatedUser {
+            id: user,
+            server: global.config().server_name.clone(),
+            username: record.username,
+            display_name: record.display_name,
+        },
+        invite_code,
+    };
+
+    let structure: CommunityStructure =
+        transport::post(&federation, server, "join", &request).await?;
+
+    global
+        .database
+        .add_remote_community_membership(user, server, structure.id)
+        .await?;
+
+    Ok(structure)
+}
+
+/// Sends a message to a community on another server on behalf of a local user
+pub async fn send_message(
+    user: UserId,
+    device: DeviceId,
+    server: &str,
+    message: ClientSentMessage,
+) -> Result<MessageConfirmation, Error> {
+    let federation = federation()?;
+    let request = MessageRequest {
+        user,
+        device,
+        message,
+    };
+
+    Ok(transport::post(&federation, server, "message", &request).await?)
+}
+
+/// Fetches the structures of all the communities on other servers that the user is a member of.
+/// Communities whose servers cannot be reached are left out.
+pub async fn remote_communities(
+    global: &Global,
+    user: UserId,
+) -> DbResult<Vec<CommunityStructure>> {
+    let federation = match federation() {
+        Ok(federation) => federation,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let memberships = global.database.get_remote_communities(user).await?;
+    let requests = memberships.iter().map(|(server, community)| {
+        let request = CommunityRequest {
+            user,
+            community: *community,
+        };
+        let federation = &federation;
+
+        async move {
+            let res: Result<CommunityStructure, _> =
+                transport::post(federation, server, "community", &request).await;
+            if let Err(e) = &res {
+                warn!("Error fetching community from {}: {:?}", server, e);
+            }
+            res.ok()
+        }
+    });
+
+    Ok(future::join_all(requests).await.into_iter().flatten().collect())
+}
+
+/// Sends a message sent in a local community to the other servers with members in it. This does
+/// not block on delivery.
+pub fn fan_out_message(
+    servers: Vec<String>,
+    community: CommunityId,
+    room: RoomId,
+    message: Message,
+    from_device: DeviceId,
+) {
+    let federation = match federation() {
+        Ok(federation) => federation,
+        Err(_) => return,
+    };
+
+    tokio::spawn(async move {
+        let author = match federated_user(&federation, message.author).await {
+            Ok(Some(author)) => author,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Error looking up author of federated message: {:?}", e);
+                return;
+            }
+        };
+
+        let event = FederatedEvent::AddMessage {
+            community,
+            room,
+            id: message.id,
+            author,
+            time_sent: message.time_sent,
+            content: message.content,
+            ciphertext: message.ciphertext,
+            author_display_name: message.author_display_name,
+            except_device: Some(from_device),
+        };
+
+        let deliveries = servers.iter().map(|server| {
+            let (federation, event) = (&federation, &event);
+            async move {
+                let res: Result<(), _> = transport::post(federation, server, "event", event).await;
+                if let Err(e) = res {
+                    warn!("Error delivering federated event to {}: {:?}", server, e);
+                }
+            }
+        });
+
+        future::join_all(deliveries).await;
+    });
+}
+
+/// Identifies a local user to other servers. If the user is a stand-in for a user of another
+/// server, they are identified as that user.
+async fn federated_user(federation: &Federation, user: UserId) -> DbResult<Option<FederatedUser>> {
+    let db = &federation.global.database;
+    let record = match db.get_user_by_id(user).await? {
+        Some(record) => record,
+        None => return Ok(None),
+    };
+
+    let user = match db.get_remote_user(user).await? {
+        Some(remote) => FederatedUser {
+            id: remote.remote_id,
+            username: Qualified::from(record.username.as_str()).name,
+            server: remote.server,
+            display_name: record.display_name,
+        },
+        None => FederatedUser {
+            id: user,
+            server: federation.global.config().server_name.clone(),
+            username: record.username,
+            display_name: record.display_name,
+        },
+    };
+
+    Ok(Some(user))
+}
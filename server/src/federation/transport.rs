@@ -0,0 +1,347 @@
+//! The server-to-server transport. Requests are JSON POSTs to `/vertex/federation/v1/<endpoint>`
+//! on the destination server, signed by the origin server's identity key. The signature covers the
+//! origin, destination, path, date and body, so that a request cannot be replayed to another
+//! server or endpoint, or replayed at all once its date is too old.
+//!
+//! A server's public key is fetched from its `/vertex/federation/v1/key` endpoint the first time it
+//! is seen, and is trusted from then on. Fetching keys is ratelimited by the address of the
+//! request that needs the key, since anyone can claim to be a server we haven't seen before.
+//!
+//! Server names come from users and other servers, so before any server is contacted, its name
+//! must be a plain `host` or `host:port`, it must be on the allowlist if there is one, and it
+//! must resolve to public addresses only (see `net`). Otherwise, they could be used to make this
+//! server send requests to e.g the database or other hosts on its private network.
+
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use http::StatusCode;
+use hyper::{Body, Request};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use vertex::prelude::*;
+
+use super::Federation;
+use crate::database::DatabaseError;
+use crate::net::{self, PublicClient};
+
+pub const ORIGIN_HEADER: &str = "x-vertex-origin";
+pub const DATE_HEADER: &str = "x-vertex-date";
+pub const SIGNATURE_HEADER: &str = "x-vertex-signature";
+
+const PATH_PREFIX: &str = "/vertex/federation/v1/";
+const MAX_CLOCK_SKEW_SECS: i64 = 5 * 60;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The body of the response to a key request
+#[derive(Serialize, Deserialize)]
+pub struct KeyResponse {
+    pub server: String,
+    /// The base64 encoded Ed25519 public key
+    pub public_key: String,
+}
+
+/// The body of an error response from another server
+#[derive(Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: Error,
+}
+
+/// The headers of an incoming signed request
+pub struct SignedRequest {
+    pub path: String,
+    pub origin: String,
+    pub date: i64,
+    pub signature: String,
+    /// The address that the request came from
+    pub addr: Option<SocketAddr>,
+}
+
+#[derive(Debug)]
+pub enum FederationError {
+    /// Federation is not enabled on this server
+    Disabled,
+    Unreachable(String),
+    /// The other server handled the request, but returned an error
+    Rejected(Error),
+    InvalidResponse,
+    /// The request was not validly signed by the server it claims to be from
+    BadSignature,
+    /// This server may not federate with the other server
+    NotAllowed,
+    /// Too many keys have been fetched for requests from the same address recently
+    RateLimited,
+    Database(DatabaseError),
+}
+
+impl From<DatabaseError> for FederationError {
+    fn from(e: DatabaseError) -> Self {
+        FederationError::Database(e)
+    }
+}
+
+impl From<FederationError> for Error {
+    fn from(e: FederationError) -> Error {
+        match e {
+            FederationError::Disabled => Error::Unimplemented,
+            FederationError::Rejected(error) => error,
+            FederationError::Database(e) => e.into(),
+            FederationError::RateLimited => Error::RateLimited,
+            e => {
+                log::warn!("Error making federated request: {:?}", e);
+                Error::RemoteServer
+            }
+        }
+    }
+}
+
+fn url(federation: &Federation, server: &str, endpoint: &str) -> String {
+    let scheme = if federation.global.config().federation_https {
+        "https"
+    } else {
+        "http"
+    };
+
+    format!("{}://{}{}{}", scheme, server, PATH_PREFIX, endpoint)
+}
+
+/// Whether the server name is a plain `host` or `host:port`, so that nothing else can be smuggled
+/// into the URLs that are built from it
+fn valid_server_name(server: &str) -> bool {
+    let (host, port) = match server.rfind(':') {
+        Some(idx) => (&server[..idx], Some(&server[idx + 1..])),
+        None => (server, None),
+    };
+
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+
+    let valid_port = match port {
+        Some(port) => u16::from_str(port).map(|port| port != 0).unwrap_or(false),
+        None => true,
+    };
+
+    host.len() <= 253 && host.split('.').all(valid_label) && valid_port
+}
+
+/// Whether this server may federate with the other server at all
+fn allowed(federation: &Federation, server: &str) -> bool {
+    let config = federation.global.config();
+    let allowlist = &config.federation_allowlist;
+
+    valid_server_name(server)
+        && server != config.server_name
+        && (allowlist.is_empty() || allowlist.iter().any(|allowed| allowed == server))
+}
+
+/// Checks that the other server may be contacted. Whether it is on the public internet is checked
+/// by the client when it connects.
+fn check_destination(federation: &Federation, server: &str) -> Result<(), FederationError> {
+    if allowed(federation, server) {
+        Ok(())
+    } else {
+        Err(FederationError::NotAllowed)
+    }
+}
+
+fn client(federation: &Federation) -> &'static PublicClient {
+    net::client(federation.global.config().federation_private_addresses)
+}
+
+/// The bytes that are signed for a request
+fn signed_bytes(origin: &str, destination: &str, path: &str, date: i64, body: &[u8]) -> Vec<u8> {
+    let mut bytes = format!("{}\n{}\n{}\n{}\n", origin, destination, path, date).into_bytes();
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// Sends a signed request to the given endpoint on another server
+pub async fn post<T, R>(
+    federation: &Federation,
+    server: &str,
+    endpoint: &str,
+    body: &T,
+) -> Result<R, FederationError>
+where
+    T: Serialize,
+    R: DeserializeOwned,
+{
+    check_destination(federation, server)?;
+
+    let body = serde_json::to_vec(body).expect("Error serializing federated request");
+    let origin = federation.global.config().server_name.clone();
+    let path = format!("{}{}", PATH_PREFIX, endpoint);
+    let date = Utc::now().timestamp();
+
+    let signature = federation
+        .identity
+        .sign(&signed_bytes(&origin, server, &path, date, &body));
+
+    let request = Request::post(url(federation, server, endpoint))
+        .header("Content-Type", "application/json")
+        .header(ORIGIN_HEADER, origin)
+        .header(DATE_HEADER, date)
+        .header(SIGNATURE_HEADER, base64::encode(&signature.to_bytes()[..]))
+        .body(Body::from(body))
+        .map_err(|e| FederationError::Unreachable(e.to_string()))?;
+
+    let sending = client(federation).request(request);
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, sending).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => return Err(FederationError::Unreachable(e.to_string())),
+        Err(_) => return Err(FederationError::Unreachable("request timed out".to_string())),
+    };
+
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| FederationError::Unreachable(e.to_string()))?;
+
+    if status.is_success() {
+        serde_json::from_slice(&bytes).map_err(|_| FederationError::InvalidResponse)
+    } else {
+        match serde_json::from_slice::<ErrorResponse>(&bytes) {
+            Ok(res) => Err(FederationError::Rejected(res.error)),
+            Err(_) => Err(FederationError::InvalidResponse),
+        }
+    }
+}
+
+/// Fetches the server's public key, without trusting any previously seen key
+async fn fetch_key(federation: &Federation, server: &str) -> Result<PublicKey, FederationError> {
+    check_destination(federation, server)?;
+
+    let uri = url(federation, server, "key")
+        .parse()
+        .map_err(|_| FederationError::Unreachable(format!("invalid server name {}", server)))?;
+
+    let fetching = client(federation).get(uri);
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, fetching).await {
+        Ok(Ok(response)) if response.status() == StatusCode::OK => response,
+        Ok(Ok(_)) => return Err(FederationError::InvalidResponse),
+        Ok(Err(e)) => return Err(FederationError::Unreachable(e.to_string())),
+        Err(_) => return Err(FederationError::Unreachable("request timed out".to_string())),
+    };
+
+    let bytes = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| FederationError::Unreachable(e.to_string()))?;
+    let key: KeyResponse =
+        serde_json::from_slice(&bytes).map_err(|_| FederationError::InvalidResponse)?;
+
+    if key.server != server {
+        return Err(FederationError::InvalidResponse);
+    }
+
+    let bytes = base64::decode(&key.public_key).map_err(|_| FederationError::InvalidResponse)?;
+    PublicKey::from_bytes(&bytes).map_err(|_| FederationError::InvalidResponse)
+}
+
+/// Gets the server's public key, fetching and remembering it if the server has not been seen before.
+/// Fetches count against the quota of the address that the request needing the key came from.
+async fn server_key(
+    federation: &Federation,
+    server: &str,
+    addr: Option<SocketAddr>,
+) -> Result<PublicKey, FederationError> {
+    let db = &federation.global.database;
+    if let Some(bytes) = db.get_server_key(server).await? {
+        return PublicKey::from_bytes(&bytes).map_err(|_| FederationError::BadSignature);
+    }
+
+    let may_fetch = federation
+        .global
+        .ratelimiters
+        .load()
+        .check_federation_key_fetch(addr);
+
+    if !may_fetch {
+        return Err(FederationError::RateLimited);
+    }
+
+    let key = fetch_key(federation, server).await?;
+    db.add_server_key(server, key.as_bytes()).await?;
+
+    // Another request may have stored a key first, in which case that one is trusted
+    match db.get_server_key(server).await? {
+        Some(bytes) => PublicKey::from_bytes(&bytes).map_err(|_| FederationError::BadSignature),
+        None => Ok(key),
+    }
+}
+
+/// Verifies that the request was signed by the server it claims to be from
+pub async fn verify(
+    federation: &Federation,
+    request: &SignedRequest,
+    body: &[u8],
+) -> Result<(), FederationError> {
+    let own_name = federation.global.config().server_name.clone();
+
+    if (Utc::now().timestamp() - request.date).abs() > MAX_CLOCK_SKEW_SECS {
+        return Err(FederationError::BadSignature);
+    }
+
+    if !allowed(federation, &request.origin) {
+        return Err(FederationError::NotAllowed);
+    }
+
+    let signature = base64::decode(&request.signature)
+        .ok()
+        .and_then(|bytes| Signature::try_from(&bytes[..]).ok())
+        .ok_or(FederationError::BadSignature)?;
+
+    let key = server_key(federation, &request.origin, request.addr).await?;
+    let signed = signed_bytes(&request.origin, &own_name, &request.path, request.date, body);
+
+    key.verify(&signed, &signature)
+        .map_err(|_| FederationError::BadSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_bytes_layout() {
+        let bytes = signed_bytes("a.example", "b.example", "/vertex/federation/v1/x", 42, b"{}");
+        assert_eq!(bytes, b"a.example\nb.example\n/vertex/federation/v1/x\n42\n{}".to_vec());
+    }
+
+    #[test]
+    fn signed_bytes_separates_fields() {
+        let a = signed_bytes("a.example", "b.example", "/x", 1, b"");
+        let b = signed_bytes("a.exampleb", ".example", "/x", 1, b"");
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn valid_server_names() {
+        assert!(valid_server_name("example.com"));
+        assert!(valid_server_name("chat.example.com:8443"));
+        assert!(valid_server_name("localhost"));
+    }
+
+    #[test]
+    fn invalid_server_names() {
+        assert!(!valid_server_name(""));
+        assert!(!valid_server_name("example.com:0"));
+        assert!(!valid_server_name("example.com:65536"));
+        assert!(!valid_server_name("example.com:"));
+        assert!(!valid_server_name("-example.com"));
+        assert!(!valid_server_name("example..com"));
+        assert!(!valid_server_name("user@example.com"));
+        assert!(!valid_server_name("example.com/path"));
+        assert!(!valid_server_name("[::1]:443"));
+        assert!(!valid_server_name(&"a".repeat(64)));
+    }
+}
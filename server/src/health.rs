@@ -0,0 +1,77 @@
+//! Health and readiness endpoints for load balancers and orchestrators. `/healthz` reports that
+//! the process is up, while `/readyz` reports whether it should be sent traffic: communities must
+//! have been loaded, the database must be reachable, and the server must not be shutting down.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use http::StatusCode;
+use warp::{Filter, Rejection};
+
+use crate::Global;
+
+const DATABASE_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+static COMMUNITIES_LOADED: AtomicBool = AtomicBool::new(false);
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+struct NotReady;
+
+impl warp::reject::Reject for NotReady {}
+
+pub fn set_communities_loaded() {
+    COMMUNITIES_LOADED.store(true, Ordering::SeqCst);
+}
+
+pub fn set_shutting_down() {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+}
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+pub fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+pub async fn readyz(global: Global) -> StatusCode {
+    if !COMMUNITIES_LOADED.load(Ordering::SeqCst) || is_shutting_down() {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+
+    match tokio::time::timeout(DATABASE_CHECK_TIMEOUT, global.database.ping()).await {
+        Ok(Ok(())) => StatusCode::OK,
+        Ok(Err(e)) => {
+            log::warn!("Readiness check failed to reach the database: {:?}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        Err(_) => {
+            log::warn!("Readiness check timed out waiting for the database");
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+/// Rejects requests until communities have been loaded, or once the server is shutting down.
+/// `recover_not_ready` turns this rejection into a 503.
+pub fn when_ready() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(|| async {
+            if COMMUNITIES_LOADED.load(Ordering::SeqCst) && !is_shutting_down() {
+                Ok(())
+            } else {
+                Err(warp::reject::custom(NotReady))
+            }
+        })
+        .untuple_one()
+}
+
+pub async fn recover_not_ready(rejection: Rejection) -> Result<StatusCode, Rejection> {
+    if rejection.find::<NotReady>().is_some() {
+        Ok(StatusCode::SERVICE_UNAVAILABLE)
+    } else {
+        Err(rejection)
+    }
+}
@@ -1,17 +1,14 @@
 #![feature(type_ascription, type_alias_impl_trait)]
 
 use std::convert::Infallible;
-use std::num::NonZeroU32;
+use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use arc_swap::ArcSwap;
 use futures::StreamExt;
-use governor::clock::DefaultClock;
-use governor::state::keyed::DashMapStateStore;
-use governor::{Quota, RateLimiter};
-use log::{info, LevelFilter};
+use log::{error, info, warn, LevelFilter};
 use warp::reply::Reply;
 use warp::Filter;
 use xtra::prelude::*;
@@ -23,23 +20,42 @@ use vertex::prelude::*;
 
 use crate::client::Authenticator;
 use crate::community::{Community, CommunityActor};
-use crate::config::Config;
-use crate::database::{DbResult, MalformedInviteCode};
+use crate::config::{Config, ConfigError};
+use crate::database::{DbResult, MalformedInviteCode, MigrationMode};
+use crate::ratelimit::Ratelimiters;
 use clap::{App, Arg};
 use crate::client::session::WsMessage;
-use vertex::RATELIMIT_BURST_PER_MIN;
 
+mod admin_cli;
+mod api;
+mod archive;
 mod auth;
+mod automod;
 mod client;
 mod community;
 mod config;
 mod database;
+mod federation;
+mod health;
+mod metrics;
+mod net;
+mod outgoing_webhook;
+mod ratelimit;
+mod shutdown;
+mod webhook;
 
 #[derive(Clone)]
 pub struct Global {
     pub database: Database,
-    pub config: Arc<Config>,
-    pub ratelimiter: ArcSwap<RateLimiter<DeviceId, DashMapStateStore<DeviceId>, DefaultClock>>,
+    /// The current config. This is swapped out when the config is reloaded.
+    pub config: Arc<ArcSwap<Config>>,
+    pub ratelimiters: Arc<ArcSwap<Ratelimiters>>,
+}
+
+impl Global {
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
 }
 
 /// Marker trait for `vertex_common` structs that are actor messages too
@@ -55,6 +71,10 @@ impl VertexActorMessage for Edit {
     type Result = ();
 }
 
+impl VertexActorMessage for webhook::WebhookSentMessage {
+    type Result = MessageConfirmation;
+}
+
 struct IdentifiedMessage<T: VertexActorMessage> {
     user: UserId,
     device: DeviceId,
@@ -69,20 +89,59 @@ where
     type Result = Result<T::Result, Error>;
 }
 
-fn new_ratelimiter() -> RateLimiter<DeviceId, DashMapStateStore<DeviceId>, DefaultClock> {
-    RateLimiter::dashmap(Quota::per_minute(NonZeroU32::new(RATELIMIT_BURST_PER_MIN).unwrap()))
-}
-
-async fn refresh_ratelimiter(
-    rl: ArcSwap<RateLimiter<DeviceId, DashMapStateStore<DeviceId>, DefaultClock>>,
-) {
+async fn shrink_ratelimiters(global: Global) {
     use tokio::time::Instant;
     let duration = Duration::from_secs(60 * 60); // 1/hr
     let mut timer = tokio::time::interval_at(Instant::now() + duration, duration);
 
     loop {
         timer.tick().await;
-        rl.store(Arc::new(new_ratelimiter()));
+        global.ratelimiters.load().retain_recent();
+    }
+}
+
+/// Reloads the config file, applying it to the running server if it is valid
+pub fn reload_config(global: &Global) -> Result<(), ConfigError> {
+    let new = match config::read_config() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Error reloading config, keeping the current config: {}", e);
+            return Err(e);
+        }
+    };
+    let old = global.config();
+
+    if Ratelimiters::quotas_changed(&old, &new) {
+        global.ratelimiters.store(Arc::new(Ratelimiters::new(&new)));
+    }
+
+    // These are only read at startup
+    let needs_restart = new.ip != old.ip
+        || new.https != old.https
+        || new.metrics_ip != old.metrics_ip
+        || new.federation != old.federation
+        || new.log_level != old.log_level;
+
+    if needs_restart {
+        warn!(
+            "The addresses, federation, and log level of the reloaded config will only take \
+            effect after a restart"
+        );
+    }
+
+    global.config.store(Arc::new(new));
+    info!("Config reloaded");
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn reload_config_on_sighup(global: Global) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup()).expect("Error listening for SIGHUP");
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading config...");
+        let _ = reload_config(&global);
     }
 }
 
@@ -96,8 +155,9 @@ fn handle_disconnected(actor_name: &'static str) -> impl Fn(Disconnected) -> Err
     }
 }
 
-async fn load_communities(db: Database) {
-    let stream = db
+async fn load_communities(global: Global) {
+    let stream = global
+        .database
         .get_all_communities()
         .await
         .expect("Error loading communities");
@@ -105,7 +165,7 @@ async fn load_communities(db: Database) {
 
     while let Some(res) = stream.next().await {
         let community_record = res.expect("Error loading community");
-        CommunityActor::load_and_spawn(community_record, db.clone())
+        CommunityActor::load_and_spawn(community_record, global.clone())
             .await
             .expect("Error loading community!");
     }
@@ -133,6 +193,17 @@ async fn main() {
                 .help("Removes a user as admin")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("migrate-only")
+                .long("migrate-only")
+                .help("Applies any pending database migrations and then exits"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Lists pending database migrations without applying them, and then exits"),
+        )
+        .subcommands(admin_cli::subcommands())
         .get_matches();
 
     println!("Vertex server starting...");
@@ -145,39 +216,59 @@ async fn main() {
 
     let (cert_path, key_path) = config::ssl_config();
     let database = Database::new().await.expect("Error in database setup");
-    tokio::spawn(database.clone().sweep_tokens_loop(
-        config.token_expiry_days,
-        Duration::from_secs(config.tokens_sweep_interval_secs),
-    ));
-    tokio::spawn(
-        database
-            .clone()
-            .sweep_invite_codes_loop(Duration::from_secs(config.invite_codes_sweep_interval_secs)),
-    );
+
+    migrate(&args, &database).await;
+    if args.is_present("migrate-only") || args.is_present("dry-run") {
+        return;
+    }
+
+    if let (name, Some(subcommand)) = args.subcommand() {
+        admin_cli::run(name, subcommand, &database, &config).await;
+        return;
+    }
 
     promote_and_demote(args, &database).await;
 
-    load_communities(database.clone()).await;
+    if let Some(addr) = config.metrics_ip {
+        info!("Serving metrics on {}", addr);
+        tokio::spawn(metrics::serve(addr));
+    }
 
+    let ratelimiters = Ratelimiters::new(&config);
     let config = Arc::new(config);
     let global = Global {
         database,
-        config: config.clone(),
-        ratelimiter: ArcSwap::from_pointee(new_ratelimiter()),
+        config: Arc::new(ArcSwap::new(config.clone())),
+        ratelimiters: Arc::new(ArcSwap::from_pointee(ratelimiters)),
     };
 
-    tokio::spawn(refresh_ratelimiter(global.ratelimiter.clone()));
+    if config.federation {
+        federation::enable(global.clone())
+            .unwrap_or_else(|e| panic_error!("Error loading server identity key: {}", e));
+    }
 
+    // The sweepers read the config before each sweep, so that reloading it applies to them
+    let database = &global.database;
+    shutdown::spawn_background(database.clone().sweep_tokens_loop(global.config.clone()));
+    shutdown::spawn_background(database.clone().sweep_invite_codes_loop(global.config.clone()));
+    shutdown::spawn_background(database.clone().sweep_messages_loop(global.config.clone()));
+    shutdown::spawn_background(database.clone().sweep_restrictions_loop(global.config.clone()));
+    shutdown::spawn_background(shrink_ratelimiters(global.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload_config_on_sighup(global.clone()));
+
+    let communities_global = global.clone();
     let global = warp::any().map(move || global.clone());
 
     let authenticate = warp::path("authenticate")
         .and(global.clone())
+        .and(warp::addr::remote())
         .and(warp::query())
         .and(warp::ws())
         .and_then(
-            |global: Global, authenticate, ws: warp::ws::Ws| async move {
+            |global: Global, addr, authenticate, ws: warp::ws::Ws| async move {
                 let response: Box<dyn warp::Reply> =
-                    match self::login(global.clone(), ws, authenticate).await {
+                    match self::login(global.clone(), addr, ws, authenticate).await {
                         Ok(response) => Box::new(response),
                         Err(e) => return reply_err(e),
                     };
@@ -187,42 +278,47 @@ async fn main() {
 
     let register = warp::path("register")
         .and(global.clone())
+        .and(warp::addr::remote())
         .and(warp::post())
         .and(warp::body::bytes())
-        .and_then(
-            |global, bytes| async move { reply_protobuf(self::register(global, bytes).await) },
-        );
+        .and_then(|global, addr, bytes| async move {
+            reply_protobuf(self::register(global, addr, bytes).await)
+        });
 
     let create_token = warp::path("create")
         .and(global.clone())
+        .and(warp::addr::remote())
         .and(warp::post())
         .and(warp::body::bytes())
-        .and_then(|global, bytes| async move {
-            reply_protobuf(self::create_token(global, bytes).await)
+        .and_then(|global, addr, bytes| async move {
+            reply_protobuf(self::create_token(global, addr, bytes).await)
         });
 
     let revoke_token = warp::path("revoke")
         .and(global.clone())
+        .and(warp::addr::remote())
         .and(warp::post())
         .and(warp::body::bytes())
-        .and_then(|global, bytes| async move {
-            reply_protobuf(self::revoke_token(global, bytes).await)
+        .and_then(|global, addr, bytes| async move {
+            reply_protobuf(self::revoke_token(global, addr, bytes).await)
         });
 
     let refresh_token = warp::path("refresh")
         .and(global.clone())
+        .and(warp::addr::remote())
         .and(warp::post())
         .and(warp::body::bytes())
-        .and_then(|global, bytes| async move {
-            reply_protobuf(self::refresh_token(global, bytes).await)
+        .and_then(|global, addr, bytes| async move {
+            reply_protobuf(self::refresh_token(global, addr, bytes).await)
         });
 
     let change_password = warp::path("change_password")
         .and(global.clone())
+        .and(warp::addr::remote())
         .and(warp::post())
         .and(warp::body::bytes())
-        .and_then(|global, bytes| async move {
-            reply_protobuf(self::change_password(global, bytes).await)
+        .and_then(|global, addr, bytes| async move {
+            reply_protobuf(self::change_password(global, addr, bytes).await)
         });
 
     let invite = warp::path!("invite" / String)
@@ -230,23 +326,137 @@ async fn main() {
         .and(global.clone())
         .and_then(|invite, global| self::invite_reply(global, invite));
 
+    let webhook = warp::path!("webhook" / uuid::Uuid / String)
+        .and(global.clone())
+        .and(warp::addr::remote())
+        .and(warp::post())
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and_then(|id, token, global, addr, message| async move {
+            let status = webhook::execute(global, addr, id, token, message).await;
+            Ok::<_, Infallible>(status)
+        });
+
+    let api = warp::path!("api" / "v1" / "request")
+        .and(global.clone())
+        .and(warp::addr::remote())
+        .and(warp::post())
+        .and(warp::header::<uuid::Uuid>("x-vertex-device"))
+        .and(warp::header::<String>("authorization"))
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and_then(|global, addr, device, authorization, request| async move {
+            let reply = api::request(global, addr, device, authorization, request).await;
+            Ok::<_, Infallible>(reply)
+        });
+
+    let federation_key = warp::path!("federation" / "v1" / "key")
+        .and(warp::get())
+        .map(federation::key);
+
+    let federation_request = warp::path!("federation" / "v1" / String)
+        .and(warp::post())
+        .and(warp::path::full())
+        .and(warp::header::<String>(federation::ORIGIN_HEADER))
+        .and(warp::header::<i64>(federation::DATE_HEADER))
+        .and(warp::header::<String>(federation::SIGNATURE_HEADER))
+        .and(warp::addr::remote())
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::bytes())
+        .and_then(
+            |endpoint, path: warp::path::FullPath, origin, date, signature, addr, body| async move {
+                let request = federation::SignedRequest {
+                    path: path.as_str().to_string(),
+                    origin,
+                    date,
+                    signature,
+                    addr,
+                };
+
+                Ok::<_, Infallible>(federation::handle(endpoint, request, body).await)
+            },
+        );
+
     let token = warp::path("token").and(create_token.or(revoke_token).or(refresh_token));
     let auth = authenticate.or(register.or(token.or(change_password)));
     let client = warp::path("client").and(auth);
-    let routes = invite.or(client);
-    let routes = warp::path("vertex").and(routes);
+    let federation = federation_key.or(federation_request);
+    let routes = invite.or(webhook).or(api).or(federation).or(client);
+    let routes = warp::path("vertex")
+        .and(health::when_ready())
+        .and(routes)
+        .recover(health::recover_not_ready);
+
+    let healthz = warp::path("healthz").map(health::healthz);
+    let readyz = warp::path("readyz")
+        .and(global.clone())
+        .and_then(|global| async move { Ok::<_, Infallible>(health::readyz(global).await) });
+    let routes = healthz.or(readyz).or(routes);
 
     info!("Vertex server starting on addr {}", config.ip);
 
-    if config.https {
-        warp::serve(routes)
+    let (stop_accepting, stop_accepting_rx) = futures::channel::oneshot::channel::<()>();
+    let stop_accepting_rx = async {
+        let _ = stop_accepting_rx.await;
+    };
+
+    let server = if config.https {
+        let (_, server) = warp::serve(routes)
             .tls()
             .cert_path(cert_path)
             .key_path(key_path)
-            .run(config.ip)
-            .await;
+            .bind_with_graceful_shutdown(config.ip, stop_accepting_rx);
+        tokio::spawn(server)
+    } else {
+        let (_, server) = warp::serve(routes)
+            .bind_with_graceful_shutdown(config.ip, stop_accepting_rx);
+        tokio::spawn(server)
+    };
+
+    // The server is started first so that it can report that it is not ready yet while loading
+    load_communities(communities_global).await;
+    health::set_communities_loaded();
+    info!("Vertex server ready");
+
+    shutdown::signal().await;
+
+    info!("Vertex server shutting down...");
+    health::set_shutting_down();
+    let _ = stop_accepting.send(());
+
+    shutdown::drain(server, Duration::from_secs(config.shutdown_timeout_secs)).await;
+    info!("Vertex server stopped");
+}
+
+async fn migrate(args: &clap::ArgMatches<'_>, database: &Database) {
+    let mode = if args.is_present("dry-run") {
+        MigrationMode::DryRun
     } else {
-        warp::serve(routes).run(config.ip).await;
+        MigrationMode::Apply
+    };
+
+    let report = database
+        .migrate(mode)
+        .await
+        .unwrap_or_else(|e| panic_error!("Error migrating database: {}", e));
+
+    match (mode, report.pending.last()) {
+        (_, None) => info!("Database schema is up to date at version {}", report.from),
+        (MigrationMode::DryRun, Some(_)) => {
+            info!(
+                "{} pending database migration(s) from version {}:",
+                report.pending.len(),
+                report.from
+            );
+
+            for migration in &report.pending {
+                info!("  {}: {}", migration.version, migration.name);
+            }
+        }
+        (MigrationMode::Apply, Some(latest)) => info!(
+            "Migrated database schema from version {} to {}",
+            report.from, latest.version
+        ),
     }
 }
 
@@ -301,22 +511,25 @@ fn reply_protobuf(res: AuthResponse) -> Result<Box<dyn warp::Reply>, Infallible>
 
 async fn login(
     global: Global,
+    addr: Option<SocketAddr>,
     ws: warp::ws::Ws,
     login: Login,
 ) -> Result<impl warp::Reply, AuthError> {
+    global.ratelimiters.load().check_auth(addr)?;
+
     let authenticator = Authenticator {
         global: global.clone(),
     };
 
     let details = authenticator.login(login.device, login.token).await?;
-    let (user, device, perms, hsv) = details;
+    let (user, device, perms, hsv, bot) = details;
 
     match client::session::insert(global.database.clone(), user, device, hsv).await? {
         Ok(_) => {
             let upgrade = ws.on_upgrade(move |websocket| {
                 let (sink, stream) = websocket.split();
 
-                let session = ActiveSession::new(sink, global, user, device, perms);
+                let session = ActiveSession::new(Some(sink), global, user, device, perms, bot);
                 session.clone().into_address().attach_stream(stream.map(WsMessage));
 
                 // if the session fails to spawn, that means it has since been removed. we can ignore the error.
@@ -331,7 +544,13 @@ async fn login(
     }
 }
 
-async fn register(global: Global, bytes: bytes::Bytes) -> AuthResponse {
+async fn register(
+    global: Global,
+    addr: Option<SocketAddr>,
+    bytes: bytes::Bytes,
+) -> AuthResponse {
+    global.ratelimiters.load().check_auth(addr)?;
+
     let register = match AuthRequest::from_protobuf_bytes(&bytes)? {
         AuthRequest::RegisterUser(register) => register,
         _ => return AuthResponse::Err(AuthError::WrongEndpoint),
@@ -343,10 +562,18 @@ async fn register(global: Global, bytes: bytes::Bytes) -> AuthResponse {
         .unwrap_or_else(|| credentials.username.clone());
 
     let authenticator = Authenticator { global };
-    authenticator.create_user(credentials, display_name).await
+    authenticator
+        .create_user(credentials, display_name, register.registration_token)
+        .await
 }
 
-async fn create_token(global: Global, bytes: bytes::Bytes) -> AuthResponse {
+async fn create_token(
+    global: Global,
+    addr: Option<SocketAddr>,
+    bytes: bytes::Bytes,
+) -> AuthResponse {
+    global.ratelimiters.load().check_auth(addr)?;
+
     let create_token = match AuthRequest::from_protobuf_bytes(&bytes)? {
         AuthRequest::CreateToken(create) => create,
         _ => return AuthResponse::Err(AuthError::WrongEndpoint),
@@ -358,7 +585,13 @@ async fn create_token(global: Global, bytes: bytes::Bytes) -> AuthResponse {
         .await
 }
 
-async fn refresh_token(global: Global, bytes: bytes::Bytes) -> AuthResponse {
+async fn refresh_token(
+    global: Global,
+    addr: Option<SocketAddr>,
+    bytes: bytes::Bytes,
+) -> AuthResponse {
+    global.ratelimiters.load().check_auth(addr)?;
+
     let refresh_token = match AuthRequest::from_protobuf_bytes(&bytes)? {
         AuthRequest::RefreshToken(refresh) => refresh,
         _ => return AuthResponse::Err(AuthError::WrongEndpoint),
@@ -370,7 +603,13 @@ async fn refresh_token(global: Global, bytes: bytes::Bytes) -> AuthResponse {
         .await
 }
 
-async fn revoke_token(global: Global, bytes: bytes::Bytes) -> AuthResponse {
+async fn revoke_token(
+    global: Global,
+    addr: Option<SocketAddr>,
+    bytes: bytes::Bytes,
+) -> AuthResponse {
+    global.ratelimiters.load().check_auth(addr)?;
+
     let revoke_token = match AuthRequest::from_protobuf_bytes(&bytes)? {
         AuthRequest::RevokeToken(revoke) => revoke,
         _ => return AuthResponse::Err(AuthError::WrongEndpoint),
@@ -382,7 +621,13 @@ async fn revoke_token(global: Global, bytes: bytes::Bytes) -> AuthResponse {
         .await
 }
 
-async fn change_password(global: Global, bytes: bytes::Bytes) -> AuthResponse {
+async fn change_password(
+    global: Global,
+    addr: Option<SocketAddr>,
+    bytes: bytes::Bytes,
+) -> AuthResponse {
+    global.ratelimiters.load().check_auth(addr)?;
+
     let change = match AuthRequest::from_protobuf_bytes(&bytes)? {
         AuthRequest::ChangePassword(change) => change,
         _ => return AuthResponse::Err(AuthError::WrongEndpoint),
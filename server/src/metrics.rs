@@ -0,0 +1,156 @@
+//! Prometheus metrics, served in the text exposition format on a separate bind address so that
+//! they are not exposed publicly alongside the client API.
+
+use std::net::SocketAddr;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Histogram, HistogramTimer, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+use warp::Filter;
+
+use vertex::prelude::*;
+
+use crate::client::session::USERS;
+use crate::community::COMMUNITIES;
+
+lazy_static! {
+    static ref ACTIVE_SESSIONS: IntGauge = register_int_gauge!(
+        "vertex_active_sessions",
+        "Number of sessions currently logged in"
+    )
+    .unwrap();
+    static ref LOADED_COMMUNITIES: IntGauge = register_int_gauge!(
+        "vertex_loaded_communities",
+        "Number of community actors currently loaded"
+    )
+    .unwrap();
+    pub static ref MESSAGES_SENT: IntCounter = register_int_counter!(
+        "vertex_messages_sent_total",
+        "Number of messages sent to rooms"
+    )
+    .unwrap();
+    static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "vertex_request_duration_seconds",
+        "Time taken to handle client requests, by request type",
+        &["request"]
+    )
+    .unwrap();
+    static ref RATELIMITED: IntCounterVec = register_int_counter_vec!(
+        "vertex_ratelimited_total",
+        "Number of messages rejected by the ratelimiter, by the kind of client that sent them",
+        &["client"]
+    )
+    .unwrap();
+    pub static ref DB_POOL_WAIT: Histogram = register_histogram!(
+        "vertex_db_pool_wait_seconds",
+        "Time spent waiting for a connection from the database pool"
+    )
+    .unwrap();
+    static ref SWEEP_DURATION: HistogramVec = register_histogram_vec!(
+        "vertex_sweep_duration_seconds",
+        "Time taken to sweep the database for expired items, by what was swept",
+        &["sweeper"],
+        vec![0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0]
+    )
+    .unwrap();
+}
+
+/// Serves the metrics endpoint at `/metrics` on the given address
+pub async fn serve(addr: SocketAddr) {
+    let metrics = warp::path("metrics").and(warp::get()).map(render);
+    warp::serve(metrics).run(addr).await;
+}
+
+fn render() -> impl warp::Reply {
+    // Gauges which can be read off the global state are only computed when scraped
+    let sessions: usize = USERS.iter().map(|user| user.sessions.len()).sum();
+    ACTIVE_SESSIONS.set(sessions as i64);
+    LOADED_COMMUNITIES.set(COMMUNITIES.len() as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buf) {
+        log::error!("Error encoding metrics: {:?}", e);
+    }
+
+    warp::http::Response::builder()
+        .header("Content-Type", encoder.format_type())
+        .body(buf)
+}
+
+/// Starts timing a request. The duration is recorded when the returned timer is dropped.
+pub fn time_request(request: &ClientRequest) -> HistogramTimer {
+    REQUEST_DURATION
+        .with_label_values(&[request_name(request)])
+        .start_timer()
+}
+
+/// Starts timing a sweep of the database. The duration is recorded when the returned timer is
+/// dropped.
+pub fn time_sweep(sweeper: &str) -> HistogramTimer {
+    SWEEP_DURATION.with_label_values(&[sweeper]).start_timer()
+}
+
+pub fn record_ratelimited(bot: bool) {
+    let client = if bot { "bot" } else { "user" };
+    RATELIMITED.with_label_values(&[client]).inc();
+}
+
+fn request_name(request: &ClientRequest) -> &'static str {
+    match request {
+        ClientRequest::LogOut => "log_out",
+        ClientRequest::SendMessage(_) => "send_message",
+        ClientRequest::EditMessage(_) => "edit_message",
+        ClientRequest::GetRoomUpdate { .. } => "get_room_update",
+        ClientRequest::GetMessages { .. } => "get_messages",
+        ClientRequest::SelectRoom { .. } => "select_room",
+        ClientRequest::DeselectRoom => "deselect_room",
+        ClientRequest::SetAsRead { .. } => "set_as_read",
+        ClientRequest::CreateCommunity { .. } => "create_community",
+        ClientRequest::CreateRoom { .. } => "create_room",
+        ClientRequest::CreateInvite { .. } => "create_invite",
+        ClientRequest::JoinCommunity(_) => "join_community",
+        ClientRequest::Delete(_) => "delete",
+        ClientRequest::ChangeUsername { .. } => "change_username",
+        ClientRequest::ChangeDisplayName { .. } => "change_display_name",
+        ClientRequest::GetProfile(_) => "get_profile",
+        ClientRequest::ChangeCommunityName { .. } => "change_community_name",
+        ClientRequest::ChangeCommunityDescription { .. } => "change_community_description",
+        ClientRequest::AdminAction(_) => "admin_action",
+        ClientRequest::Report { .. } => "report",
+        ClientRequest::CreateBot { .. } => "create_bot",
+        ClientRequest::CreateBotToken { .. } => "create_bot_token",
+        ClientRequest::InstallBot { .. } => "install_bot",
+        ClientRequest::CreateWebhook { .. } => "create_webhook",
+        ClientRequest::ListWebhooks { .. } => "list_webhooks",
+        ClientRequest::RevokeWebhook { .. } => "revoke_webhook",
+        ClientRequest::CreateOutgoingWebhook { .. } => "create_outgoing_webhook",
+        ClientRequest::ListOutgoingWebhooks { .. } => "list_outgoing_webhooks",
+        ClientRequest::DeleteOutgoingWebhook { .. } => "delete_outgoing_webhook",
+        ClientRequest::GetWebhookDeliveries { .. } => "get_webhook_deliveries",
+        ClientRequest::RegisterCommands { .. } => "register_commands",
+        ClientRequest::ListCommands { .. } => "list_commands",
+        ClientRequest::InvokeCommand { .. } => "invoke_command",
+        ClientRequest::PublishDeviceKeys { .. } => "publish_device_keys",
+        ClientRequest::GetDeviceKeys { .. } => "get_device_keys",
+        ClientRequest::ClaimDeviceKeys { .. } => "claim_device_keys",
+        ClientRequest::SetRetention { .. } => "set_retention",
+        ClientRequest::ExportCommunity { .. } => "export_community",
+        ClientRequest::ImportCommunity { .. } => "import_community",
+        ClientRequest::AddAutomodRule { .. } => "add_automod_rule",
+        ClientRequest::ListAutomodRules { .. } => "list_automod_rules",
+        ClientRequest::DeleteAutomodRule { .. } => "delete_automod_rule",
+        ClientRequest::GetAutomodHits { .. } => "get_automod_hits",
+        ClientRequest::SetRoomSettings { .. } => "set_room_settings",
+        ClientRequest::ListInvites { .. } => "list_invites",
+        ClientRequest::RevokeInvite { .. } => "revoke_invite",
+        ClientRequest::GetInviteUses { .. } => "get_invite_uses",
+        ClientRequest::SetCommunityPublic { .. } => "set_community_public",
+        ClientRequest::ListPublicCommunities { .. } => "list_public_communities",
+        ClientRequest::JoinPublicCommunity(_) => "join_public_community",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,212 @@
+//! An HTTP client for contacting hosts which are named by users or other servers, such as other
+//! servers in the federation and outgoing webhooks. Unless private addresses are allowed, it only
+//! connects to addresses on the public internet, so that it can't be used to make this server send
+//! requests to e.g the database or other hosts on its private network.
+//!
+//! The check is made by the connector itself, on the same addresses that it then connects to. If
+//! names were resolved and checked beforehand, they would be resolved again to connect, and a name
+//! could resolve to a public address the first time and a private one the second.
+
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future;
+use hyper::client::connect::dns::Name;
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::{Client, Uri};
+use hyper_tls::HttpsConnector;
+use lazy_static::lazy_static;
+use tokio::net::TcpStream;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub type PublicClient = Client<HttpsConnector<PublicConnector>>;
+
+lazy_static! {
+    static ref PUBLIC_CLIENT: PublicClient = build_client(false);
+    static ref ANY_CLIENT: PublicClient = build_client(true);
+}
+
+/// Returns the client to make requests with. Private addresses should only be allowed for
+/// testing, e.g to run more than one server on a machine.
+pub fn client(allow_private: bool) -> &'static PublicClient {
+    if allow_private {
+        &ANY_CLIENT
+    } else {
+        &PUBLIC_CLIENT
+    }
+}
+
+fn build_client(allow_private: bool) -> PublicClient {
+    let mut http = HttpConnector::new_with_resolver(PublicResolver { allow_private });
+    http.enforce_http(false);
+
+    let tls = native_tls::TlsConnector::new().expect("Error creating TLS connector");
+    let connector = PublicConnector {
+        http,
+        allow_private,
+    };
+
+    Client::builder().build(HttpsConnector::from((connector, tls.into())))
+}
+
+/// Whether the address is on the public internet, rather than e.g loopback or a private network
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            let shared = octets[0] == 100 && (octets[1] & 0xc0) == 64; // 100.64.0.0/10
+            let reserved = octets[0] >= 240; // 240.0.0.0/4, including broadcast
+
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || ip.is_unspecified()
+                || octets[0] == 0
+                || shared
+                || reserved)
+        }
+        IpAddr::V6(ip) => {
+            // IPv4 addresses can be embedded in IPv6 ones, e.g `::ffff:127.0.0.1`
+            if let Some(ip) = ip.to_ipv4() {
+                return is_public(IpAddr::V4(ip));
+            }
+
+            let segments = ip.segments();
+            let unique_local = (segments[0] & 0xfe00) == 0xfc00; // fc00::/7
+            let link_local = (segments[0] & 0xffc0) == 0xfe80; // fe80::/10
+            let documentation = segments[0] == 0x2001 && segments[1] == 0xdb8; // 2001:db8::/32
+
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || unique_local
+                || link_local
+                || documentation)
+        }
+    }
+}
+
+fn not_public() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        "host is not on the public internet",
+    )
+}
+
+/// Resolves names, refusing those which resolve to any address that is not public
+#[derive(Clone)]
+pub struct PublicResolver {
+    allow_private: bool,
+}
+
+impl Service<Name> for PublicResolver {
+    type Response = std::vec::IntoIter<IpAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let allow_private = self.allow_private;
+
+        Box::pin(async move {
+            let addrs: Vec<IpAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .map(|addr| addr.ip())
+                .collect();
+
+            if !allow_private && !addrs.iter().all(|ip| is_public(*ip)) {
+                return Err(not_public());
+            }
+
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// Connects over TCP through a `PublicResolver`. Hosts which are IP addresses are not resolved, so
+/// they are checked here instead.
+#[derive(Clone)]
+pub struct PublicConnector {
+    http: HttpConnector<PublicResolver>,
+    allow_private: bool,
+}
+
+impl Service<Uri> for PublicConnector {
+    type Response = TcpStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<TcpStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let literal = uri
+            .host()
+            .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+            .and_then(|host| host.parse::<IpAddr>().ok());
+
+        if let Some(ip) = literal {
+            if !self.allow_private && !is_public(ip) {
+                return Box::pin(future::err(not_public().into()));
+            }
+        }
+
+        let connecting = self.http.call(uri);
+        Box::pin(async move { connecting.await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_addresses() {
+        assert!(is_public("93.184.216.34".parse().unwrap()));
+        assert!(is_public("2606:2800:220:1::".parse().unwrap()));
+    }
+
+    #[test]
+    fn non_public_addresses() {
+        let addresses = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "0.1.2.3",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "::ffff:127.0.0.1",
+            "fd00::1",
+            "fe80::1",
+            "224.0.0.1",
+            "239.255.255.250",
+            "240.0.0.1",
+            "ff02::1",
+            "2001:db8::1",
+        ];
+
+        for address in &addresses {
+            assert!(
+                !is_public(address.parse().unwrap()),
+                "{} is not public",
+                address
+            );
+        }
+    }
+}
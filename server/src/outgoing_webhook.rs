@@ -0,0 +1,415 @@
+//! Outgoing webhooks, which POST a JSON rendering of community events to an external URL. Failed
+//! deliveries are retried with exponential backoff, and every attempt is recorded in the webhook's
+//! delivery log.
+//!
+//! Each request is signed with HMAC-SHA256 using the webhook's secret. The signature covers
+//! `{timestamp}.{body}`, where the timestamp is the UNIX time in seconds that is sent in the
+//! `X-Vertex-Timestamp` header. Receivers should check the signature and reject requests whose
+//! timestamp is more than five minutes away from their own clock, so that a captured request
+//! cannot be replayed later. Each retry is signed afresh.
+//!
+//! Webhook URLs are chosen by community moderators, so deliveries are only made to addresses on
+//! the public internet unless `outgoing_webhook_private_addresses` is set. Otherwise, webhooks and
+//! their delivery log could be used to probe the server's private network.
+
+use std::future::Future;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use hyper::{Body, Request};
+use serde::Serialize;
+use sha2::Sha256;
+use url::{Host, Url};
+use uuid::Uuid;
+
+use vertex::prelude::*;
+
+use crate::database::{OutgoingWebhookRecord, WebhookDeliveryRecord};
+use crate::net::{self, PublicClient};
+use crate::{shutdown, Global};
+
+const MAX_ATTEMPTS: u32 = 5;
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An event in a community, as rendered to JSON for outgoing webhooks
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum OutgoingEvent {
+    AddMessage {
+        community: Uuid,
+        room: Uuid,
+        message: Uuid,
+        author: Uuid,
+        time_sent: DateTime<Utc>,
+        content: String,
+    },
+    Edit {
+        community: Uuid,
+        room: Uuid,
+        message: Uuid,
+        new_content: String,
+    },
+    Delete {
+        community: Uuid,
+        room: Uuid,
+        message: Uuid,
+    },
+    AddRoom {
+        community: Uuid,
+        room: Uuid,
+        name: String,
+    },
+    MemberJoin {
+        community: Uuid,
+        user: Uuid,
+    },
+}
+
+impl OutgoingEvent {
+    fn flag(&self) -> WebhookEventFlags {
+        match self {
+            OutgoingEvent::AddMessage { .. } => WebhookEventFlags::ADD_MESSAGE,
+            OutgoingEvent::Edit { .. } => WebhookEventFlags::EDIT,
+            OutgoingEvent::Delete { .. } => WebhookEventFlags::DELETE,
+            OutgoingEvent::AddRoom { .. } => WebhookEventFlags::ADD_ROOM,
+            OutgoingEvent::MemberJoin { .. } => WebhookEventFlags::MEMBER_JOIN,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            OutgoingEvent::AddMessage { .. } => "add_message",
+            OutgoingEvent::Edit { .. } => "edit",
+            OutgoingEvent::Delete { .. } => "delete",
+            OutgoingEvent::AddRoom { .. } => "add_room",
+            OutgoingEvent::MemberJoin { .. } => "member_join",
+        }
+    }
+}
+
+/// Whether the URL may be used for an outgoing webhook. Hosts that are names are checked once they
+/// are resolved, when each delivery is made.
+pub fn valid_url(url: &str, allow_private: bool) -> bool {
+    let url = match Url::parse(url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+        _ => return false,
+    };
+
+    match url.host() {
+        Some(Host::Domain(_)) => true,
+        Some(Host::Ipv4(ip)) => allow_private || net::is_public(ip.into()),
+        Some(Host::Ipv6(ip)) => allow_private || net::is_public(ip.into()),
+        None => false,
+    }
+}
+
+/// Delivers the event to all of the given webhooks which are subscribed to it. This does not block
+/// on delivery.
+pub fn dispatch(global: &Global, webhooks: &[OutgoingWebhookRecord], event: OutgoingEvent) {
+    let flag = event.flag();
+    let mut subscribed = webhooks
+        .iter()
+        .filter(|w| w.events.contains(flag))
+        .peekable();
+
+    if subscribed.peek().is_none() {
+        return;
+    }
+
+    let body = match serde_json::to_vec(&event) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("Error serializing outgoing webhook event: {:?}", e);
+            return;
+        }
+    };
+
+    for webhook in subscribed {
+        shutdown::spawn_tracked(deliver(
+            global.clone(),
+            webhook.clone(),
+            event.name(),
+            body.clone(),
+        ));
+    }
+}
+
+async fn deliver(
+    global: Global,
+    webhook: OutgoingWebhookRecord,
+    event: &'static str,
+    body: Vec<u8>,
+) {
+    let client = net::client(global.config().outgoing_webhook_private_addresses);
+    let database = &global.database;
+
+    let record = |record| async move {
+        if let Err(e) = database.record_webhook_delivery(record).await {
+            log::error!("Error recording outgoing webhook delivery: {:?}", e);
+        }
+    };
+
+    attempt_delivery(client, BASE_RETRY_DELAY, &webhook, event, &body, record).await;
+}
+
+/// Sends the event until it is delivered successfully or runs out of attempts, passing a record of
+/// each attempt to `record`
+async fn attempt_delivery<F, R>(
+    client: &PublicClient,
+    base_retry_delay: Duration,
+    webhook: &OutgoingWebhookRecord,
+    event: &'static str,
+    body: &[u8],
+    mut record: F,
+) where
+    F: FnMut(WebhookDeliveryRecord) -> R,
+    R: Future<Output = ()>,
+{
+    for attempt in 1..=MAX_ATTEMPTS {
+        let attempted_at = Utc::now();
+        let timestamp = attempted_at.timestamp();
+        let signature = sign(&webhook.secret, timestamp, body);
+        let res = send(
+            client,
+            &webhook.url,
+            event,
+            timestamp,
+            &signature,
+            body.to_vec(),
+        )
+        .await;
+        let (status_code, error) = match res {
+            Ok(status) => (Some(status), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        let success = status_code
+            .map(|s| (200..300).contains(&s))
+            .unwrap_or(false);
+
+        record(WebhookDeliveryRecord {
+            webhook: webhook.id,
+            event,
+            attempted_at,
+            attempt,
+            status_code,
+            error,
+            success,
+        })
+        .await;
+
+        if success {
+            return;
+        }
+
+        // Deliveries in progress are let finish, but are not retried once the server is stopping
+        if attempt < MAX_ATTEMPTS {
+            let delay = tokio::time::delay_for(base_retry_delay * 2u32.pow(attempt - 1));
+            tokio::select! {
+                _ = delay => {},
+                _ = shutdown::stopping() => return,
+            }
+        }
+    }
+}
+
+/// Sends the request through the given client, which refuses to connect to private addresses
+/// unless they are allowed in the config
+async fn send(
+    client: &PublicClient,
+    url: &str,
+    event: &str,
+    timestamp: i64,
+    signature: &str,
+    body: Vec<u8>,
+) -> Result<u16, String> {
+    let request = Request::post(url)
+        .header("Content-Type", "application/json")
+        .header("X-Vertex-Event", event)
+        .header("X-Vertex-Timestamp", timestamp.to_string())
+        .header("X-Vertex-Signature", format!("sha256={}", signature))
+        .body(Body::from(body))
+        .map_err(|e| e.to_string())?;
+
+    match tokio::time::timeout(REQUEST_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) => Ok(response.status().as_u16()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("request timed out".to_string()),
+    }
+}
+
+/// Signs the timestamp and body with HMAC-SHA256, returning the lowercase hex digest
+fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.input(timestamp.to_string().as_bytes());
+    mac.input(b".");
+    mac.input(body);
+
+    mac.result()
+        .code()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{HeaderMap, Response, Server};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    const RETRY_DELAY: Duration = Duration::from_millis(1);
+
+    struct Received {
+        headers: HeaderMap,
+        body: Vec<u8>,
+    }
+
+    /// Starts an HTTP server on a local port, which responds to each request it receives with the
+    /// next of the given statuses, or 200 once they run out
+    fn stub(statuses: Vec<u16>) -> (SocketAddr, Arc<Mutex<Vec<Received>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+
+        let log = received.clone();
+        let make_service = make_service_fn(move |_| {
+            let log = log.clone();
+            let statuses = statuses.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let log = log.clone();
+                    let statuses = statuses.clone();
+
+                    async move {
+                        let headers = request.headers().clone();
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        log.lock().unwrap().push(Received {
+                            headers,
+                            body: body.to_vec(),
+                        });
+
+                        let status = statuses.lock().unwrap().next().unwrap_or(200);
+                        let response = Response::builder().status(status).body(Body::empty());
+                        Ok::<_, Infallible>(response.unwrap())
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        (addr, received)
+    }
+
+    fn webhook(addr: SocketAddr) -> OutgoingWebhookRecord {
+        OutgoingWebhookRecord {
+            id: WebhookId(Uuid::new_v4()),
+            community: CommunityId(Uuid::new_v4()),
+            url: format!("http://{}/hook", addr),
+            events: WebhookEventFlags::all(),
+            secret: "secret".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_until_delivered() {
+        let (addr, received) = stub(vec![500, 503]);
+        let webhook = webhook(addr);
+        let body = br#"{"event":"add_room"}"#;
+
+        let mut records = Vec::new();
+        let record = |record| {
+            records.push(record);
+            future::ready(())
+        };
+        let client = net::client(true);
+        attempt_delivery(client, RETRY_DELAY, &webhook, "add_room", body, record).await;
+
+        let attempts: Vec<_> = records
+            .iter()
+            .map(|r| (r.attempt, r.status_code, r.success, r.error.is_some()))
+            .collect();
+        let expected = vec![
+            (1, Some(500), false, false),
+            (2, Some(503), false, false),
+            (3, Some(200), true, false),
+        ];
+        assert_eq!(attempts, expected);
+        assert!(records
+            .iter()
+            .all(|r| r.webhook == webhook.id && r.event == "add_room"));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+
+        for request in received.iter() {
+            let header = |name| request.headers[name].to_str().unwrap().to_string();
+            let timestamp: i64 = header("X-Vertex-Timestamp").parse().unwrap();
+            let signature = format!("sha256={}", sign("secret", timestamp, body));
+
+            assert_eq!(header("X-Vertex-Signature"), signature);
+            assert_eq!(header("X-Vertex-Event"), "add_room");
+            assert_eq!(request.body, body.to_vec());
+        }
+    }
+
+    #[tokio::test]
+    async fn private_addresses_are_refused() {
+        let (addr, received) = stub(Vec::new());
+        let webhook = webhook(addr);
+
+        let mut records = Vec::new();
+        let record = |record| {
+            records.push(record);
+            future::ready(())
+        };
+        let client = net::client(false);
+        attempt_delivery(client, RETRY_DELAY, &webhook, "add_room", b"{}", record).await;
+
+        assert_eq!(records.len(), MAX_ATTEMPTS as usize);
+        assert!(records
+            .iter()
+            .all(|r| !r.success && r.status_code.is_none() && r.error.is_some()));
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn webhook_urls() {
+        assert!(valid_url("https://example.com/hook", false));
+        assert!(valid_url("http://93.184.216.34:8080/hook", false));
+        assert!(valid_url("http://127.0.0.1:8080/hook", true));
+
+        assert!(!valid_url("ftp://example.com/hook", false));
+        assert!(!valid_url("not a url", false));
+        assert!(!valid_url("http://127.0.0.1:8080/hook", false));
+        assert!(!valid_url("http://169.254.169.254/latest/meta-data", false));
+        assert!(!valid_url("http://[::1]/hook", false));
+    }
+
+    #[test]
+    fn signature_covers_timestamp_and_body() {
+        assert_eq!(
+            sign("secret", 1600000000, br#"{"event":"ping"}"#),
+            "f1629e66e53028c0a0ed0f843c4f60e3379f3b998c821cf99eae2fd6dcaa0969"
+        );
+    }
+
+    #[test]
+    fn signature_changes_with_timestamp() {
+        let body = br#"{"event":"ping"}"#;
+        assert_ne!(
+            sign("secret", 1600000000, body),
+            sign("secret", 1600000001, body)
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Ratelimiting. Every message from a device counts against a general quota, and some classes of
+//! request (see `RatelimitClass`) count against their own, tighter quota too. Unauthenticated auth
+//! endpoints are ratelimited by IP address to stop brute forcing, as are federated requests which
+//! make this server fetch another server's key.
+
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use governor::clock::{Clock, DefaultClock};
+use governor::state::keyed::DashMapStateStore;
+use governor::{Quota, RateLimiter};
+
+use vertex::prelude::*;
+
+use crate::config::Config;
+
+type KeyedRatelimiter<K> = RateLimiter<K, DashMapStateStore<K>, DefaultClock>;
+
+pub struct Ratelimiters {
+    general: KeyedRatelimiter<DeviceId>,
+    bot: KeyedRatelimiter<DeviceId>,
+    messages: KeyedRatelimiter<DeviceId>,
+    invites: KeyedRatelimiter<DeviceId>,
+    room_creations: KeyedRatelimiter<DeviceId>,
+    auth: KeyedRatelimiter<IpAddr>,
+    federation_key_fetches: KeyedRatelimiter<IpAddr>,
+}
+
+fn new_ratelimiter<K>(per_min: u32) -> KeyedRatelimiter<K>
+where
+    K: Clone + Eq + std::hash::Hash,
+{
+    RateLimiter::dashmap(Quota::per_minute(NonZeroU32::new(per_min).unwrap()))
+}
+
+impl Ratelimiters {
+    pub fn new(config: &Config) -> Self {
+        Ratelimiters {
+            general: new_ratelimiter(config.ratelimit_burst_per_min),
+            bot: new_ratelimiter(config.bot_ratelimit_burst_per_min),
+            messages: new_ratelimiter(config.message_ratelimit_per_min),
+            invites: new_ratelimiter(config.invite_ratelimit_per_min),
+            room_creations: new_ratelimiter(config.room_creation_ratelimit_per_min),
+            auth: new_ratelimiter(config.auth_ratelimit_per_min),
+            federation_key_fetches: new_ratelimiter(config.federation_key_fetch_ratelimit_per_min),
+        }
+    }
+
+    /// Whether any quota differs between the two configs, so the ratelimiters must be recreated
+    pub fn quotas_changed(old: &Config, new: &Config) -> bool {
+        old.ratelimit_burst_per_min != new.ratelimit_burst_per_min
+            || old.bot_ratelimit_burst_per_min != new.bot_ratelimit_burst_per_min
+            || old.message_ratelimit_per_min != new.message_ratelimit_per_min
+            || old.invite_ratelimit_per_min != new.invite_ratelimit_per_min
+            || old.room_creation_ratelimit_per_min != new.room_creation_ratelimit_per_min
+            || old.auth_ratelimit_per_min != new.auth_ratelimit_per_min
+            || old.federation_key_fetch_ratelimit_per_min
+                != new.federation_key_fetch_ratelimit_per_min
+    }
+
+    /// The quotas to advertise to a client, so that it can avoid being ratelimited
+    pub fn quotas(config: &Config, bot: bool) -> RatelimitQuotas {
+        let general_per_min = if bot {
+            config.bot_ratelimit_burst_per_min
+        } else {
+            config.ratelimit_burst_per_min
+        };
+
+        RatelimitQuotas {
+            general_per_min,
+            messages_per_min: config.message_ratelimit_per_min,
+            invites_per_min: config.invite_ratelimit_per_min,
+            room_creations_per_min: config.room_creation_ratelimit_per_min,
+        }
+    }
+
+    /// Checks the general quota for a message from the device. If it is exceeded, returns how long
+    /// until the device may send again.
+    pub fn check_general(&self, device: DeviceId, bot: bool) -> Result<(), Duration> {
+        let ratelimiter = if bot { &self.bot } else { &self.general };
+        ratelimiter
+            .check_key(&device)
+            .map_err(|not_until| not_until.wait_time_from(DefaultClock::default().now()))
+    }
+
+    /// Checks the quota for the request's class, if it has one
+    pub fn check_request(&self, device: DeviceId, request: &ClientRequest) -> Result<(), Error> {
+        let ratelimiter = match request.ratelimit_class() {
+            Some(RatelimitClass::Message) => &self.messages,
+            Some(RatelimitClass::Invite) => &self.invites,
+            Some(RatelimitClass::RoomCreation) => &self.room_creations,
+            None => return Ok(()),
+        };
+
+        ratelimiter
+            .check_key(&device)
+            .map_err(|_| Error::RateLimited)
+    }
+
+    /// Checks the quota for an unauthenticated auth request from the given address
+    pub fn check_auth(&self, addr: Option<SocketAddr>) -> Result<(), AuthError> {
+        self.auth
+            .check_key(&ip_key(addr))
+            .map_err(|_| AuthError::RateLimited)
+    }
+
+    /// Checks the quota for a federated request from the given address which needs the key of a
+    /// server that has not been seen before to be fetched. Returns whether it may be fetched.
+    pub fn check_federation_key_fetch(&self, addr: Option<SocketAddr>) -> bool {
+        self.federation_key_fetches.check_key(&ip_key(addr)).is_ok()
+    }
+
+    /// Forgets about keys which have not been ratelimited recently, so that the ratelimiters do not
+    /// grow without bound
+    pub fn retain_recent(&self) {
+        self.general.retain_recent();
+        self.bot.retain_recent();
+        self.messages.retain_recent();
+        self.invites.retain_recent();
+        self.room_creations.retain_recent();
+        self.auth.retain_recent();
+        self.federation_key_fetches.retain_recent();
+    }
+}
+
+/// If the address is unknown, we can't key by it, so it shares a quota with others
+fn ip_key(addr: Option<SocketAddr>) -> IpAddr {
+    addr.map(|addr| addr.ip())
+        .unwrap_or_else(|| IpAddr::from([0, 0, 0, 0]))
+}
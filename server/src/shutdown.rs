@@ -0,0 +1,130 @@
+//! Graceful shutdown. Once a shutdown signal is received, the server stops accepting connections,
+//! tells connected clients to reconnect later, stops its background tasks, and waits for in-flight
+//! requests, sessions and background tasks to finish, up to a configurable deadline.
+
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use log::{info, warn};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use crate::client::session::{ShutDownSession, USERS};
+use crate::client::Session;
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    static ref STOPPING: (watch::Sender<bool>, watch::Receiver<bool>) = watch::channel(false);
+    /// Every tracked task holds a clone of the sender, so that shutdown can wait for them all to
+    /// finish by waiting for the channel to close. The sender is dropped once shutdown begins.
+    static ref TRACKED: Mutex<(Option<mpsc::Sender<()>>, Option<mpsc::Receiver<()>>)> = {
+        let (running, finished) = mpsc::channel(1);
+        Mutex::new((Some(running), Some(finished)))
+    };
+}
+
+/// Resolves once the server has begun to shut down
+pub async fn stopping() {
+    let mut stopping = STOPPING.1.clone();
+    while let Some(value) = stopping.recv().await {
+        if value {
+            return;
+        }
+    }
+}
+
+/// Spawns a task which the server waits for before it exits. The task should finish promptly once
+/// `stopping` resolves.
+pub fn spawn_tracked<F>(task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let running = TRACKED.lock().unwrap().0.clone();
+    tokio::spawn(async move {
+        task.await;
+        drop(running);
+    });
+}
+
+/// Spawns a task which runs until the server shuts down, e.g a sweeper. It is stopped at its next
+/// await point once shutdown begins.
+pub fn spawn_background<F>(task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    spawn_tracked(async move {
+        tokio::select! {
+            _ = task => {},
+            _ = stopping() => {},
+        }
+    });
+}
+
+/// Resolves once the process receives SIGTERM or SIGINT (Ctrl-C)
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Error listening for SIGTERM");
+
+        tokio::select! {
+            _ = terminate.recv() => {},
+            res = tokio::signal::ctrl_c() => res.expect("Error listening for Ctrl-C"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Error listening for Ctrl-C");
+}
+
+/// Tells every connected session that the server is shutting down, then waits for the server to
+/// stop serving requests and all sessions to log out. Gives up once the deadline has passed.
+pub async fn drain(server: JoinHandle<()>, deadline: Duration) {
+    let _ = STOPPING.0.broadcast(true);
+    let tracked = {
+        let mut tracked = TRACKED.lock().unwrap();
+        tracked.0 = None;
+        tracked.1.take()
+    };
+
+    let sessions: Vec<_> = USERS
+        .iter()
+        .flat_map(|user| {
+            user.sessions
+                .values()
+                .filter_map(Session::as_active_actor)
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    info!("Disconnecting {} session(s)", sessions.len());
+    for session in sessions {
+        let _ = session.address().do_send(ShutDownSession);
+    }
+
+    let drained = async {
+        let _ = server.await;
+
+        while !USERS.is_empty() {
+            tokio::time::delay_for(DRAIN_POLL_INTERVAL).await;
+        }
+
+        // This resolves once every tracked task has dropped its sender
+        if let Some(mut tracked) = tracked {
+            let _ = tracked.recv().await;
+        }
+    };
+
+    if tokio::time::timeout(deadline, drained).await.is_err() {
+        warn!(
+            "Timed out after {}s waiting for requests, sessions and background tasks to finish",
+            deadline.as_secs(),
+        );
+    }
+}
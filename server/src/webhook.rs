@@ -0,0 +1,118 @@
+//! Incoming webhooks, which allow external services (e.g CI) to post messages into a room over HTTP
+
+use std::net::SocketAddr;
+
+use http::StatusCode;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use vertex::prelude::*;
+
+use crate::database::DbResult;
+use crate::{auth, community, handle_disconnected, metrics, Global, IdentifiedMessage};
+
+/// The JSON body of a message posted to a webhook
+#[derive(Deserialize)]
+pub struct WebhookMessage {
+    pub content: String,
+    /// Overrides the webhook's display name for this message only
+    #[serde(default)]
+    pub display_name: Option<String>,
+}
+
+/// A message posted through a webhook, which may be shown under another name than the webhook's
+pub struct WebhookSentMessage {
+    pub message: ClientSentMessage,
+    pub display_name: Option<String>,
+}
+
+pub async fn execute(
+    global: Global,
+    addr: Option<SocketAddr>,
+    id: Uuid,
+    token: String,
+    message: WebhookMessage,
+) -> StatusCode {
+    match try_execute(global, addr, WebhookId(id), token, message).await {
+        Ok(code) => code,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn try_execute(
+    global: Global,
+    addr: Option<SocketAddr>,
+    id: WebhookId,
+    token: String,
+    message: WebhookMessage,
+) -> DbResult<StatusCode> {
+    // The ratelimits are checked before the token is, so that guessing tokens is slow and can't be
+    // used to make the server spend its time hashing
+    if global.ratelimiters.load().check_auth(addr).is_err() {
+        return Ok(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    let db = &global.database;
+    let webhook = match db.get_webhook(id).await? {
+        Some(webhook) => webhook,
+        None => return Ok(StatusCode::NOT_FOUND),
+    };
+
+    // Webhooks are keyed by their ID so they do not share a ratelimit with any real device
+    let device = DeviceId(id.0);
+    if global.ratelimiters.load().check_general(device, true).is_err() {
+        metrics::record_ratelimited(true);
+        return Ok(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    if token.len() > auth::MAX_TOKEN_LENGTH
+        || !auth::verify(token, webhook.token_hash, webhook.hash_scheme_version).await
+    {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+
+    let config = global.config();
+    if message.content.trim().is_empty() || message.content.len() > config.max_message_len as usize
+    {
+        return Ok(StatusCode::BAD_REQUEST);
+    }
+
+    if let Some(display_name) = &message.display_name {
+        if !auth::valid_display_name(display_name, &config) {
+            return Ok(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    let community = match community::address_of(webhook.community) {
+        Ok(addr) => addr,
+        Err(_) => return Ok(StatusCode::NOT_FOUND),
+    };
+
+    let message = IdentifiedMessage {
+        user: webhook.user,
+        device,
+        message: WebhookSentMessage {
+            message: ClientSentMessage {
+                to_community: webhook.community,
+                to_room: webhook.room,
+                content: message.content,
+                ciphertext: None,
+            },
+            display_name: message.display_name,
+        },
+    };
+
+    let res = community
+        .send(message)
+        .await
+        .map_err(handle_disconnected("Community"));
+
+    Ok(match res {
+        Ok(Ok(_)) => StatusCode::NO_CONTENT,
+        // The room was made encrypted, which webhooks cannot post to
+        Ok(Err(Error::EncryptedRoom)) => StatusCode::BAD_REQUEST,
+        // The room's maximum message length is lower than the server's
+        Ok(Err(Error::MessageTooLong)) => StatusCode::BAD_REQUEST,
+        Ok(Err(_)) | Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    })
+}